@@ -0,0 +1,340 @@
+use pollster::FutureExt;
+use std::{cell::RefCell, rc::Rc, time::Duration};
+use web_time::Instant;
+use winit::{
+    application::ApplicationHandler,
+    event::{DeviceEvent, DeviceId, ElementState, KeyEvent, WindowEvent},
+    event_loop::{ActiveEventLoop, EventLoopProxy},
+    keyboard::{KeyCode, PhysicalKey},
+    window::WindowId,
+};
+
+use crate::{RenderContext, RenderContextOptions, WindowConfig, file_loader::FileId};
+
+/// Custom events the event loop of [`App`] carries, see [`App::set_event_proxy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserEvent {
+    /// A load of the file finished, [`crate::file_loader::FileLoader::poll`] hands it out
+    FileReady(FileId),
+}
+
+/// Application specific part of the frame loop driven by [`App`].
+pub trait Renderer {
+    /// Called once the window and render context are created.
+    fn new(render_context: Rc<RefCell<RenderContext>>) -> Self
+    where
+        Self: Sized;
+
+    /// Device requirements, asked for before the render context is created.
+    fn render_context_options() -> RenderContextOptions
+    where
+        Self: Sized,
+    {
+        RenderContextOptions::default()
+    }
+
+    /// Called after the surface was reconfigured to the new size.
+    fn resize(&mut self, width: u32, height: u32);
+
+    /// Called once per frame before `render`.
+    fn update(&mut self);
+
+    /// Records and presents a frame.
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError>;
+
+    /// Returns true if the event was consumed and should not be processed by the app.
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, event: &WindowEvent) -> bool;
+
+    /// Raw input that does not depend on the window, like mouse motion past the screen edge.
+    fn device_event(&mut self, _event_loop: &ActiveEventLoop, _event: &DeviceEvent) {}
+
+    /// Called right after `new` when the app has a proxy, e.g. to give it to a file loader.
+    fn set_event_proxy(&mut self, _proxy: EventLoopProxy<UserEvent>) {}
+
+    /// An event sent through the proxy arrived.
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, _event: UserEvent) {}
+}
+
+struct AppState<R: Renderer> {
+    render_context: Rc<RefCell<RenderContext>>,
+    renderer: R,
+}
+
+// Frame rate cap while the window is in the background and `pause_on_blur` is set
+const BLUR_FPS: u32 = 5;
+
+/// Frame rate cap for the focus state of the window. `None` is not capped.
+fn paced_fps(target_fps: Option<u32>, focused: bool, pause_on_blur: bool) -> Option<u32> {
+    match (focused || !pause_on_blur, target_fps) {
+        (true, target_fps) => target_fps,
+        (false, Some(target_fps)) => Some(target_fps.min(BLUR_FPS)),
+        (false, None) => Some(BLUR_FPS),
+    }
+}
+
+/// How long to wait before the next frame so the frame rate does not exceed `target_fps`.
+fn frame_sleep_duration(last_frame: Instant, now: Instant, target_fps: u32) -> Duration {
+    let frame_time = Duration::from_secs_f64(1.0 / target_fps as f64);
+    frame_time.saturating_sub(now.saturating_duration_since(last_frame))
+}
+
+/// Implements the winit event loop handling shared by all tutorials:
+/// window creation, resizing, redraw requests and surface errors.
+pub struct App<R: Renderer> {
+    state: Option<AppState<R>>,
+    target_fps: Option<u32>,
+    last_frame: Instant,
+    pause_on_blur: bool,
+    window_config: WindowConfig,
+    event_proxy: Option<EventLoopProxy<UserEvent>>,
+}
+
+impl<R: Renderer> App<R> {
+    pub fn new() -> Self {
+        Self {
+            state: None,
+            target_fps: None,
+            last_frame: Instant::now(),
+            pause_on_blur: false,
+            window_config: WindowConfig::default(),
+            event_proxy: None,
+        }
+    }
+
+    pub fn target_fps(&self) -> Option<u32> {
+        self.target_fps
+    }
+
+    /// Caps the frame rate, useful with present modes that do not wait for vsync.
+    /// `None` or zero renders as fast as possible.
+    pub fn set_target_fps(&mut self, target_fps: Option<u32>) {
+        self.target_fps = target_fps.filter(|fps| *fps > 0);
+    }
+
+    pub fn pause_on_blur(&self) -> bool {
+        self.pause_on_blur
+    }
+
+    /// Drops to a few frames per second while the window is not focused, to save power
+    pub fn set_pause_on_blur(&mut self, pause_on_blur: bool) {
+        self.pause_on_blur = pause_on_blur;
+    }
+
+    pub fn window_config(&self) -> &WindowConfig {
+        &self.window_config
+    }
+
+    /// Title and size of the window. Takes effect when the window is created on resume.
+    pub fn set_window_config(&mut self, window_config: WindowConfig) {
+        self.window_config = window_config;
+    }
+
+    /// Proxy of the event loop the app runs on, handed to the renderer when it is created.
+    /// The loop has to be built with `EventLoop::<UserEvent>::with_user_event()`.
+    pub fn set_event_proxy(&mut self, proxy: EventLoopProxy<UserEvent>) {
+        self.event_proxy = Some(proxy);
+    }
+
+    // Returns false if the frame comes too early and has to be skipped
+    fn pace_frame(target_fps: Option<u32>, last_frame: &mut Instant) -> bool {
+        if let Some(target_fps) = target_fps {
+            let wait = frame_sleep_duration(*last_frame, Instant::now(), target_fps);
+            if !wait.is_zero() {
+                cfg_if::cfg_if! {
+                    if #[cfg(target_arch = "wasm32")] {
+                        // Can't block the browser. Redraws are driven by requestAnimationFrame,
+                        // so skipping frames until the time is up does not spin.
+                        return false;
+                    } else {
+                        std::thread::sleep(wait);
+                    }
+                }
+            }
+        }
+
+        *last_frame = Instant::now();
+        true
+    }
+}
+
+impl<R: Renderer> Default for App<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Renderer> AppState<R> {
+    fn resize(&mut self, width: u32, height: u32) {
+        self.render_context.borrow_mut().resize(width, height);
+        if self.render_context.borrow().is_configured() {
+            self.renderer.resize(width, height);
+        }
+    }
+
+    fn redraw(&mut self, event_loop: &ActiveEventLoop) {
+        // This tells winit that we want another frame after this one
+        self.render_context.borrow().window().request_redraw();
+
+        let canvas_size = self.render_context.borrow().poll_canvas_resize();
+        if let Some((width, height)) = canvas_size {
+            self.resize(width, height);
+        }
+
+        if !self.render_context.borrow().is_configured() {
+            return;
+        }
+
+        self.renderer.update();
+        match self.renderer.render() {
+            Ok(_) => {}
+            // Reconfigure the surface if it's lost or outdated
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                let (w, h) = {
+                    let ctx = self.render_context.borrow();
+                    (ctx.config.width, ctx.config.height)
+                };
+                self.resize(w, h)
+            }
+            // The system is out of memory, we should probably quit
+            Err(wgpu::SurfaceError::OutOfMemory | wgpu::SurfaceError::Other) => {
+                log::error!("OutOfMemory");
+                event_loop.exit();
+            }
+            // This happens when the a frame takes too long to present
+            Err(wgpu::SurfaceError::Timeout) => {
+                log::warn!("Surface timeout")
+            }
+        }
+    }
+}
+
+impl<R: Renderer> ApplicationHandler<UserEvent> for App<R> {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let window = event_loop
+            .create_window(self.window_config.attributes())
+            .unwrap();
+        let render_context =
+            match RenderContext::with_options(window, R::render_context_options()).block_on() {
+                Ok(render_context) => Rc::new(RefCell::new(render_context)),
+                Err(err) => {
+                    log::error!("{err:#}");
+                    event_loop.exit();
+                    return;
+                }
+            };
+        let mut renderer = R::new(render_context.clone());
+        if let Some(proxy) = &self.event_proxy {
+            renderer.set_event_proxy(proxy.clone());
+        }
+
+        self.state = Some(AppState {
+            render_context,
+            renderer,
+        });
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        _window_id: WindowId,
+        event: WindowEvent,
+    ) {
+        let Some(state) = &mut self.state else {
+            return;
+        };
+
+        // Stored on the context before the renderer sees the event, so it can't be missed
+        if let WindowEvent::Focused(focused) = event {
+            state.render_context.borrow_mut().set_focused(focused);
+        }
+
+        if state.renderer.window_event(event_loop, &event) {
+            return;
+        }
+
+        match event {
+            WindowEvent::CloseRequested
+            | WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(KeyCode::Escape),
+                        ..
+                    },
+                ..
+            } => {
+                println!("The close button was pressed; stopping");
+                event_loop.exit()
+            }
+            WindowEvent::Resized(physical_size) => {
+                log::info!("physical_size: {physical_size:?}");
+                state.resize(physical_size.width, physical_size.height);
+            }
+            WindowEvent::RedrawRequested => {
+                let focused = state.render_context.borrow().is_focused();
+                let fps = paced_fps(self.target_fps, focused, self.pause_on_blur);
+                if Self::pace_frame(fps, &mut self.last_frame) {
+                    state.redraw(event_loop);
+                } else {
+                    state.render_context.borrow().window().request_redraw();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn device_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        if let Some(state) = &mut self.state {
+            state.renderer.device_event(event_loop, &event);
+        }
+    }
+
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: UserEvent) {
+        if let Some(state) = &mut self.state {
+            state.renderer.user_event(event_loop, event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_sleep_duration() {
+        let last_frame = Instant::now();
+
+        // 50 fps gives 20ms per frame
+        let wait = frame_sleep_duration(last_frame, last_frame + Duration::from_millis(5), 50);
+        assert_eq!(wait, Duration::from_millis(15));
+
+        let wait = frame_sleep_duration(last_frame, last_frame, 50);
+        assert_eq!(wait, Duration::from_millis(20));
+
+        // The frame took longer than the budget, no need to wait
+        let wait = frame_sleep_duration(last_frame, last_frame + Duration::from_millis(25), 50);
+        assert!(wait.is_zero());
+
+        // The clock should not go backwards, but it must not panic if it does
+        let wait = frame_sleep_duration(last_frame + Duration::from_millis(5), last_frame, 50);
+        assert_eq!(wait, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_paced_fps_on_focus_change() {
+        // Focused windows keep the target
+        assert_eq!(paced_fps(None, true, true), None);
+        assert_eq!(paced_fps(Some(144), true, true), Some(144));
+
+        // Background windows slow down only with pause_on_blur
+        assert_eq!(paced_fps(None, false, false), None);
+        assert_eq!(paced_fps(None, false, true), Some(BLUR_FPS));
+        assert_eq!(paced_fps(Some(144), false, true), Some(BLUR_FPS));
+        assert_eq!(paced_fps(Some(2), false, true), Some(2));
+    }
+}
@@ -1,7 +1,11 @@
-use cgmath::{Matrix4, Point3, Transform, Vector3};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Transform, Vector3};
 use std::cell::{Ref, RefCell};
 
-use crate::rotator::Rotator;
+use crate::{
+    Viewport,
+    frustum::{Frustum, frustum_corners},
+    rotator::Rotator,
+};
 
 struct CameraCache {
     forward: Vector3<f32>,
@@ -10,16 +14,79 @@ struct CameraCache {
     view_matrix: Matrix4<f32>,
 }
 
+#[rustfmt::skip]
+const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// Vertical field of view in degrees
+    Perspective { fovy: f32 },
+    /// World space height of the view volume
+    Orthographic { height: f32 },
+}
+
+/// Which world axis points up. Rotators work in a Z-up frame that is turned to match it,
+/// so the yaw always turns around the world up axis.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CoordinateSystem {
+    /// A zero rotator looks down +X with +Z up
+    #[default]
+    ZUp,
+    /// A zero rotator looks down -Z with +Y up, like most Y-up tools
+    YUp,
+}
+
+impl CoordinateSystem {
+    /// Where the X, Y and Z axes of the rotator frame end up in the world
+    pub fn basis(self) -> Matrix3<f32> {
+        match self {
+            CoordinateSystem::ZUp => Matrix3::identity(),
+            CoordinateSystem::YUp => {
+                Matrix3::from_cols(-Vector3::unit_z(), -Vector3::unit_x(), Vector3::unit_y())
+            }
+        }
+    }
+
+    pub fn up(self) -> Vector3<f32> {
+        self.basis().z
+    }
+
+    /// Two axes spanning the ground plane through the origin, e.g. for a grid
+    pub fn ground_axes(self) -> (Vector3<f32>, Vector3<f32>) {
+        match self {
+            CoordinateSystem::ZUp => (Vector3::unit_x(), Vector3::unit_y()),
+            CoordinateSystem::YUp => (Vector3::unit_x(), Vector3::unit_z()),
+        }
+    }
+}
+
+/// Where a camera is and where it looks, without the projection
+#[derive(Copy, Clone, Debug)]
+pub struct CameraPose {
+    pub eye: Point3<f32>,
+    pub rotator: Rotator,
+}
+
 pub struct Camera {
     eye: cgmath::Point3<f32>,
     rotator: Rotator,
 
     aspect: f32,
-    fovy: f32,
+    projection: Projection,
     znear: f32,
     zfar: f32,
 
+    coordinate_system: CoordinateSystem,
+
     cache: RefCell<Option<CameraCache>>,
+    // Times the cache was computed
+    #[cfg(feature = "debug-instrumentation")]
+    recompute_count: std::cell::Cell<u32>,
 }
 
 impl Camera {
@@ -35,22 +102,57 @@ impl Camera {
             eye,
             rotator: rot,
             aspect,
-            fovy: fov,
+            projection: Projection::Perspective { fovy: fov },
             znear,
             zfar,
+            coordinate_system: CoordinateSystem::default(),
             cache: RefCell::new(None),
+            #[cfg(feature = "debug-instrumentation")]
+            recompute_count: std::cell::Cell::new(0),
         }
     }
 
+    pub fn new_orthographic(
+        eye: Point3<f32>,
+        rot: Rotator,
+        aspect: f32,
+        height: f32,
+        znear: f32,
+        zfar: f32,
+    ) -> Self {
+        let mut camera = Self::new(eye, rot, aspect, 90.0, znear, zfar);
+        camera.projection = Projection::Orthographic { height };
+        camera
+    }
+
     fn build_view_projection_matrix(&self) -> Matrix4<f32> {
         let cache = self.get_cache();
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
-        // OPENGL_TO_WGPU_MATRIX * proj * cache.view_matrix
-        proj * cache.view_matrix
+        let proj = match self.projection {
+            Projection::Perspective { fovy } => {
+                cgmath::perspective(cgmath::Deg(fovy), self.aspect, self.znear, self.zfar)
+            }
+            Projection::Orthographic { height } => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                cgmath::ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+            }
+        };
+        // Depth goes from 0 at the near plane to 1 at the far plane, like wgpu expects
+        OPENGL_TO_WGPU_MATRIX * proj * cache.view_matrix
     }
 
     fn compute_cache(&self) -> CameraCache {
-        let r = self.rotator.to_matrix();
+        #[cfg(feature = "debug-instrumentation")]
+        self.recompute_count.set(self.recompute_count.get() + 1);
+
+        let r = Matrix4::from(self.coordinate_system.basis()) * self.rotator.to_matrix();
         let forward = r.transform_vector(Vector3::unit_x());
         let right = r.transform_vector(Vector3::unit_y());
         let up = r.transform_vector(Vector3::unit_z());
@@ -90,8 +192,79 @@ impl Camera {
     }
 
     pub fn set_rotator(&mut self, rotator: Rotator) {
-        self.rotator = rotator;
-        self.clear_cache();
+        if self.rotator != rotator {
+            self.rotator = rotator;
+            self.clear_cache();
+        }
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        CameraPose {
+            eye: self.eye,
+            rotator: self.rotator,
+        }
+    }
+
+    pub fn set_pose(&mut self, pose: CameraPose) {
+        self.set_eye(pose.eye);
+        self.set_rotator(pose.rotator);
+    }
+
+    /// Turns the camera to look at `target` without moving it. The roll is reset to zero.
+    pub fn face_towards(&mut self, target: Point3<f32>) {
+        let direction = target - self.eye;
+        if direction.magnitude2() > 0.0 {
+            // Back to the frame the rotator works in
+            let local = self.coordinate_system.basis().transpose() * direction;
+            self.set_rotator(Rotator::from_direction(local));
+        }
+    }
+
+    /// Moves the camera back along its view direction until the sphere fits the view.
+    /// The rotation is kept. Orthographic cameras also fit their height to the sphere.
+    pub fn frame_sphere(&mut self, center: Point3<f32>, radius: f32) {
+        let radius = radius.max(f32::EPSILON);
+        let distance = match self.projection {
+            Projection::Perspective { fovy } => {
+                // The narrower of the vertical and horizontal field of view
+                let half_fovy = cgmath::Rad::from(cgmath::Deg(fovy / 2.0)).0;
+                let half_fov = half_fovy.min((half_fovy.tan() * self.aspect).atan());
+                radius / half_fov.sin()
+            }
+            Projection::Orthographic { .. } => {
+                self.projection = Projection::Orthographic {
+                    height: 2.0 * radius * (1.0 / self.aspect).max(1.0),
+                };
+                2.0 * radius
+            }
+        };
+        let forward = self.forward();
+        self.set_eye(center - forward * distance);
+    }
+
+    pub fn coordinate_system(&self) -> CoordinateSystem {
+        self.coordinate_system
+    }
+
+    /// Keeps the rotator, so the camera turns with the world axes
+    pub fn set_coordinate_system(&mut self, coordinate_system: CoordinateSystem) {
+        if self.coordinate_system != coordinate_system {
+            self.coordinate_system = coordinate_system;
+            self.clear_cache();
+        }
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn set_clip_planes(&mut self, znear: f32, zfar: f32) {
+        self.znear = znear;
+        self.zfar = zfar;
     }
 
     pub fn set_aspect(&mut self, aspect: f32) {
@@ -101,6 +274,11 @@ impl Camera {
         }
     }
 
+    /// Distances of the near and far planes
+    pub fn clip_planes(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
+
     pub fn forward(&self) -> Vector3<f32> {
         self.get_cache().forward
     }
@@ -113,6 +291,42 @@ impl Camera {
         self.get_cache().up
     }
 
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(self.build_view_projection_matrix())
+    }
+
+    /// Corners of [`Camera::frustum`] in the world, see [`frustum_corners`] for the order
+    pub fn frustum_corners(&self) -> [Point3<f32>; 8] {
+        frustum_corners(self.build_view_projection_matrix())
+            .expect("Camera projection is always invertible")
+    }
+
+    /// Pixel coordinates of a world point in `viewport`, with Y going down like window
+    /// coordinates. None if the point is behind the camera or outside the clip volume.
+    pub fn world_to_screen(&self, point: Point3<f32>, viewport: Viewport) -> Option<(f32, f32)> {
+        let clip = self.build_view_projection_matrix() * point.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+
+        let ndc = clip.truncate() / clip.w;
+        if ndc.x.abs() > 1.0 || ndc.y.abs() > 1.0 || ndc.z > 1.0 {
+            return None;
+        }
+
+        Some((
+            viewport.x as f32 + (ndc.x + 1.0) * 0.5 * viewport.width as f32,
+            viewport.y as f32 + (1.0 - ndc.y) * 0.5 * viewport.height as f32,
+        ))
+    }
+
+    /// How many times the view was recomputed after a change, to check that setters
+    /// which change nothing keep the cache
+    #[cfg(feature = "debug-instrumentation")]
+    pub fn cache_recompute_count(&self) -> u32 {
+        self.recompute_count.get()
+    }
+
     pub fn clear_cache(&mut self) {
         self.cache = RefCell::new(None);
     }
@@ -126,6 +340,10 @@ pub struct CameraUniform {
     // We can't use cgmath with bytemuck directly, so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     pub view_proj: [[f32; 4]; 4],
+    /// Position of the camera for view dependent shading, w is 1
+    pub eye: [f32; 4],
+    /// Distances of the near and far planes in x and y, to linearise depth
+    pub clip_planes: [f32; 4],
 }
 
 impl CameraUniform {
@@ -133,11 +351,16 @@ impl CameraUniform {
         use cgmath::SquareMatrix;
         Self {
             view_proj: Matrix4::identity().into(),
+            eye: [0.0, 0.0, 0.0, 1.0],
+            clip_planes: [0.1, 1000.0, 0.0, 0.0],
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
         self.view_proj = camera.build_view_projection_matrix().into();
+        self.eye = camera.get_eye().to_homogeneous().into();
+        let (znear, zfar) = camera.clip_planes();
+        self.clip_planes = [znear, zfar, 0.0, 0.0];
     }
 }
 
@@ -145,8 +368,47 @@ impl CameraUniform {
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
+    use crate::common::test_utils::*;
     use cgmath::Deg;
 
+    #[test]
+    fn test_frame_sphere() {
+        let mut camera = Camera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Rotator {
+                yaw: Deg(30.0),
+                pitch: Deg(-20.0),
+                roll: Deg(0.0),
+            },
+            2.0,
+            90.0,
+            0.1,
+            1000.0,
+        );
+        let rotator = *camera.get_rotator();
+        let center = Point3::new(10.0, -5.0, 3.0);
+        camera.frame_sphere(center, 4.0);
+
+        // Looks at the center from the distance where the sphere touches the 90 degree fovy
+        assert_eq!(*camera.get_rotator(), rotator);
+        let to_center = center - *camera.get_eye();
+        assert!((to_center.normalize() - camera.forward()).magnitude() < 1e-5);
+        assert!((to_center.magnitude() - 4.0 * 2.0f32.sqrt()).abs() < 1e-4);
+
+        // Narrow windows move further back to fit the sphere horizontally
+        camera.set_aspect(0.5);
+        camera.frame_sphere(center, 4.0);
+        assert!((center - *camera.get_eye()).magnitude() > 4.0 * 2.0f32.sqrt() + 1.0);
+
+        let mut camera =
+            Camera::new_orthographic(Point3::new(0.0, 0.0, 0.0), rotator, 2.0, 1.0, 0.1, 1000.0);
+        camera.frame_sphere(center, 4.0);
+        assert_eq!(
+            camera.projection(),
+            Projection::Orthographic { height: 8.0 }
+        );
+    }
+
     #[test]
     fn test_add() {
         let c = Camera::new(
@@ -172,4 +434,280 @@ mod tests {
         println!("  {:?} -> {:?}", b, v.transform_point(b));
         println!("  {:?} -> {:?}", c, v.transform_point(c));
     }
+
+    #[test]
+    fn test_uniform_has_the_clip_planes() {
+        let rotator = Rotator {
+            yaw: Deg(0.0),
+            pitch: Deg(0.0),
+            roll: Deg(0.0),
+        };
+        let camera = Camera::new((0.0, 0.0, 0.0).into(), rotator, 1.0, 90.0, 0.5, 250.0);
+
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera);
+        assert_eq!(uniform.clip_planes, [0.5, 250.0, 0.0, 0.0]);
+    }
+
+    fn make_camera(aspect: f32) -> Camera {
+        // Zero rotator looks down +X with +Z up
+        Camera::new(
+            (0.0, 0.0, 0.0).into(),
+            Rotator {
+                yaw: Deg(0.0),
+                pitch: Deg(0.0),
+                roll: Deg(0.0),
+            },
+            aspect,
+            90.0,
+            0.1,
+            100.0,
+        )
+    }
+
+    fn project(camera: &Camera, point: Point3<f32>) -> cgmath::Vector4<f32> {
+        camera.build_view_projection_matrix() * point.to_homogeneous()
+    }
+
+    #[test]
+    fn test_point_in_front_projects_to_center() {
+        let camera = make_camera(1.0);
+        let clip = project(&camera, Point3::new(1.0, 0.0, 0.0));
+
+        assert!(clip.w > 0.0);
+        let ndc = clip.truncate() / clip.w;
+        assert!(almost_equal(ndc.x, 0.0, 1e-6));
+        assert!(almost_equal(ndc.y, 0.0, 1e-6));
+
+        // The projection uses wgpu depth convention (near plane maps to 0, far plane to 1)
+        let (near, far) = (0.1, 100.0);
+        let expected_depth = far * (1.0 - near) / (far - near);
+        assert!(almost_equal(ndc.z, expected_depth, 1e-5));
+        assert!(ndc.z > 0.0 && ndc.z < 1.0);
+    }
+
+    #[test]
+    fn test_point_behind_is_clipped() {
+        let camera = make_camera(1.0);
+        let clip = project(&camera, Point3::new(-1.0, 0.0, 0.0));
+
+        // A point is inside of the clip volume only when -w <= x, y <= w and 0 <= z <= w
+        assert!(clip.w < 0.0);
+        assert!(clip.z > clip.w || clip.z < 0.0);
+    }
+
+    #[test]
+    fn test_screen_axes() {
+        let camera = make_camera(1.0);
+
+        // Note that `Camera::right()` (+Y here) ends up on the left side of the screen.
+        // CameraController accounts for that by moving along it when "left" is pressed.
+        let left = project(&camera, Point3::new(1.0, 0.5, 0.0));
+        assert!(left.x / left.w < 0.0);
+        assert!(almost_equal(left.y / left.w, 0.0, 1e-6));
+        assert!(almost_equal_vec(camera.right(), Vector3::unit_y(), 1e-6));
+
+        let up = project(&camera, Point3::new(1.0, 0.0, 0.5));
+        assert!(up.y / up.w > 0.0);
+        assert!(almost_equal(up.x / up.w, 0.0, 1e-6));
+    }
+
+    #[test]
+    fn test_wider_aspect_compresses_x() {
+        let point = Point3::new(1.0, 0.5, 0.5);
+        let square = project(&make_camera(1.0), point);
+        let wide = project(&make_camera(2.0), point);
+
+        assert!(almost_equal(
+            wide.x / wide.w,
+            square.x / square.w / 2.0,
+            1e-6
+        ));
+        assert!(almost_equal(wide.y / wide.w, square.y / square.w, 1e-6));
+    }
+
+    #[test]
+    fn test_orthographic_depth_range() {
+        let mut camera = make_camera(1.0);
+        camera.set_projection(Projection::Orthographic { height: 10.0 });
+        camera.set_clip_planes(1.0, 11.0);
+
+        let near = project(&camera, Point3::new(1.0, 0.0, 0.0));
+        let far = project(&camera, Point3::new(11.0, 0.0, 0.0));
+        assert!(almost_equal(near.w, 1.0, 1e-6));
+        assert!(almost_equal(near.z, 0.0, 1e-6));
+        assert!(almost_equal(far.z, 1.0, 1e-6));
+
+        // Size does not depend on the distance
+        let close = project(&camera, Point3::new(2.0, 0.0, 5.0));
+        let distant = project(&camera, Point3::new(10.0, 0.0, 5.0));
+        assert!(almost_equal(close.y, 1.0, 1e-6));
+        assert!(almost_equal(distant.y, 1.0, 1e-6));
+    }
+
+    #[test]
+    fn test_face_towards_origin() {
+        let mut camera = make_camera(1.0);
+        let start = camera.pose();
+        camera.set_eye(Point3::new(3.0, -4.0, 5.0));
+        camera.set_rotator(Rotator {
+            yaw: Deg(10.0),
+            pitch: Deg(20.0),
+            roll: Deg(30.0),
+        });
+
+        camera.face_towards(Point3::new(0.0, 0.0, 0.0));
+        let expected = (Point3::new(0.0, 0.0, 0.0) - camera.get_eye()).normalize();
+        assert!(almost_equal_vec(camera.forward(), expected, 1e-5));
+        assert_eq!(camera.get_rotator().roll, Deg(0.0));
+
+        camera.set_pose(start);
+        assert_eq!(*camera.get_eye(), Point3::new(0.0, 0.0, 0.0));
+        assert!(almost_equal_vec(camera.forward(), Vector3::unit_x(), 1e-6));
+    }
+
+    #[test]
+    fn test_same_rotator_keeps_the_cache() {
+        let mut camera = make_camera(1.0);
+        let rotator = Rotator {
+            yaw: Deg(45.0),
+            pitch: Deg(-10.0),
+            roll: Deg(0.0),
+        };
+        camera.set_rotator(rotator);
+        camera.forward();
+        assert!(camera.cache.borrow().is_some());
+
+        camera.set_rotator(rotator);
+        assert!(camera.cache.borrow().is_some());
+
+        // Moving without turning still invalidates it
+        camera.set_pose(CameraPose {
+            eye: Point3::new(1.0, 2.0, 3.0),
+            rotator,
+        });
+        assert!(camera.cache.borrow().is_none());
+    }
+
+    #[cfg(feature = "debug-instrumentation")]
+    #[test]
+    fn test_cache_recomputes_once_per_change() {
+        let mut camera = make_camera(1.0);
+        let pose = camera.pose();
+        camera.forward();
+        assert_eq!(camera.cache_recompute_count(), 1);
+
+        // Reads and no-op setters keep the cache
+        camera.up();
+        camera.right();
+        camera.set_eye(pose.eye);
+        camera.set_rotator(pose.rotator);
+        camera.set_pose(pose);
+        camera.set_aspect(1.0);
+        camera.forward();
+        assert_eq!(camera.cache_recompute_count(), 1);
+
+        // Every real change recomputes once, however often the result is read
+        camera.set_eye(Point3::new(1.0, 0.0, 0.0));
+        camera.forward();
+        camera.up();
+        assert_eq!(camera.cache_recompute_count(), 2);
+
+        camera.set_rotator(Rotator {
+            yaw: Deg(90.0),
+            ..pose.rotator
+        });
+        camera.forward();
+        assert_eq!(camera.cache_recompute_count(), 3);
+
+        camera.set_aspect(2.0);
+        camera.frustum();
+        assert_eq!(camera.cache_recompute_count(), 4);
+
+        // Changing the eye and the rotator together is still one recompute
+        camera.set_pose(pose);
+        camera.forward();
+        assert_eq!(camera.cache_recompute_count(), 5);
+    }
+
+    #[test]
+    fn test_y_up_zero_rotator() {
+        let mut camera = make_camera(1.0);
+        camera.set_coordinate_system(CoordinateSystem::YUp);
+        assert!(almost_equal_vec(camera.forward(), -Vector3::unit_z(), 1e-6));
+        assert!(almost_equal_vec(camera.up(), Vector3::unit_y(), 1e-6));
+        assert_eq!(CoordinateSystem::YUp.up(), Vector3::unit_y());
+
+        // A point ahead projects to the center and a point above to the top of the screen
+        let ahead = project(&camera, Point3::new(0.0, 0.0, -1.0));
+        assert!(ahead.w > 0.0);
+        assert!(almost_equal(ahead.x / ahead.w, 0.0, 1e-6));
+        assert!(almost_equal(ahead.y / ahead.w, 0.0, 1e-6));
+        let above = project(&camera, Point3::new(0.0, 0.5, -1.0));
+        assert!(above.y / above.w > 0.0);
+
+        // Yaw turns around the world up axis
+        camera.set_rotator(Rotator {
+            yaw: Deg(90.0),
+            pitch: Deg(0.0),
+            roll: Deg(0.0),
+        });
+        assert!(almost_equal(camera.forward().y, 0.0, 1e-6));
+        assert!(almost_equal_vec(camera.up(), Vector3::unit_y(), 1e-6));
+
+        camera.set_eye(Point3::new(3.0, 4.0, -5.0));
+        camera.face_towards(Point3::new(0.0, 0.0, 0.0));
+        let expected = (Point3::new(0.0, 0.0, 0.0) - camera.get_eye()).normalize();
+        assert!(almost_equal_vec(camera.forward(), expected, 1e-5));
+        assert!(camera.up().y > 0.0);
+    }
+
+    #[test]
+    fn test_coordinate_system_bases_are_rotations() {
+        for system in [CoordinateSystem::ZUp, CoordinateSystem::YUp] {
+            let basis = system.basis();
+            assert!(almost_equal(basis.determinant(), 1.0, 1e-6));
+            let (u, v) = system.ground_axes();
+            assert_eq!(u.dot(system.up()), 0.0);
+            assert_eq!(v.dot(system.up()), 0.0);
+        }
+        assert_eq!(CoordinateSystem::default(), CoordinateSystem::ZUp);
+    }
+
+    #[test]
+    fn test_world_to_screen() {
+        let camera = make_camera(2.0);
+        let viewport = Viewport {
+            x: 100,
+            y: 50,
+            width: 800,
+            height: 400,
+        };
+
+        let (x, y) = camera
+            .world_to_screen(Point3::new(5.0, 0.0, 0.0), viewport)
+            .unwrap();
+        assert!(almost_equal(x, 500.0, 1e-3));
+        assert!(almost_equal(y, 250.0, 1e-3));
+
+        // Up in the world is up on the screen, which has Y going down
+        let (_, y) = camera
+            .world_to_screen(Point3::new(5.0, 0.0, 1.0), viewport)
+            .unwrap();
+        assert!(y < 250.0);
+
+        // Behind the camera and out of view
+        assert_eq!(
+            camera.world_to_screen(Point3::new(-5.0, 0.0, 0.0), viewport),
+            None
+        );
+        assert_eq!(
+            camera.world_to_screen(Point3::new(1.0, 0.0, 5.0), viewport),
+            None
+        );
+        assert_eq!(
+            camera.world_to_screen(Point3::new(500.0, 0.0, 0.0), viewport),
+            None
+        );
+    }
 }
@@ -1,39 +1,175 @@
 use crate::camera::Camera;
-use cgmath::{Deg, Vector2};
+use cgmath::{Deg, InnerSpace, Vector2};
 use winit::event::MouseButton;
 
+// Degrees per update while a roll key is held
+const DEFAULT_ROLL_SPEED: f32 = 1.0;
+// Part of the roll that is left after each update while it is being reset
+const ROLL_RESET_FACTOR: f32 = 0.85;
+// Below this many degrees the reset snaps to zero
+const ROLL_RESET_SNAP: f32 = 0.05;
+// Smoothing never goes above this, so the camera always catches up with the mouse
+const MAX_LOOK_SMOOTHING: f32 = 0.99;
+// Smoothed cursor movement below this many pixels is applied at once
+const LOOK_SETTLE_PIXELS: f32 = 0.01;
+
 pub struct CameraController {
     forward: bool,
     back: bool,
     left: bool,
     right: bool,
+    boost: bool,
+    precision: bool,
+    roll_left: bool,
+    roll_right: bool,
+    resetting_roll: bool,
 
     rmb: bool,
     prev_cursor: Option<Vector2<f32>>,
     current_cursor: Option<Vector2<f32>>,
+    // Cursor movement that smoothing has not applied to the camera yet
+    pending_look: Vector2<f32>,
+    look_smoothing: f32,
+    // Set by the first device mouse motion, cursor movement is not used for looking after that
+    raw_mouse_motion: bool,
 
     move_speed: f32,
-    rotation_speed: f32,
+    // Degrees per pixel of cursor movement
+    yaw_sensitivity: f32,
+    pitch_sensitivity: f32,
+    invert_y: bool,
+    roll_speed: f32,
+    boost_multiplier: f32,
+    precision_multiplier: f32,
 }
 
 impl CameraController {
     pub fn new(move_speed: f32, rotation_speed: f32) -> Self {
         Self {
             move_speed,
-            rotation_speed,
+            yaw_sensitivity: rotation_speed,
+            pitch_sensitivity: rotation_speed,
+            invert_y: false,
             forward: false,
             back: false,
             left: false,
             rmb: false,
             prev_cursor: None,
             current_cursor: None,
+            pending_look: Vector2::new(0.0, 0.0),
+            look_smoothing: 0.0,
+            raw_mouse_motion: false,
             right: false,
+            boost: false,
+            precision: false,
+            roll_left: false,
+            roll_right: false,
+            resetting_roll: false,
+            roll_speed: DEFAULT_ROLL_SPEED,
+            boost_multiplier: 5.0,
+            precision_multiplier: 0.2,
+        }
+    }
+
+    /// Speed multiplier while left shift is held
+    pub fn set_boost_multiplier(&mut self, multiplier: f32) {
+        self.boost_multiplier = multiplier;
+    }
+
+    /// Speed multiplier while left control is held. Multiplies with the boost if both are held.
+    pub fn set_precision_multiplier(&mut self, multiplier: f32) {
+        self.precision_multiplier = multiplier;
+    }
+
+    /// Degrees of yaw per pixel of horizontal cursor movement
+    pub fn set_yaw_sensitivity(&mut self, sensitivity: f32) {
+        self.yaw_sensitivity = sensitivity;
+    }
+
+    /// Degrees of pitch per pixel of vertical cursor movement
+    pub fn set_pitch_sensitivity(&mut self, sensitivity: f32) {
+        self.pitch_sensitivity = sensitivity;
+    }
+
+    /// Flight sim style: dragging up looks down
+    pub fn set_invert_y(&mut self, invert_y: bool) {
+        self.invert_y = invert_y;
+    }
+
+    /// Part of the cursor movement that is left for the next updates, in 0..1.
+    /// 0 applies the movement at once, higher values follow the mouse slower but smoother.
+    pub fn set_look_smoothing(&mut self, smoothing: f32) {
+        self.look_smoothing = smoothing.clamp(0.0, MAX_LOOK_SMOOTHING);
+    }
+
+    // Part of the pending cursor movement to apply in this update
+    fn take_look_delta(&mut self) -> Vector2<f32> {
+        let delta = match self.pending_look.magnitude() > LOOK_SETTLE_PIXELS {
+            true => self.pending_look * (1.0 - self.look_smoothing),
+            false => self.pending_look,
+        };
+        self.pending_look -= delta;
+        delta
+    }
+
+    /// Degrees per update while Z or X is held
+    pub fn set_roll_speed(&mut self, roll_speed: f32) {
+        self.roll_speed = roll_speed;
+    }
+
+    /// Eases the roll back to zero over the next updates. Rolling manually cancels it.
+    pub fn reset_roll(&mut self) {
+        self.resetting_roll = true;
+    }
+
+    fn update_roll(&mut self, roll: Deg<f32>) -> Deg<f32> {
+        let mut direction = 0.0;
+        if self.roll_left {
+            direction -= 1.0;
+        }
+        if self.roll_right {
+            direction += 1.0;
+        }
+
+        if direction != 0.0 {
+            self.resetting_roll = false;
+            return roll + Deg(direction * self.roll_speed);
+        }
+
+        if self.resetting_roll {
+            let eased = roll * ROLL_RESET_FACTOR;
+            if eased.0.abs() > ROLL_RESET_SNAP {
+                return eased;
+            }
+            self.resetting_roll = false;
+            return Deg(0.0);
+        }
+
+        roll
+    }
+
+    fn current_move_speed(&self) -> f32 {
+        let mut speed = self.move_speed;
+        if self.boost {
+            speed *= self.boost_multiplier;
+        }
+        if self.precision {
+            speed *= self.precision_multiplier;
+        }
+        speed
+    }
+
+    // The cursor delta accumulates until `update_camera` applies it,
+    // so no movement is lost on frames that do not update the camera
+    fn keep_look_origin(&mut self) {
+        if self.prev_cursor.is_none() {
+            self.prev_cursor = self.current_cursor;
         }
     }
 
     pub fn process_events(&mut self, event: &winit::event::WindowEvent) -> bool {
         use winit::event::{ElementState, KeyEvent, TouchPhase, WindowEvent};
-        use winit::keyboard::{KeyCode, PhysicalKey};
+        use winit::keyboard::PhysicalKey;
 
         match event {
             WindowEvent::Touch(touch) => {
@@ -47,7 +183,7 @@ impl CameraController {
                         self.current_cursor = None;
                     }
                     TouchPhase::Moved => {
-                        self.prev_cursor = self.current_cursor;
+                        self.keep_look_origin();
                         self.current_cursor = Some(Vector2::new(
                             touch.location.x as f32,
                             touch.location.y as f32,
@@ -60,7 +196,9 @@ impl CameraController {
                 device_id: _,
                 position,
             } => {
-                self.prev_cursor = self.current_cursor;
+                if !self.raw_mouse_motion {
+                    self.keep_look_origin();
+                }
                 self.current_cursor = Some(Vector2::new(position.x as f32, position.y as f32));
                 false
             }
@@ -84,44 +222,95 @@ impl CameraController {
                         ..
                     },
                 ..
-            } => {
-                let k = *state == ElementState::Pressed;
-                match keycode {
-                    KeyCode::KeyW | KeyCode::ArrowUp => {
-                        self.forward = k;
-                        true
-                    }
-                    KeyCode::KeyA | KeyCode::ArrowLeft => {
-                        self.left = k;
-                        true
-                    }
-                    KeyCode::KeyS | KeyCode::ArrowDown => {
-                        self.back = k;
-                        true
-                    }
-                    KeyCode::KeyD | KeyCode::ArrowRight => {
-                        self.right = k;
-                        true
-                    }
-                    _ => false,
+            } => self.process_key(*keycode, *state == ElementState::Pressed),
+            _ => false,
+        }
+    }
+
+    /// Raw input that does not go through the window. Mouse motion keeps coming when the cursor
+    /// hits the edge of the screen, so once it arrives it replaces cursor movement for looking.
+    pub fn process_device_event(&mut self, event: &winit::event::DeviceEvent) -> bool {
+        use winit::event::{DeviceEvent, ElementState, RawKeyEvent};
+        use winit::keyboard::PhysicalKey;
+
+        match event {
+            DeviceEvent::MouseMotion { delta } => {
+                self.raw_mouse_motion = true;
+                if self.rmb {
+                    self.pending_look += Vector2::new(delta.0 as f32, delta.1 as f32);
                 }
+                self.rmb
+            }
+            DeviceEvent::Key(RawKeyEvent {
+                physical_key: PhysicalKey::Code(keycode),
+                state,
+            }) => self.process_key(*keycode, *state == ElementState::Pressed),
+            _ => false,
+        }
+    }
+
+    fn process_key(&mut self, keycode: winit::keyboard::KeyCode, k: bool) -> bool {
+        use winit::keyboard::KeyCode;
+
+        match keycode {
+            KeyCode::KeyW | KeyCode::ArrowUp => {
+                self.forward = k;
+                true
+            }
+            KeyCode::KeyA | KeyCode::ArrowLeft => {
+                self.left = k;
+                true
+            }
+            KeyCode::KeyS | KeyCode::ArrowDown => {
+                self.back = k;
+                true
+            }
+            KeyCode::KeyD | KeyCode::ArrowRight => {
+                self.right = k;
+                true
+            }
+            KeyCode::ShiftLeft => {
+                self.boost = k;
+                true
+            }
+            KeyCode::ControlLeft => {
+                self.precision = k;
+                true
+            }
+            KeyCode::KeyZ => {
+                self.roll_left = k;
+                true
+            }
+            KeyCode::KeyX => {
+                self.roll_right = k;
+                true
             }
             _ => false,
         }
     }
 
     pub fn update_camera(&mut self, camera: &mut Camera) {
+        // Only yaw and pitch follow the mouse, the roll is kept
+        let mut r = *camera.get_rotator();
         match (self.rmb, self.prev_cursor, self.current_cursor) {
             (true, Some(prev), Some(curr)) => {
-                let delta = (curr - prev) * self.rotation_speed;
-                let mut r = *camera.get_rotator();
-                r.yaw += Deg(delta.x);
-                r.pitch += Deg(delta.y);
-                camera.set_rotator(r);
-                self.prev_cursor = None;
+                self.pending_look += curr - prev;
             }
             _ => {}
         };
+        self.prev_cursor = None;
+
+        // Smoothed movement keeps being applied after the mouse stops or is released
+        let delta = self.take_look_delta();
+        let pitch_sign = match self.invert_y {
+            true => -1.0,
+            false => 1.0,
+        };
+        r.yaw += Deg(delta.x * self.yaw_sensitivity);
+        r.pitch += Deg(delta.y * self.pitch_sensitivity * pitch_sign);
+
+        r.roll = self.update_roll(r.roll);
+        camera.set_rotator(r);
 
         let mut forward = 0;
         let mut right = 0;
@@ -140,11 +329,198 @@ impl CameraController {
         }
 
         if forward != 0 || right != 0 {
+            let move_speed = self.current_move_speed();
             camera.set_eye(
                 camera.get_eye()
-                    + camera.forward() * (forward as f32) * self.move_speed
-                    + camera.right() * (right as f32) * self.move_speed,
+                    + camera.forward() * (forward as f32) * move_speed
+                    + camera.right() * (right as f32) * move_speed,
             );
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_utils::*;
+    use crate::rotator::Rotator;
+    use cgmath::{MetricSpace, Point3};
+
+    fn make_camera() -> Camera {
+        Camera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Rotator {
+                yaw: Deg(0.0),
+                pitch: Deg(0.0),
+                roll: Deg(0.0),
+            },
+            1.0,
+            90.0,
+            0.1,
+            100.0,
+        )
+    }
+
+    fn distance_moved_forward(controller: &mut CameraController) -> f32 {
+        let mut camera = make_camera();
+        controller.forward = true;
+        controller.update_camera(&mut camera);
+        camera.get_eye().distance(Point3::new(0.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn test_boost_multiplies_move_speed() {
+        let mut controller = CameraController::new(0.2, 0.2);
+        let base = distance_moved_forward(&mut controller);
+        assert!(almost_equal(base, 0.2, 1e-5));
+
+        controller.boost = true;
+        assert!(almost_equal(
+            distance_moved_forward(&mut controller),
+            5.0 * base,
+            1e-5
+        ));
+
+        controller.set_boost_multiplier(3.0);
+        assert!(almost_equal(
+            distance_moved_forward(&mut controller),
+            3.0 * base,
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn test_boost_and_precision_stack() {
+        let mut controller = CameraController::new(0.2, 0.2);
+        controller.precision = true;
+        assert!(almost_equal(
+            distance_moved_forward(&mut controller),
+            0.04,
+            1e-5
+        ));
+
+        controller.boost = true;
+        assert!(almost_equal(
+            distance_moved_forward(&mut controller),
+            0.2,
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn test_roll_key_tilts_up_vector() {
+        let mut controller = CameraController::new(0.2, 0.2);
+        controller.set_roll_speed(3.0);
+        let mut camera = make_camera();
+
+        // Camera right is +Y, so rolling right tilts up towards -Y
+        controller.roll_right = true;
+        for _ in 0..10 {
+            controller.update_camera(&mut camera);
+        }
+        assert!(almost_equal(camera.get_rotator().roll.0, 30.0, 1e-4));
+        let (sin, cos) = 30.0f32.to_radians().sin_cos();
+        assert!(almost_equal_vec(
+            camera.up(),
+            cgmath::Vector3::new(0.0, -sin, cos),
+            1e-5
+        ));
+
+        // Mouse look keeps the roll
+        controller.roll_right = false;
+        controller.rmb = true;
+        controller.prev_cursor = Some(Vector2::new(0.0, 0.0));
+        controller.current_cursor = Some(Vector2::new(10.0, 0.0));
+        controller.update_camera(&mut camera);
+        assert!(almost_equal(camera.get_rotator().yaw.0, 2.0, 1e-4));
+        assert!(almost_equal(camera.get_rotator().roll.0, 30.0, 1e-4));
+
+        controller.reset_roll();
+        for _ in 0..100 {
+            controller.update_camera(&mut camera);
+        }
+        assert_eq!(camera.get_rotator().roll, Deg(0.0));
+    }
+
+    fn pitch_after_drag(controller: &mut CameraController, delta_y: f32) -> f32 {
+        let mut camera = make_camera();
+        controller.rmb = true;
+        controller.prev_cursor = Some(Vector2::new(0.0, 0.0));
+        controller.current_cursor = Some(Vector2::new(0.0, delta_y));
+        controller.update_camera(&mut camera);
+        camera.get_rotator().pitch.0
+    }
+
+    #[test]
+    fn test_invert_y_flips_pitch() {
+        let mut controller = CameraController::new(0.2, 0.5);
+        controller.set_pitch_sensitivity(0.25);
+
+        // Window y grows downwards, so dragging up is a negative delta.
+        // Positive pitch looks down, so by default dragging up looks up.
+        assert!(almost_equal(
+            pitch_after_drag(&mut controller, -8.0),
+            -2.0,
+            1e-5
+        ));
+
+        controller.set_invert_y(true);
+        assert!(almost_equal(
+            pitch_after_drag(&mut controller, -8.0),
+            2.0,
+            1e-5
+        ));
+    }
+
+    #[test]
+    fn test_look_smoothing_spreads_delta_over_frames() {
+        let mut controller = CameraController::new(0.2, 0.1);
+        controller.set_look_smoothing(0.5);
+        let mut camera = make_camera();
+
+        controller.rmb = true;
+        controller.prev_cursor = Some(Vector2::new(0.0, 0.0));
+        controller.current_cursor = Some(Vector2::new(100.0, 0.0));
+        controller.update_camera(&mut camera);
+        assert!(almost_equal(camera.get_rotator().yaw.0, 5.0, 1e-4));
+
+        controller.update_camera(&mut camera);
+        assert!(almost_equal(camera.get_rotator().yaw.0, 7.5, 1e-4));
+
+        // The rest decays to zero once the mouse stops
+        for _ in 0..100 {
+            controller.update_camera(&mut camera);
+        }
+        assert!(almost_equal(camera.get_rotator().yaw.0, 10.0, 1e-4));
+        assert_eq!(controller.pending_look, Vector2::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_device_motion_rotates_camera() {
+        use winit::event::DeviceEvent;
+
+        let mut controller = CameraController::new(0.2, 0.5);
+        let mut camera = make_camera();
+
+        // Ignored until the right button is held
+        let motion = DeviceEvent::MouseMotion {
+            delta: (10.0, -4.0),
+        };
+        assert!(!controller.process_device_event(&motion));
+        controller.rmb = true;
+        assert!(controller.process_device_event(&motion));
+
+        // Cursor movement is not counted a second time
+        for x in [100.0, 200.0] {
+            controller.process_events(&winit::event::WindowEvent::CursorMoved {
+                device_id: winit::event::DeviceId::dummy(),
+                position: (x, 100.0).into(),
+            });
+        }
+        assert_eq!(controller.prev_cursor, None);
+
+        controller.update_camera(&mut camera);
+        assert!(almost_equal(camera.get_rotator().yaw.0, 5.0, 1e-5));
+        assert!(almost_equal(camera.get_rotator().pitch.0, -2.0, 1e-5));
+    }
+}
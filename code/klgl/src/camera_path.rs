@@ -0,0 +1,217 @@
+use crate::{CameraPose, Rotator};
+use cgmath::{Deg, EuclideanSpace, Point3, Vector3};
+
+#[derive(serde::Deserialize)]
+struct KeyframeDesc {
+    eye: [f32; 3],
+    yaw: f32,
+    pitch: f32,
+    #[serde(default)]
+    roll: f32,
+    // Seconds to the next keyframe
+    duration: f32,
+}
+
+#[derive(serde::Deserialize)]
+struct CameraPathDesc {
+    keyframes: Vec<KeyframeDesc>,
+}
+
+/// Closed loop of camera poses. The last keyframe moves back to the first one.
+pub struct CameraPath {
+    keyframes: Vec<CameraPose>,
+    // durations[i] is the time from keyframe i to the next one
+    durations: Vec<f32>,
+    // starts[i] is the time the camera passes keyframe i
+    starts: Vec<f32>,
+    // Velocity at each keyframe, so the speed does not jump between segments
+    tangents: Vec<Vector3<f32>>,
+}
+
+impl CameraPath {
+    /// `durations[i]` is how long the camera moves from keyframe `i` to the next one
+    pub fn new(keyframes: Vec<CameraPose>, durations: Vec<f32>) -> anyhow::Result<Self> {
+        anyhow::ensure!(!keyframes.is_empty(), "Camera path has no keyframes");
+        anyhow::ensure!(
+            keyframes.len() == durations.len(),
+            "Camera path has {} keyframes but {} durations",
+            keyframes.len(),
+            durations.len()
+        );
+        anyhow::ensure!(
+            durations.iter().all(|d| d.is_finite() && *d > 0.0),
+            "Camera path durations must be positive"
+        );
+
+        let starts = durations
+            .iter()
+            .scan(0.0, |time, duration| {
+                let start = *time;
+                *time += duration;
+                Some(start)
+            })
+            .collect();
+
+        // Catmull-Rom tangents, divided by the time between the neighbours instead of
+        // the index distance so uneven durations still give a smooth velocity
+        let n = keyframes.len();
+        let tangents = (0..n)
+            .map(|i| {
+                let prev = (i + n - 1) % n;
+                let next = (i + 1) % n;
+                (keyframes[next].eye - keyframes[prev].eye) / (durations[prev] + durations[i])
+            })
+            .collect();
+
+        Ok(Self {
+            keyframes,
+            durations,
+            starts,
+            tangents,
+        })
+    }
+
+    /// Reads `{"keyframes": [{"eye": [x, y, z], "yaw": 0, "pitch": 0, "roll": 0, "duration": 1}]}`.
+    /// Angles are in degrees, the roll may be omitted.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let desc: CameraPathDesc = serde_json::from_str(json)?;
+        let (keyframes, durations) = desc
+            .keyframes
+            .into_iter()
+            .map(|keyframe| {
+                let pose = CameraPose {
+                    eye: Point3::from(keyframe.eye),
+                    rotator: Rotator {
+                        yaw: Deg(keyframe.yaw),
+                        pitch: Deg(keyframe.pitch),
+                        roll: Deg(keyframe.roll),
+                    },
+                };
+                (pose, keyframe.duration)
+            })
+            .unzip();
+        Self::new(keyframes, durations)
+    }
+
+    /// Time of one loop in seconds
+    pub fn duration(&self) -> f32 {
+        self.durations.iter().sum()
+    }
+
+    /// Pose at `t` seconds, wrapping around after [`Self::duration`]
+    pub fn sample(&self, t: f32) -> CameraPose {
+        let t = t.rem_euclid(self.duration());
+        let i = self.starts.partition_point(|start| *start <= t).max(1) - 1;
+        let next = (i + 1) % self.keyframes.len();
+        let duration = self.durations[i];
+        let u = ((t - self.starts[i]) / duration).min(1.0);
+
+        // Cubic Hermite basis
+        let u2 = u * u;
+        let u3 = u2 * u;
+        let h00 = 2.0 * u3 - 3.0 * u2 + 1.0;
+        let h10 = u3 - 2.0 * u2 + u;
+        let h01 = -2.0 * u3 + 3.0 * u2;
+        let h11 = u3 - u2;
+
+        let (a, b) = (&self.keyframes[i], &self.keyframes[next]);
+        let eye = a.eye * h00
+            + self.tangents[i] * (h10 * duration)
+            + b.eye.to_vec() * h01
+            + self.tangents[next] * (h11 * duration);
+
+        CameraPose {
+            eye,
+            rotator: a.rotator.slerp(&b.rotator, u),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_utils::almost_equal_vec;
+    use cgmath::InnerSpace;
+
+    fn pose(x: f32, y: f32, z: f32, yaw: f32) -> CameraPose {
+        CameraPose {
+            eye: Point3::new(x, y, z),
+            rotator: Rotator {
+                yaw: Deg(yaw),
+                pitch: Deg(10.0),
+                roll: Deg(0.0),
+            },
+        }
+    }
+
+    fn make_path() -> CameraPath {
+        CameraPath::new(
+            vec![
+                pose(10.0, 0.0, 5.0, 180.0),
+                pose(0.0, 10.0, 3.0, -90.0),
+                pose(-10.0, 0.0, 5.0, 0.0),
+                pose(0.0, -10.0, 7.0, 90.0),
+            ],
+            vec![2.0, 1.0, 3.0, 1.5],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_keyframes_are_exact() {
+        let path = make_path();
+        for (keyframe, start) in path.keyframes.iter().zip(&path.starts) {
+            for t in [*start, start + path.duration()] {
+                let pose = path.sample(t);
+                assert_eq!(pose.eye, keyframe.eye);
+                assert_eq!(pose.rotator.yaw, keyframe.rotator.yaw);
+                assert_eq!(pose.rotator.pitch, keyframe.rotator.pitch);
+                assert_eq!(pose.rotator.roll, keyframe.rotator.roll);
+            }
+        }
+    }
+
+    #[test]
+    fn test_position_is_c1_across_segments() {
+        let path = make_path();
+        let h = 1e-2;
+        let eye = |t: f32| path.sample(t).eye.to_vec();
+
+        for (i, start) in path.starts.iter().enumerate() {
+            let start = start + path.duration();
+            // Second order one sided differences, each uses a single segment
+            let incoming =
+                (eye(start) * 3.0 - eye(start - h) * 4.0 + eye(start - 2.0 * h)) / (2.0 * h);
+            let outgoing =
+                (eye(start + h) * 4.0 - eye(start) * 3.0 - eye(start + 2.0 * h)) / (2.0 * h);
+            assert!(
+                almost_equal_vec(incoming, outgoing, 1e-2),
+                "keyframe {i}: {incoming:?} != {outgoing:?}"
+            );
+            assert!(almost_equal_vec(incoming, path.tangents[i], 1e-2));
+            assert!(incoming.magnitude() > 1.0);
+        }
+    }
+
+    #[test]
+    fn test_from_json() {
+        let path = CameraPath::from_json(
+            r#"{"keyframes": [
+                {"eye": [1, 2, 3], "yaw": 45, "pitch": -10, "duration": 2},
+                {"eye": [4, 5, 6], "yaw": 90, "pitch": 0, "roll": 5, "duration": 0.5}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(path.duration(), 2.5);
+        assert_eq!(path.sample(2.0).eye, Point3::new(4.0, 5.0, 6.0));
+        assert_eq!(path.sample(2.0).rotator.roll, Deg(5.0));
+
+        assert!(CameraPath::from_json(r#"{"keyframes": []}"#).is_err());
+        assert!(
+            CameraPath::from_json(
+                r#"{"keyframes": [{"eye": [0, 0, 0], "yaw": 0, "pitch": 0, "duration": 0}]}"#
+            )
+            .is_err()
+        );
+    }
+}
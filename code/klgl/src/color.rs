@@ -0,0 +1,44 @@
+// Decodes one sRGB component, the inverse of what an sRGB target does on write
+fn srgb_to_linear(value: f64) -> f64 {
+    match value <= 0.04045 {
+        true => value / 12.92,
+        false => ((value + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+/// Clear color from the sRGB values people pick colors in, so 0.5 is a middle gray on screen.
+/// Only for sRGB targets: they encode the linear value again on write. Targets without sRGB
+/// store the components as they are and should be cleared with a plain `wgpu::Color`.
+/// Alpha is linear in both cases and is passed through.
+pub fn srgb_color(r: f64, g: f64, b: f64, a: f64) -> wgpu::Color {
+    wgpu::Color {
+        r: srgb_to_linear(r),
+        g: srgb_to_linear(g),
+        b: srgb_to_linear(b),
+        a,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_utils::almost_equal;
+
+    #[test]
+    fn test_srgb_to_linear_pairs() {
+        for (srgb, linear) in [
+            (0.0, 0.0),
+            (0.02, 0.001548),
+            (0.2, 0.033105),
+            (0.5, 0.214041),
+            (0.8, 0.603827),
+            (1.0, 1.0),
+        ] {
+            assert!(almost_equal(srgb_to_linear(srgb), linear, 1e-6), "{srgb}");
+        }
+
+        let color = srgb_color(0.5, 1.0, 0.0, 0.5);
+        assert!(almost_equal(color.r, 0.214041, 1e-6));
+        assert_eq!((color.g, color.b, color.a), (1.0, 0.0, 0.5));
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use crate::{SamplerOptions, Texture};
+
+/// Block compressed formats a KTX2 file can hold. Desktop GPUs have BC, mobile and web ones
+/// usually ETC2 or ASTC. Desire them in [`crate::RenderContextOptions`] to load such files.
+pub const TEXTURE_COMPRESSION_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_COMPRESSION_BC
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ASTC);
+
+fn astc(block: wgpu::AstcBlock, srgb: bool) -> wgpu::TextureFormat {
+    wgpu::TextureFormat::Astc {
+        block,
+        channel: match srgb {
+            true => wgpu::AstcChannel::UnormSrgb,
+            false => wgpu::AstcChannel::Unorm,
+        },
+    }
+}
+
+/// wgpu format of the compressed formats KTX2 files store
+fn wgpu_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    use wgpu::{AstcBlock as B, TextureFormat as F};
+    Some(match format {
+        // BC1 without alpha decodes with an opaque alpha
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => F::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => F::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => F::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => F::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => F::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => F::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => F::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => F::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => F::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => F::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => F::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => F::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK => F::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => F::Bc7RgbaUnormSrgb,
+        K::ETC2_R8G8B8_UNORM_BLOCK => F::Etc2Rgb8Unorm,
+        K::ETC2_R8G8B8_SRGB_BLOCK => F::Etc2Rgb8UnormSrgb,
+        K::ETC2_R8G8B8A1_UNORM_BLOCK => F::Etc2Rgb8A1Unorm,
+        K::ETC2_R8G8B8A1_SRGB_BLOCK => F::Etc2Rgb8A1UnormSrgb,
+        K::ETC2_R8G8B8A8_UNORM_BLOCK => F::Etc2Rgba8Unorm,
+        K::ETC2_R8G8B8A8_SRGB_BLOCK => F::Etc2Rgba8UnormSrgb,
+        K::EAC_R11_UNORM_BLOCK => F::EacR11Unorm,
+        K::EAC_R11_SNORM_BLOCK => F::EacR11Snorm,
+        K::EAC_R11G11_UNORM_BLOCK => F::EacRg11Unorm,
+        K::EAC_R11G11_SNORM_BLOCK => F::EacRg11Snorm,
+        K::ASTC_4x4_UNORM_BLOCK => astc(B::B4x4, false),
+        K::ASTC_4x4_SRGB_BLOCK => astc(B::B4x4, true),
+        K::ASTC_5x5_UNORM_BLOCK => astc(B::B5x5, false),
+        K::ASTC_5x5_SRGB_BLOCK => astc(B::B5x5, true),
+        K::ASTC_6x6_UNORM_BLOCK => astc(B::B6x6, false),
+        K::ASTC_6x6_SRGB_BLOCK => astc(B::B6x6, true),
+        K::ASTC_8x8_UNORM_BLOCK => astc(B::B8x8, false),
+        K::ASTC_8x8_SRGB_BLOCK => astc(B::B8x8, true),
+        K::ASTC_10x10_UNORM_BLOCK => astc(B::B10x10, false),
+        K::ASTC_10x10_SRGB_BLOCK => astc(B::B10x10, true),
+        K::ASTC_12x12_UNORM_BLOCK => astc(B::B12x12, false),
+        K::ASTC_12x12_SRGB_BLOCK => astc(B::B12x12, true),
+        _ => return None,
+    })
+}
+
+/// Format to upload a KTX2 file with as is, or why the device can't use it
+fn select_format(header: &ktx2::Header, features: wgpu::Features) -> Result<wgpu::TextureFormat> {
+    if let Some(scheme) = header.supercompression_scheme {
+        bail!(
+            "KTX2 supercompression {:?} is not supported, store the texture without it",
+            scheme
+        );
+    }
+    let Some(format) = header.format else {
+        bail!(
+            "KTX2 file without a format needs Basis Universal transcoding, which is not supported"
+        );
+    };
+    let Some(wgpu_format) = wgpu_format(format) else {
+        bail!(
+            "KTX2 format {:?} is not a supported compressed format",
+            format
+        );
+    };
+
+    let required = wgpu_format.required_features();
+    if !features.contains(required) {
+        bail!(
+            "KTX2 format {:?} needs {:?} which the device does not support",
+            format,
+            required
+        );
+    }
+    Ok(wgpu_format)
+}
+
+/// Everything needed to create the texture of a KTX2 file
+#[derive(Debug)]
+struct Ktx2Image {
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    mip_level_count: u32,
+    // All mip levels from the largest one, in the layout the GPU expects
+    data: Vec<u8>,
+}
+
+fn parse_ktx2(bytes: &[u8], features: wgpu::Features) -> Result<Ktx2Image> {
+    let reader = ktx2::Reader::new(bytes)?;
+    let header = reader.header();
+    let format = select_format(&header, features)?;
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count != 1 {
+        bail!("Only 2D KTX2 textures are supported, not arrays, cubes or volumes");
+    }
+
+    Ok(Ktx2Image {
+        format,
+        size: wgpu::Extent3d {
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: header.level_count.max(1),
+        data: reader
+            .levels()
+            .flat_map(|level| level.data)
+            .copied()
+            .collect(),
+    })
+}
+
+impl Texture {
+    /// Uploads the compressed mip levels of a KTX2 file without decoding them.
+    /// Fails if the device lacks the feature for its format, see [`TEXTURE_COMPRESSION_FEATURES`].
+    pub fn from_ktx2_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let image = parse_ktx2(bytes, device.features()).with_context(|| label.to_string())?;
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size: image.size,
+                mip_level_count: image.mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: image.format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::MipMajor,
+            &image.data,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerOptions::default().descriptor(1));
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            compare: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Smallest valid KTX2 file: a single 4x4 block with one mip level
+    fn tiny_ktx2(format: Option<ktx2::Format>, block: &[u8]) -> Vec<u8> {
+        let level_offset = (ktx2::Header::LENGTH + ktx2::LevelIndex::LENGTH + 4) as u64;
+        let header = ktx2::Header {
+            format,
+            type_size: 1,
+            pixel_width: 4,
+            pixel_height: 4,
+            pixel_depth: 0,
+            layer_count: 0,
+            face_count: 1,
+            level_count: 1,
+            supercompression_scheme: None,
+            index: ktx2::Index {
+                dfd_byte_offset: (ktx2::Header::LENGTH + ktx2::LevelIndex::LENGTH) as u32,
+                dfd_byte_length: 4,
+                kvd_byte_offset: 0,
+                kvd_byte_length: 0,
+                sgd_byte_offset: 0,
+                sgd_byte_length: 0,
+            },
+        };
+        let level = ktx2::LevelIndex {
+            byte_offset: level_offset,
+            byte_length: block.len() as u64,
+            uncompressed_byte_length: block.len() as u64,
+        };
+
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(&level.as_bytes());
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(block);
+        bytes
+    }
+
+    #[test]
+    fn test_format_matches_the_features() {
+        let bytes = tiny_ktx2(Some(ktx2::Format::BC7_SRGB_BLOCK), &[7; 16]);
+        let image = parse_ktx2(&bytes, wgpu::Features::TEXTURE_COMPRESSION_BC).unwrap();
+        assert_eq!(image.format, wgpu::TextureFormat::Bc7RgbaUnormSrgb);
+        assert_eq!((image.size.width, image.size.height), (4, 4));
+        assert_eq!(image.mip_level_count, 1);
+        assert_eq!(image.data, [7; 16]);
+
+        // A mobile device without BC can't use the file
+        let error = parse_ktx2(&bytes, wgpu::Features::TEXTURE_COMPRESSION_ETC2).unwrap_err();
+        assert!(
+            error.to_string().contains("TEXTURE_COMPRESSION_BC"),
+            "{error}"
+        );
+
+        let bytes = tiny_ktx2(Some(ktx2::Format::ETC2_R8G8B8A8_SRGB_BLOCK), &[0; 16]);
+        let image = parse_ktx2(&bytes, TEXTURE_COMPRESSION_FEATURES).unwrap();
+        assert_eq!(image.format, wgpu::TextureFormat::Etc2Rgba8UnormSrgb);
+        assert!(parse_ktx2(&bytes, wgpu::Features::TEXTURE_COMPRESSION_BC).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_files_fail_clearly() {
+        // Basis Universal files have no format of their own
+        let bytes = tiny_ktx2(None, &[0; 16]);
+        let error = parse_ktx2(&bytes, TEXTURE_COMPRESSION_FEATURES).unwrap_err();
+        assert!(error.to_string().contains("Basis"), "{error}");
+
+        // Uncompressed formats go through the image loader instead
+        let bytes = tiny_ktx2(Some(ktx2::Format::R8_UNORM), &[0; 16]);
+        assert!(parse_ktx2(&bytes, TEXTURE_COMPRESSION_FEATURES).is_err());
+
+        assert!(parse_ktx2(b"not a ktx2 file", TEXTURE_COMPRESSION_FEATURES).is_err());
+    }
+}
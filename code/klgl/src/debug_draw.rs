@@ -0,0 +1,176 @@
+use cgmath::Point3;
+use std::cell::RefCell;
+
+// Segments per ring of a sphere
+const SPHERE_SEGMENTS: usize = 32;
+
+/// Same layout as the colored line vertices of the tutorials
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+/// Collects line segments for one frame. Every two vertices make a segment.
+#[derive(Default)]
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vertices(&self) -> &[DebugVertex] {
+        &self.vertices
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn line(&mut self, start: Point3<f32>, end: Point3<f32>, color: [f32; 3]) {
+        self.vertices.push(DebugVertex {
+            position: start.into(),
+            color,
+        });
+        self.vertices.push(DebugVertex {
+            position: end.into(),
+            color,
+        });
+    }
+
+    /// The 12 edges of an axis aligned box
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 3]) {
+        // Bits of the index select min or max on each axis
+        let corner = |index: usize| {
+            Point3::new(
+                if index & 1 == 0 { min.x } else { max.x },
+                if index & 2 == 0 { min.y } else { max.y },
+                if index & 4 == 0 { min.z } else { max.z },
+            )
+        };
+
+        // Corners that differ in a single bit share an edge
+        for index in 0..8 {
+            for bit in [1, 2, 4] {
+                if index & bit == 0 {
+                    self.line(corner(index), corner(index | bit), color);
+                }
+            }
+        }
+    }
+
+    /// Approximates a sphere with a ring around each axis
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 3]) {
+        let point = |axis: usize, step: usize| {
+            let angle = step as f32 / SPHERE_SEGMENTS as f32 * std::f32::consts::TAU;
+            let (sin, cos) = angle.sin_cos();
+            let mut offset = [0.0; 3];
+            offset[(axis + 1) % 3] = cos * radius;
+            offset[(axis + 2) % 3] = sin * radius;
+            Point3::new(
+                center.x + offset[0],
+                center.y + offset[1],
+                center.z + offset[2],
+            )
+        };
+
+        for axis in 0..3 {
+            for step in 0..SPHERE_SEGMENTS {
+                self.line(point(axis, step), point(axis, step + 1), color);
+            }
+        }
+    }
+}
+
+thread_local! {
+    static DEBUG_DRAW: RefCell<DebugDraw> = RefCell::new(DebugDraw::new());
+}
+
+/// Adds a segment to the debug lines of the current frame
+pub fn debug_draw_line(start: Point3<f32>, end: Point3<f32>, color: [f32; 3]) {
+    DEBUG_DRAW.with_borrow_mut(|debug_draw| debug_draw.line(start, end, color));
+}
+
+/// Adds a box to the debug lines of the current frame
+pub fn debug_draw_aabb(min: Point3<f32>, max: Point3<f32>, color: [f32; 3]) {
+    DEBUG_DRAW.with_borrow_mut(|debug_draw| debug_draw.aabb(min, max, color));
+}
+
+/// Adds a sphere to the debug lines of the current frame
+pub fn debug_draw_sphere(center: Point3<f32>, radius: f32, color: [f32; 3]) {
+    DEBUG_DRAW.with_borrow_mut(|debug_draw| debug_draw.sphere(center, radius, color));
+}
+
+/// Hands the debug lines of this frame to `consume` and starts the next frame
+pub fn flush_debug_draw(consume: impl FnOnce(&[DebugVertex])) {
+    DEBUG_DRAW.with_borrow_mut(|debug_draw| {
+        consume(debug_draw.vertices());
+        debug_draw.clear();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::MetricSpace;
+
+    #[test]
+    fn test_aabb_edges() {
+        let mut debug_draw = DebugDraw::new();
+        debug_draw.aabb(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 2.0, 3.0),
+            [1.0; 3],
+        );
+
+        let segments: Vec<&[DebugVertex]> = debug_draw.vertices().chunks_exact(2).collect();
+        assert_eq!(segments.len(), 12);
+        for segment in segments {
+            let (start, end) = (segment[0].position, segment[1].position);
+            let changed = (0..3).filter(|axis| start[*axis] != end[*axis]).count();
+            assert_eq!(changed, 1);
+        }
+    }
+
+    #[test]
+    fn test_sphere_rings() {
+        let mut debug_draw = DebugDraw::new();
+        let center = Point3::new(1.0, 2.0, 3.0);
+        debug_draw.sphere(center, 2.0, [1.0; 3]);
+
+        assert_eq!(debug_draw.vertices().len(), 3 * SPHERE_SEGMENTS * 2);
+        for vertex in debug_draw.vertices() {
+            let distance = Point3::from(vertex.position).distance(center);
+            assert!((distance - 2.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_flush_clears_each_frame() {
+        debug_draw_line(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            [1.0; 3],
+        );
+        debug_draw_aabb(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 1.0),
+            [1.0; 3],
+        );
+
+        let mut flushed = 0;
+        flush_debug_draw(|vertices| flushed = vertices.len());
+        assert_eq!(flushed, 2 + 24);
+
+        flush_debug_draw(|vertices| flushed = vertices.len());
+        assert_eq!(flushed, 0);
+    }
+}
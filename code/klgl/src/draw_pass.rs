@@ -0,0 +1,393 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::Viewport;
+
+/// Views every pass of a frame renders into.
+pub struct PassTargets<'a> {
+    /// Scene color. Same as `surface` unless the scene is rendered offscreen.
+    pub color: &'a wgpu::TextureView,
+    pub depth: Option<&'a wgpu::TextureView>,
+    /// The texture that gets presented
+    pub surface: &'a wgpu::TextureView,
+    /// Region of `color` and `depth` the scene is confined to, see [`crate::RenderContext::viewport`].
+    /// `None` uses the whole targets.
+    pub viewport: Option<Viewport>,
+    /// Camera bind group of the view being drawn, see [`PassList::execute_views`].
+    /// `None` keeps the camera the pass was created with.
+    pub camera: Option<&'a wgpu::BindGroup>,
+}
+
+impl<'a> PassTargets<'a> {
+    /// Camera bind group to draw with, `own` unless a view overrides it
+    pub fn camera_or<'b>(&self, own: &'b wgpu::BindGroup) -> &'b wgpu::BindGroup
+    where
+        'a: 'b,
+    {
+        self.camera.unwrap_or(own)
+    }
+
+    /// Begins a render pass that keeps the current contents of the targets.
+    /// Drawing is limited to the viewport if there is one.
+    pub fn begin_render_pass<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        label: &str,
+    ) -> wgpu::RenderPass<'e> {
+        self.begin_render_pass_with_queries(encoder, label, None)
+    }
+
+    /// Same as [`PassTargets::begin_render_pass`] with occlusion queries written to `occlusion_query_set`.
+    pub fn begin_render_pass_with_queries<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        label: &str,
+        occlusion_query_set: Option<&wgpu::QuerySet>,
+    ) -> wgpu::RenderPass<'e> {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.color,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: self.depth.map(|view| {
+                wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }
+            }),
+            timestamp_writes: None,
+            occlusion_query_set,
+        });
+
+        if let Some(viewport) = self.viewport {
+            render_pass.set_viewport(
+                viewport.x as f32,
+                viewport.y as f32,
+                viewport.width as f32,
+                viewport.height as f32,
+                0.0,
+                1.0,
+            );
+            render_pass.set_scissor_rect(viewport.x, viewport.y, viewport.width, viewport.height);
+        }
+
+        render_pass
+    }
+
+    /// Same targets without the depth attachment, for overlays and post processing.
+    pub fn color_only(&self) -> Self {
+        Self {
+            color: self.color,
+            depth: None,
+            surface: self.surface,
+            viewport: self.viewport,
+            camera: self.camera,
+        }
+    }
+
+    /// Targets the presented texture directly, for post processing output.
+    /// Post processing maps the scene color one to one, so it covers the whole surface
+    /// and the bars around a viewport come along.
+    pub fn surface_only(&self) -> Self {
+        Self {
+            color: self.surface,
+            depth: None,
+            surface: self.surface,
+            viewport: None,
+            camera: None,
+        }
+    }
+}
+
+pub trait DrawPass {
+    /// Unique name used to find the pass in a [`PassList`].
+    fn name(&self) -> &str;
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &PassTargets);
+
+    /// Whether the pass draws the scene from the camera. [`PassList::execute_views`] records
+    /// such passes once per view and the others, e.g. clearing and post processing, once.
+    fn per_view(&self) -> bool {
+        false
+    }
+}
+
+/// What a [`ClearPass`] does with each attachment. `None` loads the current contents,
+/// so a second scene can be drawn over the image of an earlier one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PassClearConfig {
+    pub color: Option<wgpu::Color>,
+    pub depth: Option<f32>,
+}
+
+impl PassClearConfig {
+    /// Starts a new image, the depth is cleared to the far plane
+    pub fn clear(color: wgpu::Color) -> Self {
+        Self {
+            color: Some(color),
+            depth: Some(1.0),
+        }
+    }
+
+    /// Keeps the color and starts over with depth, e.g. for an overlay scene
+    pub fn depth_only() -> Self {
+        Self {
+            color: None,
+            depth: Some(1.0),
+        }
+    }
+
+    /// A render pass can only clear whole attachments, so with a viewport the color is
+    /// cleared to black to letterbox the scene
+    fn color_load_op(&self, has_viewport: bool) -> wgpu::LoadOp<wgpu::Color> {
+        match (self.color, has_viewport) {
+            (Some(_), true) => wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            (Some(color), false) => wgpu::LoadOp::Clear(color),
+            (None, _) => wgpu::LoadOp::Load,
+        }
+    }
+
+    fn depth_load_op(&self) -> wgpu::LoadOp<f32> {
+        match self.depth {
+            Some(depth) => wgpu::LoadOp::Clear(depth),
+            None => wgpu::LoadOp::Load,
+        }
+    }
+}
+
+/// Prepares the color and depth targets as its [`PassClearConfig`] says.
+/// Usually the first pass of a frame.
+pub struct ClearPass {
+    pub config: PassClearConfig,
+}
+
+impl ClearPass {
+    pub const NAME: &str = "clear";
+
+    /// Clears both attachments
+    pub fn new(color: wgpu::Color) -> Self {
+        Self::with_config(PassClearConfig::clear(color))
+    }
+
+    pub fn with_config(config: PassClearConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl DrawPass for ClearPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &PassTargets) {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Clear Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: targets.color,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: self.config.color_load_op(targets.viewport.is_some()),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: targets.depth.map(|view| {
+                wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: self.config.depth_load_op(),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+    }
+}
+
+pub type SharedDrawPass = Rc<RefCell<dyn DrawPass>>;
+
+/// Ordered list of passes executed each frame.
+#[derive(Default)]
+pub struct PassList {
+    passes: Vec<SharedDrawPass>,
+}
+
+impl PassList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, pass: SharedDrawPass) {
+        self.passes.push(pass);
+    }
+
+    /// Inserts the pass right before the pass with the given name or at the end if there is no such pass.
+    pub fn insert_before(&mut self, name: &str, pass: SharedDrawPass) {
+        let index = self.position(name).unwrap_or(self.passes.len());
+        self.passes.insert(index, pass);
+    }
+
+    /// Inserts the pass right after the pass with the given name or at the end if there is no such pass.
+    pub fn insert_after(&mut self, name: &str, pass: SharedDrawPass) {
+        let index = self
+            .position(name)
+            .map_or(self.passes.len(), |index| index + 1);
+        self.passes.insert(index, pass);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<SharedDrawPass> {
+        let index = self.position(name)?;
+        Some(self.passes.remove(index))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.passes
+            .iter()
+            .map(|pass| pass.borrow().name().to_string())
+            .collect()
+    }
+
+    pub fn execute(&self, encoder: &mut wgpu::CommandEncoder, targets: &PassTargets) {
+        for pass in &self.passes {
+            pass.borrow().record(encoder, targets);
+        }
+    }
+
+    /// Draws the scene once per view. Each view is a rectangle of the targets with its own
+    /// camera bind group, the passes that don't depend on the camera run once for all of them.
+    pub fn execute_views(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        targets: &PassTargets,
+        views: &[(Viewport, &wgpu::BindGroup)],
+    ) {
+        for pass in &self.passes {
+            let pass = pass.borrow();
+            if !pass.per_view() {
+                pass.record(encoder, targets);
+                continue;
+            }
+
+            for (viewport, camera) in views {
+                let view_targets = PassTargets {
+                    color: targets.color,
+                    depth: targets.depth,
+                    surface: targets.surface,
+                    viewport: Some(*viewport),
+                    camera: Some(camera),
+                };
+                pass.record(encoder, &view_targets);
+            }
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.passes
+            .iter()
+            .position(|pass| pass.borrow().name() == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NamedPass(&'static str);
+
+    impl DrawPass for NamedPass {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn record(&self, _: &mut wgpu::CommandEncoder, _: &PassTargets) {}
+    }
+
+    fn named(name: &'static str) -> SharedDrawPass {
+        Rc::new(RefCell::new(NamedPass(name)))
+    }
+
+    #[test]
+    fn test_clear_config_load_ops() {
+        let color = wgpu::Color::RED;
+        let config = PassClearConfig::clear(color);
+        assert_eq!(config.color_load_op(false), wgpu::LoadOp::Clear(color));
+        assert_eq!(
+            config.color_load_op(true),
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+        );
+        assert_eq!(config.depth_load_op(), wgpu::LoadOp::Clear(1.0));
+
+        // A later scene draws over the color of the earlier one
+        let config = PassClearConfig::depth_only();
+        assert_eq!(config.color_load_op(false), wgpu::LoadOp::Load);
+        assert_eq!(config.color_load_op(true), wgpu::LoadOp::Load);
+        assert_eq!(config.depth_load_op(), wgpu::LoadOp::Clear(1.0));
+
+        let config = PassClearConfig {
+            color: Some(color),
+            depth: None,
+        };
+        assert_eq!(config.depth_load_op(), wgpu::LoadOp::Load);
+        assert_eq!(ClearPass::new(color).config, PassClearConfig::clear(color));
+    }
+
+    #[test]
+    fn test_passes_keep_push_order() {
+        let mut passes = PassList::new();
+        passes.push(named("clear"));
+        passes.push(named("lines"));
+        passes.push(named("models"));
+        assert_eq!(passes.names(), ["clear", "lines", "models"]);
+    }
+
+    #[test]
+    fn test_insert_before() {
+        let mut passes = PassList::new();
+        passes.push(named("clear"));
+        passes.push(named("models"));
+        passes.insert_before("models", named("shadows"));
+        passes.insert_before("missing", named("overlay"));
+        assert_eq!(passes.names(), ["clear", "shadows", "models", "overlay"]);
+    }
+
+    #[test]
+    fn test_insert_after() {
+        let mut passes = PassList::new();
+        passes.push(named("clear"));
+        passes.push(named("tonemap"));
+        passes.push(named("overlay"));
+        passes.insert_after("tonemap", named("fxaa"));
+        passes.insert_after("missing", named("debug"));
+        assert_eq!(
+            passes.names(),
+            ["clear", "tonemap", "fxaa", "overlay", "debug"]
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut passes = PassList::new();
+        passes.push(named("clear"));
+        passes.push(named("overlay"));
+        passes.push(named("models"));
+
+        assert!(passes.remove("overlay").is_some());
+        assert!(passes.remove("overlay").is_none());
+        assert!(!passes.contains("overlay"));
+        assert_eq!(passes.names(), ["clear", "models"]);
+    }
+}
@@ -1,5 +1,13 @@
 use cfg_if::cfg_if;
-use std::{cell::RefCell, collections::HashMap, path::Path, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    path::Path,
+    rc::Rc,
+};
+use winit::event_loop::EventLoopProxy;
+
+use crate::UserEvent;
 
 #[cfg(target_arch = "wasm32")]
 fn format_url<P: AsRef<Path>>(file_name: P) -> anyhow::Result<reqwest::Url> {
@@ -44,6 +52,74 @@ pub async fn load_binary<P: AsRef<Path>>(file_name: P) -> anyhow::Result<Vec<u8>
     }
 }
 
+// Browsers limit the number of connections per host, and requests above the limit time out
+cfg_if! {
+    if #[cfg(target_arch = "wasm32")] {
+        const DEFAULT_MAX_CONCURRENT: usize = 6;
+    } else {
+        const DEFAULT_MAX_CONCURRENT: usize = 32;
+    }
+}
+
+// Sizes are unknown until a file arrives, so every load in flight counts as this many bytes
+// against the in-flight byte budget
+const DEFAULT_REQUEST_SIZE_ESTIMATE: usize = 4 * 1024 * 1024;
+
+type LoadResult = (String, anyhow::Result<Vec<u8>>);
+
+// Called from the task that loaded the file, so it has to be sendable on native
+#[cfg(target_arch = "wasm32")]
+type ReadyNotifier = Rc<dyn Fn(FileId)>;
+
+#[cfg(not(target_arch = "wasm32"))]
+type ReadyNotifier = std::sync::Arc<dyn Fn(FileId) + Send + Sync>;
+
+#[cfg(target_arch = "wasm32")]
+fn ready_notifier(notify: impl Fn(FileId) + 'static) -> ReadyNotifier {
+    Rc::new(notify)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn ready_notifier(notify: impl Fn(FileId) + Send + Sync + 'static) -> ReadyNotifier {
+    std::sync::Arc::new(notify)
+}
+
+// Where a fetch delivers its result
+struct FetchReply {
+    id: FileId,
+    sender: async_channel::Sender<LoadResult>,
+    notifier: Option<ReadyNotifier>,
+}
+
+impl FetchReply {
+    // Queues the result for `FileLoader::poll` and then tells the notifier, so the poll finds it
+    async fn send(self, path: String, result: anyhow::Result<Vec<u8>>) {
+        if self.sender.send((path, result)).await.is_ok()
+            && let Some(notifier) = &self.notifier
+        {
+            notifier(self.id);
+        }
+    }
+}
+
+// Starts loading a file and sends the result to the reply when done
+type Fetcher = Box<dyn Fn(String, FetchReply)>;
+
+fn spawn_fetch(path: String, reply: FetchReply) {
+    let loader_fn = async move {
+        let result = load_binary(&path).await;
+        reply.send(path, result).await;
+    };
+
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            wasm_bindgen_futures::spawn_local(loader_fn);
+        } else {
+            async_std::task::spawn(loader_fn);
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FileId(u32);
 
@@ -67,23 +143,48 @@ pub type FileDataHandle = Rc<FileData>; // Use `Rc<T>` in WebAssembly
 #[cfg(not(target_arch = "wasm32"))]
 pub type FileDataHandle = std::sync::Arc<FileData>; // Use `Arc<T>` in native platforms
 
+#[cfg(target_arch = "wasm32")]
+type FileDataWeakHandle = std::rc::Weak<FileData>;
+
+#[cfg(not(target_arch = "wasm32"))]
+type FileDataWeakHandle = std::sync::Weak<FileData>;
+
 #[derive(Clone)]
 pub struct FileLoader {
     inner: Rc<RefCell<FileLoaderInner>>,
 }
 
 pub struct FileLoaderInner {
-    sender: async_channel::Sender<(String, Vec<u8>)>,
-    receiver: async_channel::Receiver<(String, Vec<u8>)>,
+    sender: async_channel::Sender<LoadResult>,
+    receiver: async_channel::Receiver<LoadResult>,
+
+    fetcher: Fetcher,
+    notifier: Option<ReadyNotifier>,
+    // Requested files wait here while `max_concurrent` others are loading
+    // or their estimated bytes would exceed `inflight_byte_budget`
+    queued: VecDeque<String>,
+    in_flight: usize,
+    max_concurrent: usize,
+    inflight_byte_budget: Option<usize>,
+    request_size_estimate: usize,
+    // Estimated bytes of every load in flight, released when it finishes
+    in_flight_bytes: HashMap<String, usize>,
 
     file_id_map: bimap::BiHashMap<String, FileId>,
     next_file_id: FileId,
 
-    endpoint_id_map: HashMap<EndpointId, async_channel::Sender<FileDataHandle>>,
+    endpoint_id_map: HashMap<EndpointId, async_channel::Sender<(FileId, FileDataHandle)>>,
     next_endpoint_id: EndpointId,
 
     ready_files: HashMap<FileId, FileDataHandle>,
     pending_files: HashMap<FileId, Rc<RefCell<PendingFile>>>,
+
+    // When enabled, files with identical content share one `FileDataHandle`.
+    // Note that `FileData::id` of a shared handle is the id of the first file loaded with these bytes.
+    dedup: bool,
+    dedup_map: HashMap<u64, FileDataWeakHandle>,
+
+    total_bytes_loaded: usize,
 }
 
 impl FileLoaderInner {
@@ -105,6 +206,66 @@ impl FileLoaderInner {
             }
         }
     }
+
+    fn estimated_in_flight_bytes(&self) -> usize {
+        self.in_flight_bytes.values().sum()
+    }
+
+    // One load is always allowed, so a budget below the estimate still makes progress
+    fn fits_byte_budget(&self) -> bool {
+        match self.inflight_byte_budget {
+            Some(budget) => {
+                self.in_flight == 0
+                    || self.estimated_in_flight_bytes() + self.request_size_estimate <= budget
+            }
+            None => true,
+        }
+    }
+
+    // Starts queued loads until the concurrency limit or the byte budget is reached
+    fn start_queued(&mut self) {
+        while self.in_flight < self.max_concurrent && self.fits_byte_budget() {
+            let Some(path) = self.queued.pop_front() else {
+                break;
+            };
+            self.in_flight += 1;
+            self.in_flight_bytes
+                .insert(path.clone(), self.request_size_estimate);
+            let reply = FetchReply {
+                id: self.find_or_add_file_id(&path),
+                sender: self.sender.clone(),
+                notifier: self.notifier.clone(),
+            };
+            (self.fetcher)(path, reply);
+        }
+    }
+
+    fn make_file_data_handle(&mut self, id: FileId, data: Vec<u8>) -> FileDataHandle {
+        if !self.dedup {
+            return FileDataHandle::new(FileData { id, data });
+        }
+
+        let hash = seahash::hash(&data);
+        let existing = self
+            .dedup_map
+            .get(&hash)
+            .and_then(|x| x.upgrade())
+            .filter(|x| x.data == data);
+
+        if let Some(existing) = existing {
+            log::info!(
+                "{:?} has the same content as {:?}. Reusing loaded data",
+                id,
+                existing.id
+            );
+            return existing;
+        }
+
+        let handle = FileDataHandle::new(FileData { id, data });
+        self.dedup_map
+            .insert(hash, FileDataHandle::downgrade(&handle));
+        handle
+    }
 }
 
 impl FileLoader {
@@ -115,6 +276,10 @@ impl FileLoader {
         }
     }
 
+    pub fn id_by_path(&self, path: &str) -> Option<FileId> {
+        self.inner.borrow().find_file_id(path)
+    }
+
     pub fn data_by_path(&self, path: &str) -> Option<FileDataHandle> {
         self.inner
             .borrow()
@@ -132,21 +297,96 @@ impl FileLoader {
     }
 
     pub fn new() -> Self {
-        let (sender, receiver) = async_channel::unbounded::<(String, Vec<u8>)>();
+        let (sender, receiver) = async_channel::unbounded::<LoadResult>();
         Self {
             inner: Rc::new(RefCell::new(FileLoaderInner {
                 sender,
                 receiver,
+                fetcher: Box::new(spawn_fetch),
+                notifier: None,
+                queued: VecDeque::new(),
+                in_flight: 0,
+                max_concurrent: DEFAULT_MAX_CONCURRENT,
+                inflight_byte_budget: None,
+                request_size_estimate: DEFAULT_REQUEST_SIZE_ESTIMATE,
+                in_flight_bytes: HashMap::new(),
                 file_id_map: bimap::BiHashMap::new(),
                 next_file_id: FileId(0),
                 endpoint_id_map: HashMap::new(),
                 next_endpoint_id: EndpointId(0),
                 ready_files: HashMap::new(),
                 pending_files: HashMap::new(),
+                dedup: false,
+                dedup_map: HashMap::new(),
+                total_bytes_loaded: 0,
             })),
         }
     }
 
+    /// Enables sharing of loaded data between files with identical content.
+    /// Only affects files received after the call.
+    pub fn set_dedup(&mut self, dedup: bool) {
+        let mut inner = self.inner.borrow_mut();
+        inner.dedup = dedup;
+        if !dedup {
+            inner.dedup_map.clear();
+        }
+    }
+
+    /// Sends [`UserEvent::FileReady`] to the event loop whenever a load finishes, successfully or not,
+    /// so the app can call [`FileLoader::poll`] from `user_event` instead of every frame.
+    /// Polling keeps working without a proxy. Applies to loads started after the call.
+    pub fn set_event_proxy(&mut self, proxy: EventLoopProxy<UserEvent>) {
+        self.set_ready_notifier(Some(ready_notifier(move |id| {
+            // The event loop is gone when the app is shutting down, nothing to wake up then
+            let _ = proxy.send_event(UserEvent::FileReady(id));
+        })));
+    }
+
+    fn set_ready_notifier(&mut self, notifier: Option<ReadyNotifier>) {
+        self.inner.borrow_mut().notifier = notifier;
+    }
+
+    /// Limits the number of files that are loaded at the same time, the rest wait in a queue.
+    pub fn set_max_concurrent(&mut self, max_concurrent: usize) {
+        let mut inner = self.inner.borrow_mut();
+        inner.max_concurrent = max_concurrent.max(1);
+        inner.start_queued();
+    }
+
+    /// Limits the estimated bytes of the files that are loaded at the same time, e.g. to keep
+    /// the memory of a browser tab in check while many large textures download. Every load
+    /// counts as [`FileLoader::set_request_size_estimate`] bytes, since sizes are unknown until
+    /// the file arrives. One file is always loaded, however small the budget. `None` removes the limit.
+    pub fn set_inflight_byte_budget(&mut self, budget: Option<usize>) {
+        let mut inner = self.inner.borrow_mut();
+        inner.inflight_byte_budget = budget;
+        inner.start_queued();
+    }
+
+    /// Bytes every load counts as against the in-flight byte budget. Applies to loads started after the call.
+    pub fn set_request_size_estimate(&mut self, bytes: usize) {
+        let mut inner = self.inner.borrow_mut();
+        inner.request_size_estimate = bytes;
+        inner.start_queued();
+    }
+
+    /// Estimated bytes of the files being loaded, see [`FileLoader::set_inflight_byte_budget`]
+    pub fn in_flight_bytes(&self) -> usize {
+        self.inner.borrow().estimated_in_flight_bytes()
+    }
+
+    /// Returns true when nothing is being downloaded and all received files were handed out by [`FileLoader::poll`].
+    pub fn is_idle(&self) -> bool {
+        let inner = self.inner.borrow();
+        inner.pending_files.is_empty() && inner.receiver.is_empty()
+    }
+
+    /// Sum of the sizes of all files received so far
+    pub fn total_bytes_loaded(&self) -> usize {
+        self.inner.borrow().total_bytes_loaded
+    }
+
     pub fn get_or_request<Callback>(&mut self, path: &str, callback: Callback) -> FileId
     where
         Callback: 'static + FnOnce(&FileDataHandle),
@@ -172,34 +412,64 @@ impl FileLoader {
             })),
         );
 
-        let sender_clone = inner.sender.clone();
-        let path_clone: String = path.into();
-        let loader_fn = async move {
-            match load_binary(&path_clone).await {
-                Ok(data) => {
-                    log::info!("Received: \"{}\"", path_clone);
-                    let _ = sender_clone.send((path_clone, data)).await;
-                }
-                Err(err) => {
-                    log::error!("Failed to load \"{}\". Reason: \"{}\"", path_clone, err);
-                }
-            };
+        inner.queued.push_back(path.into());
+        inner.start_queued();
+
+        return id;
+    }
+
+    /// Drops the cached data of `path`, so the next request loads the file again,
+    /// e.g. after it was edited. Returns false if there was no cached data.
+    /// A load that is still in progress is not affected.
+    pub fn invalidate(&mut self, path: &str) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        let Some(id) = inner.find_file_id(path) else {
+            return false;
         };
+        inner.ready_files.remove(&id).is_some()
+    }
 
-        cfg_if::cfg_if! {
-            if #[cfg(target_arch = "wasm32")] {
-                wasm_bindgen_futures::spawn_local(loader_fn);
-            } else {
-                async_std::task::spawn(loader_fn);
-            }
+    /// Makes data that is already in memory (e.g. embedded into the binary) available under `path`,
+    /// so requests for it resolve without loading anything. Ignored if the file was already requested.
+    pub fn insert(&mut self, path: &str, data: Vec<u8>) -> FileId {
+        let mut inner = self.inner.borrow_mut();
+        let id = inner.find_or_add_file_id(path);
+        if inner.ready_files.contains_key(&id) || inner.pending_files.contains_key(&id) {
+            log::warn!(
+                "\"{}\" was already requested, inserted data is ignored",
+                path
+            );
+            return id;
         }
 
-        return id;
+        inner.total_bytes_loaded += data.len();
+        let handle = inner.make_file_data_handle(id, data);
+        inner.ready_files.insert(id, handle);
+        id
     }
 
     pub fn poll(&mut self) {
         let mut inner = self.inner.borrow_mut();
-        while let Ok((path, data)) = inner.receiver.try_recv() {
+        while let Ok((path, result)) = inner.receiver.try_recv() {
+            inner.in_flight = inner.in_flight.saturating_sub(1);
+            inner.in_flight_bytes.remove(&path);
+            inner.start_queued();
+
+            let data = match result {
+                Ok(data) => {
+                    log::info!("Received: \"{}\"", path);
+                    data
+                }
+                Err(err) => {
+                    // Callbacks are dropped, so the file can be requested again
+                    log::error!("Failed to load \"{}\". Reason: \"{}\"", path, err);
+                    let id = inner.find_or_add_file_id(&path);
+                    inner.pending_files.remove(&id);
+                    continue;
+                }
+            };
+
+            inner.total_bytes_loaded += data.len();
             let id = inner.find_or_add_file_id(&path);
             let removed_entry = inner.pending_files.remove_entry(&id);
 
@@ -216,9 +486,8 @@ impl FileLoader {
                 }
                 None => {
                     // Add to ready files
-                    inner
-                        .ready_files
-                        .insert(id, FileDataHandle::new(FileData { id, data }));
+                    let handle = inner.make_file_data_handle(id, data);
+                    inner.ready_files.insert(id, handle);
                 }
             }
 
@@ -242,7 +511,7 @@ impl FileLoader {
 
     pub fn make_endpoint(&mut self) -> FileLoaderEndpoint {
         let (id, receiver) = {
-            let (sender, receiver) = async_channel::unbounded::<FileDataHandle>();
+            let (sender, receiver) = async_channel::unbounded::<(FileId, FileDataHandle)>();
             let mut inner = self.inner.borrow_mut();
             let id = inner.next_endpoint_id;
             inner.next_endpoint_id = EndpointId(id.0 + 1);
@@ -262,7 +531,9 @@ impl FileLoader {
 pub struct FileLoaderEndpoint {
     pub loader: FileLoader,
     id: EndpointId,
-    pub receiver: async_channel::Receiver<FileDataHandle>,
+    // Yields the id of the requested file along with its data.
+    // With deduplication enabled they may differ from `FileData::id`.
+    pub receiver: async_channel::Receiver<(FileId, FileDataHandle)>,
 }
 
 impl FileLoaderEndpoint {
@@ -275,10 +546,11 @@ impl FileLoaderEndpoint {
             .get(&self.id)
             .expect("Endpoint wasn't registered?")
             .clone();
+        let file_id = self.loader.inner.borrow_mut().find_or_add_file_id(path);
         self.loader.get_or_request(path, move |x| {
             let x = x.clone();
             let loader_fn = async move {
-                match sender.send(x.clone()).await {
+                match sender.send((file_id, x.clone())).await {
                     Ok(_) => {}
                     Err(err) => {
                         log::error!("Failed to load . Error: \"{}\"", err);
@@ -296,3 +568,201 @@ impl FileLoaderEndpoint {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Simulates a finished download without touching the file system
+    fn receive(loader: &mut FileLoader, path: &str, data: &[u8]) {
+        let sender = loader.inner.borrow().sender.clone();
+        sender.try_send((path.into(), Ok(data.to_vec()))).unwrap();
+        loader.poll();
+    }
+
+    #[test]
+    fn test_dedup_identical_files() {
+        let mut loader = FileLoader::new();
+        loader.set_dedup(true);
+
+        receive(&mut loader, "a.png", &[1, 2, 3, 4]);
+        receive(&mut loader, "b.png", &[1, 2, 3, 4]);
+        receive(&mut loader, "c.png", &[4, 3, 2, 1]);
+
+        let a = loader.data_by_path("a.png").unwrap();
+        let b = loader.data_by_path("b.png").unwrap();
+        let c = loader.data_by_path("c.png").unwrap();
+
+        assert!(FileDataHandle::ptr_eq(&a, &b));
+        assert!(!FileDataHandle::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn test_idle_after_request_is_resolved() {
+        let mut loader = FileLoader::new();
+        loader.inner.borrow_mut().fetcher = Box::new(|_, _| {});
+        assert!(loader.is_idle());
+
+        let resolved = Rc::new(RefCell::new(false));
+        let resolved_clone = resolved.clone();
+        loader.get_or_request("missing.bin", move |_| *resolved_clone.borrow_mut() = true);
+        assert!(!loader.is_idle());
+
+        receive(&mut loader, "missing.bin", &[1, 2, 3]);
+        assert!(*resolved.borrow());
+        assert!(loader.is_idle());
+        assert_eq!(loader.total_bytes_loaded(), 3);
+    }
+
+    #[test]
+    fn test_concurrent_loads_are_limited() {
+        let mut loader = FileLoader::new();
+        loader.set_max_concurrent(3);
+
+        // Records started loads instead of reading files, `receive` completes them
+        let started = Rc::new(RefCell::new(Vec::<String>::new()));
+        let started_clone = started.clone();
+        loader.inner.borrow_mut().fetcher =
+            Box::new(move |path, _| started_clone.borrow_mut().push(path));
+
+        let paths: Vec<String> = (0..10).map(|i| format!("{i}.bin")).collect();
+        for path in &paths {
+            loader.get_or_request(path, |_| {});
+        }
+        assert_eq!(started.borrow().len(), 3);
+
+        let mut peak = 0;
+        for path in &paths {
+            peak = peak.max(loader.inner.borrow().in_flight);
+            receive(&mut loader, path, &[0]);
+        }
+
+        assert_eq!(peak, 3);
+        assert_eq!(*started.borrow(), paths);
+        assert!(loader.is_idle());
+    }
+
+    #[test]
+    fn test_in_flight_bytes_are_limited() {
+        let mut loader = FileLoader::new();
+        loader.set_request_size_estimate(100);
+        loader.set_inflight_byte_budget(Some(250));
+
+        let started = Rc::new(RefCell::new(Vec::<String>::new()));
+        let started_clone = started.clone();
+        loader.inner.borrow_mut().fetcher =
+            Box::new(move |path, _| started_clone.borrow_mut().push(path));
+
+        let paths: Vec<String> = (0..6).map(|i| format!("{i}.png")).collect();
+        for path in &paths {
+            loader.get_or_request(path, |_| {});
+        }
+        // A third load would be 300 estimated bytes
+        assert_eq!(started.borrow().len(), 2);
+        assert_eq!(loader.in_flight_bytes(), 200);
+
+        let mut peak = 0;
+        for path in &paths {
+            peak = peak.max(loader.in_flight_bytes());
+            receive(&mut loader, path, &[0; 1000]);
+        }
+        assert_eq!(peak, 200);
+        assert_eq!(*started.borrow(), paths);
+        assert_eq!(loader.in_flight_bytes(), 0);
+        assert!(loader.is_idle());
+
+        // A budget below the estimate still loads one file at a time
+        loader.set_inflight_byte_budget(Some(10));
+        loader.get_or_request("big.png", |_| {});
+        loader.get_or_request("bigger.png", |_| {});
+        assert_eq!(started.borrow().len(), 7);
+        receive(&mut loader, "big.png", &[0]);
+        assert_eq!(started.borrow().len(), 8);
+
+        // Raising the budget starts the queued loads right away
+        loader.get_or_request("a.png", |_| {});
+        loader.get_or_request("b.png", |_| {});
+        assert_eq!(started.borrow().len(), 8);
+        loader.set_inflight_byte_budget(None);
+        assert_eq!(started.borrow().len(), 10);
+    }
+
+    #[test]
+    fn test_invalidate_refetches() {
+        let mut loader = FileLoader::new();
+        let started = Rc::new(RefCell::new(0));
+        let started_clone = started.clone();
+        loader.inner.borrow_mut().fetcher = Box::new(move |_, _| *started_clone.borrow_mut() += 1);
+
+        loader.get_or_request("model.obj", |_| {});
+        receive(&mut loader, "model.obj", &[1]);
+        assert_eq!(*started.borrow(), 1);
+
+        // Cached data is handed out without loading
+        loader.get_or_request("model.obj", |_| {});
+        assert_eq!(*started.borrow(), 1);
+
+        assert!(loader.invalidate("model.obj"));
+        assert!(loader.data_by_path("model.obj").is_none());
+        assert!(!loader.invalidate("model.obj"));
+        assert!(!loader.invalidate("unknown.obj"));
+
+        let data = Rc::new(RefCell::new(Vec::new()));
+        let data_clone = data.clone();
+        loader.get_or_request("model.obj", move |file| {
+            *data_clone.borrow_mut() = file.data.clone()
+        });
+        assert_eq!(*started.borrow(), 2);
+
+        receive(&mut loader, "model.obj", &[2]);
+        assert_eq!(*data.borrow(), [2]);
+    }
+
+    #[test]
+    fn test_notifier_is_told_when_a_file_resolves() {
+        let mut loader = FileLoader::new();
+        // Stands in for the event loop proxy, creating an event loop needs a display and the main thread
+        let (events, received) = std::sync::mpsc::channel();
+        let events = std::sync::Mutex::new(events);
+        loader.set_ready_notifier(Some(ready_notifier(move |id| {
+            events
+                .lock()
+                .unwrap()
+                .send(UserEvent::FileReady(id))
+                .unwrap();
+        })));
+
+        // Resolves from another thread like a real fetch does
+        loader.inner.borrow_mut().fetcher = Box::new(|path, reply| {
+            async_std::task::spawn(reply.send(path, Ok(vec![1, 2, 3])));
+        });
+
+        let resolved = Rc::new(RefCell::new(false));
+        let resolved_clone = resolved.clone();
+        let id = loader.get_or_request("a.bin", move |_| *resolved_clone.borrow_mut() = true);
+
+        let event = received
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(event, UserEvent::FileReady(id));
+
+        // The data is queued by the time the event arrives
+        assert!(!*resolved.borrow());
+        loader.poll();
+        assert!(*resolved.borrow());
+        assert!(loader.is_idle());
+    }
+
+    #[test]
+    fn test_no_dedup_by_default() {
+        let mut loader = FileLoader::new();
+
+        receive(&mut loader, "a.png", &[1, 2, 3, 4]);
+        receive(&mut loader, "b.png", &[1, 2, 3, 4]);
+
+        let a = loader.data_by_path("a.png").unwrap();
+        let b = loader.data_by_path("b.png").unwrap();
+
+        assert!(!FileDataHandle::ptr_eq(&a, &b));
+    }
+}
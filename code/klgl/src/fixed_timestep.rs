@@ -0,0 +1,122 @@
+use web_time::Duration;
+
+// Steps above this count per frame are dropped, so a long hitch does not stall the next frames
+const DEFAULT_MAX_STEPS: u32 = 8;
+
+/// Steps to simulate for one frame
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FixedSteps {
+    pub count: u32,
+    /// How far the time is between the last two simulated states, in 0..1.
+    /// Render `previous + (current - previous) * alpha` for smooth motion.
+    pub alpha: f32,
+}
+
+// Splits real time into steps of equal length, so the simulation
+// does not depend on the frame rate
+pub struct FixedTimestep {
+    dt: Duration,
+    accumulated: Duration,
+    max_steps: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(dt: Duration) -> Self {
+        assert!(!dt.is_zero(), "Fixed timestep must be positive");
+        Self {
+            dt,
+            accumulated: Duration::ZERO,
+            max_steps: DEFAULT_MAX_STEPS,
+        }
+    }
+
+    /// Steps of `1 / hz` seconds, truncated to whole nanoseconds
+    pub fn from_rate(hz: u32) -> Self {
+        Self::new(Duration::from_secs(1) / hz)
+    }
+
+    pub fn dt(&self) -> Duration {
+        self.dt
+    }
+
+    pub fn set_max_steps(&mut self, max_steps: u32) {
+        self.max_steps = max_steps.max(1);
+    }
+
+    /// Time that was not enough for a whole step yet
+    pub fn remainder(&self) -> Duration {
+        self.accumulated
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.accumulated.as_secs_f32() / self.dt.as_secs_f32()
+    }
+
+    /// Adds the real time that passed since the last call and returns how many steps to simulate
+    pub fn advance(&mut self, elapsed: Duration) -> FixedSteps {
+        self.accumulated += elapsed;
+
+        let mut count = 0;
+        while self.accumulated >= self.dt {
+            self.accumulated -= self.dt;
+            count += 1;
+        }
+
+        if count > self.max_steps {
+            log::warn!(
+                "Simulation is behind by {} steps, skipping them",
+                count - self.max_steps
+            );
+            count = self.max_steps;
+        }
+
+        FixedSteps {
+            count,
+            alpha: self.alpha(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_utils::almost_equal;
+
+    #[test]
+    fn test_steps_and_remainder() {
+        let mut timestep = FixedTimestep::from_rate(60);
+        let steps = timestep.advance(Duration::from_millis(100));
+        assert_eq!(steps.count, 6);
+        assert_eq!(
+            timestep.remainder(),
+            Duration::from_millis(100) - timestep.dt() * 6
+        );
+        assert!(steps.alpha < 1e-5);
+    }
+
+    #[test]
+    fn test_remainder_carries_over() {
+        let mut timestep = FixedTimestep::new(Duration::from_millis(10));
+
+        let steps = timestep.advance(Duration::from_millis(25));
+        assert_eq!(steps.count, 2);
+        assert!(almost_equal(steps.alpha, 0.5, 1e-5));
+
+        let steps = timestep.advance(Duration::from_millis(4));
+        assert_eq!(steps.count, 0);
+        assert!(almost_equal(steps.alpha, 0.9, 1e-5));
+
+        let steps = timestep.advance(Duration::from_millis(1));
+        assert_eq!(steps.count, 1);
+        assert_eq!(timestep.remainder(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_max_steps() {
+        let mut timestep = FixedTimestep::new(Duration::from_millis(10));
+        timestep.set_max_steps(3);
+        let steps = timestep.advance(Duration::from_millis(105));
+        assert_eq!(steps.count, 3);
+        assert_eq!(timestep.remainder(), Duration::from_millis(5));
+    }
+}
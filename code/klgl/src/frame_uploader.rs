@@ -0,0 +1,153 @@
+use std::num::NonZeroU64;
+
+// Staging memory is allocated in chunks of this size and reused once the GPU is done with it
+const DEFAULT_CHUNK_SIZE: wgpu::BufferAddress = 64 * 1024;
+
+// Copies between buffers have to be a multiple of 4 bytes long
+fn aligned_size(len: usize) -> wgpu::BufferAddress {
+    wgpu::util::align_to(len as wgpu::BufferAddress, wgpu::COPY_BUFFER_ALIGNMENT)
+}
+
+// Fills the staging memory of one write. The padding is zeroed, so it does not leave
+// stale bytes of earlier frames in the target.
+fn fill_staging(staging: &mut [u8], data: &[u8]) {
+    let (head, padding) = staging.split_at_mut(data.len());
+    head.copy_from_slice(data);
+    padding.fill(0);
+}
+
+/// Uploads per frame data, like uniforms and instances, with a staging belt. The copies are
+/// recorded into the frame's command encoder and the staging memory is reused between frames,
+/// unlike `queue.write_buffer` that may allocate on every call.
+///
+/// Call `write` while recording, `finish` before the encoder is finished and `recall` after
+/// the commands were submitted.
+pub struct FrameUploader {
+    belt: wgpu::util::StagingBelt,
+}
+
+impl FrameUploader {
+    pub fn new() -> Self {
+        Self::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Writes larger than `chunk_size` get a chunk of their own
+    pub fn with_chunk_size(chunk_size: wgpu::BufferAddress) -> Self {
+        Self {
+            belt: wgpu::util::StagingBelt::new(chunk_size),
+        }
+    }
+
+    /// Copies `data` to `target` at `offset` when the encoder runs. `offset` has to be a
+    /// multiple of 4 and the data is padded with zeros to a multiple of 4 bytes, so the target
+    /// needs room for the padding.
+    pub fn write(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        target: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[u8],
+    ) {
+        let Some(size) = NonZeroU64::new(aligned_size(data.len())) else {
+            return;
+        };
+        let mut staging = self
+            .belt
+            .write_buffer(encoder, target, offset, size, device);
+        fill_staging(&mut staging, data);
+    }
+
+    /// Unmaps the staging memory of this frame. No writes until `recall`.
+    pub fn finish(&mut self) {
+        self.belt.finish();
+    }
+
+    /// Makes the staging memory reusable once the GPU is done with it
+    pub fn recall(&mut self) {
+        self.belt.recall();
+    }
+}
+
+impl Default for FrameUploader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pollster::FutureExt;
+
+    // Copies `buffer` to the CPU
+    fn read_buffer(ctx: &crate::RenderContext, buffer: &wgpu::Buffer) -> Vec<u8> {
+        let readback = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Test Readback Buffer"),
+            size: buffer.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, buffer.size());
+        ctx.queue.submit([encoder.finish()]);
+
+        readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        ctx.device.poll(wgpu::Maintain::Wait);
+        readback.slice(..).get_mapped_range().to_vec()
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_staging_bytes() {
+        let ctx = crate::RenderContext::headless(1, 1)
+            .block_on()
+            .expect("No adapter to run the ignored GPU tests on");
+
+        let target = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Target"),
+            size: 32,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let uniform = [1.0f32, 2.0, 3.0, 4.0];
+        let mut uploader = FrameUploader::new();
+
+        // Leaves stale bytes in the staging memory and in the target
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        uploader.write(&mut encoder, &ctx.device, &target, 0, &[0xff; 32]);
+        uploader.finish();
+        ctx.queue.submit([encoder.finish()]);
+        ctx.device.poll(wgpu::Maintain::Wait);
+        uploader.recall();
+
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        uploader.write(
+            &mut encoder,
+            &ctx.device,
+            &target,
+            0,
+            bytemuck::cast_slice(&uniform),
+        );
+        // Odd sizes are padded with zeros
+        uploader.write(&mut encoder, &ctx.device, &target, 16, &[1, 2, 3, 4, 5]);
+        uploader.write(&mut encoder, &ctx.device, &target, 24, &[]);
+        uploader.finish();
+        ctx.queue.submit([encoder.finish()]);
+        ctx.device.poll(wgpu::Maintain::Wait);
+        uploader.recall();
+
+        let bytes = read_buffer(&ctx, &target);
+        assert_eq!(bytes[..16], *bytemuck::cast_slice::<f32, u8>(&uniform));
+        assert_eq!(bytes[16..24], [1, 2, 3, 4, 5, 0, 0, 0]);
+        assert_eq!(bytes[24..], [0xff; 8]);
+    }
+}
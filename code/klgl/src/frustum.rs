@@ -0,0 +1,158 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Point3, SquareMatrix, Vector4};
+
+/// The volume a view projection matrix maps to the screen, as six planes facing inwards.
+/// Used to skip objects the camera can't see.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    // xyz is the unit normal, w the distance, so points inside have dot(plane, (p, 1)) >= 0
+    planes: [Vector4<f32>; 6],
+}
+
+fn normalize_plane(plane: Vector4<f32>) -> Vector4<f32> {
+    let length = plane.truncate().magnitude();
+    match length > 0.0 {
+        true => plane / length,
+        false => plane,
+    }
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix. The near plane assumes the -w..w
+    /// depth range, which is also correct, just a bit loose, for projections mapping to 0..w.
+    pub fn from_view_projection(view_projection: Matrix4<f32>) -> Self {
+        let x = view_projection.row(0);
+        let y = view_projection.row(1);
+        let z = view_projection.row(2);
+        let w = view_projection.row(3);
+        Self {
+            planes: [w + x, w - x, w + y, w - y, w + z, w - z].map(normalize_plane),
+        }
+    }
+
+    /// The same frustum in the local space of an object, so bounds can be tested
+    /// without transforming them to the world first
+    pub fn to_model_space(&self, model: Matrix4<f32>) -> Self {
+        let transposed = model.transpose();
+        Self {
+            planes: self.planes.map(|plane| normalize_plane(transposed * plane)),
+        }
+    }
+
+    /// False only if the sphere is completely outside of the frustum
+    pub fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        let center = center.to_homogeneous();
+        self.planes.iter().all(|plane| plane.dot(center) >= -radius)
+    }
+}
+
+/// Corners of the volume `view_projection` maps to the screen, found by unprojecting the
+/// corners of the clip volume, with depth from 0 at the near plane to 1 at the far one.
+/// Near corners come first, each face in the order (-x, -y), (x, -y), (x, y), (-x, y) in
+/// clip space. None if the matrix can't be inverted.
+pub fn frustum_corners(view_projection: Matrix4<f32>) -> Option<[Point3<f32>; 8]> {
+    let inverse = view_projection.invert()?;
+    let face = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+    Some(std::array::from_fn(|index| {
+        let (x, y) = face[index % 4];
+        let z = match index < 4 {
+            true => 0.0,
+            false => 1.0,
+        };
+        let corner = inverse * Vector4::new(x, y, z, 1.0);
+        Point3::from_homogeneous(corner)
+    }))
+}
+
+/// The 12 edges between the corners from [`frustum_corners`] as pairs of indices
+pub const FRUSTUM_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (1, 2),
+    (2, 3),
+    (3, 0),
+    (4, 5),
+    (5, 6),
+    (6, 7),
+    (7, 4),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Camera, Rotator};
+    use cgmath::{Deg, Vector3};
+
+    // Looks down +X with a 90 degree field of view
+    fn make_camera() -> Camera {
+        Camera::new(
+            (0.0, 0.0, 0.0).into(),
+            Rotator {
+                yaw: Deg(0.0),
+                pitch: Deg(0.0),
+                roll: Deg(0.0),
+            },
+            1.0,
+            90.0,
+            0.1,
+            100.0,
+        )
+    }
+
+    #[test]
+    fn test_sphere_against_camera_frustum() {
+        let frustum = make_camera().frustum();
+        assert!(frustum.intersects_sphere(Point3::new(10.0, 0.0, 0.0), 1.0));
+        // Behind the camera
+        assert!(!frustum.intersects_sphere(Point3::new(-10.0, 0.0, 0.0), 1.0));
+        // Outside of the field of view and beyond the far plane
+        assert!(!frustum.intersects_sphere(Point3::new(10.0, 0.0, 15.0), 1.0));
+        assert!(!frustum.intersects_sphere(Point3::new(110.0, 0.0, 0.0), 1.0));
+        // Crosses the edge of the field of view
+        assert!(frustum.intersects_sphere(Point3::new(10.0, 0.0, 10.5), 1.0));
+    }
+
+    #[test]
+    fn test_corners_from_inverse_view_projection() {
+        use crate::common::test_utils::almost_equal;
+
+        let camera = make_camera();
+        let corners = camera.frustum_corners();
+        // The near plane is 0.1 ahead with a 90 degree field of view, so as wide as it is far
+        for corner in &corners[..4] {
+            assert!(almost_equal(corner.x, 0.1, 1e-5), "{corner:?}");
+            assert!(almost_equal(corner.y.abs(), 0.1, 1e-4), "{corner:?}");
+            assert!(almost_equal(corner.z.abs(), 0.1, 1e-4), "{corner:?}");
+        }
+        for corner in &corners[4..] {
+            assert!(almost_equal(corner.x, 100.0, 1e-2), "{corner:?}");
+            assert!(almost_equal(corner.z.abs(), 100.0, 1e-2), "{corner:?}");
+        }
+        // Clip space y is up, which is +Z in the world
+        assert!(corners[0].z < 0.0 && corners[2].z > 0.0);
+
+        // Every corner is on the boundary of the frustum
+        let frustum = camera.frustum();
+        for corner in corners {
+            assert!(frustum.intersects_sphere(corner, 1e-2));
+            // Sideways out of the field of view
+            let outward = Point3::new(corner.x, corner.y * 1.1, corner.z * 1.1);
+            assert!(!frustum.intersects_sphere(outward, 1e-3));
+        }
+
+        assert!(frustum_corners(Matrix4::from_scale(0.0)).is_none());
+    }
+
+    #[test]
+    fn test_model_space_frustum() {
+        let frustum = make_camera().frustum();
+        let model = Matrix4::from_translation(Vector3::new(-20.0, 0.0, 0.0));
+        let local = frustum.to_model_space(model);
+
+        // Local (30, 0, 0) is (10, 0, 0) in the world
+        assert!(local.intersects_sphere(Point3::new(30.0, 0.0, 0.0), 1.0));
+        assert!(!local.intersects_sphere(Point3::new(10.0, 0.0, 0.0), 1.0));
+    }
+}
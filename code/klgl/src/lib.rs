@@ -1,15 +1,51 @@
+mod app;
 mod camera;
 mod camera_controller;
+mod camera_path;
+mod color;
 mod common;
+mod compressed_texture;
+mod debug_draw;
+mod draw_pass;
 pub mod file_loader;
+mod fixed_timestep;
 mod fps_counter;
+mod frame_uploader;
+mod frustum;
+mod normal_matrix;
+mod orbit_scaling;
 mod render_context;
+mod render_target;
 mod rotator;
+mod sim_clock;
+mod split_view;
 mod texture;
+mod texture_loader;
+mod texture_pool;
+mod window_config;
 
-pub use camera::{Camera, CameraUniform};
+pub use app::{App, Renderer, UserEvent};
+pub use camera::{Camera, CameraPose, CameraUniform, CoordinateSystem, Projection};
 pub use camera_controller::CameraController;
+pub use camera_path::CameraPath;
+pub use color::srgb_color;
+pub use compressed_texture::TEXTURE_COMPRESSION_FEATURES;
+pub use debug_draw::{
+    DebugDraw, DebugVertex, debug_draw_aabb, debug_draw_line, debug_draw_sphere, flush_debug_draw,
+};
+pub use draw_pass::{ClearPass, DrawPass, PassClearConfig, PassList, PassTargets, SharedDrawPass};
+pub use fixed_timestep::{FixedSteps, FixedTimestep};
 pub use fps_counter::FpsCounter;
-pub use render_context::RenderContext;
+pub use frame_uploader::FrameUploader;
+pub use frustum::{FRUSTUM_EDGES, Frustum, frustum_corners};
+pub use normal_matrix::normal_matrix;
+pub use orbit_scaling::{OrbitScaling, ZoomCurve};
+pub use render_context::{AdapterReport, RenderContext, RenderContextOptions, Viewport};
+pub use render_target::{RENDER_TARGET_USAGE, RenderTarget};
 pub use rotator::Rotator;
-pub use texture::Texture;
+pub use sim_clock::SimClock;
+pub use split_view::{SplitLayout, SplitView, split_viewports};
+pub use texture::{SamplerOptions, Texture};
+pub use texture_loader::{AssetHandle, AssetState, TextureLoader};
+pub use texture_pool::{TextureKey, TexturePool};
+pub use window_config::WindowConfig;
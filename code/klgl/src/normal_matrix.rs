@@ -0,0 +1,41 @@
+use cgmath::{Matrix, Matrix3, Matrix4, SquareMatrix};
+
+/// Transforms normals of a model the way `model` transforms its surfaces.
+/// Unlike the model matrix itself it keeps normals perpendicular to the surface
+/// under non-uniform scale. Normals still have to be normalized after it.
+pub fn normal_matrix(model: &Matrix4<f32>) -> Matrix3<f32> {
+    let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+    // A flattened model has no meaningful normals, keep whatever the linear part gives
+    linear
+        .invert()
+        .map(|inverse| inverse.transpose())
+        .unwrap_or(linear)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, InnerSpace, Vector3};
+
+    #[test]
+    fn test_normal_stays_perpendicular_under_non_uniform_scale() {
+        let model = Matrix4::from_translation(Vector3::new(5.0, -2.0, 1.0))
+            * Matrix4::from_angle_z(Deg(30.0))
+            * Matrix4::from_nonuniform_scale(4.0, 0.5, 2.0);
+
+        // A sloped surface spanned by two tangents
+        let tangent_a = Vector3::new(1.0, 1.0, 0.0);
+        let tangent_b = Vector3::new(0.0, 1.0, 1.0);
+        let normal = tangent_a.cross(tangent_b);
+
+        let world_a = (model * tangent_a.extend(0.0)).truncate();
+        let world_b = (model * tangent_b.extend(0.0)).truncate();
+        let world_normal = (normal_matrix(&model) * normal).normalize();
+        assert!(world_normal.dot(world_a.normalize()).abs() < 1e-5);
+        assert!(world_normal.dot(world_b.normalize()).abs() < 1e-5);
+
+        // The model matrix alone skews the normal
+        let skewed = (model * normal.extend(0.0)).truncate().normalize();
+        assert!(skewed.dot(world_a.normalize()).abs() > 0.1);
+    }
+}
@@ -0,0 +1,109 @@
+use cgmath::Vector2;
+
+/// How the orbit radius changes with the scroll wheel
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ZoomCurve {
+    /// Every scroll step moves the same distance
+    Linear,
+    /// Every scroll step changes the radius by the same factor, so zoom slows down near the target
+    Logarithmic,
+}
+
+/// Distance dependent speeds for an orbit style camera controller.
+///
+/// There is no orbit controller in klgl yet. These are the scaling rules it is meant to use,
+/// kept separate so they can be tested without a window.
+#[derive(Copy, Clone, Debug)]
+pub struct OrbitScaling {
+    /// World units the target moves per pixel of cursor movement at radius 1
+    pub pan_speed: f32,
+    /// Multiply the pan by the orbit radius so the target follows the cursor at any zoom level
+    pub pan_scale_with_distance: bool,
+    pub zoom_curve: ZoomCurve,
+    /// Distance per scroll step for `Linear`, relative change per step for `Logarithmic`
+    pub zoom_speed: f32,
+    pub min_radius: f32,
+}
+
+impl Default for OrbitScaling {
+    fn default() -> Self {
+        Self {
+            pan_speed: 0.002,
+            pan_scale_with_distance: true,
+            zoom_curve: ZoomCurve::Logarithmic,
+            zoom_speed: 0.1,
+            min_radius: 0.01,
+        }
+    }
+}
+
+impl OrbitScaling {
+    /// Target movement in the camera plane for a cursor movement in pixels
+    pub fn pan_delta(&self, cursor_delta: Vector2<f32>, radius: f32) -> Vector2<f32> {
+        let scale = match self.pan_scale_with_distance {
+            true => radius,
+            false => 1.0,
+        };
+        cursor_delta * self.pan_speed * scale
+    }
+
+    /// New orbit radius after scrolling by `steps`. Positive steps zoom in.
+    pub fn zoom(&self, radius: f32, steps: f32) -> f32 {
+        let radius = match self.zoom_curve {
+            ZoomCurve::Linear => radius - steps * self.zoom_speed,
+            ZoomCurve::Logarithmic => radius * (-steps * self.zoom_speed).exp(),
+        };
+        radius.max(self.min_radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_utils::*;
+
+    #[test]
+    fn test_pan_scales_with_radius() {
+        let mut scaling = OrbitScaling::default();
+        let cursor_delta = Vector2::new(10.0, -4.0);
+
+        let near = scaling.pan_delta(cursor_delta, 10.0);
+        let far = scaling.pan_delta(cursor_delta, 100.0);
+        assert!(almost_equal(far.x, near.x * 10.0, 1e-5));
+        assert!(almost_equal(far.y, near.y * 10.0, 1e-5));
+        assert!(almost_equal(near.x, 10.0 * 0.002 * 10.0, 1e-5));
+
+        scaling.pan_scale_with_distance = false;
+        assert_eq!(
+            scaling.pan_delta(cursor_delta, 10.0),
+            scaling.pan_delta(cursor_delta, 100.0)
+        );
+    }
+
+    #[test]
+    fn test_logarithmic_zoom_slows_down_near_target() {
+        let scaling = OrbitScaling::default();
+        let near_step = 10.0 - scaling.zoom(10.0, 1.0);
+        let far_step = 100.0 - scaling.zoom(100.0, 1.0);
+        assert!(almost_equal(far_step, near_step * 10.0, 1e-3));
+
+        // Zooming in and out by the same amount returns to the same radius
+        assert!(almost_equal(
+            scaling.zoom(scaling.zoom(10.0, 3.0), -3.0),
+            10.0,
+            1e-4
+        ));
+    }
+
+    #[test]
+    fn test_linear_zoom_is_clamped() {
+        let scaling = OrbitScaling {
+            zoom_curve: ZoomCurve::Linear,
+            zoom_speed: 1.0,
+            ..Default::default()
+        };
+        assert!(almost_equal(scaling.zoom(10.0, 1.0), 9.0, 1e-5));
+        assert!(almost_equal(scaling.zoom(100.0, 1.0), 99.0, 1e-5));
+        assert_eq!(scaling.zoom(0.5, 1.0), scaling.min_radius);
+    }
+}
@@ -1,24 +1,337 @@
-use std::pin::Pin;
+use std::{collections::HashMap, pin::Pin};
+
+// Winit does not report CSS-driven size changes of the canvas,
+// so on web we watch them with a ResizeObserver instead.
+#[cfg(target_arch = "wasm32")]
+struct CanvasResizeObserver {
+    observer: web_sys::ResizeObserver,
+    _callback: wasm_bindgen::closure::Closure<dyn FnMut(js_sys::Array)>,
+    receiver: async_channel::Receiver<(u32, u32)>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl CanvasResizeObserver {
+    fn new(canvas: &web_sys::HtmlCanvasElement) -> Self {
+        use wasm_bindgen::JsCast;
+
+        let (sender, receiver) = async_channel::unbounded::<(u32, u32)>();
+        let callback = wasm_bindgen::closure::Closure::<dyn FnMut(js_sys::Array)>::new(
+            move |entries: js_sys::Array| {
+                let scale = web_sys::window()
+                    .map(|w| w.device_pixel_ratio())
+                    .unwrap_or(1.0);
+                for entry in entries.iter() {
+                    if let Ok(entry) = entry.dyn_into::<web_sys::ResizeObserverEntry>() {
+                        let rect = entry.content_rect();
+                        let width = (rect.width() * scale).round() as u32;
+                        let height = (rect.height() * scale).round() as u32;
+                        let _ = sender.try_send((width, height));
+                    }
+                }
+            },
+        );
+
+        let observer = web_sys::ResizeObserver::new(callback.as_ref().unchecked_ref())
+            .expect("Failed to create ResizeObserver");
+        observer.observe(canvas);
+
+        Self {
+            observer,
+            _callback: callback,
+            receiver,
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for CanvasResizeObserver {
+    fn drop(&mut self) {
+        self.observer.disconnect();
+    }
+}
+
+// The surface should match the physical resolution of the screen, which on high-DPI displays
+// is larger than the size of the canvas in CSS pixels. Only the drawing buffer is scaled, the
+// canvas keeps the CSS size winit or the page gave it.
+#[cfg(target_arch = "wasm32")]
+fn web_canvas_physical_size(canvas: &web_sys::HtmlCanvasElement) -> (u32, u32) {
+    let scale = web_sys::window()
+        .map(|w| w.device_pixel_ratio())
+        .unwrap_or(1.0);
+    log::info!("canvas scale factor: {}", scale);
+
+    let width = (canvas.client_width() as f64 * scale).round() as u32;
+    let height = (canvas.client_height() as f64 * scale).round() as u32;
+    (width.max(1), height.max(1))
+}
+
+/// A zero-sized surface cannot be configured, so it is the only reason to skip a frame.
+fn is_renderable_size(width: u32, height: u32) -> bool {
+    width > 0 && height > 0
+}
+
+/// Configuration of a new surface at the size of its window. It is configured right away
+/// instead of waiting for the first `Resized` event, which some platforms never send, so the
+/// first redraw proceeds. The second value is false for a size that cannot be configured yet.
+fn initial_surface_config(
+    caps: &wgpu::SurfaceCapabilities,
+    width: u32,
+    height: u32,
+) -> (wgpu::SurfaceConfiguration, bool) {
+    let config = wgpu::SurfaceConfiguration {
+        // Copies of the frame allow reading it back, e.g. to record videos
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (caps.usages & wgpu::TextureUsages::COPY_SRC),
+        format: select_surface_format(&caps.formats),
+        width,
+        height,
+        present_mode: caps.present_modes[0],
+        alpha_mode: caps.alpha_modes[0],
+        desired_maximum_frame_latency: 2,
+        view_formats: vec![],
+    };
+    (config, is_renderable_size(width, height))
+}
+
+/// Prefers an sRGB format so the hardware encodes colors on write. Shaders that write to a
+/// surface without one have to encode themselves, see [`RenderContext::surface_is_srgb`].
+fn select_surface_format(formats: &[wgpu::TextureFormat]) -> wgpu::TextureFormat {
+    formats
+        .iter()
+        .copied()
+        .find(|f| f.is_srgb())
+        .unwrap_or(formats[0])
+}
+
+/// Name of the pipeline-overridable bool constant that enables sRGB encoding in surface shaders.
+const ENCODE_SRGB_CONSTANT: &str = "ENCODE_SRGB";
+
+fn surface_shader_constants(surface_is_srgb: bool) -> HashMap<String, f64> {
+    let encode = if surface_is_srgb { 0.0 } else { 1.0 };
+    HashMap::from([(ENCODE_SRGB_CONSTANT.to_string(), encode)])
+}
+
+// Backends that can't filter anisotropically ignore everything above 1
+fn max_sampler_anisotropy(flags: wgpu::DownlevelFlags) -> u16 {
+    match flags.contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING) {
+        true => crate::SamplerOptions::MAX_ANISOTROPY,
+        false => 1,
+    }
+}
+
+/// Region of the render targets the scene is drawn into, in physical pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Largest centered region of a `width` x `height` target with the given aspect.
+/// The rest of the target is left for the bars.
+fn letterbox_viewport(width: u32, height: u32, aspect: f32) -> Viewport {
+    let window_aspect = width as f32 / height as f32;
+    if window_aspect > aspect {
+        // Bars on the left and right
+        let viewport_width = ((height as f32 * aspect).round() as u32).clamp(1, width);
+        Viewport {
+            x: (width - viewport_width) / 2,
+            y: 0,
+            width: viewport_width,
+            height,
+        }
+    } else {
+        // Bars at the top and bottom
+        let viewport_height = ((width as f32 / aspect).round() as u32).clamp(1, height);
+        Viewport {
+            x: 0,
+            y: (height - viewport_height) / 2,
+            width,
+            height: viewport_height,
+        }
+    }
+}
+
+/// Adapter, device and surface capabilities, see [`RenderContext::report`].
+/// Its `Display` output is meant for logs and bug reports.
+#[derive(Clone, Debug)]
+pub struct AdapterReport {
+    pub info: wgpu::AdapterInfo,
+    /// Everything the adapter supports
+    pub adapter_features: wgpu::Features,
+    /// Features enabled on the device
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    pub surface_formats: Vec<wgpu::TextureFormat>,
+    pub present_modes: Vec<wgpu::PresentMode>,
+    /// Format the surface is configured with
+    pub surface_format: wgpu::TextureFormat,
+    pub present_mode: wgpu::PresentMode,
+}
+
+impl std::fmt::Display for AdapterReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let info = &self.info;
+        writeln!(
+            f,
+            "adapter: {} ({:?}, {:?})",
+            info.name, info.backend, info.device_type
+        )?;
+        writeln!(f, "driver: {} {}", info.driver, info.driver_info)?;
+        writeln!(
+            f,
+            "vendor: {:#06x}, device: {:#06x}",
+            info.vendor, info.device
+        )?;
+        writeln!(f, "adapter features: {:?}", self.adapter_features)?;
+        writeln!(f, "enabled features: {:?}", self.features)?;
+        writeln!(f, "surface formats: {:?}", self.surface_formats)?;
+        writeln!(f, "present modes: {:?}", self.present_modes)?;
+        writeln!(
+            f,
+            "surface: {:?}, {:?}",
+            self.surface_format, self.present_mode
+        )?;
+        write!(f, "limits: {:#?}", self.limits)
+    }
+}
+
+/// What an app needs from the device. See [`crate::Renderer::render_context_options`].
+#[derive(Clone, Debug)]
+pub struct RenderContextOptions {
+    /// Creating the context fails if the adapter can't provide these
+    pub required_limits: wgpu::Limits,
+    /// Optional features, enabled where the adapter has them. Check with
+    /// [`RenderContext::has_feature`] before using one.
+    pub desired_features: wgpu::Features,
+}
+
+impl Default for RenderContextOptions {
+    fn default() -> Self {
+        Self {
+            // WebGL doesn't support all of wgpu's features, so if
+            // we're building for the web we'll have to disable some.
+            required_limits: if cfg!(target_arch = "wasm32") {
+                let mut l = wgpu::Limits::downlevel_webgl2_defaults();
+                l.max_texture_dimension_2d = 4096;
+                l
+            } else {
+                wgpu::Limits::default()
+            },
+            desired_features: wgpu::Features::empty(),
+        }
+    }
+}
+
+/// Overrides the backends native apps pick their adapter from, e.g. `KLGL_BACKEND=dx12`
+#[cfg(not(target_arch = "wasm32"))]
+const BACKEND_ENV_VAR: &str = "KLGL_BACKEND";
+
+/// Backends for a value of [`BACKEND_ENV_VAR`], None if it names no backend
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_backends(value: &str) -> Option<wgpu::Backends> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "vulkan" => Some(wgpu::Backends::VULKAN),
+        "dx12" => Some(wgpu::Backends::DX12),
+        "metal" => Some(wgpu::Backends::METAL),
+        "gl" => Some(wgpu::Backends::GL),
+        "primary" => Some(wgpu::Backends::PRIMARY),
+        _ => None,
+    }
+}
+
+/// Backends named by [`BACKEND_ENV_VAR`], primary ones if it is not set
+#[cfg(not(target_arch = "wasm32"))]
+fn backends_from_env() -> wgpu::Backends {
+    let Ok(value) = std::env::var(BACKEND_ENV_VAR) else {
+        return wgpu::Backends::PRIMARY;
+    };
+    parse_backends(&value).unwrap_or_else(|| {
+        log::warn!(
+            "{}={:?} is not one of vulkan, dx12, metal, gl or primary. Using primary",
+            BACKEND_ENV_VAR,
+            value
+        );
+        wgpu::Backends::PRIMARY
+    })
+}
+
+/// The desired features the adapter supports. The rest is logged and left disabled.
+fn negotiate_features(desired: wgpu::Features, supported: wgpu::Features) -> wgpu::Features {
+    let missing = desired - supported;
+    if !missing.is_empty() {
+        log::warn!("Adapter does not support features {:?}", missing);
+    }
+    desired & supported
+}
+
+// wgpu does not report the latencies a surface supports and backends clamp on their own.
+// One frame is the lowest latency, more than three only adds input lag.
+const MIN_FRAME_LATENCY: u32 = 1;
+const MAX_FRAME_LATENCY: u32 = 3;
+
+// Returns the latency that was stored
+fn set_config_frame_latency(config: &mut wgpu::SurfaceConfiguration, latency: u32) -> u32 {
+    config.desired_maximum_frame_latency = latency.clamp(MIN_FRAME_LATENCY, MAX_FRAME_LATENCY);
+    config.desired_maximum_frame_latency
+}
+
+/// Names every requested limit the adapter does not reach, wgpu would only report the first one
+fn check_limits(requested: &wgpu::Limits, supported: &wgpu::Limits) -> anyhow::Result<()> {
+    let mut failures = Vec::new();
+    requested.check_limits_with_fail_fn(supported, false, |name, requested, supported| {
+        failures.push(format!(
+            "{name} (requested {requested}, supported {supported})"
+        ));
+    });
+
+    match failures.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!(
+            "The adapter does not support the required limits: {}",
+            failures.join(", ")
+        )),
+    }
+}
 
 pub struct RenderContext {
     pub instance: wgpu::Instance,
-    pub window: Pin<Box<winit::window::Window>>,
-    pub surface: wgpu::Surface<'static>,
+    // Both are `None` for a headless context
+    window: Option<Pin<Box<winit::window::Window>>>,
+    surface: Option<wgpu::Surface<'static>>,
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
+    configured: bool,
+    fixed_aspect: Option<f32>,
+    // Follows `WindowEvent::Focused`, see `App::set_pause_on_blur`
+    focused: bool,
+    #[cfg(target_arch = "wasm32")]
+    canvas_resize_observer: Option<CanvasResizeObserver>,
 }
 
 impl RenderContext {
     pub async fn new(w: winit::window::Window) -> Self {
+        Self::with_options(w, RenderContextOptions::default())
+            .await
+            .expect("Failed to create the render context")
+    }
+
+    pub async fn with_options(
+        w: winit::window::Window,
+        options: RenderContextOptions,
+    ) -> anyhow::Result<Self> {
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
+        #[cfg(not(target_arch = "wasm32"))]
+        let backends = backends_from_env();
+        #[cfg(target_arch = "wasm32")]
+        let backends = wgpu::Backends::GL;
+        log::info!("Backends: {:?}", backends);
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
-            #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::GL,
+            backends,
             ..Default::default()
         });
 
@@ -36,35 +349,25 @@ impl RenderContext {
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
+            .ok_or_else(|| anyhow::anyhow!("No compatible graphics adapter"))?;
+
+        check_limits(&options.required_limits, &adapter.limits())?;
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    required_features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    required_limits: if cfg!(target_arch = "wasm32") {
-                        let mut l = wgpu::Limits::downlevel_webgl2_defaults();
-                        l.max_texture_dimension_2d = 4096;
-                        l
-                    } else {
-                        wgpu::Limits::default()
-                    },
+                    required_features: negotiate_features(
+                        options.desired_features,
+                        adapter.features(),
+                    ),
+                    required_limits: options.required_limits,
                     memory_hints: Default::default(),
                 },
                 // Some(&std::path::Path::new("trace")), // Trace path
                 None,
             )
-            .await
-            .unwrap();
-
-        let device_limits = device.limits();
-        log::info!("device limits: {:?}", device_limits);
-
-        let adapter_info = adapter.get_info();
-        log::info!("adapter info: {:?}", adapter_info);
+            .await?;
 
         #[cfg(target_arch = "wasm32")]
         {
@@ -83,46 +386,466 @@ impl RenderContext {
         }
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an Srgb surface texture. Using a different
-        // one will result all the colors comming out darker. If you want to support non
-        // Srgb surfaces, you'll need to account for that when drawing to the frame.
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
-
-        let size = window.inner_size();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let (width, height) = {
+            let size = window.inner_size();
+            (size.width, size.height)
+        };
+
+        #[cfg(target_arch = "wasm32")]
+        let (width, height) = {
+            use winit::platform::web::WindowExtWebSys;
+            web_canvas_physical_size(&window.canvas().expect("Window has no canvas"))
+        };
+
+        let (config, configured) = initial_surface_config(&surface_caps, width, height);
+        if !config.format.is_srgb() {
+            log::warn!(
+                "Surface format {:?} is not sRGB, shaders will encode colors",
+                config.format
+            );
+        }
+        if configured {
+            surface.configure(&device, &config);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        let canvas_resize_observer = {
+            use winit::platform::web::WindowExtWebSys;
+            window
+                .canvas()
+                .map(|canvas| CanvasResizeObserver::new(&canvas))
+        };
+
+        let context = Self {
+            instance,
+            window: Some(window_box),
+            surface: Some(surface),
+            adapter,
+            device,
+            queue,
+            config,
+            configured,
+            fixed_aspect: None,
+            focused: true,
+            #[cfg(target_arch = "wasm32")]
+            canvas_resize_observer,
+        };
+        log::info!("{}", context.report());
+        Ok(context)
+    }
+
+    /// A context without a window, e.g. for benchmarks. Passes render into their own
+    /// targets, `config` only describes the size and format of an imaginary surface.
+    /// Fails if there is no adapter.
+    pub async fn headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let backends = backends_from_env();
+        #[cfg(target_arch = "wasm32")]
+        let backends = wgpu::Backends::PRIMARY;
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends,
+            ..Default::default()
+        });
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No adapter is available"))?;
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .await?;
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
             desired_maximum_frame_latency: 2,
             view_formats: vec![],
         };
 
-        Self {
+        Ok(Self {
             instance,
-            window: window_box,
-            surface,
+            window: None,
+            surface: None,
             adapter,
             device,
             queue,
             config,
+            configured: true,
+            fixed_aspect: None,
+            focused: true,
+            #[cfg(target_arch = "wasm32")]
+            canvas_resize_observer: None,
+        })
+    }
+
+    /// Panics for a headless context
+    pub fn window(&self) -> &winit::window::Window {
+        self.window
+            .as_ref()
+            .expect("A headless render context has no window")
+    }
+
+    /// Panics for a headless context
+    pub fn surface(&self) -> &wgpu::Surface<'static> {
+        self.surface
+            .as_ref()
+            .expect("A headless render context has no surface")
+    }
+
+    /// Returns the latest physical size of the canvas if it was resized by the page layout.
+    /// Always returns `None` on native platforms where winit reports resizes itself.
+    pub fn poll_canvas_resize(&self) -> Option<(u32, u32)> {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let receiver = &self.canvas_resize_observer.as_ref()?.receiver;
+                let mut latest = None;
+                while let Ok(size) = receiver.try_recv() {
+                    latest = Some(size);
+                }
+                latest
+            } else {
+                None
+            }
         }
     }
 
+    /// Whether the window has the keyboard focus. Always true for a headless context.
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    /// Set by [`crate::App`] when the window gains or loses the focus
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    /// Aspect the camera should use. Follows the window unless a fixed aspect is set.
     pub fn aspect(&self) -> f32 {
-        return self.config.width as f32 / self.config.height as f32;
+        self.fixed_aspect
+            .unwrap_or(self.config.width as f32 / self.config.height as f32)
+    }
+
+    /// Keeps the scene at the given aspect regardless of the window size by drawing it into
+    /// a centered [`Viewport`] with black bars around it. `None` fills the whole window again.
+    /// Renderers should update their camera aspect after changing it.
+    pub fn set_fixed_aspect(&mut self, aspect: Option<f32>) {
+        self.fixed_aspect = aspect.filter(|aspect| aspect.is_finite() && *aspect > 0.0);
+    }
+
+    pub fn fixed_aspect(&self) -> Option<f32> {
+        self.fixed_aspect
+    }
+
+    /// The letterboxed region of the surface if a fixed aspect is set.
+    pub fn viewport(&self) -> Option<Viewport> {
+        self.fixed_aspect
+            .map(|aspect| letterbox_viewport(self.config.width, self.config.height, aspect))
+    }
+
+    /// Region the scene is drawn into, the whole surface unless a fixed aspect is set.
+    pub fn scene_viewport(&self) -> Viewport {
+        self.viewport().unwrap_or(Viewport {
+            x: 0,
+            y: 0,
+            width: self.config.width,
+            height: self.config.height,
+        })
+    }
+
+    /// Highest anisotropy samplers can use on this adapter, see [`crate::SamplerOptions::anisotropy`]
+    pub fn max_sampler_anisotropy(&self) -> u16 {
+        max_sampler_anisotropy(self.adapter.get_downlevel_capabilities().flags)
+    }
+
+    /// Whether the surface encodes linear colors to sRGB on write.
+    pub fn surface_is_srgb(&self) -> bool {
+        self.config.format.is_srgb()
+    }
+
+    /// Values for the overridable constants of shaders that write to the surface.
+    /// Such shaders declare `override ENCODE_SRGB: bool = false;` and convert their
+    /// output with it when the surface is not sRGB.
+    pub fn surface_shader_constants(&self) -> HashMap<String, f64> {
+        surface_shader_constants(self.surface_is_srgb())
+    }
+
+    /// What the adapter and the surface support and what the context picked from it.
+    /// A headless context reports no surface formats or present modes.
+    pub fn report(&self) -> AdapterReport {
+        let surface_caps = self
+            .surface
+            .as_ref()
+            .map(|surface| surface.get_capabilities(&self.adapter))
+            .unwrap_or_default();
+        AdapterReport {
+            info: self.adapter.get_info(),
+            adapter_features: self.adapter.features(),
+            features: self.device.features(),
+            limits: self.device.limits(),
+            surface_formats: surface_caps.formats,
+            present_modes: surface_caps.present_modes,
+            surface_format: self.config.format,
+            present_mode: self.config.present_mode,
+        }
+    }
+
+    /// True if the feature was desired in [`RenderContextOptions`] and the adapter supports it
+    pub fn has_feature(&self, feature: wgpu::Features) -> bool {
+        self.device.features().contains(feature)
+    }
+
+    /// How many frames can be queued before presenting blocks
+    pub fn frame_latency(&self) -> u32 {
+        self.config.desired_maximum_frame_latency
+    }
+
+    /// 1 gives the lowest input lag, 3 the smoothest frame rate. Clamped to `1..=3`.
+    /// Resources rewritten every frame need one copy more than this.
+    /// Returns the latency that was set.
+    pub fn set_frame_latency(&mut self, latency: u32) -> u32 {
+        let latency = set_config_frame_latency(&mut self.config, latency);
+        if self.configured
+            && let Some(surface) = &self.surface
+        {
+            surface.configure(&self.device, &self.config);
+        }
+        latency
+    }
+
+    /// Returns false while the surface has a zero size (e.g. minimized window).
+    pub fn is_configured(&self) -> bool {
+        self.configured
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
+        self.configured = is_renderable_size(width, height);
+        if !self.configured {
+            return;
+        }
+
         self.config.width = width;
         self.config.height = height;
-        self.surface.configure(&self.device, &self.config);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adapter_report_display() {
+        let report = AdapterReport {
+            info: wgpu::AdapterInfo {
+                name: "Test GPU".to_string(),
+                vendor: 0x10de,
+                device: 0x1234,
+                device_type: wgpu::DeviceType::DiscreteGpu,
+                driver: "test driver".to_string(),
+                driver_info: "1.0".to_string(),
+                backend: wgpu::Backend::Vulkan,
+            },
+            adapter_features: wgpu::Features::TEXTURE_COMPRESSION_BC,
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+            surface_formats: vec![wgpu::TextureFormat::Bgra8UnormSrgb],
+            present_modes: vec![wgpu::PresentMode::Fifo],
+            surface_format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        let text = report.to_string();
+        assert!(text.contains("Test GPU (Vulkan, DiscreteGpu)"), "{text}");
+        assert!(text.contains("TEXTURE_COMPRESSION_BC"), "{text}");
+        assert!(text.contains("Bgra8UnormSrgb, Fifo"), "{text}");
+        assert!(text.contains("max_texture_dimension_2d: 8192"), "{text}");
+        assert!(text.contains("max_bind_groups: 4"), "{text}");
+    }
+
+    #[test]
+    fn test_parse_backends() {
+        assert_eq!(parse_backends("vulkan"), Some(wgpu::Backends::VULKAN));
+        assert_eq!(parse_backends("dx12"), Some(wgpu::Backends::DX12));
+        assert_eq!(parse_backends("metal"), Some(wgpu::Backends::METAL));
+        assert_eq!(parse_backends("gl"), Some(wgpu::Backends::GL));
+        assert_eq!(parse_backends("primary"), Some(wgpu::Backends::PRIMARY));
+        assert_eq!(parse_backends(" Vulkan\n"), Some(wgpu::Backends::VULKAN));
+        assert_eq!(parse_backends("DX12"), Some(wgpu::Backends::DX12));
+        assert_eq!(parse_backends("directx"), None);
+        assert_eq!(parse_backends(""), None);
+    }
+
+    #[test]
+    fn test_max_sampler_anisotropy() {
+        assert_eq!(
+            max_sampler_anisotropy(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING),
+            16
+        );
+        // WebGPU compliance does not promise anisotropic filtering
+        assert_eq!(max_sampler_anisotropy(wgpu::DownlevelFlags::compliant()), 1);
+        assert_eq!(max_sampler_anisotropy(wgpu::DownlevelFlags::all()), 16);
+    }
+
+    #[test]
+    fn test_unsupported_features_are_left_out() {
+        let supported = wgpu::Features::INDIRECT_FIRST_INSTANCE | wgpu::Features::POLYGON_MODE_LINE;
+        let desired = wgpu::Features::POLYGON_MODE_LINE | wgpu::Features::TIMESTAMP_QUERY;
+        let enabled = negotiate_features(desired, supported);
+        assert_eq!(enabled, wgpu::Features::POLYGON_MODE_LINE);
+        assert!(!enabled.contains(wgpu::Features::TIMESTAMP_QUERY));
+        assert!(negotiate_features(wgpu::Features::empty(), supported).is_empty());
+    }
+
+    #[test]
+    fn test_impossible_limits_are_an_error() {
+        let supported = wgpu::Limits::downlevel_defaults();
+        assert!(check_limits(&wgpu::Limits::downlevel_webgl2_defaults(), &supported).is_ok());
+
+        let requested = wgpu::Limits {
+            max_storage_buffers_per_shader_stage: supported.max_storage_buffers_per_shader_stage
+                + 1,
+            max_bind_groups: 100,
+            ..supported.clone()
+        };
+        let error = check_limits(&requested, &supported)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("max_storage_buffers_per_shader_stage"),
+            "{error}"
+        );
+        assert!(error.contains("max_bind_groups (requested 100"), "{error}");
+    }
+
+    #[test]
+    fn test_renders_without_initial_resize() {
+        let caps = wgpu::SurfaceCapabilities {
+            formats: vec![
+                wgpu::TextureFormat::Bgra8Unorm,
+                wgpu::TextureFormat::Bgra8UnormSrgb,
+            ],
+            present_modes: vec![wgpu::PresentMode::Fifo],
+            alpha_modes: vec![wgpu::CompositeAlphaMode::Opaque],
+            usages: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        };
+
+        // The surface is configured at the window size before any `Resized` event
+        let (config, configured) = initial_surface_config(&caps, 800, 600);
+        assert!(configured);
+        assert_eq!((config.width, config.height), (800, 600));
+        assert_eq!(config.format, wgpu::TextureFormat::Bgra8UnormSrgb);
+        assert!(config.usage.contains(wgpu::TextureUsages::COPY_SRC));
+
+        // A window created minimized waits for its first resize
+        let (_, configured) = initial_surface_config(&caps, 0, 600);
+        assert!(!configured);
+    }
+
+    #[test]
+    fn test_select_surface_format() {
+        use wgpu::TextureFormat::*;
+
+        assert_eq!(
+            select_surface_format(&[Bgra8Unorm, Bgra8UnormSrgb, Rgba8Unorm]),
+            Bgra8UnormSrgb
+        );
+
+        // Without sRGB formats the first one is used and shaders have to encode
+        let format = select_surface_format(&[Rgba16Float, Bgra8Unorm]);
+        assert_eq!(format, Rgba16Float);
+        assert_eq!(
+            surface_shader_constants(format.is_srgb())[ENCODE_SRGB_CONSTANT],
+            1.0
+        );
+        assert_eq!(
+            surface_shader_constants(Bgra8UnormSrgb.is_srgb())[ENCODE_SRGB_CONSTANT],
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_letterbox_viewport() {
+        let wide = 16.0 / 9.0;
+
+        // A 4:3 window gets bars at the top and bottom
+        assert_eq!(
+            letterbox_viewport(800, 600, wide),
+            Viewport {
+                x: 0,
+                y: 75,
+                width: 800,
+                height: 450,
+            }
+        );
+
+        // An ultrawide window gets bars on the sides
+        assert_eq!(
+            letterbox_viewport(2560, 1080, wide),
+            Viewport {
+                x: 320,
+                y: 0,
+                width: 1920,
+                height: 1080,
+            }
+        );
+
+        // A matching window is filled completely
+        assert_eq!(
+            letterbox_viewport(1920, 1080, wide),
+            Viewport {
+                x: 0,
+                y: 0,
+                width: 1920,
+                height: 1080,
+            }
+        );
+
+        // Extreme aspects still leave at least one pixel
+        assert_eq!(letterbox_viewport(100, 100, 1000.0).height, 1);
+    }
+
+    #[test]
+    fn test_frame_latency_is_clamped() {
+        let mut config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: 800,
+            height: 600,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            desired_maximum_frame_latency: 2,
+            view_formats: vec![],
+        };
+
+        assert_eq!(set_config_frame_latency(&mut config, 1), 1);
+        assert_eq!(config.desired_maximum_frame_latency, 1);
+        assert_eq!(set_config_frame_latency(&mut config, 3), 3);
+        assert_eq!(config.desired_maximum_frame_latency, 3);
+
+        assert_eq!(set_config_frame_latency(&mut config, 0), MIN_FRAME_LATENCY);
+        assert_eq!(
+            set_config_frame_latency(&mut config, 100),
+            MAX_FRAME_LATENCY
+        );
+        assert_eq!(config.desired_maximum_frame_latency, MAX_FRAME_LATENCY);
+    }
+
+    #[test]
+    fn test_zero_size_is_skipped() {
+        assert!(!is_renderable_size(0, 600));
+        assert!(!is_renderable_size(800, 0));
+        assert!(!is_renderable_size(0, 0));
     }
 }
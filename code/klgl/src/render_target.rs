@@ -0,0 +1,121 @@
+use crate::{PassTargets, Texture};
+
+/// Usage of the color texture of a [`RenderTarget`]. Passes render into it, it is sampled
+/// like any other texture afterwards and can be copied out, e.g. for a screenshot.
+pub const RENDER_TARGET_USAGE: wgpu::TextureUsages = wgpu::TextureUsages::RENDER_ATTACHMENT
+    .union(wgpu::TextureUsages::TEXTURE_BINDING)
+    .union(wgpu::TextureUsages::COPY_SRC);
+
+fn color_descriptor(
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> wgpu::TextureDescriptor<'static> {
+    wgpu::TextureDescriptor {
+        label: Some("render_target.color"),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: RENDER_TARGET_USAGE,
+        view_formats: &[],
+    }
+}
+
+/// Offscreen color with its own depth. The passes of a [`crate::PassList`] render into it
+/// instead of the screen when executed with [`RenderTarget::targets`], so the scene can be
+/// shown on a UI panel or through a portal.
+pub struct RenderTarget {
+    color: Texture,
+    depth: Texture,
+}
+
+impl RenderTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let texture = device.create_texture(&color_descriptor(width, height, format));
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            color: Texture {
+                texture,
+                view,
+                sampler,
+                compare: None,
+            },
+            depth: Texture::create_depth_texture(device, width, height, "render_target.depth"),
+        }
+    }
+
+    /// Color texture with a linear sampler, to draw the result with
+    pub fn color(&self) -> &Texture {
+        &self.color
+    }
+
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color.view
+    }
+
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth.view
+    }
+
+    pub fn width(&self) -> u32 {
+        self.color.texture.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.color.texture.height()
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.color.texture.format()
+    }
+
+    /// Targets that make the passes render into this target.
+    /// Post processing that writes to the surface ends up in the color texture too.
+    pub fn targets(&self) -> PassTargets<'_> {
+        PassTargets {
+            color: &self.color.view,
+            depth: Some(&self.depth.view),
+            surface: &self.color.view,
+            viewport: None,
+            camera: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_usage() {
+        let descriptor = color_descriptor(0, 240, wgpu::TextureFormat::Rgba16Float);
+        assert!(descriptor.usage.contains(
+            wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+        ));
+        assert_eq!(descriptor.size.width, 1);
+        assert_eq!(descriptor.size.height, 240);
+    }
+}
@@ -1,7 +1,7 @@
-use cgmath::Matrix4;
-use cgmath::{Deg, Rad};
+use cgmath::{Deg, InnerSpace, Quaternion, Rad};
+use cgmath::{Matrix3, Matrix4, Vector3};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Rotator {
     pub yaw: Deg<f32>,
     pub pitch: Deg<f32>,
@@ -14,6 +14,46 @@ fn sincos(angle: Rad<f32>) -> (f32, f32) {
 }
 
 impl Rotator {
+    /// Rotator with zero roll that turns +X (the camera forward axis) into `direction`.
+    pub fn from_direction(direction: Vector3<f32>) -> Self {
+        let d = direction.normalize();
+        Self {
+            yaw: Rad(d.y.atan2(d.x)).into(),
+            // Positive pitch looks down
+            pitch: Rad(-d.z.clamp(-1.0, 1.0).asin()).into(),
+            roll: Deg(0.0),
+        }
+    }
+
+    /// Inverse of `to_matrix` for rotation matrices. Pitch is kept in -90..90 degrees.
+    pub fn from_matrix(m: Matrix3<f32>) -> Self {
+        Self {
+            yaw: Rad(m.x.y.atan2(m.x.x)).into(),
+            pitch: Rad((-m.x.z).clamp(-1.0, 1.0).asin()).into(),
+            roll: Rad(m.y.z.atan2(m.z.z)).into(),
+        }
+    }
+
+    /// Interpolates along the shortest arc between the orientations.
+    /// Returns the ends unchanged for `t` of 0 and 1.
+    pub fn slerp(&self, other: &Self, t: f32) -> Self {
+        if t <= 0.0 {
+            return *self;
+        }
+        if t >= 1.0 {
+            return *other;
+        }
+
+        let a = Quaternion::from(self.to_matrix3());
+        let b = Quaternion::from(other.to_matrix3());
+        Self::from_matrix(a.slerp(b, t).into())
+    }
+
+    fn to_matrix3(self) -> Matrix3<f32> {
+        let m = self.to_matrix();
+        Matrix3::from_cols(m.x.truncate(), m.y.truncate(), m.z.truncate())
+    }
+
     pub fn to_matrix(&self) -> Matrix4<f32> {
         let (sa, ca) = sincos(self.roll.into());
         let (sb, cb) = sincos(self.pitch.into());
@@ -44,7 +84,7 @@ impl Rotator {
 mod tests {
     use super::*;
     use crate::common::test_utils::*;
-    use cgmath::{Transform, Vector3};
+    use cgmath::Transform;
 
     #[test]
     fn test_zero_rotator() {
@@ -157,4 +197,54 @@ mod tests {
             1e-6
         ));
     }
+
+    #[test]
+    fn test_slerp() {
+        let a = Rotator {
+            yaw: Deg(10.0),
+            pitch: Deg(20.0),
+            roll: Deg(-5.0),
+        };
+        let b = Rotator {
+            yaw: Deg(70.0),
+            pitch: Deg(20.0),
+            roll: Deg(-5.0),
+        };
+
+        let back = Rotator::from_matrix(a.to_matrix3());
+        assert!(almost_equal(back.yaw.0, a.yaw.0, 1e-4));
+        assert!(almost_equal(back.pitch.0, a.pitch.0, 1e-4));
+        assert!(almost_equal(back.roll.0, a.roll.0, 1e-4));
+
+        assert_eq!(a.slerp(&b, 0.0).yaw, a.yaw);
+        assert_eq!(a.slerp(&b, 1.0).yaw, b.yaw);
+
+        // Forward vectors at the ends and the middle are evenly spaced
+        let forward = |r: Rotator| r.to_matrix().transform_vector(Vector3::unit_x());
+        let middle = forward(a.slerp(&b, 0.5));
+        assert!(almost_equal(
+            middle.angle(forward(a)).0,
+            middle.angle(forward(b)).0,
+            1e-4
+        ));
+    }
+
+    #[test]
+    fn test_from_direction() {
+        let directions = [
+            Vector3::unit_x(),
+            Vector3::new(0.0, -2.0, 0.0),
+            Vector3::new(0.3, 0.2, -1.0),
+            Vector3::new(-1.0, 1.0, 1.0),
+        ];
+
+        for direction in directions {
+            let m = Rotator::from_direction(direction).to_matrix();
+            assert!(almost_equal_vec(
+                m.transform_vector(Vector3::unit_x()),
+                direction.normalize(),
+                1e-6
+            ));
+        }
+    }
 }
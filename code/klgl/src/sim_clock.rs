@@ -0,0 +1,94 @@
+use web_time::{Duration, Instant};
+
+// A clock for animations that can be paused and advanced manually
+pub struct SimClock {
+    accumulated: Duration,
+    // None while the clock is paused
+    resumed_at: Option<Instant>,
+}
+
+impl SimClock {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            accumulated: Duration::ZERO,
+            resumed_at: Some(now),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.resumed_at.is_none()
+    }
+
+    pub fn pause(&mut self, now: Instant) {
+        if let Some(resumed_at) = self.resumed_at.take() {
+            self.accumulated += now.duration_since(resumed_at);
+        }
+    }
+
+    pub fn resume(&mut self, now: Instant) {
+        if self.resumed_at.is_none() {
+            self.resumed_at = Some(now);
+        }
+    }
+
+    pub fn step(&mut self, dt: Duration) {
+        self.accumulated += dt;
+    }
+
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        match self.resumed_at {
+            Some(resumed_at) => self.accumulated + now.duration_since(resumed_at),
+            None => self.accumulated,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_running_clock() {
+        let start = Instant::now();
+        let clock = SimClock::new(start);
+        assert_eq!(
+            clock.elapsed(start + Duration::from_secs(3)),
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let start = Instant::now();
+        let mut clock = SimClock::new(start);
+
+        clock.pause(start + Duration::from_secs(2));
+        assert!(clock.is_paused());
+        assert_eq!(
+            clock.elapsed(start + Duration::from_secs(10)),
+            Duration::from_secs(2)
+        );
+
+        clock.resume(start + Duration::from_secs(10));
+        assert!(!clock.is_paused());
+        assert_eq!(
+            clock.elapsed(start + Duration::from_secs(11)),
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn test_step_while_paused() {
+        let start = Instant::now();
+        let mut clock = SimClock::new(start);
+        clock.pause(start + Duration::from_secs(1));
+
+        clock.step(Duration::from_millis(250));
+        clock.step(Duration::from_millis(250));
+
+        assert_eq!(
+            clock.elapsed(start + Duration::from_secs(5)),
+            Duration::from_millis(1500)
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{Camera, Viewport};
+
+/// How the views of a [`SplitView`] share the screen
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SplitLayout {
+    /// Side by side, from left to right
+    Horizontal,
+    /// Stacked, from top to bottom
+    Vertical,
+    /// Rows of up to `ceil(sqrt(n))` views, filled from the top left
+    Grid,
+}
+
+// Splits `length` pixels starting at `start` into `count` spans without gaps
+fn split_span(start: u32, length: u32, count: u32, index: u32) -> (u32, u32) {
+    let from = (length as u64 * index as u64 / count as u64) as u32;
+    let to = (length as u64 * (index as u64 + 1) / count as u64) as u32;
+    (start + from, to - from)
+}
+
+/// Rectangles of `count` views in `area`. They cover the area without overlapping,
+/// the last row of a grid may have empty cells.
+pub fn split_viewports(layout: SplitLayout, count: usize, area: Viewport) -> Vec<Viewport> {
+    let count = count as u32;
+    let (columns, rows) = match layout {
+        SplitLayout::Horizontal => (count, 1),
+        SplitLayout::Vertical => (1, count),
+        SplitLayout::Grid => {
+            let columns = (count as f32).sqrt().ceil() as u32;
+            (columns, count.div_ceil(columns.max(1)))
+        }
+    };
+
+    (0..count)
+        .map(|index| {
+            let (x, width) = split_span(area.x, area.width, columns, index % columns);
+            let (y, height) = split_span(area.y, area.height, rows, index / columns);
+            Viewport {
+                x,
+                y,
+                width,
+                height,
+            }
+        })
+        .collect()
+}
+
+/// Several cameras drawn into parts of the same target, e.g. to compare two angles side by side.
+/// The passes record once per view with [`crate::PassList::execute_views`].
+pub struct SplitView {
+    pub cameras: Vec<Camera>,
+    pub layout: SplitLayout,
+}
+
+impl SplitView {
+    pub fn new(cameras: Vec<Camera>, layout: SplitLayout) -> Self {
+        Self { cameras, layout }
+    }
+
+    /// Rectangle of each camera in `area`
+    pub fn viewports(&self, area: Viewport) -> Vec<Viewport> {
+        split_viewports(self.layout, self.cameras.len(), area)
+    }
+
+    /// Matches the aspect of each camera to its rectangle in `area`
+    pub fn update_aspects(&mut self, area: Viewport) {
+        let viewports = self.viewports(area);
+        for (camera, viewport) in self.cameras.iter_mut().zip(viewports) {
+            camera.set_aspect(viewport.width.max(1) as f32 / viewport.height.max(1) as f32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Viewport = Viewport {
+        x: 10,
+        y: 20,
+        width: 801,
+        height: 600,
+    };
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Viewport {
+        Viewport {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_two_views() {
+        assert_eq!(
+            split_viewports(SplitLayout::Horizontal, 2, AREA),
+            [rect(10, 20, 400, 600), rect(410, 20, 401, 600)]
+        );
+        assert_eq!(
+            split_viewports(SplitLayout::Vertical, 2, AREA),
+            [rect(10, 20, 801, 300), rect(10, 320, 801, 300)]
+        );
+        assert_eq!(
+            split_viewports(SplitLayout::Grid, 2, AREA),
+            split_viewports(SplitLayout::Horizontal, 2, AREA)
+        );
+    }
+
+    #[test]
+    fn test_four_views() {
+        assert_eq!(
+            split_viewports(SplitLayout::Grid, 4, AREA),
+            [
+                rect(10, 20, 400, 300),
+                rect(410, 20, 401, 300),
+                rect(10, 320, 400, 300),
+                rect(410, 320, 401, 300),
+            ]
+        );
+
+        // Spans tile the area exactly
+        let views = split_viewports(SplitLayout::Horizontal, 4, AREA);
+        for (left, right) in views.iter().zip(&views[1..]) {
+            assert_eq!(left.x + left.width, right.x);
+        }
+        let last = views.last().unwrap();
+        assert_eq!(last.x + last.width, AREA.x + AREA.width);
+    }
+
+    #[test]
+    fn test_single_view_fills_the_area() {
+        for layout in [
+            SplitLayout::Horizontal,
+            SplitLayout::Vertical,
+            SplitLayout::Grid,
+        ] {
+            assert_eq!(split_viewports(layout, 1, AREA), [AREA]);
+        }
+        assert!(split_viewports(SplitLayout::Grid, 0, AREA).is_empty());
+    }
+}
@@ -6,6 +6,135 @@ pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    pub(crate) compare: Option<wgpu::CompareFunction>,
+}
+
+/// How color textures are sampled outside of the [0, 1] texture coordinate range
+/// and how sharp they stay at grazing angles
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SamplerOptions {
+    pub address_mode: wgpu::AddressMode,
+    /// Requested anisotropic filtering, 1 turns it off. Clamped to what the adapter supports
+    /// when the sampler is created, see [`crate::RenderContext::max_sampler_anisotropy`].
+    pub anisotropy: u16,
+}
+
+impl SamplerOptions {
+    /// Highest anisotropy wgpu accepts
+    pub const MAX_ANISOTROPY: u16 = 16;
+
+    /// Tiled materials such as bricks and floors
+    pub const REPEAT: Self = Self {
+        address_mode: wgpu::AddressMode::Repeat,
+        anisotropy: 1,
+    };
+    /// UI and other textures that must not bleed in from the opposite edge
+    pub const CLAMP: Self = Self {
+        address_mode: wgpu::AddressMode::ClampToEdge,
+        anisotropy: 1,
+    };
+
+    /// Same options with the given anisotropy
+    pub const fn with_anisotropy(self, anisotropy: u16) -> Self {
+        Self { anisotropy, ..self }
+    }
+
+    pub(crate) fn descriptor(&self, max_anisotropy: u16) -> wgpu::SamplerDescriptor<'static> {
+        let anisotropy = self.anisotropy.clamp(1, max_anisotropy.max(1));
+        if anisotropy != self.anisotropy {
+            log::info!(
+                "Sampler anisotropy {}x is clamped to {}x, the most the adapter supports",
+                self.anisotropy,
+                anisotropy
+            );
+        }
+
+        // Anisotropic filtering only works with linear filtering
+        let min_filter = match anisotropy > 1 {
+            true => wgpu::FilterMode::Linear,
+            false => wgpu::FilterMode::Nearest,
+        };
+        wgpu::SamplerDescriptor {
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter,
+            mipmap_filter: min_filter,
+            anisotropy_clamp: anisotropy,
+            ..Default::default()
+        }
+    }
+
+    /// Repeat, MirrorRepeat and ClampToEdge work everywhere, the border mode needs a device feature
+    fn validate(&self, features: wgpu::Features) -> Result<()> {
+        let required = match self.address_mode {
+            wgpu::AddressMode::ClampToBorder => wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER,
+            _ => wgpu::Features::empty(),
+        };
+        if !features.contains(required) {
+            bail!(
+                "Address mode {:?} needs {:?} which the device does not support",
+                self.address_mode,
+                required
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self::REPEAT
+    }
+}
+
+fn depth_sampler_descriptor(
+    compare: Option<wgpu::CompareFunction>,
+) -> wgpu::SamplerDescriptor<'static> {
+    // Comparison samplers are used for percentage closer filtering so they get linear filtering.
+    let filter = match compare {
+        Some(_) => wgpu::FilterMode::Linear,
+        None => wgpu::FilterMode::Nearest,
+    };
+
+    wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        // If we do decide to render our depth texture, we need to use CompareFunction::LessEqual.
+        // This is due to how the sampler_comparison and textureSampleCompare() interact with the texture() function in GLSL.
+        compare,
+        lod_min_clamp: 0.0,
+        lod_max_clamp: 100.0,
+        ..Default::default()
+    }
+}
+
+fn sampler_binding_type(compare: Option<wgpu::CompareFunction>) -> wgpu::SamplerBindingType {
+    match compare {
+        Some(_) => wgpu::SamplerBindingType::Comparison,
+        None => wgpu::SamplerBindingType::Filtering,
+    }
+}
+
+// Layers of a texture array share one size, so all images are scaled to the largest of them
+fn array_layer_size(dimensions: &[(u32, u32)], max_dimension: u32) -> (u32, u32) {
+    let (width, height) = dimensions
+        .iter()
+        .fold((1, 1), |(w, h), &(x, y)| (w.max(x), h.max(y)));
+    (width.min(max_dimension), height.min(max_dimension))
+}
+
+fn decode_image(bytes: &[u8], flip_y: bool) -> Result<image::DynamicImage> {
+    let img = image::load_from_memory(bytes)?;
+    Ok(match flip_y {
+        true => img.flipv(),
+        false => img,
+    })
 }
 
 impl Texture {
@@ -17,8 +146,47 @@ impl Texture {
         bytes: &[u8],
         label: &str,
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_bytes_with_sampler(
+            device,
+            queue,
+            bytes,
+            label,
+            SamplerOptions::default(),
+            1,
+            false,
+        )
+    }
+
+    /// `max_anisotropy` is the limit of the adapter, see [`crate::RenderContext::max_sampler_anisotropy`].
+    /// `flip_y` mirrors the rows of the decoded image, for sources stored bottom row first.
+    pub fn from_bytes_with_sampler(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        sampler: SamplerOptions,
+        max_anisotropy: u16,
+        flip_y: bool,
+    ) -> Result<Self> {
+        let img = decode_image(bytes, flip_y)?;
+        Self::from_image_with_sampler(device, queue, &img, Some(label), sampler, max_anisotropy)
+    }
+
+    /// Placeholder image for missing textures. The pattern makes them obvious without hiding the shape of the model.
+    pub fn checkerboard_image() -> image::DynamicImage {
+        const SIZE: u32 = 64;
+        const CELL: u32 = 8;
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+            match (x / CELL + y / CELL) % 2 {
+                0 => image::Rgba([255, 0, 255, 255]),
+                _ => image::Rgba([32, 32, 32, 255]),
+            }
+        }))
+    }
+
+    /// Single texel of an sRGB `color`, e.g. for materials without a texture
+    pub fn solid_color_image(color: [u8; 4]) -> image::DynamicImage {
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)))
     }
 
     pub fn from_image(
@@ -27,6 +195,19 @@ impl Texture {
         img: &image::DynamicImage,
         label: Option<&str>,
     ) -> Result<Self> {
+        Self::from_image_with_sampler(device, queue, img, label, SamplerOptions::default(), 1)
+    }
+
+    /// `max_anisotropy` is the limit of the adapter, see [`crate::RenderContext::max_sampler_anisotropy`]
+    pub fn from_image_with_sampler(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        sampler: SamplerOptions,
+        max_anisotropy: u16,
+    ) -> Result<Self> {
+        sampler.validate(device.features())?;
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
 
@@ -62,22 +243,168 @@ impl Texture {
             size,
         );
 
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&sampler.descriptor(max_anisotropy));
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            compare: None,
+        })
+    }
+
+    /// Texture with [`Texture::checkerboard_image`]
+    pub fn create_checkerboard(device: &wgpu::Device, queue: &wgpu::Queue, label: &str) -> Self {
+        Self::from_image(device, queue, &Self::checkerboard_image(), Some(label))
+            .expect("Checkerboard image is always valid")
+    }
+
+    /// Texture with [`Texture::solid_color_image`]
+    pub fn solid_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+    ) -> Self {
+        Self::from_image(device, queue, &Self::solid_color_image(color), Some(label))
+            .expect("Solid color image is always valid")
+    }
+
+    /// Packs the images into the layers of a `D2Array` texture. Images of different sizes are
+    /// scaled to the size of the largest one. The view has to be bound with `TextureViewDimension::D2Array`.
+    pub fn array_from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[image::DynamicImage],
+        label: &str,
+        sampler: SamplerOptions,
+        max_anisotropy: u16,
+    ) -> Result<Self> {
+        sampler.validate(device.features())?;
+        let limits = device.limits();
+        if images.is_empty() || images.len() as u32 > limits.max_texture_array_layers {
+            bail!(
+                "{}: texture array needs between 1 and {} layers, got {}",
+                label,
+                limits.max_texture_array_layers,
+                images.len()
+            );
+        }
+
+        let dimensions: Vec<(u32, u32)> = images.iter().map(|img| img.dimensions()).collect();
+        let (width, height) = array_layer_size(&dimensions, limits.max_texture_dimension_2d);
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: images.len() as u32,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (layer, img) in images.iter().enumerate() {
+            let rgba = match img.dimensions() == (width, height) {
+                true => img.to_rgba8(),
+                false => image::imageops::resize(
+                    &img.to_rgba8(),
+                    width,
+                    height,
+                    image::imageops::FilterType::Triangle,
+                ),
+            };
+
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                &rgba,
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&sampler.descriptor(max_anisotropy));
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            compare: None,
+        })
+    }
+
+    /// Color texture that can be rendered to and then sampled by a later pass
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // Copies allow filling the target or reading back what a pass wrote
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-        })
+            compare: None,
+        }
     }
 
     pub fn create_depth_texture(
@@ -85,6 +412,42 @@ impl Texture {
         width: u32,
         height: u32,
         label: &str,
+    ) -> Self {
+        Self::create_depth_texture_impl(device, width, height, label, None)
+    }
+
+    /// Depth texture with a comparison sampler for shadow mapping. It has to be bound with
+    /// `TextureSampleType::Depth` and `SamplerBindingType::Comparison`.
+    pub fn create_depth_texture_with_comparison(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Self {
+        Self::create_depth_texture_impl(
+            device,
+            width,
+            height,
+            label,
+            Some(wgpu::CompareFunction::LessEqual),
+        )
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        self.compare.is_some()
+    }
+
+    /// Sampler binding type that matches the sampler of this texture.
+    pub fn sampler_binding_type(&self) -> wgpu::SamplerBindingType {
+        sampler_binding_type(self.compare)
+    }
+
+    fn create_depth_texture_impl(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: &str,
+        compare: Option<wgpu::CompareFunction>,
     ) -> Self {
         let size = wgpu::Extent3d {
             width: width.max(1),
@@ -99,7 +462,10 @@ impl Texture {
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             // Since we are rendering to this texture, we need to add the RENDER_ATTACHMENT flag to it.
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            // Copies allow reading the depth back, e.g. to check what a pass wrote.
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[Self::DEPTH_FORMAT],
         });
 
@@ -107,26 +473,145 @@ impl Texture {
 
         // We technically don't need a sampler for a depth texture,
         // but our Texture struct requires it, and we need one if we ever want to sample it.
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            // If we do decide to render our depth texture, we need to use CompareFunction::LessEqual.
-            // This is due to how the sampler_comparison and textureSampleCompare() interact with the texture() function in GLSL.
-            // compare: Some(wgpu::CompareFunction::LessEqual),
-            compare: None,
-            lod_min_clamp: 0.0,
-            lod_max_clamp: 100.0,
-            ..Default::default()
-        });
+        let sampler = device.create_sampler(&depth_sampler_descriptor(compare));
 
         Self {
             texture,
             view,
             sampler,
+            compare,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comparison_sampler_has_compare_function() {
+        let descriptor = depth_sampler_descriptor(Some(wgpu::CompareFunction::LessEqual));
+        assert_eq!(descriptor.compare, Some(wgpu::CompareFunction::LessEqual));
+        assert_eq!(
+            sampler_binding_type(descriptor.compare),
+            wgpu::SamplerBindingType::Comparison
+        );
+    }
+
+    #[test]
+    fn test_regular_depth_sampler() {
+        let descriptor = depth_sampler_descriptor(None);
+        assert_eq!(descriptor.compare, None);
+        assert_eq!(
+            sampler_binding_type(descriptor.compare),
+            wgpu::SamplerBindingType::Filtering
+        );
+    }
+
+    #[test]
+    fn test_flip_y_swaps_rows() {
+        let top = image::Rgba([255, 0, 0, 255]);
+        let bottom = image::Rgba([0, 0, 255, 255]);
+        let img = image::RgbaImage::from_fn(2, 2, |_, y| match y {
+            0 => top,
+            _ => bottom,
+        });
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageRgba8(img)
+            .write_to(
+                &mut std::io::Cursor::new(&mut bytes),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+
+        let kept = decode_image(&bytes, false).unwrap().to_rgba8();
+        assert_eq!(*kept.get_pixel(1, 0), top);
+        assert_eq!(*kept.get_pixel(1, 1), bottom);
+
+        let flipped = decode_image(&bytes, true).unwrap().to_rgba8();
+        assert_eq!(*flipped.get_pixel(0, 0), bottom);
+        assert_eq!(*flipped.get_pixel(1, 0), bottom);
+        assert_eq!(*flipped.get_pixel(0, 1), top);
+        assert_eq!(*flipped.get_pixel(1, 1), top);
+    }
+
+    #[test]
+    fn test_checkerboard_image() {
+        let img = Texture::checkerboard_image().to_rgba8();
+        assert_ne!(img.get_pixel(0, 0), img.get_pixel(8, 0));
+        assert_eq!(img.get_pixel(0, 0), img.get_pixel(8, 8));
+    }
+
+    #[test]
+    fn test_array_layer_size() {
+        assert_eq!(
+            array_layer_size(&[(512, 512), (1024, 256), (64, 64)], 4096),
+            (1024, 512)
+        );
+        assert_eq!(array_layer_size(&[(8192, 100)], 4096), (4096, 100));
+    }
+
+    #[test]
+    fn test_sampler_descriptor_address_mode() {
+        for address_mode in [
+            wgpu::AddressMode::Repeat,
+            wgpu::AddressMode::MirrorRepeat,
+            wgpu::AddressMode::ClampToEdge,
+        ] {
+            let descriptor = SamplerOptions {
+                address_mode,
+                anisotropy: 1,
+            }
+            .descriptor(SamplerOptions::MAX_ANISOTROPY);
+            assert_eq!(descriptor.address_mode_u, address_mode);
+            assert_eq!(descriptor.address_mode_v, address_mode);
+            assert_eq!(descriptor.address_mode_w, address_mode);
         }
+        assert_eq!(SamplerOptions::default(), SamplerOptions::REPEAT);
+    }
+
+    #[test]
+    fn test_sampler_address_mode_support() {
+        let no_features = wgpu::Features::empty();
+        let mirror = SamplerOptions {
+            address_mode: wgpu::AddressMode::MirrorRepeat,
+            anisotropy: 1,
+        };
+        assert!(mirror.validate(no_features).is_ok());
+        assert!(SamplerOptions::CLAMP.validate(no_features).is_ok());
+
+        let border = SamplerOptions {
+            address_mode: wgpu::AddressMode::ClampToBorder,
+            anisotropy: 1,
+        };
+        assert!(border.validate(no_features).is_err());
+        assert!(
+            border
+                .validate(wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_anisotropy_is_clamped_to_the_adapter() {
+        let options = SamplerOptions::REPEAT.with_anisotropy(16);
+        let descriptor = options.descriptor(4);
+        assert_eq!(descriptor.anisotropy_clamp, 4);
+        // wgpu rejects anisotropy without linear filtering
+        assert_eq!(descriptor.min_filter, wgpu::FilterMode::Linear);
+        assert_eq!(descriptor.mag_filter, wgpu::FilterMode::Linear);
+        assert_eq!(descriptor.mipmap_filter, wgpu::FilterMode::Linear);
+
+        assert_eq!(options.descriptor(16).anisotropy_clamp, 16);
+        let descriptor = options.descriptor(1);
+        assert_eq!(descriptor.anisotropy_clamp, 1);
+        assert_eq!(descriptor.min_filter, wgpu::FilterMode::Nearest);
+        assert_eq!(
+            SamplerOptions::REPEAT
+                .with_anisotropy(0)
+                .descriptor(16)
+                .anisotropy_clamp,
+            1
+        );
     }
 }
@@ -0,0 +1,235 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    Texture,
+    file_loader::{FileDataHandle, FileId, FileLoader, FileLoaderEndpoint},
+};
+
+pub enum AssetState<T> {
+    Loading,
+    Ready(Rc<T>),
+    Failed(String),
+}
+
+/// Shared handle to an asset that is built from a file once its bytes arrive.
+/// All clones observe the same state.
+pub struct AssetHandle<T> {
+    state: Rc<RefCell<AssetState<T>>>,
+}
+
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> AssetHandle<T> {
+    fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(AssetState::Loading)),
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        matches!(*self.state.borrow(), AssetState::Loading)
+    }
+
+    /// The asset if it was built successfully
+    pub fn get(&self) -> Option<Rc<T>> {
+        match &*self.state.borrow() {
+            AssetState::Ready(asset) => Some(asset.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn error(&self) -> Option<String> {
+        match &*self.state.borrow() {
+            AssetState::Failed(error) => Some(error.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Requests files through a [`FileLoader`] endpoint and turns their bytes into assets.
+/// The same path requested twice shares one handle.
+struct AssetLoader<T> {
+    endpoint: FileLoaderEndpoint,
+    handles: HashMap<FileId, AssetHandle<T>>,
+}
+
+impl<T> AssetLoader<T> {
+    fn new(file_loader: &mut FileLoader) -> Self {
+        Self {
+            endpoint: file_loader.make_endpoint(),
+            handles: HashMap::new(),
+        }
+    }
+
+    fn load(&mut self, path: &str) -> AssetHandle<T> {
+        if let Some(handle) = self
+            .endpoint
+            .loader
+            .id_by_path(path)
+            .and_then(|id| self.handles.get(&id))
+        {
+            return handle.clone();
+        }
+
+        self.endpoint.request(path);
+        let handle = AssetHandle::new();
+        match self.endpoint.loader.id_by_path(path) {
+            Some(id) => {
+                self.handles.insert(id, handle.clone());
+            }
+            None => {
+                log::error!("Failed to request \"{}\", it got no file id", path);
+                *handle.state.borrow_mut() =
+                    AssetState::Failed(format!("\"{}\" got no file id", path));
+            }
+        }
+        handle
+    }
+
+    // Builds assets for all files received since the last call
+    fn poll<F>(&mut self, mut build: F)
+    where
+        F: FnMut(&str, &FileDataHandle) -> anyhow::Result<T>,
+    {
+        while let Ok((file_id, file_handle)) = self.endpoint.receiver.try_recv() {
+            let Some(handle) = self.handles.get(&file_id) else {
+                continue;
+            };
+
+            if !handle.is_loading() {
+                continue;
+            }
+
+            let Some(path) = self.endpoint.loader.path_by_id(file_id) else {
+                log::error!("Received {:?} that has no path", file_id);
+                *handle.state.borrow_mut() =
+                    AssetState::Failed(format!("{:?} has no path", file_id));
+                continue;
+            };
+            let state = match build(&path, &file_handle) {
+                Ok(asset) => AssetState::Ready(Rc::new(asset)),
+                Err(err) => {
+                    log::error!("Failed to build \"{}\". Reason: \"{}\"", path, err);
+                    AssetState::Failed(err.to_string())
+                }
+            };
+            *handle.state.borrow_mut() = state;
+        }
+    }
+}
+
+/// Loads image files and uploads them as textures.
+///
+/// [`FileLoader::poll`] receives the bytes, then [`TextureLoader::poll`] creates the textures.
+pub struct TextureLoader {
+    loader: AssetLoader<Texture>,
+}
+
+impl TextureLoader {
+    pub fn new(file_loader: &mut FileLoader) -> Self {
+        Self {
+            loader: AssetLoader::new(file_loader),
+        }
+    }
+
+    pub fn load(&mut self, path: &str) -> AssetHandle<Texture> {
+        self.loader.load(path)
+    }
+
+    pub fn poll(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.loader
+            .poll(|path, file| Texture::from_bytes(device, queue, &file.data, path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+    use pollster::FutureExt;
+
+    fn png_bytes(width: u32, height: u32) -> Vec<u8> {
+        let img = image::RgbaImage::from_pixel(width, height, image::Rgba([255, 0, 0, 255]));
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    // Endpoints forward files from a spawned task, so they arrive shortly after `FileLoader::poll`
+    fn wait_for_files<T>(loader: &AssetLoader<T>) {
+        for _ in 0..1000 {
+            if !loader.endpoint.receiver.is_empty() {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("Files did not arrive");
+    }
+
+    // Decoding is the part of building a texture that does not need a GPU
+    fn decode(_path: &str, file: &FileDataHandle) -> anyhow::Result<image::DynamicImage> {
+        Ok(image::load_from_memory(&file.data)?)
+    }
+
+    #[test]
+    fn test_handle_becomes_ready() {
+        let mut file_loader = FileLoader::new();
+        file_loader.insert("embedded.png", png_bytes(4, 2));
+
+        let mut loader = AssetLoader::<image::DynamicImage>::new(&mut file_loader);
+        let handle = loader.load("embedded.png");
+        let same_handle = loader.load("embedded.png");
+        assert!(handle.is_loading());
+
+        file_loader.poll();
+        wait_for_files(&loader);
+        loader.poll(decode);
+
+        let image = handle.get().unwrap();
+        assert_eq!(image.dimensions(), (4, 2));
+        assert!(Rc::ptr_eq(&image, &same_handle.get().unwrap()));
+    }
+
+    #[test]
+    fn test_handle_reports_invalid_data() {
+        let mut file_loader = FileLoader::new();
+        file_loader.insert("broken.png", vec![1, 2, 3]);
+
+        let mut loader = AssetLoader::<image::DynamicImage>::new(&mut file_loader);
+        let handle = loader.load("broken.png");
+
+        file_loader.poll();
+        wait_for_files(&loader);
+        loader.poll(decode);
+
+        assert!(handle.get().is_none());
+        assert!(handle.error().is_some());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_texture_handle_becomes_ready() {
+        let ctx = crate::RenderContext::headless(1, 1)
+            .block_on()
+            .expect("No adapter to run the ignored GPU tests on");
+        let mut file_loader = FileLoader::new();
+        file_loader.insert("embedded.png", png_bytes(4, 2));
+
+        let mut loader = TextureLoader::new(&mut file_loader);
+        let handle = loader.load("embedded.png");
+
+        file_loader.poll();
+        wait_for_files(&loader.loader);
+        loader.poll(&ctx.device, &ctx.queue);
+
+        let texture = handle.get().unwrap();
+        let size = texture.texture.size();
+        assert_eq!((size.width, size.height), (4, 2));
+    }
+}
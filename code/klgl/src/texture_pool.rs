@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+
+use crate::Texture;
+
+// Free textures kept at most. A window drag goes through many sizes that never come back,
+// so keeping all of them would only hold on to memory.
+const MAX_FREE_TEXTURES: usize = 4;
+
+/// What makes textures interchangeable
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureKey {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+}
+
+impl TextureKey {
+    pub fn of(texture: &Texture) -> Self {
+        Self {
+            width: texture.texture.width(),
+            height: texture.texture.height(),
+            format: texture.texture.format(),
+            usage: texture.texture.usage(),
+        }
+    }
+}
+
+// Free items by key, the oldest is dropped first when there are too many
+struct Pool<T> {
+    free: VecDeque<(TextureKey, T)>,
+}
+
+impl<T> Pool<T> {
+    fn new() -> Self {
+        Self {
+            free: VecDeque::new(),
+        }
+    }
+
+    fn acquire(&mut self, key: &TextureKey, create: impl FnOnce() -> T) -> T {
+        match self.free.iter().position(|(free_key, _)| free_key == key) {
+            Some(index) => self.free.remove(index).unwrap().1,
+            None => create(),
+        }
+    }
+
+    fn release(&mut self, key: TextureKey, item: T) {
+        if self.free.len() == MAX_FREE_TEXTURES {
+            self.free.pop_front();
+        }
+        self.free.push_back((key, item));
+    }
+}
+
+/// Recycles the render and depth targets that are recreated on resize,
+/// so going back to a size that was used recently does not allocate.
+pub struct TexturePool {
+    pool: Pool<Texture>,
+}
+
+impl TexturePool {
+    pub fn new() -> Self {
+        Self { pool: Pool::new() }
+    }
+
+    /// Render target from [`Texture::create_render_target`], reused if one with the same key
+    /// was handed back
+    pub fn render_target(
+        &mut self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Texture {
+        self.acquire(device, width, height, format, label)
+    }
+
+    /// Depth texture from [`Texture::create_depth_texture`], reused if one with the same key
+    /// was handed back
+    pub fn depth_texture(
+        &mut self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Texture {
+        self.acquire(device, width, height, Texture::DEPTH_FORMAT, label)
+    }
+
+    /// Hands the texture back for reuse. Depth textures with a comparison sampler are dropped,
+    /// they are not interchangeable with the ones this pool creates.
+    pub fn recycle(&mut self, texture: Texture) {
+        if !texture.is_comparison() {
+            self.pool.release(TextureKey::of(&texture), texture);
+        }
+    }
+
+    /// Replaces `texture` with one of the new size from the pool and recycles the old one.
+    /// Keeps the texture as it is if the size did not change.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        texture: &mut Texture,
+        width: u32,
+        height: u32,
+        label: &str,
+    ) {
+        let key = TextureKey::of(texture);
+        if (key.width, key.height) == (width.max(1), height.max(1)) {
+            return;
+        }
+
+        let resized = self.acquire(device, width, height, key.format, label);
+        self.recycle(std::mem::replace(texture, resized));
+    }
+
+    fn acquire(
+        &mut self,
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Texture {
+        // Both kinds of textures are created with at least one pixel
+        let width = width.max(1);
+        let height = height.max(1);
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        let key = TextureKey {
+            width,
+            height,
+            format,
+            usage,
+        };
+        self.pool
+            .acquire(&key, || match format.is_depth_stencil_format() {
+                true => Texture::create_depth_texture(device, width, height, label),
+                false => Texture::create_render_target(device, width, height, format, label),
+            })
+    }
+}
+
+impl Default for TexturePool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(width: u32, height: u32) -> TextureKey {
+        TextureKey {
+            width,
+            height,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        }
+    }
+
+    #[test]
+    fn test_same_size_reuses_texture() {
+        let mut pool = Pool::new();
+        let mut created = 0;
+        let mut create = || {
+            created += 1;
+            created
+        };
+
+        let first = pool.acquire(&key(800, 600), &mut create);
+        pool.release(key(800, 600), first);
+        assert_eq!(pool.acquire(&key(800, 600), &mut create), first);
+
+        // A different size or format is a new texture
+        pool.release(key(800, 600), first);
+        assert_ne!(pool.acquire(&key(1024, 768), &mut create), first);
+        let depth = TextureKey {
+            format: Texture::DEPTH_FORMAT,
+            ..key(800, 600)
+        };
+        assert_ne!(pool.acquire(&depth, &mut create), first);
+        assert_eq!(created, 3);
+    }
+
+    #[test]
+    fn test_oldest_free_texture_is_dropped() {
+        let mut pool = Pool::new();
+        for size in 0..=MAX_FREE_TEXTURES as u32 {
+            pool.release(key(size, size), size);
+        }
+        assert_eq!(pool.free.len(), MAX_FREE_TEXTURES);
+        assert_eq!(pool.acquire(&key(0, 0), || 100), 100);
+        assert_eq!(pool.acquire(&key(1, 1), || 100), 1);
+    }
+}
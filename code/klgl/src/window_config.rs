@@ -0,0 +1,78 @@
+use winit::{dpi::LogicalSize, window::WindowAttributes};
+
+/// How the window looks when the app starts, see [`crate::App::set_window_config`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WindowConfig {
+    pub title: String,
+    /// Inner size in logical pixels, so the window looks the same on high DPI screens.
+    /// On web this is the size of the canvas.
+    pub width: u32,
+    pub height: u32,
+    pub resizable: bool,
+    /// Title bar and borders, ignored on web
+    pub decorations: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "klgl".to_string(),
+            width: 1280,
+            height: 720,
+            resizable: true,
+            decorations: true,
+        }
+    }
+}
+
+impl WindowConfig {
+    /// Default window with a custom title
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Attributes to create the window with
+    pub fn attributes(&self) -> WindowAttributes {
+        WindowAttributes::default()
+            .with_title(self.title.clone())
+            .with_inner_size(LogicalSize::new(self.width.max(1), self.height.max(1)))
+            .with_resizable(self.resizable)
+            .with_decorations(self.decorations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::dpi::Size;
+
+    #[test]
+    fn test_attributes_follow_the_config() {
+        let config = WindowConfig {
+            title: "Models".to_string(),
+            width: 640,
+            height: 480,
+            resizable: false,
+            decorations: false,
+        };
+        let attributes = config.attributes();
+        assert_eq!(attributes.title, "Models");
+        assert_eq!(
+            attributes.inner_size,
+            Some(Size::Logical(LogicalSize::new(640.0, 480.0)))
+        );
+        assert!(!attributes.resizable);
+        assert!(!attributes.decorations);
+
+        let attributes = WindowConfig::new("Lights").attributes();
+        assert_eq!(attributes.title, "Lights");
+        assert!(attributes.resizable && attributes.decorations);
+        assert_eq!(
+            attributes.inner_size,
+            Some(Size::Logical(LogicalSize::new(1280.0, 720.0)))
+        );
+    }
+}
@@ -8,5 +8,17 @@ pub const TUTORIAL_7_SHADER: &'static str = include_str!("../../../content/tutor
 pub const TUTORIAL_9_SHADER: &'static str = include_str!("../../../content/tutorial_9_shader.wgsl");
 pub const COLORED_VERTICES_SHADER: &'static str =
     include_str!("../../../content/colored_vertices_shader.wgsl");
+pub const LIGHT_MARKERS_SHADER: &'static str =
+    include_str!("../../../content/light_markers_shader.wgsl");
+pub const PARTICLES_SHADER: &'static str = include_str!("../../../content/particles_shader.wgsl");
+pub const BLOOM_SHADER: &'static str = include_str!("../../../content/bloom_shader.wgsl");
+pub const FXAA_SHADER: &'static str = include_str!("../../../content/fxaa_shader.wgsl");
+pub const GRID_SHADER: &'static str = include_str!("../../../content/grid_shader.wgsl");
+pub const TONEMAP_SHADER: &'static str = include_str!("../../../content/tonemap_shader.wgsl");
 pub const FULL_SCREEN_TEXTURE_SHADER: &'static str =
     include_str!("../../../content/display_depth_shader.wgsl");
+pub const DEMO_CAMERA_PATH: &'static str = include_str!("../../../content/demo_camera_path.json");
+pub const POINTS_SHADER: &'static str = include_str!("../../../content/points_shader.wgsl");
+pub const PORTAL_SHADER: &'static str = include_str!("../../../content/portal_shader.wgsl");
+pub const OVERDRAW_SHADER: &'static str = include_str!("../../../content/overdraw_shader.wgsl");
+pub const SKYBOX_SHADER: &'static str = include_str!("../../../content/skybox_shader.wgsl");
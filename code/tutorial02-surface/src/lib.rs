@@ -40,7 +40,7 @@ impl<'a> ApplicationHandler for App<'a> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let renderer = Renderer::new(
             event_loop
-                .create_window(Window::default_attributes())
+                .create_window(klgl::WindowConfig::new("Tutorial 2: Surface").attributes())
                 .unwrap(),
         )
         .block_on();
@@ -262,6 +262,13 @@ impl<'a> Renderer<'a> {
                 label: Some("Render Encoder"),
             });
 
+        // The cursor picks sRGB values, the surface encodes linear ones when it is sRGB
+        let c = self.clear_color;
+        let clear_color = match self.config.format.is_srgb() {
+            true => klgl::srgb_color(c.r, c.g, c.b, c.a),
+            false => c,
+        };
+
         {
             let _render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -269,7 +276,7 @@ impl<'a> Renderer<'a> {
                     view: &view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.clear_color),
+                        load: wgpu::LoadOp::Clear(clear_color),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
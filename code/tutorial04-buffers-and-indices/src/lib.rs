@@ -109,7 +109,7 @@ impl<'a> ApplicationHandler for App<'a> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let renderer = Renderer::new(
             event_loop
-                .create_window(Window::default_attributes())
+                .create_window(klgl::WindowConfig::new("Tutorial 4: Buffers and Indices").attributes())
                 .unwrap(),
         )
         .block_on();
@@ -446,6 +446,16 @@ impl<'a> Renderer<'a> {
                 label: Some("Render Encoder"),
             });
 
+        let clear_color = match self.config.format.is_srgb() {
+            true => klgl::srgb_color(0.1, 0.2, 0.3, 1.0),
+            false => wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+        };
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -455,12 +465,7 @@ impl<'a> Renderer<'a> {
                         view: &view,
                         resolve_target: None,
                         ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.1,
-                                g: 0.2,
-                                b: 0.3,
-                                a: 1.0,
-                            }),
+                            load: wgpu::LoadOp::Clear(clear_color),
                             store: wgpu::StoreOp::Store,
                         },
                     }),
@@ -121,7 +121,7 @@ impl<'a> ApplicationHandler for App<'a> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let renderer = Renderer::new(
             event_loop
-                .create_window(Window::default_attributes())
+                .create_window(klgl::WindowConfig::new("Tutorial 6: Uniforms").attributes())
                 .unwrap(),
         )
         .block_on();
@@ -140,6 +140,12 @@ impl<'a> ApplicationHandler for App<'a> {
             _ => {}
         }
     }
+
+    fn device_event(&mut self, _: &ActiveEventLoop, _: DeviceId, event: DeviceEvent) {
+        if let Some(s) = &mut self.renderer {
+            s.camera_controller.process_device_event(&event);
+        }
+    }
 }
 
 fn transform_model(vertices: &mut [ModelVertex]) {
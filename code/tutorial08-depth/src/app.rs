@@ -10,14 +10,25 @@ use winit::{
 
 use crate::models_draw_pass::ModelsDrawPass;
 use crate::{display_depth_draw_pass::DisplayDepthDrawPass, lines_draw_pass::LinesDrawPass};
-use klgl::{Camera, CameraController, CameraUniform, Rotator};
+use klgl::{Camera, CameraController, CameraPose, CameraUniform, Rotator};
 
-use cgmath::Deg;
+use cgmath::{Deg, Point3};
 use std::{iter, pin::Pin};
-use web_time::Instant;
+use web_time::{Duration, Instant};
+
+// Length of one simulation step. Also how far the animation advances per single step while it is paused.
+const SIMULATION_STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
 struct Renderer<'a> {
-    start_time: Instant,
+    animation_clock: klgl::SimClock,
+    // Animation runs on the animation clock, so it stops on pause while the camera still moves
+    animation_timestep: klgl::FixedTimestep,
+    animation_clock_elapsed: Duration,
+    animation_time: Duration,
+    camera_timestep: klgl::FixedTimestep,
+    last_update: Instant,
+    // Eye before the last camera step, rendering interpolates from it
+    prev_eye: Point3<f32>,
     window: Pin<Box<Window>>,
     surface: wgpu::Surface<'a>,
     device: wgpu::Device,
@@ -35,6 +46,8 @@ struct Renderer<'a> {
     display_depth_draw_pass: Option<DisplayDepthDrawPass>,
 
     camera: Camera,
+    // Pose at startup, T teleports back to it
+    initial_camera_pose: CameraPose,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
@@ -57,7 +70,7 @@ impl<'a> ApplicationHandler for App<'a> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let renderer = Renderer::new(
             event_loop
-                .create_window(Window::default_attributes())
+                .create_window(klgl::WindowConfig::new("Tutorial 8: Depth").attributes())
                 .unwrap(),
         )
         .block_on();
@@ -76,6 +89,12 @@ impl<'a> ApplicationHandler for App<'a> {
             _ => {}
         }
     }
+
+    fn device_event(&mut self, _: &ActiveEventLoop, _: DeviceId, event: DeviceEvent) {
+        if let Some(s) = &mut self.renderer {
+            s.camera_controller.process_device_event(&event);
+        }
+    }
 }
 
 impl<'a> Renderer<'a> {
@@ -251,7 +270,13 @@ impl<'a> Renderer<'a> {
         );
 
         Self {
-            start_time: Instant::now(),
+            animation_clock: klgl::SimClock::new(Instant::now()),
+            animation_timestep: klgl::FixedTimestep::new(SIMULATION_STEP),
+            animation_clock_elapsed: Duration::ZERO,
+            animation_time: Duration::ZERO,
+            camera_timestep: klgl::FixedTimestep::new(SIMULATION_STEP),
+            last_update: Instant::now(),
+            prev_eye: *camera.get_eye(),
             window: window_box,
             surface,
             device,
@@ -266,6 +291,7 @@ impl<'a> Renderer<'a> {
             lines_draw_pass,
             models_draw_pass,
             display_depth_draw_pass: None,
+            initial_camera_pose: camera.pose(),
             camera,
             camera_uniform,
             camera_buffer,
@@ -303,6 +329,32 @@ impl<'a> Renderer<'a> {
                 PhysicalKey::Code(KeyCode::KeyO) => {
                     self.show_depth = event.state == ElementState::Pressed;
                 }
+                PhysicalKey::Code(KeyCode::Home)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.camera.face_towards(Point3::new(0.0, 0.0, 0.0));
+                }
+                PhysicalKey::Code(KeyCode::KeyT)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.camera.set_pose(self.initial_camera_pose);
+                    self.prev_eye = self.initial_camera_pose.eye;
+                }
+                PhysicalKey::Code(KeyCode::Space)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let now = Instant::now();
+                    if self.animation_clock.is_paused() {
+                        self.animation_clock.resume(now);
+                    } else {
+                        self.animation_clock.pause(now);
+                    }
+                }
+                PhysicalKey::Code(KeyCode::Period)
+                    if event.state == ElementState::Pressed && self.animation_clock.is_paused() =>
+                {
+                    self.animation_clock.step(SIMULATION_STEP);
+                }
                 _ => {}
             },
             WindowEvent::Resized(physical_size) => {
@@ -398,14 +450,35 @@ impl<'a> Renderer<'a> {
             );
         }
 
-        let dur_since_start = now.duration_since(self.start_time);
+        let clock_elapsed = self.animation_clock.elapsed(now);
+        let animation_steps = self
+            .animation_timestep
+            .advance(clock_elapsed.saturating_sub(self.animation_clock_elapsed));
+        self.animation_clock_elapsed = clock_elapsed;
+        self.animation_time += SIMULATION_STEP * animation_steps.count;
+
+        // Render between the last two steps
+        let dur_since_start = self.animation_time.saturating_sub(SIMULATION_STEP)
+            + SIMULATION_STEP.mul_f32(animation_steps.alpha);
         self.models_draw_pass.set_active_texture(
             (((dur_since_start.as_secs_f64() / 3.0) as u32)
                 % (self.models_draw_pass.textures.len() as u32)) as u32,
         );
 
-        self.camera_controller.update_camera(&mut self.camera);
+        let camera_steps = self
+            .camera_timestep
+            .advance(now.saturating_duration_since(self.last_update));
+        self.last_update = now;
+        for _ in 0..camera_steps.count {
+            self.prev_eye = *self.camera.get_eye();
+            self.camera_controller.update_camera(&mut self.camera);
+        }
+
+        let eye = *self.camera.get_eye();
+        self.camera
+            .set_eye(self.prev_eye + (eye - self.prev_eye) * camera_steps.alpha);
         self.camera_uniform.update_view_proj(&self.camera);
+        self.camera.set_eye(eye);
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
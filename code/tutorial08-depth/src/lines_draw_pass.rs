@@ -73,7 +73,8 @@ impl LinesDrawPass {
                 topology: wgpu::PrimitiveTopology::LineList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
+                // Lines have no faces to cull
+                cull_mode: None,
                 polygon_mode: wgpu::PolygonMode::Fill, // others require Features::NON_FILL_POLYGON_MODE
                 unclipped_depth: false,                // Requires Features::DEPTH_CLIP_CONTROL
                 conservative: false, // Requires Features::CONSERVATIVE_RASTERIZATION
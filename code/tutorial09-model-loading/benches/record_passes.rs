@@ -0,0 +1,136 @@
+//! CPU time it takes to record the models and lines passes into a command encoder.
+//! Nothing is submitted, so GPU time is not part of it. Criterion compares every run
+//! with the previous one, so a regression in command recording shows up as a change.
+//! Skipped when the machine has no adapter.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use klgl::{CameraUniform, DrawPass};
+use pollster::FutureExt;
+use tutorial09_model_loading::bench_api::{LightManager, LinesDrawPass, ModelsDrawPass};
+use wgpu::util::DeviceExt;
+
+const WIDTH: u32 = 1280;
+const HEIGHT: u32 = 720;
+const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// Sponza has many textures, reading and decoding them takes a while
+const LOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+fn record_passes(c: &mut Criterion) {
+    let ctx = match klgl::RenderContext::headless(WIDTH, HEIGHT).block_on() {
+        Ok(ctx) => Rc::new(RefCell::new(ctx)),
+        Err(err) => {
+            eprintln!("Skipping the draw pass benches: {err:#}");
+            return;
+        }
+    };
+
+    let (camera_bind_group_layout, camera_bind_group) = {
+        let device = &ctx.borrow().device;
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("camera_bind_group_layout"),
+        });
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("camera_bind_group"),
+        });
+        (layout, bind_group)
+    };
+
+    let depth_stencil_state = Some(wgpu::DepthStencilState {
+        format: klgl::Texture::DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::Less,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    });
+
+    let mut file_loader = klgl::file_loader::FileLoader::new();
+    let lights = LightManager::new(ctx.clone());
+    let mut models = ModelsDrawPass::new(
+        &mut file_loader,
+        ctx.clone(),
+        &camera_bind_group_layout,
+        &camera_bind_group,
+        &lights,
+        COLOR_FORMAT,
+        depth_stencil_state.clone(),
+    )
+    .block_on();
+    let lines = LinesDrawPass::new(
+        ctx.clone(),
+        &camera_bind_group_layout,
+        &camera_bind_group,
+        COLOR_FORMAT,
+        depth_stencil_state,
+    );
+
+    let load_start = Instant::now();
+    while !models.is_loaded() {
+        if load_start.elapsed() > LOAD_TIMEOUT {
+            eprintln!("Skipping the draw pass benches: the model did not load");
+            return;
+        }
+        file_loader.poll();
+        models.update();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    // Recording time depends on how often the material changes between meshes
+    if let Some(model) = models.model() {
+        let (switches, load_order_switches) = model.bind_group_switches();
+        eprintln!("Material bind group switches: {switches}, {load_order_switches} in load order");
+    }
+
+    let ctx = ctx.borrow();
+    let color =
+        klgl::Texture::create_render_target(&ctx.device, WIDTH, HEIGHT, COLOR_FORMAT, "color");
+    let depth = klgl::Texture::create_depth_texture(&ctx.device, WIDTH, HEIGHT, "depth");
+    let targets = klgl::PassTargets {
+        color: &color.view,
+        depth: Some(&depth.view),
+        surface: &color.view,
+        viewport: None,
+        camera: None,
+    };
+
+    let record = |pass: &dyn DrawPass| {
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Bench Encoder"),
+            });
+        pass.record(&mut encoder, &targets);
+        encoder.finish()
+    };
+
+    c.bench_function("record models pass", |b| b.iter(|| record(&models)));
+    c.bench_function("record lines pass", |b| b.iter(|| record(&lines)));
+}
+
+criterion_group!(benches, record_passes);
+criterion_main!(benches);
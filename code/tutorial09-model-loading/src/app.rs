@@ -1,96 +1,236 @@
 use pollster::FutureExt;
 use wgpu::util::DeviceExt;
 use winit::{
-    application::ApplicationHandler,
     event::*,
-    event_loop::ActiveEventLoop,
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowId},
+    event_loop::{ActiveEventLoop, EventLoopProxy},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
 };
 
-use crate::models_draw_pass::ModelsDrawPass;
+use crate::bloom_pass::BloomPass;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::frame_recorder::FrameRecorder;
+use crate::fxaa_pass::FxaaPass;
+use crate::light_markers_draw_pass::LightMarkersDrawPass;
+use crate::lights::{LightManager, PointLight};
+use crate::material_editor::MaterialEditor;
+use crate::model::ModelFormat;
+use crate::models_draw_pass::{ModelsDrawPass, next_cull_mode};
+use crate::overdraw_pass::OverdrawPass;
+use crate::particles::{EmitParams, ParticleSystem};
+use crate::points_draw_pass::{Point, PointsDrawPass};
+use crate::portal_pass::PortalPass;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::scene::Scene;
+use crate::shader_grid_pass::{GridPlane, ShaderGridPass};
+use crate::shadow_draw_pass::ShadowDrawPass;
+use crate::skybox_draw_pass::SkyboxDrawPass;
+use crate::tonemap_pass::TonemapPass;
 use crate::{display_depth_draw_pass::DisplayDepthDrawPass, lines_draw_pass::LinesDrawPass};
-use klgl::{Camera, CameraController, CameraUniform, Rotator};
+use klgl::{Camera, CameraController, CameraPose, CameraUniform, Rotator};
 
-use cgmath::Deg;
+use cgmath::{Deg, InnerSpace, Point3, Vector3};
 use std::{cell::RefCell, iter, rc::Rc};
 use web_time::Instant;
 
-struct Renderer {
+const LIGHT_DIRECTION: Vector3<f32> = Vector3::new(0.3, 0.2, -1.0);
+// Width of the grid lines in logical pixels
+const LINE_WIDTH: f32 = 1.5;
+// Constant depth bias of the lines changes by this on every key press
+const LINE_DEPTH_BIAS_STEP: i32 = 1;
+// Exposure is multiplied or divided by this on every key press
+const EXPOSURE_STEP: f32 = 1.25;
+const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// Spacing of the shader grid, or its fade distance with Alt, is multiplied or divided by
+// this on every key press
+const SHADER_GRID_STEP: f32 = 2.0;
+// Aspect of the letterbox toggled with a key
+const LETTERBOX_ASPECT: f32 = 16.0 / 9.0;
+// Diameter in logical pixels of the points at the instance origins shown with the bounds
+const ORIGIN_POINT_SIZE: f32 = 10.0;
+// Opacity of the depth overlay when it is see-through
+const DEPTH_OVERLAY_OPACITY: f32 = 0.5;
+// Normal lines are this part of the size of the model, so they fit any model
+const NORMAL_LENGTH_FRACTION: f32 = 0.01;
+// Color of the lines of the frozen culling frustum
+const FROZEN_FRUSTUM_COLOR: [f32; 3] = [1.0, 0.0, 1.0];
+// Lights that circle the scene. Lights of a scene file come after them and stay in place.
+const ORBITING_LIGHT_COLORS: [[f32; 3]; 2] = [[1.0, 0.6, 0.2], [0.2, 0.5, 1.0]];
+const MAX_PARTICLES: u32 = 8192;
+// Particles spawned by one press of the burst key
+const PARTICLE_BURST: u32 = 1024;
+// Long hitches would make particles jump through the scene
+const MAX_PARTICLE_DT: f32 = 0.1;
+// Downloading all textures of a large model at once can run a browser tab out of memory
+#[cfg(target_arch = "wasm32")]
+const WASM_INFLIGHT_BYTE_BUDGET: usize = 64 * 1024 * 1024;
+// Written by the dump key
+#[cfg(not(target_arch = "wasm32"))]
+const MODEL_DUMP_PATH: &str = "dump.obj";
+// Frames recorded with F9 go here, played back at this rate
+#[cfg(not(target_arch = "wasm32"))]
+const RECORDING_DIR: &str = "recording";
+#[cfg(not(target_arch = "wasm32"))]
+const RECORDING_FPS: u32 = 60;
+
+// Background of the portal, so it stands out from the main view
+const PORTAL_CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.02,
+    g: 0.03,
+    b: 0.08,
+    a: 1.0,
+};
+
+// The second split screen view looks at the origin from here, across from the start pose
+const SPLIT_VIEW_EYE: Point3<f32> = Point3::new(-19.0, 5.0, 23.0);
+
+/// Format of the offscreen scene color. Falls back to LDR where float targets are not renderable (WebGL2).
+fn scene_color_format(ctx: &klgl::RenderContext) -> wgpu::TextureFormat {
+    let features = ctx.adapter.get_texture_format_features(HDR_FORMAT);
+    if features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+    {
+        HDR_FORMAT
+    } else {
+        wgpu::TextureFormat::Rgba8Unorm
+    }
+}
+
+// Camera uniform of one split screen view
+struct ViewCamera {
+    uniform: CameraUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ViewCamera {
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> Self {
+        let uniform = CameraUniform::new();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("View Camera Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("view_camera_bind_group"),
+        });
+
+        Self {
+            uniform,
+            buffer,
+            bind_group,
+        }
+    }
+}
+
+pub struct Renderer {
     file_loader: klgl::file_loader::FileLoader,
     render_context: Rc<RefCell<klgl::RenderContext>>,
 
     clear_color: wgpu::Color,
-    surface_configured: bool,
     frame_counter: klgl::FpsCounter,
     last_stat_print: Instant,
 
     depth_texture: klgl::Texture,
-    lines_draw_pass: LinesDrawPass,
-    models_draw_pass: ModelsDrawPass,
-    display_depth_draw_pass: Option<DisplayDepthDrawPass>,
+    hdr_texture: klgl::Texture,
+    // Tonemapped image read by FXAA
+    ldr_texture: klgl::Texture,
+    passes: klgl::PassList,
+    models_draw_pass: Rc<RefCell<ModelsDrawPass>>,
+    display_depth_draw_pass: Option<Rc<RefCell<DisplayDepthDrawPass>>>,
+    shadow_draw_pass: Option<Rc<RefCell<ShadowDrawPass>>>,
+    lines_draw_pass: Rc<RefCell<LinesDrawPass>>,
+    points_draw_pass: Rc<RefCell<PointsDrawPass>>,
+    // Sky behind the models in place of the clear color, toggled with F5
+    skybox_draw_pass: Rc<RefCell<SkyboxDrawPass>>,
+    // Toggled with J, Alt+J cycles its plane. Page Up and Page Down scale the spacing of
+    // its lines, with Alt the distance it fades out at.
+    shader_grid_pass: Rc<RefCell<ShaderGridPass>>,
+    bloom_pass: Rc<RefCell<BloomPass>>,
+    tonemap_pass: Rc<RefCell<TonemapPass>>,
+    fxaa_pass: Rc<RefCell<FxaaPass>>,
+    // Offscreen copy of the scene in a corner, toggled with F2
+    portal_pass: Rc<RefCell<PortalPass>>,
+    // Heat map of the fragments per pixel in place of the image, toggled with F4
+    overdraw_pass: Option<Rc<RefCell<OverdrawPass>>>,
+
+    light_markers_draw_pass: Rc<RefCell<LightMarkersDrawPass>>,
+    lights: Rc<RefCell<LightManager>>,
+    // Seconds the scene was animated for
+    scene_time: f32,
+    // None where compute shaders are not supported
+    particle_system: Option<Rc<RefCell<ParticleSystem>>>,
+    last_update: Instant,
+    particle_dt: f32,
 
     camera: Camera,
+    // Pose at startup, T teleports back to it
+    initial_camera_pose: CameraPose,
+    // Fly-through toggled with M, loops until toggled again. None if its json is invalid.
+    demo_path: Option<klgl::CameraPath>,
+    demo_time: Option<f32>,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
     camera_controller: CameraController,
-
-    show_depth: bool,
-}
-
-pub struct App {
-    renderer: Option<Renderer>,
+    // Toggled with F3. The first view follows the main camera, the second one stays in place.
+    split_screen: bool,
+    split_view: klgl::SplitView,
+    view_cameras: Vec<ViewCamera>,
+    uploader: klgl::FrameUploader,
+    texture_pool: klgl::TexturePool,
+    #[cfg(not(target_arch = "wasm32"))]
+    frame_recorder: FrameRecorder,
+    // Alt with [ ] selects a material, Alt with \ the value and Alt with + - changes it.
+    // None until used, the title shows the material while it is edited.
+    material_editor: Option<MaterialEditor>,
+    // The only mesh drawn, cycled with Alt with , and . and cleared with Alt with /
+    solo_mesh: Option<usize>,
+    modifiers: ModifiersState,
+    // Captured with K. Culls in place of the cameras, so flying around shows what it skips.
+    // Drawn as lines from its corners.
+    frozen_frustum: Option<(klgl::Frustum, [Point3<f32>; 8])>,
 }
 
-impl App {
-    pub async fn new() -> Self {
-        Self { renderer: None }
-    }
-}
-
-impl<'a> ApplicationHandler for App {
-    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let renderer = Renderer::new(
-            event_loop
-                .create_window(Window::default_attributes())
-                .unwrap(),
-        )
-        .block_on();
-
-        self.renderer = Some(renderer);
-    }
-
-    fn window_event(
-        &mut self,
-        event_loop: &ActiveEventLoop,
-        window_id: WindowId,
-        event: WindowEvent,
-    ) {
-        match &mut self.renderer {
-            Some(s) => s.window_event(event_loop, window_id, event),
-            _ => {}
-        }
-    }
-}
-
-impl Renderer {
-    async fn new(w: Window) -> Self {
-        let render_context = Rc::new(RefCell::new(klgl::RenderContext::new(w).await));
-
-        let size = render_context.borrow().window.inner_size();
-        let depth_texture = klgl::Texture::create_depth_texture(
+impl klgl::Renderer for Renderer {
+    fn new(render_context: Rc<RefCell<klgl::RenderContext>>) -> Self {
+        let size = render_context.borrow().window().inner_size();
+        // Render targets are recreated on every resize, the pool reuses recent ones
+        let mut texture_pool = klgl::TexturePool::new();
+        let depth_texture = texture_pool.depth_texture(
             &render_context.borrow().device,
             size.width,
             size.height,
             "depth_texture",
         );
 
+        let color_format = scene_color_format(&render_context.borrow());
+        log::info!("Scene color format: {:?}", color_format);
+        let hdr_texture = texture_pool.render_target(
+            &render_context.borrow().device,
+            size.width,
+            size.height,
+            color_format,
+            "hdr_texture",
+        );
+        let ldr_texture = texture_pool.render_target(
+            &render_context.borrow().device,
+            size.width,
+            size.height,
+            render_context.borrow().config.format,
+            "ldr_texture",
+        );
+
         let camera_bind_group_layout = render_context.borrow().device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    // Fragment shaders read the eye position for highlights
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -144,6 +284,33 @@ impl Renderer {
                     label: Some("camera_bind_group"),
                 });
 
+        let split_view = klgl::SplitView::new(
+            vec![
+                Camera::new(
+                    *camera.get_eye(),
+                    *camera.get_rotator(),
+                    1.0,
+                    90.0,
+                    0.1,
+                    1000.0,
+                ),
+                Camera::new(
+                    SPLIT_VIEW_EYE,
+                    Rotator::from_direction(Point3::new(0.0, 0.0, 0.0) - SPLIT_VIEW_EYE),
+                    1.0,
+                    90.0,
+                    0.1,
+                    1000.0,
+                ),
+            ],
+            klgl::SplitLayout::Horizontal,
+        );
+        let view_cameras = split_view
+            .cameras
+            .iter()
+            .map(|_| ViewCamera::new(&render_context.borrow().device, &camera_bind_group_layout))
+            .collect();
+
         let depth_stencil_state = Some(wgpu::DepthStencilState {
             format: klgl::Texture::DEPTH_FORMAT,
             depth_write_enabled: true,
@@ -155,107 +322,549 @@ impl Renderer {
         });
 
         let mut file_loader = klgl::file_loader::FileLoader::new();
+        #[cfg(target_arch = "wasm32")]
+        file_loader.set_inflight_byte_budget(Some(WASM_INFLIGHT_BYTE_BUDGET));
+
+        let lights = Rc::new(RefCell::new(LightManager::new(render_context.clone())));
+        for color in ORBITING_LIGHT_COLORS {
+            lights.borrow_mut().add_point_light(PointLight {
+                position: [0.0, 0.0, 0.0],
+                radius: 150.0,
+                color,
+                intensity: 2.0,
+            });
+        }
+
+        let models_draw_pass = Rc::new(RefCell::new(
+            ModelsDrawPass::new(
+                &mut file_loader,
+                render_context.clone(),
+                &camera_bind_group_layout,
+                &camera_bind_group,
+                &lights.borrow(),
+                color_format,
+                depth_stencil_state.clone(),
+            )
+            .block_on(),
+        ));
 
-        let models_draw_pass = ModelsDrawPass::new(
-            &mut file_loader,
+        let lines_draw_pass = Rc::new(RefCell::new(LinesDrawPass::new(
             render_context.clone(),
             &camera_bind_group_layout,
-            depth_stencil_state.clone(),
-        )
-        .await;
+            &camera_bind_group,
+            color_format,
+            depth_stencil_state,
+        )));
+        // Thin lines are hard to see on high-DPI screens
+        let scale_factor = render_context.borrow().window().scale_factor() as f32;
+        lines_draw_pass
+            .borrow_mut()
+            .set_line_width(LINE_WIDTH * scale_factor);
 
-        let lines_draw_pass = LinesDrawPass::new(
+        // Replaces the lines when enabled with a key
+        let shader_grid_pass = Rc::new(RefCell::new(ShaderGridPass::new(
             render_context.clone(),
             &camera_bind_group_layout,
-            depth_stencil_state,
-        );
+            &camera_bind_group,
+            color_format,
+        )));
 
-        Self {
+        let mut passes = klgl::PassList::new();
+        passes.push(Rc::new(RefCell::new(klgl::ClearPass::new(
+            wgpu::Color::BLACK,
+        ))));
+        passes.push(models_draw_pass.clone());
+
+        // Enabled with a key. Fills what the opaque models left of the background,
+        // before anything is blended over it.
+        let skybox_draw_pass = Rc::new(RefCell::new(SkyboxDrawPass::new(
+            render_context.clone(),
+            &camera_bind_group_layout,
+            &camera_bind_group,
+            color_format,
+            camera.coordinate_system().up(),
+        )));
+        passes.push(skybox_draw_pass.clone());
+        // Lines don't write depth, so they are tested against the models drawn before them
+        passes.push(lines_draw_pass.clone());
+
+        let points_draw_pass = Rc::new(RefCell::new(PointsDrawPass::new(
+            render_context.clone(),
+            &camera_bind_group_layout,
+            &camera_bind_group,
+            color_format,
+        )));
+        passes.push(points_draw_pass.clone());
+
+        let light_markers_draw_pass = Rc::new(RefCell::new(LightMarkersDrawPass::new(
+            render_context.clone(),
+            lights.clone(),
+            &camera_bind_group_layout,
+            &camera_bind_group,
+            color_format,
+        )));
+        passes.push(light_markers_draw_pass.clone());
+
+        let particle_system = match ParticleSystem::is_supported(&render_context.borrow()) {
+            true => {
+                let particle_system = Rc::new(RefCell::new(ParticleSystem::new(
+                    render_context.clone(),
+                    &camera_bind_group_layout,
+                    &camera_bind_group,
+                    color_format,
+                    MAX_PARTICLES,
+                )));
+                // Particles are blended over the opaque scene and the sky
+                passes.insert_after(SkyboxDrawPass::NAME, particle_system.clone());
+                Some(particle_system)
+            }
+            false => {
+                log::warn!("Compute shaders are not supported, particles are disabled");
+                None
+            }
+        };
+
+        // Enabled with a key
+        let bloom_pass = Rc::new(RefCell::new(BloomPass::new(
+            render_context.clone(),
+            &hdr_texture,
+        )));
+
+        let tonemap_pass = Rc::new(RefCell::new(TonemapPass::new(
+            render_context.clone(),
+            &hdr_texture,
+        )));
+        passes.push(tonemap_pass.clone());
+
+        // Enabled with a key
+        let fxaa_pass = Rc::new(RefCell::new(FxaaPass::new(
+            render_context.clone(),
+            &ldr_texture,
+        )));
+
+        // Same camera as the main view, rendered offscreen without the effects
+        let mut portal_scene = klgl::PassList::new();
+        portal_scene.push(Rc::new(RefCell::new(klgl::ClearPass::new(
+            PORTAL_CLEAR_COLOR,
+        ))));
+        portal_scene.push(models_draw_pass.clone());
+        portal_scene.push(lines_draw_pass.clone());
+        let portal_pass = Rc::new(RefCell::new(PortalPass::new(
+            render_context.clone(),
+            portal_scene,
+            color_format,
+        )));
+
+        #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+        let mut renderer = Self {
             render_context,
             depth_texture,
+            hdr_texture,
+            ldr_texture,
             clear_color: wgpu::Color::BLACK,
-            surface_configured: false,
             frame_counter: klgl::FpsCounter::new(),
             last_stat_print: Instant::now(),
-            lines_draw_pass,
+            passes,
             models_draw_pass,
             display_depth_draw_pass: None,
+            shadow_draw_pass: None,
+            lines_draw_pass,
+            points_draw_pass,
+            skybox_draw_pass,
+            shader_grid_pass,
+            bloom_pass,
+            tonemap_pass,
+            fxaa_pass,
+            portal_pass,
+            overdraw_pass: None,
+            light_markers_draw_pass,
+            lights,
+            scene_time: 0.0,
+            particle_system,
+            last_update: Instant::now(),
+            particle_dt: 0.0,
+            initial_camera_pose: camera.pose(),
+            demo_path: klgl::CameraPath::from_json(tutorial_embedded_content::DEMO_CAMERA_PATH)
+                .inspect_err(|err| {
+                    log::error!(
+                        "Invalid demo camera path, demo mode is disabled. Error: {:#}",
+                        err
+                    )
+                })
+                .ok(),
+            demo_time: None,
             camera,
             camera_uniform,
             camera_buffer,
-            camera_bind_group,
             camera_controller: CameraController::new(0.2, 0.2),
-            show_depth: false,
+            split_screen: false,
+            split_view,
+            view_cameras,
+            uploader: klgl::FrameUploader::new(),
+            texture_pool,
+            #[cfg(not(target_arch = "wasm32"))]
+            frame_recorder: FrameRecorder::new(RECORDING_FPS),
             file_loader,
+            material_editor: None,
+            solo_mesh: None,
+            modifiers: ModifiersState::empty(),
+            frozen_frustum: None,
+        };
+        // A model given on the command line is read from the disk right away instead of the
+        // default one from the assets
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(path) = std::env::args_os().nth(1) {
+            renderer.load_dropped_file(std::path::Path::new(&path));
         }
+        renderer.update_title();
+        renderer
     }
 
     #[allow(unused_variables)]
-    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
-        if self.camera_controller.process_events(&event) {
-            return;
+    fn window_event(&mut self, _: &ActiveEventLoop, event: &WindowEvent) -> bool {
+        if self.camera_controller.process_events(event) {
+            return true;
         }
 
         match event {
-            WindowEvent::CloseRequested
-            | WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state: ElementState::Pressed,
-                        physical_key: PhysicalKey::Code(KeyCode::Escape),
-                        ..
-                    },
-                ..
-            } => {
-                println!("The close button was pressed; stopping");
-                event_loop.exit()
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
             }
             WindowEvent::KeyboardInput { event, .. } => match event.physical_key {
-                PhysicalKey::Code(KeyCode::Escape) => {
-                    println!("The close button was pressed; stopping");
-                    event_loop.exit()
+                PhysicalKey::Code(KeyCode::BracketLeft | KeyCode::BracketRight)
+                    if event.state == ElementState::Pressed && self.modifiers.alt_key() =>
+                {
+                    let offset = match event.physical_key {
+                        PhysicalKey::Code(KeyCode::BracketLeft) => -1,
+                        _ => 1,
+                    };
+                    self.edit_material(offset, 0);
+                }
+                PhysicalKey::Code(KeyCode::Backslash)
+                    if event.state == ElementState::Pressed
+                        && !event.repeat
+                        && self.modifiers.alt_key() =>
+                {
+                    let editor = self.material_editor.get_or_insert_default();
+                    editor.field = editor.field.next();
+                    self.update_title();
+                }
+                PhysicalKey::Code(
+                    code @ (KeyCode::Equal
+                    | KeyCode::NumpadAdd
+                    | KeyCode::Minus
+                    | KeyCode::NumpadSubtract),
+                ) if event.state == ElementState::Pressed && self.modifiers.alt_key() => {
+                    let steps = match code {
+                        KeyCode::Equal | KeyCode::NumpadAdd => 1,
+                        _ => -1,
+                    };
+                    self.edit_material(0, steps);
+                }
+                PhysicalKey::Code(KeyCode::KeyO)
+                    if event.state == ElementState::Pressed
+                        && !event.repeat
+                        && self.modifiers.alt_key() =>
+                {
+                    let draw_pass = self.display_depth_draw_pass();
+                    let mut draw_pass = draw_pass.borrow_mut();
+                    let opacity = match draw_pass.opacity() < 1.0 {
+                        true => 1.0,
+                        false => DEPTH_OVERLAY_OPACITY,
+                    };
+                    draw_pass.set_opacity(&self.render_context.borrow().queue, opacity);
+                    log::info!("Depth overlay opacity: {}", opacity);
                 }
                 PhysicalKey::Code(KeyCode::KeyO) => {
-                    self.show_depth = event.state == ElementState::Pressed;
+                    self.show_depth(event.state == ElementState::Pressed);
                 }
-                _ => {}
-            },
-            WindowEvent::Resized(physical_size) => {
-                log::info!("physical_size: {physical_size:?}");
-                self.surface_configured = true;
-                self.resize(physical_size.width, physical_size.height);
-            }
-            WindowEvent::RedrawRequested => {
-                // This tells winit that we want another frame after this one
-                self.render_context.borrow().window.request_redraw();
-
-                if !self.surface_configured {
-                    return;
+                PhysicalKey::Code(KeyCode::Home)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.camera.face_towards(Point3::new(0.0, 0.0, 0.0));
                 }
-
-                self.update();
-                match self.render() {
-                    Ok(_) => {}
-                    // Reconfigure the surface if it's lost or outdated
-                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let (w, h) = {
-                            let ctx = self.render_context.borrow();
-                            (ctx.config.width, ctx.config.height)
+                PhysicalKey::Code(KeyCode::KeyT)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.camera.set_pose(self.initial_camera_pose);
+                }
+                PhysicalKey::Code(KeyCode::KeyM)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    if self.demo_path.is_none() {
+                        log::warn!("Demo mode is disabled, its camera path failed to load");
+                    } else {
+                        self.demo_time = match self.demo_time {
+                            Some(_) => None,
+                            None => Some(0.0),
                         };
-                        self.resize(w, h)
+                        log::info!("Demo mode: {}", self.demo_time.is_some());
                     }
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory | wgpu::SurfaceError::Other) => {
-                        log::error!("OutOfMemory");
-                        event_loop.exit();
+                }
+                PhysicalKey::Code(KeyCode::Equal | KeyCode::NumpadAdd)
+                    if event.state == ElementState::Pressed =>
+                {
+                    let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+                    let n = models_draw_pass.instances_per_row() + 1;
+                    let ctx = self.render_context.borrow();
+                    models_draw_pass.set_instance_grid(&ctx.device, n);
+                }
+                PhysicalKey::Code(KeyCode::Minus | KeyCode::NumpadSubtract)
+                    if event.state == ElementState::Pressed =>
+                {
+                    let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+                    let n = models_draw_pass.instances_per_row().saturating_sub(1);
+                    let ctx = self.render_context.borrow();
+                    models_draw_pass.set_instance_grid(&ctx.device, n);
+                }
+                PhysicalKey::Code(KeyCode::KeyH)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let enabled = !self.passes.contains(ShadowDrawPass::NAME);
+                    log::info!("Shadows: {}", enabled);
+                    self.set_shadows(enabled);
+                }
+                PhysicalKey::Code(KeyCode::KeyL)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let show = !self.passes.contains(LightMarkersDrawPass::NAME);
+                    self.set_show_light_markers(show);
+                }
+                PhysicalKey::Code(KeyCode::KeyU)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let enabled = !self.passes.contains(BloomPass::NAME);
+                    self.set_bloom(enabled);
+                }
+                PhysicalKey::Code(KeyCode::KeyF)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let enabled = !self.passes.contains(FxaaPass::NAME);
+                    self.set_fxaa(enabled);
+                }
+                PhysicalKey::Code(KeyCode::BracketLeft) if event.state == ElementState::Pressed => {
+                    self.scale_exposure(1.0 / EXPOSURE_STEP);
+                }
+                PhysicalKey::Code(KeyCode::BracketRight)
+                    if event.state == ElementState::Pressed =>
+                {
+                    self.scale_exposure(EXPOSURE_STEP);
+                }
+                PhysicalKey::Code(KeyCode::KeyG)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let operator = self.tonemap_pass.borrow().operator().next();
+                    self.tonemap_pass.borrow_mut().set_operator(operator);
+                    self.update_title();
+                }
+                PhysicalKey::Code(KeyCode::KeyJ)
+                    if event.state == ElementState::Pressed
+                        && !event.repeat
+                        && self.modifiers.alt_key() =>
+                {
+                    let mut shader_grid_pass = self.shader_grid_pass.borrow_mut();
+                    let plane = shader_grid_pass.plane().next();
+                    log::info!("Shader grid plane: {:?}", plane);
+                    shader_grid_pass.set_plane(plane);
+                }
+                PhysicalKey::Code(KeyCode::KeyJ)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let enabled = !self.passes.contains(ShaderGridPass::NAME);
+                    self.set_shader_grid(enabled);
+                }
+                PhysicalKey::Code(code @ (KeyCode::PageUp | KeyCode::PageDown))
+                    if event.state == ElementState::Pressed =>
+                {
+                    let factor = match code {
+                        KeyCode::PageUp => SHADER_GRID_STEP,
+                        _ => 1.0 / SHADER_GRID_STEP,
+                    };
+                    self.scale_shader_grid(factor);
+                }
+                PhysicalKey::Code(KeyCode::KeyB)
+                    if event.state == ElementState::Pressed
+                        && !event.repeat
+                        && self.modifiers.alt_key() =>
+                {
+                    let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+                    let show_normals = !models_draw_pass.show_normals();
+                    let length = models_draw_pass.world_bounds().map_or(1.0, |bounds| {
+                        bounds.size().magnitude() * NORMAL_LENGTH_FRACTION
+                    });
+                    models_draw_pass.set_show_normals(show_normals, length);
+                    log::info!("Show normals: {}", show_normals);
+                }
+                PhysicalKey::Code(KeyCode::KeyB)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+                    let show_bounds = !models_draw_pass.show_bounds();
+                    models_draw_pass.set_show_bounds(show_bounds);
+                }
+                PhysicalKey::Code(KeyCode::KeyY)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let mut lines_draw_pass = self.lines_draw_pass.borrow_mut();
+                    let depth_test = !lines_draw_pass.depth_test();
+                    lines_draw_pass.set_depth_test(depth_test);
+                    log::info!("Lines depth test: {}", depth_test);
+                }
+                PhysicalKey::Code(KeyCode::F1)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    log::info!("{}", self.render_context.borrow().report());
+                }
+                PhysicalKey::Code(KeyCode::F2)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let enabled = !self.passes.contains(PortalPass::NAME);
+                    self.set_portal(enabled);
+                }
+                PhysicalKey::Code(KeyCode::F3)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.split_screen = !self.split_screen;
+                    log::info!("Split screen: {}", self.split_screen);
+                }
+                PhysicalKey::Code(KeyCode::F4)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let enabled = self.overdraw_pass.is_none();
+                    log::info!("Overdraw: {}", enabled);
+                    self.set_overdraw(enabled);
+                }
+                PhysicalKey::Code(KeyCode::F5)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let mut skybox_draw_pass = self.skybox_draw_pass.borrow_mut();
+                    let enabled = !skybox_draw_pass.enabled();
+                    log::info!("Skybox: {}", enabled);
+                    skybox_draw_pass.set_enabled(enabled);
+                }
+                PhysicalKey::Code(code @ (KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3))
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let latency = match code {
+                        KeyCode::Digit1 => 1,
+                        KeyCode::Digit2 => 2,
+                        _ => 3,
+                    };
+                    self.set_frame_latency(latency);
+                }
+                PhysicalKey::Code(code @ (KeyCode::Comma | KeyCode::Period))
+                    if event.state == ElementState::Pressed && self.modifiers.alt_key() =>
+                {
+                    let offset = match code {
+                        KeyCode::Comma => -1,
+                        _ => 1,
+                    };
+                    self.step_solo_mesh(offset);
+                }
+                PhysicalKey::Code(KeyCode::Slash)
+                    if event.state == ElementState::Pressed
+                        && !event.repeat
+                        && self.modifiers.alt_key() =>
+                {
+                    self.solo_mesh = None;
+                    if let Some(model) = self.models_draw_pass.borrow_mut().model_mut() {
+                        model.show_all_meshes();
                     }
-
-                    // This happens when the a frame takes too long to present
-                    Err(wgpu::SurfaceError::Timeout) => {
-                        log::warn!("Surface timeout")
+                    log::info!("Showing all meshes");
+                }
+                PhysicalKey::Code(KeyCode::Comma) if event.state == ElementState::Pressed => {
+                    self.step_line_depth_bias(-LINE_DEPTH_BIAS_STEP);
+                }
+                PhysicalKey::Code(KeyCode::Period) if event.state == ElementState::Pressed => {
+                    self.step_line_depth_bias(LINE_DEPTH_BIAS_STEP);
+                }
+                PhysicalKey::Code(KeyCode::KeyC)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+                    let cull_mode = next_cull_mode(models_draw_pass.cull_mode());
+                    log::info!("Cull mode: {:?}", cull_mode);
+                    let ctx = self.render_context.borrow();
+                    models_draw_pass.set_cull_mode(&ctx.device, cull_mode);
+                }
+                PhysicalKey::Code(KeyCode::KeyI)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+                    let indirect = !models_draw_pass.indirect();
+                    models_draw_pass.set_indirect(indirect);
+                    log::info!("Indirect draws: {}", models_draw_pass.indirect());
+                }
+                PhysicalKey::Code(KeyCode::KeyR)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.models_draw_pass
+                        .borrow_mut()
+                        .reload_current(&mut self.file_loader);
+                }
+                PhysicalKey::Code(KeyCode::KeyQ)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+                    let enabled = !models_draw_pass.occlusion_culling();
+                    log::info!("Occlusion culling: {}", enabled);
+                    models_draw_pass.set_occlusion_culling(enabled);
+                }
+                PhysicalKey::Code(KeyCode::KeyK)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.frozen_frustum = match self.frozen_frustum {
+                        Some(_) => None,
+                        None => Some((self.camera.frustum(), self.camera.frustum_corners())),
+                    };
+                    log::info!("Frozen frustum: {}", self.frozen_frustum.is_some());
+                }
+                PhysicalKey::Code(KeyCode::KeyE)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.emit_particle_burst();
+                }
+                PhysicalKey::Code(KeyCode::KeyV)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let mut ctx = self.render_context.borrow_mut();
+                    let aspect = match ctx.fixed_aspect() {
+                        Some(_) => None,
+                        None => Some(LETTERBOX_ASPECT),
+                    };
+                    log::info!("Fixed aspect: {:?}", aspect);
+                    ctx.set_fixed_aspect(aspect);
+                    self.camera.set_aspect(ctx.aspect());
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                PhysicalKey::Code(KeyCode::F9)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    match self.frame_recorder.is_recording() {
+                        true => self.frame_recorder.stop(),
+                        false => {
+                            if let Err(err) = self.frame_recorder.start(RECORDING_DIR) {
+                                log::error!("Failed to start recording: {}", err);
+                            }
+                        }
                     }
                 }
-            }
+                #[cfg(not(target_arch = "wasm32"))]
+                PhysicalKey::Code(KeyCode::KeyP)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.dump_model();
+                }
+                PhysicalKey::Code(KeyCode::KeyN)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+                    let mode = models_draw_pass.debug_mode().next();
+                    log::info!("Debug mode: {:?}", mode);
+                    models_draw_pass.set_debug_mode(mode);
+                }
+                _ => {}
+            },
             WindowEvent::CursorMoved {
                 device_id,
                 position,
@@ -269,48 +878,111 @@ impl Renderer {
                 state,
                 button,
             } => {
-                if button == MouseButton::Left && state == ElementState::Pressed {
-                    self.models_draw_pass.swap_model();
+                if *button == MouseButton::Left && *state == ElementState::Pressed {
+                    self.models_draw_pass.borrow_mut().swap_model();
                 }
             }
             WindowEvent::Touch(touch) => {
                 if touch.phase == TouchPhase::Started {
-                    self.models_draw_pass.swap_model();
+                    self.models_draw_pass.borrow_mut().swap_model();
                 }
             }
+            #[cfg(not(target_arch = "wasm32"))]
+            WindowEvent::DroppedFile(path) => self.load_dropped_file(path),
             _ => {}
         }
+
+        false
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        if width > 0 && height > 0 {
-            {
-                let mut ctx = self.render_context.borrow_mut();
-                ctx.resize(width, height);
-                self.depth_texture = klgl::Texture::create_depth_texture(
-                    &ctx.device,
-                    ctx.config.width,
-                    ctx.config.height,
-                    "depth_texture",
-                );
-            }
+    fn device_event(&mut self, _: &ActiveEventLoop, event: &DeviceEvent) {
+        self.camera_controller.process_device_event(event);
+    }
 
-            match &mut self.display_depth_draw_pass {
-                Some(draw_pass) => {
-                    let ctx = self.render_context.borrow();
-                    draw_pass.on_resize(&ctx.device, &self.depth_texture)
-                }
-                _ => {}
-            }
+    fn set_event_proxy(&mut self, proxy: EventLoopProxy<klgl::UserEvent>) {
+        self.file_loader.set_event_proxy(proxy);
+    }
+
+    fn user_event(&mut self, _: &ActiveEventLoop, event: klgl::UserEvent) {
+        match event {
+            // Callbacks run as soon as the file arrives instead of on the next frame
+            klgl::UserEvent::FileReady(_) => self.file_loader.poll(),
+        }
+    }
 
-            let ctx = self.render_context.borrow();
-            self.camera.set_aspect(ctx.aspect());
+    fn resize(&mut self, _width: u32, _height: u32) {
+        let ctx = self.render_context.borrow();
+        self.texture_pool.resize(
+            &ctx.device,
+            &mut self.depth_texture,
+            ctx.config.width,
+            ctx.config.height,
+            "depth_texture",
+        );
+
+        self.texture_pool.resize(
+            &ctx.device,
+            &mut self.hdr_texture,
+            ctx.config.width,
+            ctx.config.height,
+            "hdr_texture",
+        );
+        self.bloom_pass
+            .borrow_mut()
+            .on_resize(&ctx.device, &self.hdr_texture);
+        self.tonemap_pass
+            .borrow_mut()
+            .on_resize(&ctx.device, &self.hdr_texture);
+
+        self.texture_pool.resize(
+            &ctx.device,
+            &mut self.ldr_texture,
+            ctx.config.width,
+            ctx.config.height,
+            "ldr_texture",
+        );
+        self.fxaa_pass
+            .borrow_mut()
+            .on_resize(&ctx.device, &self.ldr_texture);
+        if self.passes.contains(FxaaPass::NAME) {
+            self.tonemap_pass
+                .borrow_mut()
+                .set_output(Some(self.ldr_texture.view.clone()));
+        }
+
+        if let Some(draw_pass) = &self.display_depth_draw_pass {
+            draw_pass
+                .borrow_mut()
+                .on_resize(&ctx.device, &self.depth_texture)
+        }
+
+        self.light_markers_draw_pass.borrow().on_resize();
+        self.lines_draw_pass.borrow().on_resize();
+        self.points_draw_pass.borrow().on_resize();
+        self.portal_pass.borrow_mut().on_resize();
+        if let Some(overdraw_pass) = &self.overdraw_pass {
+            overdraw_pass.borrow_mut().on_resize();
         }
+        if let Some(particle_system) = &self.particle_system {
+            particle_system.borrow().on_resize();
+        }
+        self.camera.set_aspect(ctx.aspect());
     }
 
     fn update(&mut self) {
-        self.file_loader.poll();
+        // Files usually arrive through `user_event`, this catches anything sent without a proxy
+        if !self.file_loader.is_idle() {
+            self.file_loader.poll();
+        }
         let now = Instant::now();
+        let dt = self.simulation_dt(now.duration_since(self.last_update));
+        self.particle_dt = dt.min(MAX_PARTICLE_DT);
+        self.scene_time += dt;
+        self.last_update = now;
+        #[cfg(not(target_arch = "wasm32"))]
+        self.frame_recorder
+            .poll(&self.render_context.borrow().device);
+
         let since_last_print = now.duration_since(self.last_stat_print);
         if since_last_print.as_secs_f32() > 5.0 {
             self.last_stat_print = now;
@@ -320,26 +992,98 @@ impl Renderer {
                 self.camera.get_eye(),
                 self.camera.get_rotator()
             );
+            if let Some(samples) = self.models_draw_pass.borrow().last_visible_samples() {
+                let occluded = samples.iter().filter(|samples| **samples == 0).count();
+                log::info!("occluded instances: {}/{}", occluded, samples.len());
+            }
+            if let Some(visible) = self.models_draw_pass.borrow().num_visible_meshes() {
+                log::info!("meshes in frustum: {}", visible);
+            }
         }
 
         self.camera_controller.update_camera(&mut self.camera);
+        if let (Some(demo_time), Some(demo_path)) = (&mut self.demo_time, &self.demo_path) {
+            *demo_time += dt;
+            self.camera.set_pose(demo_path.sample(*demo_time));
+        }
         self.camera_uniform.update_view_proj(&self.camera);
-        self.render_context.borrow().queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
 
-        self.models_draw_pass.update();
+        let mut frustums = match self.split_screen {
+            true => {
+                self.update_split_view();
+                self.split_view
+                    .cameras
+                    .iter()
+                    .map(|camera| camera.frustum())
+                    .collect()
+            }
+            false => vec![self.camera.frustum()],
+        };
+        if let Some((frustum, corners)) = &self.frozen_frustum {
+            for (start, end) in klgl::FRUSTUM_EDGES {
+                klgl::debug_draw_line(corners[start], corners[end], FROZEN_FRUSTUM_COLOR);
+            }
+            frustums = vec![*frustum];
+        }
+
+        {
+            let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+            models_draw_pass.update();
+            models_draw_pass.cull(&frustums);
+            if let Some(segments) = models_draw_pass.take_segments() {
+                self.lines_draw_pass.borrow_mut().set_segments(&segments);
+
+                // Origins of the instances, at the same time as their bounds
+                let size =
+                    ORIGIN_POINT_SIZE * self.render_context.borrow().window().scale_factor() as f32;
+                let points: Vec<Point> = match models_draw_pass.show_bounds() {
+                    false => Vec::new(),
+                    true => models_draw_pass
+                        .instance_origins()
+                        .map(|position| Point {
+                            position,
+                            size,
+                            color: [1.0, 0.5, 0.0, 1.0],
+                        })
+                        .collect(),
+                };
+                self.points_draw_pass.borrow_mut().set_points(&points);
+            }
+        }
+        self.shader_grid_pass
+            .borrow_mut()
+            .set_eye(*self.camera.get_eye());
+
+        // Lights circle around the center of the scene in opposite phases
+        let time = self.scene_time;
+        {
+            let mut lights = self.lights.borrow_mut();
+            for index in 0..ORBITING_LIGHT_COLORS.len().min(lights.point_lights().len()) {
+                let angle = time * 0.5 + index as f32 * std::f32::consts::PI;
+                let position = [100.0 * angle.cos(), 100.0 * angle.sin(), 40.0];
+                lights.set_light_position(index, position);
+            }
+            lights.update();
+        }
+
+        if let Some(shadow_draw_pass) = &self.shadow_draw_pass {
+            let radius = self.models_draw_pass.borrow().bounding_radius();
+            shadow_draw_pass.borrow_mut().set_light(
+                LIGHT_DIRECTION,
+                Point3::new(0.0, 0.0, 0.0),
+                radius,
+            );
+        }
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         self.frame_counter.register_entry(Instant::now());
-        if !self.surface_configured {
-            return Ok(());
-        }
 
-        let output = self.render_context.borrow().surface.get_current_texture()?;
+        let output = self
+            .render_context
+            .borrow()
+            .surface()
+            .get_current_texture()?;
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -350,86 +1094,464 @@ impl Renderer {
             },
         );
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[
-                    // This is what @location(0) in the fragment shader targets
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.0,
-                                g: 0.0,
-                                b: 0.0,
-                                a: 1.0,
-                            }),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    }),
-                ],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
+        // Per frame data is copied at the start of the frame, before the passes read it
+        self.uploader.write(
+            &mut encoder,
+            &self.render_context.borrow().device,
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+        if self.split_screen {
+            for view in &self.view_cameras {
+                self.uploader.write(
+                    &mut encoder,
+                    &self.render_context.borrow().device,
+                    &view.buffer,
+                    0,
+                    bytemuck::cast_slice(&[view.uniform]),
+                );
+            }
+        }
+        self.models_draw_pass
+            .borrow_mut()
+            .upload_instances(&mut self.uploader, &mut encoder);
 
+        // Lines added with klgl::debug_draw_* during this frame
+        klgl::flush_debug_draw(|vertices| {
             self.lines_draw_pass
-                .render(&mut render_pass, &self.camera_bind_group);
+                .borrow_mut()
+                .set_debug_segments(vertices)
+        });
 
-            self.models_draw_pass
-                .render(&mut render_pass, &self.camera_bind_group);
+        let targets = klgl::PassTargets {
+            color: &self.hdr_texture.view,
+            depth: Some(&self.depth_texture.view),
+            surface: &view,
+            viewport: self.render_context.borrow().viewport(),
+            camera: None,
+        };
+        if let Some(particle_system) = &self.particle_system {
+            particle_system
+                .borrow()
+                .update(&mut encoder, self.particle_dt);
         }
-
-        if self.show_depth {
-            if self.display_depth_draw_pass.is_none() {
-                let ctx_clone = self.render_context.clone();
-                let ctx = ctx_clone.borrow();
-                self.display_depth_draw_pass = Some(DisplayDepthDrawPass::new(
-                    &ctx.device,
-                    ctx.config.format,
-                    &self.depth_texture,
-                ));
-            }
-
-            match &mut self.display_depth_draw_pass {
-                Some(draw_pass) => {
-                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: Some("Display Depth Render Pass"),
-                        color_attachments: &[
-                            // This is what @location(0) in the fragment shader targets
-                            Some(wgpu::RenderPassColorAttachment {
-                                view: &view,
-                                resolve_target: None,
-                                ops: wgpu::Operations {
-                                    load: wgpu::LoadOp::Load,
-                                    store: wgpu::StoreOp::Store,
-                                },
-                            }),
-                        ],
-                        depth_stencil_attachment: None,
-                        timestamp_writes: None,
-                        occlusion_query_set: None,
-                    });
-
-                    draw_pass.render(&mut render_pass);
-                }
-                _ => {}
+        match self.split_screen {
+            true => {
+                let viewports = self
+                    .split_view
+                    .viewports(self.render_context.borrow().scene_viewport());
+                let views: Vec<_> = viewports
+                    .into_iter()
+                    .zip(self.view_cameras.iter().map(|view| &view.bind_group))
+                    .collect();
+                self.passes.execute_views(&mut encoder, &targets, &views);
             }
+            false => self.passes.execute(&mut encoder, &targets),
         }
+        #[cfg(not(target_arch = "wasm32"))]
+        self.frame_recorder.capture(
+            &self.render_context.borrow().device,
+            &mut encoder,
+            &output.texture,
+        );
 
+        self.uploader.finish();
         self.render_context
             .borrow()
             .queue
             .submit(iter::once(encoder.finish()));
+        self.uploader.recall();
+        self.models_draw_pass.borrow().after_submit();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.frame_recorder.after_submit();
         output.present();
         Ok(())
     }
 }
+
+impl Renderer {
+    // Seconds to advance the scene by. Fixed while recording, so the recorded
+    // frames are evenly spaced in time however long they took to render.
+    fn simulation_dt(&self, elapsed: web_time::Duration) -> f32 {
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.frame_recorder.is_recording() {
+            return self.frame_recorder.frame_dt().as_secs_f32();
+        }
+        elapsed.as_secs_f32()
+    }
+
+    fn update_split_view(&mut self) {
+        let area = self.render_context.borrow().scene_viewport();
+        self.split_view.update_aspects(area);
+        self.split_view.cameras[0].set_pose(self.camera.pose());
+
+        for (camera, view) in self.split_view.cameras.iter().zip(&mut self.view_cameras) {
+            view.uniform.update_view_proj(camera);
+        }
+    }
+
+    fn emit_particle_burst(&mut self) {
+        let Some(particle_system) = &self.particle_system else {
+            return;
+        };
+
+        // A fountain above the model, bright enough to bloom
+        particle_system.borrow_mut().emit(
+            PARTICLE_BURST,
+            &EmitParams {
+                position: [0.0, 0.0, 10.0],
+                velocity: [0.0, 0.0, 30.0],
+                spread: 15.0,
+                lifetime: 2.5,
+                color: [4.0, 1.6, 0.4, 1.0],
+            },
+        );
+    }
+
+    fn scale_exposure(&mut self, factor: f32) {
+        let exposure = self.tonemap_pass.borrow().exposure() * factor;
+        self.tonemap_pass.borrow_mut().set_exposure(exposure);
+        self.update_title();
+    }
+
+    fn scale_shader_grid(&mut self, factor: f32) {
+        let mut shader_grid_pass = self.shader_grid_pass.borrow_mut();
+        if self.modifiers.alt_key() {
+            let fade_distance = shader_grid_pass.fade_distance() * factor;
+            shader_grid_pass.set_fade_distance(fade_distance);
+            log::info!("Shader grid fade distance: {}", fade_distance);
+        } else {
+            let spacing = shader_grid_pass.spacing() * factor;
+            shader_grid_pass.set_spacing(spacing);
+            log::info!("Shader grid spacing: {}", spacing);
+        }
+    }
+
+    fn set_frame_latency(&mut self, latency: u32) {
+        let latency = self.render_context.borrow_mut().set_frame_latency(latency);
+        self.models_draw_pass
+            .borrow_mut()
+            .set_frame_latency(&self.render_context.borrow().device, latency);
+        log::info!("Frame latency: {}", latency);
+    }
+
+    // Negative steps move the lines towards the camera
+    fn step_line_depth_bias(&mut self, step: i32) {
+        let mut lines_draw_pass = self.lines_draw_pass.borrow_mut();
+        let bias = lines_draw_pass.depth_bias();
+        let constant = bias.constant.saturating_add(step);
+        lines_draw_pass.set_depth_bias(
+            &self.render_context.borrow().device,
+            constant,
+            bias.slope_scale,
+        );
+        log::info!("Lines depth bias: {}", constant);
+    }
+
+    fn update_title(&self) {
+        let tonemap_pass = self.tonemap_pass.borrow();
+        let mut title = format!(
+            "Tutorial 9: exposure {:.2}, {:?}",
+            tonemap_pass.exposure(),
+            tonemap_pass.operator()
+        );
+        let models_draw_pass = self.models_draw_pass.borrow();
+        if let Some((editor, model)) = self.material_editor.zip(models_draw_pass.model())
+            && let Some(material) = model.materials.get(editor.material)
+        {
+            title += &format!(" | {}", editor.describe(&material.name, &material.uniform));
+        }
+        self.render_context.borrow().window().set_title(&title);
+    }
+
+    // Replaces the model with a dropped model file, or the one given on the command line, and
+    // moves the camera back to show all of it. A json file is loaded as a scene instead.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_dropped_file(&mut self, path: &std::path::Path) {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            if let Err(err) = self.load_scene(path) {
+                log::error!("Failed to load scene {}. Error: {:#}", path.display(), err);
+            }
+            return;
+        }
+
+        match ModelFormat::of_path(path) {
+            Some(ModelFormat::Obj) => {
+                let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+                if let Err(err) = models_draw_pass.load_from_disk(path) {
+                    log::error!("Failed to load {}. Error: {:#}", path.display(), err);
+                    return;
+                }
+                if let Some(bounds) = models_draw_pass.world_bounds() {
+                    let radius = bounds.size().magnitude() / 2.0;
+                    self.camera.frame_sphere(bounds.center(), radius);
+                }
+            }
+            Some(ModelFormat::Gltf) => {
+                log::warn!(
+                    "Can't load {}, glTF models are not supported yet. Export it as obj",
+                    path.display()
+                );
+            }
+            None => log::warn!("Can't load {}, it is not a model file", path.display()),
+        }
+    }
+
+    // Starts loading the models of a scene file and moves the camera and lights where it says.
+    // Model paths in the file are asset paths, like the ones of the file loader.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_scene(&mut self, path: &std::path::Path) -> anyhow::Result<()> {
+        let json = std::fs::read_to_string(path)?;
+        let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+        let scene = Scene::load(
+            &json,
+            &mut self.file_loader,
+            self.render_context.clone(),
+            models_draw_pass.texture_bind_group_layout(),
+        )?;
+        log::info!("Loading scene {}", path.display());
+
+        if let Some(pose) = scene.camera() {
+            self.demo_time = None;
+            self.camera.set_pose(pose);
+        }
+        {
+            let mut lights = self.lights.borrow_mut();
+            lights.truncate(ORBITING_LIGHT_COLORS.len());
+            for light in scene.lights() {
+                if lights.add_point_light(*light).is_none() {
+                    log::warn!(
+                        "Scene has more than {} lights",
+                        crate::lights::MAX_POINT_LIGHTS
+                    );
+                    break;
+                }
+            }
+        }
+        models_draw_pass.set_scene(Some(scene));
+        Ok(())
+    }
+
+    // Selects another material `offset` materials away and changes its selected value by `steps`
+    fn edit_material(&mut self, offset: isize, steps: i32) {
+        let editor = self.material_editor.get_or_insert_default();
+        {
+            let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+            let Some(model) = models_draw_pass.model_mut() else {
+                return;
+            };
+            if model.materials.is_empty() {
+                log::warn!("Model has no materials to edit, it uses a texture array");
+                return;
+            }
+            editor.select(offset, model.materials.len());
+            let material = &mut model.materials[editor.material];
+            let uniform = editor.adjusted(material.uniform, steps);
+            if uniform != material.uniform {
+                material.set_uniform(&self.render_context.borrow().queue, uniform);
+            }
+        }
+        self.update_title();
+    }
+
+    // Draws only the mesh `offset` meshes away from the soloed one, wrapping around
+    fn step_solo_mesh(&mut self, offset: isize) {
+        let mut models_draw_pass = self.models_draw_pass.borrow_mut();
+        let Some(model) = models_draw_pass.model_mut() else {
+            return;
+        };
+        let count = model.meshes.len() as isize;
+        if count == 0 {
+            return;
+        }
+
+        let index = match self.solo_mesh {
+            Some(index) => (index as isize + offset).rem_euclid(count),
+            None if offset < 0 => count - 1,
+            None => 0,
+        } as usize;
+        model.solo(index);
+        self.solo_mesh = Some(index);
+        log::info!("Solo mesh {}: {}", index, model.meshes[index].name);
+    }
+
+    pub fn set_show_light_markers(&mut self, show: bool) {
+        if !show {
+            self.passes.remove(LightMarkersDrawPass::NAME);
+        } else if !self.passes.contains(LightMarkersDrawPass::NAME) {
+            // Markers are part of the scene so they go before post processing
+            let next = match self.passes.contains(BloomPass::NAME) {
+                true => BloomPass::NAME,
+                false => TonemapPass::NAME,
+            };
+            self.passes
+                .insert_before(next, self.light_markers_draw_pass.clone());
+        }
+    }
+
+    /// Shows the scene rendered offscreen on a quad in the corner
+    pub fn set_portal(&mut self, enabled: bool) {
+        if !enabled {
+            self.passes.remove(PortalPass::NAME);
+        } else if !self.passes.contains(PortalPass::NAME) {
+            // Drawn over the scene color, so it is tonemapped with the rest
+            let next = match self.passes.contains(BloomPass::NAME) {
+                true => BloomPass::NAME,
+                false => TonemapPass::NAME,
+            };
+            self.passes.insert_before(next, self.portal_pass.clone());
+        }
+    }
+
+    pub fn set_bloom(&mut self, enabled: bool) {
+        if !enabled {
+            self.passes.remove(BloomPass::NAME);
+        } else if !self.passes.contains(BloomPass::NAME) {
+            let bloom_pass = self.bloom_pass.borrow();
+            log::info!(
+                "Bloom: threshold {}, intensity {}, iterations {}",
+                bloom_pass.threshold(),
+                bloom_pass.intensity(),
+                bloom_pass.iterations()
+            );
+            self.passes
+                .insert_before(TonemapPass::NAME, self.bloom_pass.clone());
+        }
+    }
+
+    pub fn set_fxaa(&mut self, enabled: bool) {
+        if !enabled {
+            self.passes.remove(FxaaPass::NAME);
+            self.tonemap_pass.borrow_mut().set_output(None);
+            return;
+        }
+
+        if self.passes.contains(FxaaPass::NAME) {
+            return;
+        }
+
+        log::info!("FXAA: {:?}", self.fxaa_pass.borrow().quality());
+        self.tonemap_pass
+            .borrow_mut()
+            .set_output(Some(self.ldr_texture.view.clone()));
+        // Overlays drawn to the surface after tonemapping stay on top of FXAA
+        self.passes
+            .insert_after(TonemapPass::NAME, self.fxaa_pass.clone());
+    }
+
+    /// Switches between the shader grid and the grid of lines.
+    /// The lines pass stays to draw the other lines, e.g. mesh bounds.
+    pub fn set_shader_grid(&mut self, enabled: bool) {
+        self.lines_draw_pass.borrow_mut().set_show_grid(!enabled);
+        if !enabled {
+            self.passes.remove(ShaderGridPass::NAME);
+        } else if !self.passes.contains(ShaderGridPass::NAME) {
+            let plane = GridPlane::ground(self.camera.coordinate_system());
+            self.shader_grid_pass.borrow_mut().set_plane(plane);
+            // Blended on top of the opaque scene and the sky
+            self.passes
+                .insert_after(SkyboxDrawPass::NAME, self.shader_grid_pass.clone());
+        }
+    }
+
+    pub fn set_shadows(&mut self, enabled: bool) {
+        if !enabled {
+            self.passes.remove(ShadowDrawPass::NAME);
+            self.shadow_draw_pass = None;
+            self.models_draw_pass.borrow_mut().set_shadows(None);
+            return;
+        }
+
+        if self.shadow_draw_pass.is_some() {
+            return;
+        }
+
+        let shadow_draw_pass = Rc::new(RefCell::new(ShadowDrawPass::new(
+            self.render_context.clone(),
+            self.models_draw_pass.clone(),
+        )));
+        let binding = shadow_draw_pass.borrow().binding();
+        self.models_draw_pass
+            .borrow_mut()
+            .set_shadows(Some(binding));
+        // The shadow map has to be ready before the models sample it
+        self.passes
+            .insert_before(klgl::ClearPass::NAME, shadow_draw_pass.clone());
+        self.shadow_draw_pass = Some(shadow_draw_pass);
+    }
+
+    pub fn set_overdraw(&mut self, enabled: bool) {
+        if !enabled {
+            self.passes.remove(OverdrawPass::NAME);
+            self.overdraw_pass = None;
+            return;
+        }
+
+        if self.overdraw_pass.is_some() {
+            return;
+        }
+
+        let overdraw_pass = Rc::new(RefCell::new(OverdrawPass::new(
+            self.render_context.clone(),
+            self.models_draw_pass.clone(),
+        )));
+        // Replaces the whole image, so it goes after the post processing and overlays
+        self.passes.push(overdraw_pass.clone());
+        self.overdraw_pass = Some(overdraw_pass);
+    }
+
+    /// Writes the loaded geometry to an obj file in the working directory
+    #[cfg(not(target_arch = "wasm32"))]
+    fn dump_model(&self) {
+        use std::io::Write;
+
+        let models_draw_pass = self.models_draw_pass.borrow();
+        let Some(model) = models_draw_pass.model() else {
+            log::warn!("Model is not loaded yet");
+            return;
+        };
+
+        let result = std::fs::File::create(MODEL_DUMP_PATH).and_then(|file| {
+            let mut writer = std::io::BufWriter::new(file);
+            model.export_obj(&mut writer)?;
+            writer.flush()
+        });
+        match result {
+            Ok(()) => log::info!("Model written to {}", MODEL_DUMP_PATH),
+            Err(err) => log::error!("Failed to write {}: {}", MODEL_DUMP_PATH, err),
+        }
+    }
+
+    fn show_depth(&mut self, show: bool) {
+        if !show {
+            self.passes.remove(DisplayDepthDrawPass::NAME);
+            return;
+        }
+
+        if self.passes.contains(DisplayDepthDrawPass::NAME) {
+            return;
+        }
+
+        let draw_pass = self.display_depth_draw_pass();
+        self.passes.push(draw_pass);
+    }
+
+    // Created on first use
+    fn display_depth_draw_pass(&mut self) -> Rc<RefCell<DisplayDepthDrawPass>> {
+        self.display_depth_draw_pass
+            .get_or_insert_with(|| {
+                let ctx = self.render_context.borrow();
+                Rc::new(RefCell::new(DisplayDepthDrawPass::new(
+                    &ctx.device,
+                    ctx.config.format,
+                    &ctx.surface_shader_constants(),
+                    &self.depth_texture,
+                )))
+            })
+            .clone()
+    }
+}
@@ -0,0 +1,493 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wgpu::util::DeviceExt;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    intensity: f32,
+    _padding: [f32; 2],
+}
+
+const ADDITIVE_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent::REPLACE,
+};
+
+/// Number of mips the chain can have for a target of the given size. The first mip is half the size.
+fn level_count(width: u32, height: u32, iterations: u32) -> u32 {
+    let max_levels = width.min(height).max(2).ilog2();
+    iterations.clamp(1, max_levels)
+}
+
+struct BloomPipelines {
+    extract: wgpu::RenderPipeline,
+    downsample: wgpu::RenderPipeline,
+    blur_horizontal: wgpu::RenderPipeline,
+    blur_vertical: wgpu::RenderPipeline,
+    upsample: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+}
+
+/// One mip of the chain. `scratch` holds the result of the horizontal blur.
+struct BloomLevel {
+    texture: klgl::Texture,
+    bind_group: wgpu::BindGroup,
+    scratch: klgl::Texture,
+    scratch_bind_group: wgpu::BindGroup,
+}
+
+/// Adds a blurred copy of the pixels brighter than `threshold` to the HDR scene color
+pub struct BloomPass {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    pipelines: BloomPipelines,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    source_bind_group: wgpu::BindGroup,
+    levels: Vec<BloomLevel>,
+    format: wgpu::TextureFormat,
+    size: (u32, u32),
+    threshold: f32,
+    intensity: f32,
+    iterations: u32,
+}
+
+impl BloomPass {
+    pub const NAME: &str = "bloom";
+
+    pub fn new(ctx: Rc<RefCell<klgl::RenderContext>>, hdr_texture: &klgl::Texture) -> Self {
+        let threshold = 1.0;
+        let intensity = 0.5;
+        let iterations = 5;
+        let format = hdr_texture.texture.format();
+        let size = (hdr_texture.texture.width(), hdr_texture.texture.height());
+
+        let (pipelines, bind_group_layout, uniform_buffer, source_bind_group, levels) = {
+            let ctx = ctx.borrow();
+            let device = &ctx.device;
+
+            let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Bloom Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[BloomUniform {
+                    threshold,
+                    intensity,
+                    _padding: [0.0; 2],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+            let bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("bloom_bind_group_layout"),
+                });
+
+            let source_bind_group =
+                Self::create_bind_group(device, &bind_group_layout, hdr_texture, &uniform_buffer);
+            let levels = Self::create_levels(
+                device,
+                &bind_group_layout,
+                &uniform_buffer,
+                format,
+                size,
+                iterations,
+            );
+            let pipelines = Self::create_pipelines(device, &bind_group_layout, format);
+
+            (
+                pipelines,
+                bind_group_layout,
+                uniform_buffer,
+                source_bind_group,
+                levels,
+            )
+        };
+
+        Self {
+            ctx,
+            pipelines,
+            bind_group_layout,
+            uniform_buffer,
+            source_bind_group,
+            levels,
+            format,
+            size,
+            threshold,
+            intensity,
+            iterations,
+        }
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    #[allow(dead_code)]
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+        self.write_uniform();
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    #[allow(dead_code)]
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+        self.write_uniform();
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// Number of downsample steps. Limited by the size of the HDR target.
+    #[allow(dead_code)]
+    pub fn set_iterations(&mut self, iterations: u32) {
+        self.iterations = iterations;
+        let ctx = self.ctx.borrow();
+        self.levels = Self::create_levels(
+            &ctx.device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            self.format,
+            self.size,
+            iterations,
+        );
+    }
+
+    /// Has to be called when the HDR texture is recreated
+    pub fn on_resize(&mut self, device: &wgpu::Device, hdr_texture: &klgl::Texture) {
+        self.size = (hdr_texture.texture.width(), hdr_texture.texture.height());
+        self.source_bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            hdr_texture,
+            &self.uniform_buffer,
+        );
+        self.levels = Self::create_levels(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            self.format,
+            self.size,
+            self.iterations,
+        );
+    }
+
+    #[allow(dead_code)]
+    fn write_uniform(&self) {
+        self.ctx.borrow().queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform {
+                threshold: self.threshold,
+                intensity: self.intensity,
+                _padding: [0.0; 2],
+            }]),
+        );
+    }
+
+    fn create_levels(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+        iterations: u32,
+    ) -> Vec<BloomLevel> {
+        (0..level_count(width, height, iterations))
+            .map(|level| {
+                let (width, height) = (width >> (level + 1), height >> (level + 1));
+                let texture = klgl::Texture::create_render_target(
+                    device,
+                    width,
+                    height,
+                    format,
+                    &format!("bloom_level_{level}"),
+                );
+                let scratch = klgl::Texture::create_render_target(
+                    device,
+                    width,
+                    height,
+                    format,
+                    &format!("bloom_scratch_{level}"),
+                );
+
+                BloomLevel {
+                    bind_group: Self::create_bind_group(device, layout, &texture, uniform_buffer),
+                    scratch_bind_group: Self::create_bind_group(
+                        device,
+                        layout,
+                        &scratch,
+                        uniform_buffer,
+                    ),
+                    texture,
+                    scratch,
+                }
+            })
+            .collect()
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        source: &klgl::Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&source.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("bloom_bind_group"),
+        })
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+    ) -> BloomPipelines {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::BLOOM_SHADER.into()),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Render Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |fragment_entry_point: &str, blend: wgpu::BlendState| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Bloom Render Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(fragment_entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        BloomPipelines {
+            extract: create_pipeline("fs_extract", wgpu::BlendState::REPLACE),
+            downsample: create_pipeline("fs_copy", wgpu::BlendState::REPLACE),
+            blur_horizontal: create_pipeline("fs_blur_horizontal", wgpu::BlendState::REPLACE),
+            blur_vertical: create_pipeline("fs_blur_vertical", wgpu::BlendState::REPLACE),
+            upsample: create_pipeline("fs_copy", ADDITIVE_BLEND),
+            composite: create_pipeline("fs_composite", ADDITIVE_BLEND),
+        }
+    }
+
+    fn draw_fullscreen(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, source, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl klgl::DrawPass for BloomPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        let pipelines = &self.pipelines;
+
+        for (index, level) in self.levels.iter().enumerate() {
+            // The first level takes the bright pixels from the scene, others halve the previous one
+            let (pipeline, source) = match index {
+                0 => (&pipelines.extract, &self.source_bind_group),
+                _ => (&pipelines.downsample, &self.levels[index - 1].bind_group),
+            };
+            Self::draw_fullscreen(
+                encoder,
+                "Bloom Downsample Pass",
+                pipeline,
+                source,
+                &level.texture.view,
+                clear,
+            );
+            Self::draw_fullscreen(
+                encoder,
+                "Bloom Horizontal Blur Pass",
+                &pipelines.blur_horizontal,
+                &level.bind_group,
+                &level.scratch.view,
+                clear,
+            );
+            Self::draw_fullscreen(
+                encoder,
+                "Bloom Vertical Blur Pass",
+                &pipelines.blur_vertical,
+                &level.scratch_bind_group,
+                &level.texture.view,
+                clear,
+            );
+        }
+
+        // Accumulate the blurred mips back into the first one
+        for pair in self.levels.windows(2).rev() {
+            Self::draw_fullscreen(
+                encoder,
+                "Bloom Upsample Pass",
+                &pipelines.upsample,
+                &pair[1].bind_group,
+                &pair[0].texture.view,
+                wgpu::LoadOp::Load,
+            );
+        }
+
+        Self::draw_fullscreen(
+            encoder,
+            "Bloom Composite Pass",
+            &pipelines.composite,
+            &self.levels[0].bind_group,
+            targets.color,
+            wgpu::LoadOp::Load,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{gpu_context, hdr_texture, read_hdr};
+
+    // Has to match BLUR_WEIGHTS in bloom_shader.wgsl
+    const BLUR_WEIGHTS: [f32; 5] = [0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216];
+
+    #[test]
+    fn test_level_count() {
+        assert_eq!(level_count(1920, 1080, 5), 5);
+        assert_eq!(level_count(64, 8, 5), 3);
+        assert_eq!(level_count(1, 1, 5), 1);
+        assert_eq!(level_count(1920, 1080, 0), 1);
+    }
+
+    #[test]
+    fn test_blur_weights_are_normalized() {
+        let sum = BLUR_WEIGHTS[0] + 2.0 * BLUR_WEIGHTS[1..].iter().sum::<f32>();
+        assert!((sum - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_bright_pixel_spreads() {
+        const SIZE: u32 = 16;
+        const CENTER: usize = (SIZE / 2 * (SIZE + 1)) as usize;
+        let ctx = gpu_context(SIZE, SIZE);
+        let mut texels = vec![[0.0, 0.0, 0.0, 1.0]; (SIZE * SIZE) as usize];
+        texels[CENTER] = [8.0, 8.0, 8.0, 1.0];
+        let hdr = hdr_texture(&ctx.borrow(), SIZE, SIZE, &texels);
+        let bloom = BloomPass::new(ctx.clone(), &hdr);
+
+        let ctx = ctx.borrow();
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let targets = klgl::PassTargets {
+            color: &hdr.view,
+            depth: None,
+            surface: &hdr.view,
+            viewport: None,
+            camera: None,
+        };
+        klgl::DrawPass::record(&bloom, &mut encoder, &targets);
+        ctx.queue.submit([encoder.finish()]);
+
+        let result = read_hdr(&ctx, &hdr.texture);
+        let size = SIZE as usize;
+        for neighbor in [CENTER - 1, CENTER + 1, CENTER - size, CENTER + size + 1] {
+            assert!(result[neighbor][0] > 0.0, "{:?}", result[neighbor]);
+        }
+        // Bloom is added to the scene, the bright pixel stays the brightest one
+        assert!(result[CENTER][0] >= 8.0);
+        assert!(result.iter().all(|texel| texel[0] <= result[CENTER][0]));
+    }
+}
@@ -0,0 +1,105 @@
+use cgmath::{EuclideanSpace, Matrix4, Point3, Transform, Vector3};
+
+/// Axis aligned box. The empty box contains nothing and is the identity for `union`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl BoundingBox {
+    pub const EMPTY: Self = Self {
+        min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+        max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |bounds, point| {
+            bounds.union(&Self {
+                min: point,
+                max: point,
+            })
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// Box around the corners of this one moved by `transform`
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        Self::from_points((0..8).map(|index| {
+            let pick = |bit: usize, min: f32, max: f32| match index & bit {
+                0 => min,
+                _ => max,
+            };
+            transform.transform_point(Point3::new(
+                pick(1, self.min.x, self.max.x),
+                pick(2, self.min.y, self.max.y),
+                pick(4, self.min.z, self.max.z),
+            ))
+        }))
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    #[test]
+    fn test_box_from_points() {
+        let bounds = BoundingBox::from_points([
+            Point3::new(1.0, -2.0, 0.5),
+            Point3::new(-1.0, 3.0, 0.0),
+            Point3::new(0.0, 0.0, 2.0),
+        ]);
+        assert_eq!(bounds.min, Point3::new(-1.0, -2.0, 0.0));
+        assert_eq!(bounds.max, Point3::new(1.0, 3.0, 2.0));
+        assert_eq!(bounds.center(), Point3::new(0.0, 0.5, 1.0));
+        assert!(!bounds.is_empty());
+
+        assert!(BoundingBox::from_points([]).is_empty());
+        assert_eq!(BoundingBox::EMPTY.union(&bounds), bounds);
+    }
+
+    #[test]
+    fn test_transformed_box() {
+        let bounds = BoundingBox {
+            min: Point3::new(-1.0, -2.0, 0.0),
+            max: Point3::new(1.0, 2.0, 1.0),
+        };
+        let transform = Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0))
+            * Matrix4::from_angle_z(cgmath::Deg(90.0));
+        let moved = bounds.transformed(&transform);
+        assert!((moved.min - Point3::new(8.0, -1.0, 0.0)).magnitude() < 1e-5);
+        assert!((moved.max - Point3::new(12.0, 1.0, 1.0)).magnitude() < 1e-5);
+        assert!(BoundingBox::EMPTY.transformed(&transform).is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, Point3};
+
+use crate::bounds::BoundingBox;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Sphere around the center of the points' bounding box. Not the smallest one,
+    /// but close enough for culling. An empty set gives a zero sized sphere at the origin.
+    pub fn from_points(points: impl Iterator<Item = Point3<f32>> + Clone) -> Self {
+        Self::around(&BoundingBox::from_points(points.clone()), points)
+    }
+
+    /// Same as `from_points` when the bounding box of the points is already known
+    pub fn around(bounds: &BoundingBox, points: impl Iterator<Item = Point3<f32>>) -> Self {
+        if bounds.is_empty() {
+            return Self {
+                center: Point3::origin(),
+                radius: 0.0,
+            };
+        }
+
+        let center = bounds.center();
+        let radius = points.map(|p| center.distance(p)).fold(0.0, f32::max);
+        Self { center, radius }
+    }
+
+    /// The smallest sphere containing both
+    pub fn merge(&self, other: &Self) -> Self {
+        let offset = other.center - self.center;
+        let distance = offset.magnitude();
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+
+        let radius = (distance + self.radius + other.radius) / 2.0;
+        Self {
+            center: self.center + offset * ((radius - self.radius) / distance),
+            radius,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum BvhNode {
+    Leaf {
+        sphere: BoundingSphere,
+        mesh: usize,
+    },
+    Inner {
+        sphere: BoundingSphere,
+        children: [usize; 2],
+    },
+}
+
+impl BvhNode {
+    fn sphere(&self) -> &BoundingSphere {
+        match self {
+            BvhNode::Leaf { sphere, .. } | BvhNode::Inner { sphere, .. } => sphere,
+        }
+    }
+}
+
+/// Binary tree of bounding spheres over the meshes of a model.
+/// Culling skips whole groups of meshes that are outside of the frustum.
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    root: Option<usize>,
+}
+
+impl Bvh {
+    /// Mesh indices are the indices in `spheres`
+    pub fn new(spheres: &[BoundingSphere]) -> Self {
+        let mut nodes = Vec::with_capacity(spheres.len() * 2);
+        let mut items: Vec<(usize, BoundingSphere)> = spheres.iter().copied().enumerate().collect();
+        let root = match items.is_empty() {
+            true => None,
+            false => Some(Self::build(&mut nodes, &mut items)),
+        };
+        Self { nodes, root }
+    }
+
+    // Splits the meshes in halves along the longest axis of their centers
+    fn build(nodes: &mut Vec<BvhNode>, items: &mut [(usize, BoundingSphere)]) -> usize {
+        if let [(mesh, sphere)] = items {
+            nodes.push(BvhNode::Leaf {
+                sphere: *sphere,
+                mesh: *mesh,
+            });
+            return nodes.len() - 1;
+        }
+
+        let centers = BoundingSphere::from_points(items.iter().map(|(_, sphere)| sphere.center));
+        let extent = items.iter().fold([0.0f32; 3], |extent, (_, sphere)| {
+            let offset = sphere.center - centers.center;
+            [
+                extent[0].max(offset.x.abs()),
+                extent[1].max(offset.y.abs()),
+                extent[2].max(offset.z.abs()),
+            ]
+        });
+        let axis = (0..3)
+            .max_by(|a, b| extent[*a].total_cmp(&extent[*b]))
+            .unwrap();
+        items.sort_by(|(_, a), (_, b)| a.center[axis].total_cmp(&b.center[axis]));
+
+        let (left, right) = items.split_at_mut(items.len() / 2);
+        let left = Self::build(nodes, left);
+        let right = Self::build(nodes, right);
+        nodes.push(BvhNode::Inner {
+            sphere: nodes[left].sphere().merge(nodes[right].sphere()),
+            children: [left, right],
+        });
+        nodes.len() - 1
+    }
+
+    /// Indices of the meshes that may be visible, in ascending order
+    pub fn cull(&self, frustum: &klgl::Frustum) -> Vec<usize> {
+        let mut visible = Vec::new();
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let sphere = node.sphere();
+            if !frustum.intersects_sphere(sphere.center, sphere.radius) {
+                continue;
+            }
+
+            match node {
+                BvhNode::Leaf { mesh, .. } => visible.push(*mesh),
+                BvhNode::Inner { children, .. } => stack.extend(children),
+            }
+        }
+
+        visible.sort_unstable();
+        visible
+    }
+}
+
+/// Per mesh flags for a list of mesh indices
+pub fn visibility_mask(num_meshes: usize, visible: &[usize]) -> Vec<bool> {
+    let mut mask = vec![false; num_meshes];
+    for index in visible {
+        mask[*index] = true;
+    }
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(outer: &BoundingSphere, inner: &BoundingSphere) -> bool {
+        outer.center.distance(inner.center) + inner.radius <= outer.radius + 1e-4
+    }
+
+    // A row of small spheres along X from -9.5 to 9.5
+    fn spheres() -> Vec<BoundingSphere> {
+        (0..20)
+            .map(|i| BoundingSphere {
+                center: Point3::new(i as f32 - 9.5, 0.0, 0.0),
+                radius: 0.25,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bvh_construction() {
+        let spheres = spheres();
+        let bvh = Bvh::new(&spheres);
+
+        // Every mesh is in exactly one leaf with its own sphere
+        let mut meshes: Vec<usize> = bvh
+            .nodes
+            .iter()
+            .filter_map(|node| match node {
+                BvhNode::Leaf { sphere, mesh } => {
+                    assert_eq!(*sphere, spheres[*mesh]);
+                    Some(*mesh)
+                }
+                BvhNode::Inner { .. } => None,
+            })
+            .collect();
+        meshes.sort();
+        assert_eq!(meshes, (0..spheres.len()).collect::<Vec<_>>());
+        assert_eq!(bvh.nodes.len(), spheres.len() * 2 - 1);
+
+        // Parents contain their children
+        for node in &bvh.nodes {
+            if let BvhNode::Inner { sphere, children } = node {
+                for child in children {
+                    assert!(contains(sphere, bvh.nodes[*child].sphere()));
+                }
+            }
+        }
+
+        assert!(
+            Bvh::new(&[])
+                .cull(&klgl::Frustum::from_view_projection(cgmath::ortho(
+                    -1.0, 1.0, -1.0, 1.0, -1.0, 1.0
+                )))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_cull_half_of_the_scene() {
+        let bvh = Bvh::new(&spheres());
+
+        // Sees only positive X
+        let frustum =
+            klgl::Frustum::from_view_projection(cgmath::ortho(0.0, 20.0, -5.0, 5.0, -5.0, 5.0));
+        assert_eq!(bvh.cull(&frustum), (10..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sphere_from_points() {
+        let sphere = BoundingSphere::from_points(
+            [Point3::new(-1.0, 0.0, 0.0), Point3::new(3.0, 0.0, 0.0)].into_iter(),
+        );
+        assert_eq!(sphere.center, Point3::new(1.0, 0.0, 0.0));
+        assert_eq!(sphere.radius, 2.0);
+
+        let merged = sphere.merge(&BoundingSphere {
+            center: Point3::new(6.0, 0.0, 0.0),
+            radius: 1.0,
+        });
+        assert_eq!(merged.center, Point3::new(3.0, 0.0, 0.0));
+        assert_eq!(merged.radius, 4.0);
+    }
+
+    #[test]
+    fn test_visibility_mask() {
+        assert_eq!(visibility_mask(4, &[1, 3]), [false, true, false, true]);
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use wgpu::util::DeviceExt;
 
 #[repr(C)]
@@ -20,17 +22,33 @@ impl Vertex {
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayUniform {
+    opacity: f32,
+    _padding: [f32; 3],
+}
+
+// The overlay covers the scene by its alpha, which `fs_overlay` scales by the opacity.
+// Blending happens in the space of the target, so it is linear on sRGB surfaces.
+const OVERLAY_BLEND: wgpu::BlendState = wgpu::BlendState::ALPHA_BLENDING;
+
 pub struct DisplayDepthDrawPass {
     pub pipeline: wgpu::RenderPipeline,
     pub vertex_buffer: wgpu::Buffer,
     texture_bind_group_layout: wgpu::BindGroupLayout,
     texture_bind_group: wgpu::BindGroup,
+    overlay_buffer: wgpu::Buffer,
+    opacity: f32,
 }
 
 impl DisplayDepthDrawPass {
+    pub const NAME: &str = "display_depth";
+
     pub fn new(
         device: &wgpu::Device,
         surface_format: wgpu::TextureFormat,
+        surface_constants: &HashMap<String, f64>,
         texture: &klgl::Texture,
     ) -> Self {
         let texture_bind_group_layout =
@@ -55,40 +73,98 @@ impl DisplayDepthDrawPass {
                         // corresponding Texture entry above.
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        count: None,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                    },
                 ],
                 label: Some("depth_pass.bind_group_layout"),
             });
 
-        let texture_bind_group = {
-            device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                    },
-                ],
-                label: Some("depth_pass.bind_group"),
-            })
-        };
+        let opacity = 1.0;
+        let overlay_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("depth_pass.overlay_buffer"),
+            contents: bytemuck::bytes_of(&OverlayUniform {
+                opacity,
+                _padding: [0.0; 3],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture_bind_group =
+            Self::create_bind_group(device, &texture_bind_group_layout, texture, &overlay_buffer);
 
-        let pipeline = Self::create_pipeline(device, surface_format, &texture_bind_group_layout);
+        let pipeline = Self::create_pipeline(
+            device,
+            surface_format,
+            surface_constants,
+            &texture_bind_group_layout,
+        );
 
         Self {
             pipeline,
             texture_bind_group_layout,
             texture_bind_group,
             vertex_buffer: Self::make_vertex_buffer(device),
+            overlay_buffer,
+            opacity,
         }
     }
 
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &klgl::Texture,
+        overlay_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: overlay_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("depth_pass.bind_group"),
+        })
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    /// How much the depth covers the scene, `1` hides it and `0` shows only the scene.
+    /// Clamped to `0..=1`.
+    pub fn set_opacity(&mut self, queue: &wgpu::Queue, opacity: f32) {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        queue.write_buffer(
+            &self.overlay_buffer,
+            0,
+            bytemuck::bytes_of(&OverlayUniform {
+                opacity: self.opacity,
+                _padding: [0.0; 3],
+            }),
+        );
+    }
+
     pub fn create_pipeline(
         device: &wgpu::Device,
         texture_format: wgpu::TextureFormat,
+        constants: &HashMap<String, f64>,
         texture_bind_group_layout: &wgpu::BindGroupLayout,
     ) -> wgpu::RenderPipeline {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -123,13 +199,16 @@ impl DisplayDepthDrawPass {
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: Some("fs_main"),
+                entry_point: Some("fs_overlay"),
                 targets: &[Some(wgpu::ColorTargetState {
                     format: texture_format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    blend: Some(OVERLAY_BLEND),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants,
+                    ..Default::default()
+                },
             }),
             depth_stencil: None,
             multisample: wgpu::MultisampleState {
@@ -161,19 +240,95 @@ impl DisplayDepthDrawPass {
     }
 
     pub fn on_resize(&mut self, device: &wgpu::Device, texture: &klgl::Texture) {
-        self.texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &self.texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ],
-            label: Some("depth_pass.bind_group"),
+        self.texture_bind_group = Self::create_bind_group(
+            device,
+            &self.texture_bind_group_layout,
+            texture,
+            &self.overlay_buffer,
+        );
+    }
+}
+
+impl klgl::DrawPass for DisplayDepthDrawPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let mut render_pass = targets
+            .surface_only()
+            .begin_render_pass(encoder, "Display Depth Render Pass");
+        self.render(&mut render_pass);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use klgl::DrawPass;
+
+    const SIZE: u32 = 4;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_half_opacity_mixes_scene_and_depth() {
+        let ctx = crate::test_utils::gpu_context(SIZE, SIZE);
+        let ctx = ctx.borrow();
+        // The shader samples the depth without a comparison sampler, which GLSL can't do
+        if ctx.adapter.get_info().backend == wgpu::Backend::Gl {
+            eprintln!("Skipping the depth overlay test, GL can't sample depth textures");
+            return;
+        }
+
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        let scene = klgl::Texture::create_render_target(&ctx.device, SIZE, SIZE, format, "scene");
+        let depth = klgl::Texture::create_depth_texture(&ctx.device, SIZE, SIZE, "depth");
+        let mut pass = DisplayDepthDrawPass::new(&ctx.device, format, &HashMap::new(), &depth);
+        let targets = klgl::PassTargets {
+            color: &scene.view,
+            depth: Some(&depth.view),
+            surface: &scene.view,
+            viewport: None,
+            camera: None,
+        };
+        // A red scene with everything at the same depth, shown as a dark gray
+        let clear = klgl::ClearPass::with_config(klgl::PassClearConfig {
+            color: Some(wgpu::Color {
+                r: 1.0,
+                g: 0.2,
+                b: 0.0,
+                a: 1.0,
+            }),
+            depth: Some(0.99),
         });
+
+        let mut render = |opacity: f32| {
+            pass.set_opacity(&ctx.queue, opacity);
+            let mut encoder = ctx
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            clear.record(&mut encoder, &targets);
+            pass.record(&mut encoder, &targets);
+            ctx.queue.submit([encoder.finish()]);
+            crate::test_utils::read_rgba8(&ctx, &scene.texture)[0]
+        };
+
+        // Opaque keeps the old look, transparent leaves the scene alone
+        let depth_color = render(1.0);
+        let scene_color = render(0.0);
+        assert_eq!(scene_color, [255, 51, 0, 255]);
+        assert_eq!(depth_color[0], depth_color[1]);
+        assert_eq!(depth_color[1], depth_color[2]);
+        assert!(
+            depth_color[0] > 0 && depth_color[0] < 128,
+            "{depth_color:?}"
+        );
+        assert_eq!(render(3.0), depth_color);
+
+        let mixed = render(0.5);
+        for i in 0..4 {
+            let expected = (depth_color[i] as f32 + scene_color[i] as f32) / 2.0;
+            assert!((mixed[i] as f32 - expected).abs() <= 1.0, "{mixed:?}");
+        }
     }
 }
@@ -0,0 +1,336 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use web_time::Duration;
+
+// Frames that can be read back at the same time before rendering waits for the oldest one
+const NUM_SLOTS: usize = 3;
+const BYTES_PER_PIXEL: u32 = 4;
+
+type MapResult = Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>;
+
+/// `frame_0000.png`, `frame_0001.png`, ...
+pub fn frame_path(dir: &Path, index: u32) -> PathBuf {
+    dir.join(format!("frame_{:04}.png", index))
+}
+
+// Rows of a texture copy are padded to COPY_BYTES_PER_ROW_ALIGNMENT
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let unpadded = width * BYTES_PER_PIXEL;
+    unpadded.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+}
+
+// Only 8 bit RGBA and BGRA can be written to PNG without conversion of the values
+fn is_bgra(format: wgpu::TextureFormat) -> Option<bool> {
+    use wgpu::TextureFormat::*;
+    match format {
+        Rgba8Unorm | Rgba8UnormSrgb => Some(false),
+        Bgra8Unorm | Bgra8UnormSrgb => Some(true),
+        _ => None,
+    }
+}
+
+/// Drops the row padding and swaps BGRA to RGBA
+fn to_rgba(data: &[u8], width: u32, height: u32, bytes_per_row: u32, bgra: bool) -> Vec<u8> {
+    let row_size = (width * BYTES_PER_PIXEL) as usize;
+    let mut rgba = Vec::with_capacity(row_size * height as usize);
+    for row in data.chunks(bytes_per_row as usize).take(height as usize) {
+        rgba.extend_from_slice(&row[..row_size]);
+    }
+    if bgra {
+        for pixel in rgba.chunks_exact_mut(BYTES_PER_PIXEL as usize) {
+            pixel.swap(0, 2);
+        }
+    }
+    rgba
+}
+
+/// Writes one frame read back from the GPU as PNG
+pub fn write_frame(
+    path: &Path,
+    width: u32,
+    height: u32,
+    bytes_per_row: u32,
+    data: &[u8],
+    bgra: bool,
+) -> anyhow::Result<()> {
+    let rgba = to_rgba(data, width, height, bytes_per_row, bgra);
+    image::save_buffer(path, &rgba, width, height, image::ExtendedColorType::Rgba8)?;
+    Ok(())
+}
+
+// Where a frame that was copied to a slot goes
+struct PendingFrame {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    bgra: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SlotState {
+    Idle,
+    /// Waits for the submit
+    Copied,
+    /// Waits for the buffer to be mapped
+    Mapping,
+}
+
+struct Slot {
+    buffer: Option<wgpu::Buffer>,
+    state: SlotState,
+    map_result: MapResult,
+    frame: Option<PendingFrame>,
+}
+
+/// Writes every rendered frame to a directory as a PNG sequence, e.g. to make a video.
+///
+/// While recording the simulation should advance by [`FrameRecorder::frame_dt`] per frame
+/// instead of the real time, so the sequence plays back at the recording rate no matter how
+/// long each frame took. Call `capture` before the submit, `after_submit` after it
+/// and `poll` once per frame.
+pub struct FrameRecorder {
+    dir: Option<PathBuf>,
+    next_frame: u32,
+    dt: Duration,
+    slots: Vec<Slot>,
+}
+
+impl FrameRecorder {
+    pub fn new(fps: u32) -> Self {
+        Self {
+            dir: None,
+            next_frame: 0,
+            dt: klgl::FixedTimestep::from_rate(fps).dt(),
+            slots: (0..NUM_SLOTS)
+                .map(|_| Slot {
+                    buffer: None,
+                    state: SlotState::Idle,
+                    map_result: Arc::new(Mutex::new(None)),
+                    frame: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Starts a new sequence in `dir`, numbered from zero
+    pub fn start(&mut self, dir: impl Into<PathBuf>) -> anyhow::Result<()> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        log::info!("Recording frames to {}", dir.display());
+        self.dir = Some(dir);
+        self.next_frame = 0;
+        Ok(())
+    }
+
+    /// Frames that were already captured are still written by `poll`
+    pub fn stop(&mut self) {
+        if self.dir.take().is_some() {
+            log::info!("Recorded {} frames", self.next_frame);
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Simulated time between two recorded frames
+    pub fn frame_dt(&self) -> Duration {
+        self.dt
+    }
+
+    /// Copies the texture for writing. Waits for an older frame if every slot is busy.
+    pub fn capture(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+
+        let Some(bgra) = is_bgra(texture.format()) else {
+            log::error!("Can't record frames of {:?}", texture.format());
+            self.stop();
+            return;
+        };
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            log::error!("Can't record frames, the surface can't be copied");
+            self.stop();
+            return;
+        }
+
+        let path = frame_path(&dir, self.next_frame);
+        let index = match self.idle_slot() {
+            Some(index) => index,
+            None => {
+                log::warn!("Frame readback can't keep up, waiting for it");
+                self.poll_with(device, wgpu::Maintain::Wait);
+                self.idle_slot().expect("Waited for the readback")
+            }
+        };
+
+        let (width, height) = (texture.width(), texture.height());
+        let bytes_per_row = padded_bytes_per_row(width);
+        let size = (bytes_per_row * height) as wgpu::BufferAddress;
+        let slot = &mut self.slots[index];
+        let buffer = match &slot.buffer {
+            Some(buffer) if buffer.size() >= size => buffer,
+            _ => slot
+                .buffer
+                .insert(device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Frame Readback Buffer"),
+                    size,
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })),
+        };
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        slot.state = SlotState::Copied;
+        slot.frame = Some(PendingFrame {
+            path,
+            width,
+            height,
+            bgra,
+        });
+        self.next_frame += 1;
+    }
+
+    /// Starts reading the frames copied by `capture`. Has to be called after the submit.
+    pub fn after_submit(&mut self) {
+        for slot in &mut self.slots {
+            if slot.state != SlotState::Copied {
+                continue;
+            }
+
+            let map_result = slot.map_result.clone();
+            if let Some(buffer) = &slot.buffer {
+                buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        *map_result.lock().unwrap() = Some(result);
+                    });
+            }
+            slot.state = SlotState::Mapping;
+        }
+    }
+
+    /// Writes the frames that were read back
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        self.poll_with(device, wgpu::Maintain::Poll);
+    }
+
+    fn poll_with(&mut self, device: &wgpu::Device, maintain: wgpu::Maintain) {
+        if self
+            .slots
+            .iter()
+            .all(|slot| slot.state != SlotState::Mapping)
+        {
+            return;
+        }
+
+        let _ = device.poll(maintain);
+        for slot in &mut self.slots {
+            if slot.state != SlotState::Mapping {
+                continue;
+            }
+            let Some(result) = slot.map_result.lock().unwrap().take() else {
+                continue;
+            };
+
+            let (Some(buffer), Some(frame)) = (&slot.buffer, slot.frame.take()) else {
+                continue;
+            };
+            match result {
+                Ok(()) => {
+                    let written = write_frame(
+                        &frame.path,
+                        frame.width,
+                        frame.height,
+                        padded_bytes_per_row(frame.width),
+                        &buffer.slice(..).get_mapped_range(),
+                        frame.bgra,
+                    );
+                    if let Err(err) = written {
+                        log::error!("Failed to write {}: {}", frame.path.display(), err);
+                    }
+                    buffer.unmap();
+                }
+                Err(err) => log::error!("Failed to read back a frame: {}", err),
+            }
+            slot.state = SlotState::Idle;
+        }
+    }
+
+    fn idle_slot(&self) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.state == SlotState::Idle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_are_unpadded() {
+        assert_eq!(padded_bytes_per_row(1), 256);
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+
+        // Two BGRA pixels per row, padded to 12 bytes
+        let data = [
+            1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, //
+            9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0,
+        ];
+        assert_eq!(
+            to_rgba(&data, 2, 2, 12, true),
+            [3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 16]
+        );
+    }
+
+    #[test]
+    fn test_three_frames_are_written_in_order() {
+        let dir = std::env::temp_dir().join(format!("frame_recorder_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (width, height) = (4, 2);
+        let bytes_per_row = padded_bytes_per_row(width);
+        for index in 0..3u32 {
+            let data = vec![index as u8 * 100; (bytes_per_row * height) as usize];
+            let path = frame_path(&dir, index);
+            write_frame(&path, width, height, bytes_per_row, &data, true).unwrap();
+        }
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["frame_0000.png", "frame_0001.png", "frame_0002.png"]
+        );
+
+        let frame = image::open(frame_path(&dir, 2)).unwrap().to_rgba8();
+        assert_eq!(frame.dimensions(), (width, height));
+        assert_eq!(frame.get_pixel(3, 1).0, [200; 4]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+/// Copies of a resource that is rewritten every frame, used in turn. Each frame writes the
+/// least recently used copy, so the copies bound in the frames the GPU may still be working
+/// on are left alone.
+pub struct FrameRing<T> {
+    items: Vec<T>,
+    current: usize,
+}
+
+impl<T> FrameRing<T> {
+    /// `make` creates the copy with the given index. The first one is current.
+    pub fn new(len: usize, make: impl FnMut(usize) -> T) -> Self {
+        assert!(len > 0, "Frame ring needs at least one item");
+        Self {
+            items: (0..len).map(make).collect(),
+            current: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// The copy written last, the one to bind
+    pub fn current(&self) -> &T {
+        &self.items[self.current]
+    }
+
+    /// Moves on to the least recently used copy and returns it for writing
+    pub fn advance(&mut self) -> &T {
+        self.current = (self.current + 1) % self.items.len();
+        &self.items[self.current]
+    }
+
+    /// Replaces every copy, e.g. when the resources have to grow
+    pub fn rebuild(&mut self, make: impl FnMut(usize) -> T) {
+        self.items = (0..self.items.len()).map(make).collect();
+    }
+
+    /// Replaces every copy with `len` new ones, e.g. when the frame latency changes
+    pub fn resize(&mut self, len: usize, make: impl FnMut(usize) -> T) {
+        assert!(len > 0, "Frame ring needs at least one item");
+        self.items = (0..len).map(make).collect();
+        self.current %= len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance_cycles_in_order() {
+        let mut ring = FrameRing::new(3, |index| index);
+        assert_eq!(*ring.current(), 0);
+
+        let mut written = Vec::new();
+        for _ in 0..7 {
+            let bound_last_frame = *ring.current();
+            let next = *ring.advance();
+            assert_ne!(next, bound_last_frame);
+            assert_eq!(*ring.current(), next);
+            written.push(next);
+        }
+        assert_eq!(written, [1, 2, 0, 1, 2, 0, 1]);
+
+        ring.rebuild(|index| index + 10);
+        assert_eq!(ring.len(), 3);
+        assert_eq!(*ring.current(), 11);
+
+        ring.resize(2, |index| index + 20);
+        assert_eq!(ring.len(), 2);
+        assert_eq!(*ring.current(), 21);
+        assert_eq!(*ring.advance(), 20);
+    }
+}
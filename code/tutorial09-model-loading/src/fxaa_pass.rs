@@ -0,0 +1,317 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wgpu::util::DeviceExt;
+
+/// Trades edge detection sensitivity and search length for speed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FxaaQuality {
+    #[allow(dead_code)]
+    Low,
+    Medium,
+    #[allow(dead_code)]
+    High,
+}
+
+impl FxaaQuality {
+    fn uniform(self, width: u32, height: u32) -> FxaaUniform {
+        // Values of the FXAA 3.11 quality presets 10, 12 and 39
+        let (edge_threshold, edge_threshold_min, search_steps) = match self {
+            FxaaQuality::Low => (0.25, 0.0833, 4),
+            FxaaQuality::Medium => (0.166, 0.0833, 5),
+            FxaaQuality::High => (0.125, 0.0312, 12),
+        };
+
+        FxaaUniform {
+            inverse_resolution: [1.0 / width.max(1) as f32, 1.0 / height.max(1) as f32],
+            edge_threshold,
+            edge_threshold_min,
+            subpixel_quality: 0.75,
+            search_steps,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// Has to match FxaaUniform in fxaa_shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct FxaaUniform {
+    inverse_resolution: [f32; 2],
+    edge_threshold: f32,
+    edge_threshold_min: f32,
+    subpixel_quality: f32,
+    search_steps: u32,
+    _padding: [u32; 2],
+}
+
+/// Smooths aliased edges of the tonemapped image and writes it to the surface
+pub struct FxaaPass {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    quality: FxaaQuality,
+    size: (u32, u32),
+}
+
+impl FxaaPass {
+    pub const NAME: &str = "fxaa";
+
+    pub fn new(ctx: Rc<RefCell<klgl::RenderContext>>, ldr_texture: &klgl::Texture) -> Self {
+        let quality = FxaaQuality::Medium;
+        let size = (ldr_texture.texture.width(), ldr_texture.texture.height());
+
+        let (pipeline, bind_group_layout, bind_group, uniform_buffer) = {
+            let ctx = ctx.borrow();
+            let device = &ctx.device;
+
+            let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("FXAA Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[quality.uniform(size.0, size.1)]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+            let bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("fxaa_bind_group_layout"),
+                });
+
+            let bind_group =
+                Self::create_bind_group(device, &bind_group_layout, ldr_texture, &uniform_buffer);
+            let pipeline = Self::create_pipeline(device, &bind_group_layout, ctx.config.format);
+            (pipeline, bind_group_layout, bind_group, uniform_buffer)
+        };
+
+        Self {
+            ctx,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            uniform_buffer,
+            quality,
+            size,
+        }
+    }
+
+    pub fn quality(&self) -> FxaaQuality {
+        self.quality
+    }
+
+    #[allow(dead_code)]
+    pub fn set_quality(&mut self, quality: FxaaQuality) {
+        self.quality = quality;
+        self.write_uniform();
+    }
+
+    /// Has to be called when the LDR texture is recreated
+    pub fn on_resize(&mut self, device: &wgpu::Device, ldr_texture: &klgl::Texture) {
+        self.size = (ldr_texture.texture.width(), ldr_texture.texture.height());
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            ldr_texture,
+            &self.uniform_buffer,
+        );
+        self.write_uniform();
+    }
+
+    fn write_uniform(&self) {
+        self.ctx.borrow().queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.quality.uniform(self.size.0, self.size.1)]),
+        );
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        ldr_texture: &klgl::Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&ldr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&ldr_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("fxaa_bind_group"),
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        surface_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("FXAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::FXAA_SHADER.into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("FXAA Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("FXAA Render Pipeline Layout"),
+                    bind_group_layouts: &[bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+impl klgl::DrawPass for FxaaPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let mut render_pass = targets
+            .surface_only()
+            .begin_render_pass(encoder, "FXAA Render Pass");
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{filled_texture, gpu_context, read_rgba8};
+
+    // Runs the pass on a grayscale image of `size` by `size` pixels, returns the gray levels
+    fn run_fxaa(quality: FxaaQuality, size: u32, image: &[u8]) -> Vec<u8> {
+        let ctx = gpu_context(size, size);
+        let format = ctx.borrow().config.format;
+        let texels: Vec<u8> = image.iter().flat_map(|&c| [c, c, c, 255]).collect();
+        let input = filled_texture(&ctx.borrow(), size, size, format, &texels);
+        let output =
+            klgl::Texture::create_render_target(&ctx.borrow().device, size, size, format, "output");
+        let mut fxaa = FxaaPass::new(ctx.clone(), &input);
+        fxaa.set_quality(quality);
+
+        let ctx = ctx.borrow();
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let targets = klgl::PassTargets {
+            color: &input.view,
+            depth: None,
+            surface: &output.view,
+            viewport: None,
+            camera: None,
+        };
+        klgl::DrawPass::record(&fxaa, &mut encoder, &targets);
+        ctx.queue.submit([encoder.finish()]);
+        read_rgba8(&ctx, &output.texture)
+            .iter()
+            .map(|texel| texel[0])
+            .collect()
+    }
+
+    #[test]
+    fn test_uniform_layout() {
+        assert_eq!(std::mem::size_of::<FxaaUniform>() % 16, 0);
+        assert_eq!(std::mem::offset_of!(FxaaUniform, search_steps), 20);
+
+        let uniform = FxaaQuality::Medium.uniform(800, 0);
+        assert_eq!(uniform.inverse_resolution, [1.0 / 800.0, 1.0]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_flat_image_is_unchanged() {
+        let output = run_fxaa(FxaaQuality::High, 8, &[128; 64]);
+        assert!(output.iter().all(|&c| c == 128), "{output:?}");
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_hard_edge_is_softened() {
+        // A staircase edge: white above the diagonal going one pixel down every two pixels
+        const SIZE: usize = 16;
+        let mut image = vec![0; SIZE * SIZE];
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                if y < x / 2 {
+                    image[y * SIZE + x] = 255;
+                }
+            }
+        }
+
+        for quality in [FxaaQuality::Low, FxaaQuality::Medium, FxaaQuality::High] {
+            let output = run_fxaa(quality, SIZE as u32, &image);
+
+            // The input only has black and white pixels, the output blends them along the edge
+            let blended = output.iter().filter(|&&c| c > 2 && c < 253).count();
+            assert!(blended > 0, "{quality:?}");
+
+            // Far from the edge nothing changes
+            assert_eq!(output[SIZE - 1], 255);
+            assert_eq!(output[(SIZE - 1) * SIZE], 0);
+        }
+    }
+}
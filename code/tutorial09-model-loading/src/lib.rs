@@ -4,10 +4,32 @@ use env_logger::Env;
 use winit::event_loop::{ControlFlow, EventLoop};
 
 mod app;
+mod bloom_pass;
+mod bounds;
+mod bvh;
 mod display_depth_draw_pass;
+#[cfg(not(target_arch = "wasm32"))]
+mod frame_recorder;
+mod frame_ring;
+mod fxaa_pass;
+mod light_markers_draw_pass;
+mod lights;
 mod lines_draw_pass;
+mod material_editor;
 mod model;
 mod models_draw_pass;
+mod occlusion_query_pass;
+mod overdraw_pass;
+mod particles;
+mod points_draw_pass;
+mod portal_pass;
+mod scene;
+mod shader_grid_pass;
+mod shadow_draw_pass;
+mod skybox_draw_pass;
+#[cfg(test)]
+mod test_utils;
+mod tonemap_pass;
 
 pub async fn run() {
     cfg_if::cfg_if! {
@@ -22,9 +44,23 @@ pub async fn run() {
         }
     }
 
-    let event_loop = EventLoop::new().unwrap();
+    let event_loop = EventLoop::<klgl::UserEvent>::with_user_event()
+        .build()
+        .unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = crate::app::App::new().await;
+    let mut app = klgl::App::<crate::app::Renderer>::new();
+    app.set_event_proxy(event_loop.create_proxy());
+    app.set_pause_on_blur(true);
+    app.set_window_config(klgl::WindowConfig::new("Tutorial 9: Model Loading"));
     event_loop.run_app(&mut app).unwrap();
 }
+
+// What the benches in `benches/` need, the modules themselves stay private
+#[doc(hidden)]
+pub mod bench_api {
+    pub use crate::lights::LightManager;
+    pub use crate::lines_draw_pass::LinesDrawPass;
+    pub use crate::model::Model;
+    pub use crate::models_draw_pass::ModelsDrawPass;
+}
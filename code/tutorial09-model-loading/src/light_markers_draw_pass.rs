@@ -0,0 +1,198 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wgpu::util::DeviceExt;
+
+use crate::lights::LightManager;
+
+// Half height of a marker in normalized device coordinates
+const MARKER_SIZE: f32 = 0.02;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MarkerUniform {
+    half_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+impl MarkerUniform {
+    fn new(aspect: f32) -> Self {
+        Self {
+            half_size: [MARKER_SIZE / aspect, MARKER_SIZE],
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Draws a small disc tinted with the light color at the position of every point light
+pub struct LightMarkersDrawPass {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    lights: Rc<RefCell<LightManager>>,
+    pipeline: wgpu::RenderPipeline,
+    camera_bind_group: wgpu::BindGroup,
+    marker_buffer: wgpu::Buffer,
+    marker_bind_group: wgpu::BindGroup,
+}
+
+impl LightMarkersDrawPass {
+    pub const NAME: &str = "light_markers";
+
+    pub fn new(
+        ctx: Rc<RefCell<klgl::RenderContext>>,
+        lights: Rc<RefCell<LightManager>>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group: &wgpu::BindGroup,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let (pipeline, marker_buffer, marker_bind_group) = {
+            let ctx = ctx.borrow();
+            let marker_buffer = ctx
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Light Marker Buffer"),
+                    contents: bytemuck::cast_slice(&[MarkerUniform::new(ctx.aspect())]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+
+            let marker_bind_group_layout =
+                ctx.device
+                    .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        entries: &[wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        }],
+                        label: Some("light_marker_bind_group_layout"),
+                    });
+
+            let marker_bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &marker_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: marker_buffer.as_entire_binding(),
+                }],
+                label: Some("light_marker_bind_group"),
+            });
+
+            let pipeline = Self::create_pipeline(
+                &ctx.device,
+                &[
+                    camera_bind_group_layout,
+                    lights.borrow().bind_group_layout(),
+                    &marker_bind_group_layout,
+                ],
+                color_format,
+            );
+
+            (pipeline, marker_buffer, marker_bind_group)
+        };
+
+        Self {
+            ctx,
+            lights,
+            pipeline,
+            camera_bind_group: camera_bind_group.clone(),
+            marker_buffer,
+            marker_bind_group,
+        }
+    }
+
+    /// Keeps the markers round after the surface was resized
+    pub fn on_resize(&self) {
+        let ctx = self.ctx.borrow();
+        ctx.queue.write_buffer(
+            &self.marker_buffer,
+            0,
+            bytemuck::cast_slice(&[MarkerUniform::new(ctx.aspect())]),
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        texture_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Markers Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                tutorial_embedded_content::LIGHT_MARKERS_SHADER.into(),
+            ),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Light Markers Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Light Markers Render Pipeline Layout"),
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: texture_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Markers are hidden by the scene but do not occlude anything themselves
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: klgl::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+impl klgl::DrawPass for LightMarkersDrawPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn per_view(&self) -> bool {
+        true
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let lights = self.lights.borrow();
+        let num_lights = lights.point_lights().len() as u32;
+        if num_lights == 0 {
+            return;
+        }
+
+        let mut render_pass = targets.begin_render_pass(encoder, "Light Markers Render Pass");
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, targets.camera_or(&self.camera_bind_group), &[]);
+        render_pass.set_bind_group(1, lights.bind_group(), &[]);
+        render_pass.set_bind_group(2, &self.marker_bind_group, &[]);
+        render_pass.draw(0..6, 0..num_lights);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wgpu::util::DeviceExt;
+
+/// Has to match the array size in the shader
+pub const MAX_POINT_LIGHTS: usize = 16;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    /// Distance at which the light fades out completely
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointLightsUniform {
+    lights: [PointLight; MAX_POINT_LIGHTS],
+    count: u32,
+    // Uniform structs are padded to 16 bytes
+    _padding: [u32; 3],
+}
+
+impl PointLightsUniform {
+    fn new() -> Self {
+        bytemuck::Zeroable::zeroed()
+    }
+
+    fn add(&mut self, light: PointLight) -> Option<usize> {
+        let index = self.count as usize;
+        if index == MAX_POINT_LIGHTS {
+            return None;
+        }
+
+        self.lights[index] = light;
+        self.count += 1;
+        Some(index)
+    }
+
+    fn active(&self) -> &[PointLight] {
+        &self.lights[..self.count as usize]
+    }
+}
+
+/// Owns the point lights buffer shared by every shader that does lighting
+pub struct LightManager {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    uniform: PointLightsUniform,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    dirty: bool,
+}
+
+impl LightManager {
+    pub fn new(ctx: Rc<RefCell<klgl::RenderContext>>) -> Self {
+        let uniform = PointLightsUniform::new();
+        let (buffer, bind_group_layout, bind_group) = {
+            let ctx = ctx.borrow();
+            let buffer = ctx
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Point Lights Buffer"),
+                    contents: bytemuck::cast_slice(&[uniform]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+
+            let bind_group_layout =
+                ctx.device
+                    .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        entries: &[wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        }],
+                        label: Some("point_lights_bind_group_layout"),
+                    });
+
+            let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+                label: Some("point_lights_bind_group"),
+            });
+
+            (buffer, bind_group_layout, bind_group)
+        };
+
+        Self {
+            ctx,
+            uniform,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            dirty: false,
+        }
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Returns the index of the new light or `None` if there are already `MAX_POINT_LIGHTS`
+    pub fn add_point_light(&mut self, light: PointLight) -> Option<usize> {
+        let index = self.uniform.add(light)?;
+        self.dirty = true;
+        Some(index)
+    }
+
+    /// Removes the lights after the first `count`
+    pub fn truncate(&mut self, count: usize) {
+        self.uniform.count = self.uniform.count.min(count as u32);
+        self.dirty = true;
+    }
+
+    pub fn set_light_position(&mut self, index: usize, position: [f32; 3]) {
+        self.uniform.lights[..self.uniform.count as usize][index].position = position;
+        self.dirty = true;
+    }
+
+    pub fn point_lights(&self) -> &[PointLight] {
+        self.uniform.active()
+    }
+
+    /// Uploads the lights if they changed since the last call
+    pub fn update(&mut self) {
+        if self.dirty {
+            self.dirty = false;
+            self.ctx.borrow().queue.write_buffer(
+                &self.buffer,
+                0,
+                bytemuck::cast_slice(&[self.uniform]),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniform_layout_is_16_byte_aligned() {
+        assert_eq!(std::mem::size_of::<PointLight>(), 32);
+        assert_eq!(std::mem::size_of::<PointLightsUniform>() % 16, 0);
+        assert_eq!(
+            std::mem::offset_of!(PointLightsUniform, count),
+            MAX_POINT_LIGHTS * std::mem::size_of::<PointLight>()
+        );
+    }
+
+    #[test]
+    fn test_add_point_light_until_full() {
+        let mut uniform = PointLightsUniform::new();
+        for i in 0..MAX_POINT_LIGHTS {
+            assert_eq!(uniform.add(PointLight::default()), Some(i));
+        }
+
+        assert_eq!(uniform.add(PointLight::default()), None);
+        assert_eq!(uniform.active().len(), MAX_POINT_LIGHTS);
+    }
+}
@@ -1,8 +1,11 @@
 use std::{cell::RefCell, rc::Rc};
 
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Matrix4, Point3, Transform, Vector3, Vector4};
 use wgpu::util::DeviceExt;
 
+use crate::bounds::BoundingBox;
+use crate::model::ModelVertex;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
@@ -25,40 +28,517 @@ impl Vertex {
     }
 }
 
+/// Every pair of vertices in the lines buffer read as a single instance
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Segment {
+    start: Vertex,
+    end: Vertex,
+}
+
+impl Segment {
+    const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x3,
+        3 => Float32x3,
+    ];
+
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineUniform {
+    viewport_size: [f32; 2],
+    line_width: f32,
+    _padding: f32,
+}
+
+/// Reference implementation of the quad expansion in `vs_thick_line`.
+/// Returns the clip space corners on both sides of the start and then of the end.
+#[allow(dead_code)]
+fn expand_segment(
+    start: Vector4<f32>,
+    end: Vector4<f32>,
+    line_width: f32,
+    viewport_size: [f32; 2],
+) -> [Vector4<f32>; 4] {
+    let clip_to_near = |point: Vector4<f32>, other: Vector4<f32>| {
+        if point.z >= 0.0 {
+            return point;
+        }
+        point + (other - point) * (point.z / (point.z - other.z))
+    };
+    let (start, end) = (clip_to_near(start, end), clip_to_near(end, start));
+
+    let pixels = [
+        (end.x / end.w - start.x / start.w) * viewport_size[0],
+        (end.y / end.w - start.y / start.w) * viewport_size[1],
+    ];
+    let length = pixels[0].hypot(pixels[1]);
+    let direction = match length > 0.0001 {
+        true => [pixels[0] / length, pixels[1] / length],
+        false => [1.0, 0.0],
+    };
+    let offset = [
+        -direction[1] * line_width / viewport_size[0],
+        direction[0] * line_width / viewport_size[1],
+    ];
+
+    let corner = |point: Vector4<f32>, side: f32| {
+        point
+            + Vector4::new(
+                offset[0] * side * point.w,
+                offset[1] * side * point.w,
+                0.0,
+                0.0,
+            )
+    };
+    [
+        corner(start, -1.0),
+        corner(start, 1.0),
+        corner(end, -1.0),
+        corner(end, 1.0),
+    ]
+}
+
+/// The 12 edges of `bounds` moved by `transform`, as vertex pairs for [`LinesDrawPass::set_segments`]
+pub fn box_segments(
+    bounds: &BoundingBox,
+    transform: &Matrix4<f32>,
+    color: [f32; 3],
+) -> Vec<Vertex> {
+    // Bits of the index select min or max on each axis
+    let corner = |index: usize| {
+        let pick = |bit: usize, min: f32, max: f32| match index & bit {
+            0 => min,
+            _ => max,
+        };
+        let point = Point3::new(
+            pick(1, bounds.min.x, bounds.max.x),
+            pick(2, bounds.min.y, bounds.max.y),
+            pick(4, bounds.min.z, bounds.max.z),
+        );
+        Vertex {
+            position: transform.transform_point(point).into(),
+            color,
+        }
+    };
+
+    // Corners that differ in a single bit share an edge
+    (0..8)
+        .flat_map(|index| [1, 2, 4].map(|bit| (index, bit)))
+        .filter(|(index, bit)| index & bit == 0)
+        .flat_map(|(index, bit)| [corner(index), corner(index | bit)])
+        .collect()
+}
+
+// Lines never write depth, so they don't hide what is drawn after them.
+// Without the depth test they stay on top. The depth format is kept because
+// the pipeline has to match the depth attachment of the render pass.
+fn line_depth_state(
+    depth_stencil_state: Option<wgpu::DepthStencilState>,
+    depth_test: bool,
+    bias: wgpu::DepthBiasState,
+) -> Option<wgpu::DepthStencilState> {
+    depth_stencil_state.map(|state| wgpu::DepthStencilState {
+        depth_write_enabled: false,
+        depth_compare: match depth_test {
+            true => state.depth_compare,
+            false => wgpu::CompareFunction::Always,
+        },
+        bias,
+        ..state
+    })
+}
+
+/// A line of `length` world units from each vertex along its normal, both moved by `transform`.
+/// Vertices without a normal are skipped.
+pub fn normal_segments<'a>(
+    vertices: impl IntoIterator<Item = &'a ModelVertex>,
+    transform: &Matrix4<f32>,
+    length: f32,
+    color: [f32; 3],
+) -> Vec<Vertex> {
+    let normal_matrix = klgl::normal_matrix(transform);
+    vertices
+        .into_iter()
+        .filter_map(|vertex| {
+            let normal = normal_matrix * Vector3::from(vertex.normal);
+            if normal.magnitude2() == 0.0 {
+                return None;
+            }
+            let start = transform.transform_point(Point3::from(vertex.position));
+            let end = start + normal.normalize() * length;
+            Some([
+                Vertex {
+                    position: start.into(),
+                    color,
+                },
+                Vertex {
+                    position: end.into(),
+                    color,
+                },
+            ])
+        })
+        .flatten()
+        .collect()
+}
+
+// Lines have no faces to cull. The quads of thick lines do, but their winding depends on
+// the direction of the segment on screen, so culling would drop about half of them.
+fn line_primitive_state(topology: wgpu::PrimitiveTopology) -> wgpu::PrimitiveState {
+    wgpu::PrimitiveState {
+        topology,
+        strip_index_format: None,
+        front_face: wgpu::FrontFace::Ccw,
+        cull_mode: None,
+        polygon_mode: wgpu::PolygonMode::Fill, // others require Features::NON_FILL_POLYGON_MODE
+        unclipped_depth: false,                // Requires Features::DEPTH_CLIP_CONTROL
+        conservative: false,                   // Requires Features::CONSERVATIVE_RASTERIZATION
+    }
+}
+
+// Depth states of the 1px and the thick pipelines. Depth bias is only defined for
+// triangles and WebGPU rejects it for line topologies, so only thick lines get it.
+fn line_depth_states(
+    depth_stencil_state: Option<wgpu::DepthStencilState>,
+    depth_test: bool,
+    bias: wgpu::DepthBiasState,
+) -> (
+    Option<wgpu::DepthStencilState>,
+    Option<wgpu::DepthStencilState>,
+) {
+    (
+        line_depth_state(
+            depth_stencil_state.clone(),
+            depth_test,
+            wgpu::DepthBiasState::default(),
+        ),
+        line_depth_state(depth_stencil_state, depth_test, bias),
+    )
+}
+
+// Vertex buffer for segments that change at runtime. Grows when needed and is reused otherwise.
+struct SegmentBuffer {
+    label: &'static str,
+    buffer: Option<wgpu::Buffer>,
+    num_vertices: u32,
+}
+
+impl SegmentBuffer {
+    fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            buffer: None,
+            num_vertices: 0,
+        }
+    }
+
+    fn write(&mut self, ctx: &klgl::RenderContext, vertices: &[Vertex]) {
+        self.num_vertices = vertices.len() as u32;
+        if vertices.is_empty() {
+            return;
+        }
+
+        let size = std::mem::size_of_val(vertices) as wgpu::BufferAddress;
+        if self
+            .buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size() < size)
+        {
+            self.buffer = Some(ctx.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(self.label),
+                size: size.next_power_of_two(),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        if let Some(buffer) = &self.buffer {
+            ctx.queue
+                .write_buffer(buffer, 0, bytemuck::cast_slice(vertices));
+        }
+    }
+
+    fn get(&self) -> Option<(&wgpu::Buffer, u32)> {
+        self.buffer
+            .as_ref()
+            .filter(|_| self.num_vertices != 0)
+            .map(|buffer| (buffer, self.num_vertices))
+    }
+}
+
+// Grid on the plane spanned by `axes`, the lines along the first axis are green
+// and the lines along the second one are red
+fn grid_vertices(axes: (Vector3<f32>, Vector3<f32>)) -> Vec<Vertex> {
+    let (u, v) = axes;
+    let ranges: [(Vector3<f32>, Vector3<f32>, i32, [f32; 3]); 2] =
+        [(u, v, 51, [1.0, 0.0, 0.0]), (v, u, 51, [0.0, 1.0, 0.0])];
+
+    ranges
+        .iter()
+        .map(|(spread_direction, line_direction, num_lines, color)| {
+            let h = num_lines / 2;
+            let hf = h as f32;
+            (-h..h)
+                .map(move |x| {
+                    [
+                        (x as f32) * spread_direction + line_direction * hf,
+                        (x as f32) * spread_direction - line_direction * hf,
+                    ]
+                })
+                .flatten()
+                .map(move |v| Vertex {
+                    position: v.into(),
+                    color: *color,
+                })
+        })
+        .flatten()
+        .collect()
+}
+
 pub struct LinesDrawPass {
-    #[allow(dead_code)]
     ctx: Rc<RefCell<klgl::RenderContext>>,
     pub pipeline: wgpu::RenderPipeline,
+    thick_pipeline: wgpu::RenderPipeline,
     pub vertex_buffer: wgpu::Buffer,
     pub num_lines: u32,
+    camera_bind_group: wgpu::BindGroup,
+    line_buffer: wgpu::Buffer,
+    line_bind_group: wgpu::BindGroup,
+    line_width: f32,
+    // Kept to recreate the pipelines when the depth test changes
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    line_bind_group_layout: wgpu::BindGroupLayout,
+    color_format: wgpu::TextureFormat,
+    depth_stencil_state: Option<wgpu::DepthStencilState>,
+    depth_test: bool,
+    depth_bias: wgpu::DepthBiasState,
+    show_grid: bool,
+    // Extra vertex pairs drawn after the grid, e.g. debug shapes
+    segments: SegmentBuffer,
+    // Lines of klgl::DebugDraw, replaced every frame
+    debug_segments: SegmentBuffer,
 }
 
 impl LinesDrawPass {
+    pub const NAME: &str = "lines";
+
+    /// Pulls the lines a bit towards the camera, so the grid is not hidden by a floor at the same height
+    pub const DEFAULT_DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState {
+        constant: -2,
+        slope_scale: -1.0,
+        clamp: 0.0,
+    };
+
     pub fn new(
         ctx: Rc<RefCell<klgl::RenderContext>>,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group: &wgpu::BindGroup,
+        color_format: wgpu::TextureFormat,
         depth_stencil_state: Option<wgpu::DepthStencilState>,
     ) -> Self {
-        let (lines_vertex_buffer, num_lines) = Self::make_lines_buffer(&ctx.borrow().device);
+        let (lines_vertex_buffer, num_lines) =
+            Self::make_lines_buffer(&ctx.borrow().device, klgl::CoordinateSystem::default());
 
-        let pipeline = {
+        let line_width = 0.0;
+        let depth_test = true;
+        let depth_bias = Self::DEFAULT_DEPTH_BIAS;
+        let (pipeline, thick_pipeline, line_buffer, line_bind_group_layout, line_bind_group) = {
             let ctx = ctx.borrow();
-            Self::create_pipeline(
+            let line_buffer = ctx
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Line Buffer"),
+                    contents: bytemuck::cast_slice(&[Self::line_uniform(&ctx, line_width)]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+
+            let line_bind_group_layout =
+                ctx.device
+                    .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        entries: &[wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        }],
+                        label: Some("line_bind_group_layout"),
+                    });
+
+            let line_bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &line_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: line_buffer.as_entire_binding(),
+                }],
+                label: Some("line_bind_group"),
+            });
+
+            let (pipeline, thick_pipeline) = Self::create_pipelines(
                 &ctx.device,
                 camera_bind_group_layout,
-                ctx.config.format,
-                depth_stencil_state,
+                &line_bind_group_layout,
+                color_format,
+                line_depth_states(depth_stencil_state.clone(), depth_test, depth_bias),
+            );
+            (
+                pipeline,
+                thick_pipeline,
+                line_buffer,
+                line_bind_group_layout,
+                line_bind_group,
             )
         };
 
         Self {
             ctx,
             pipeline,
+            thick_pipeline,
             vertex_buffer: lines_vertex_buffer,
             num_lines,
+            camera_bind_group: camera_bind_group.clone(),
+            line_buffer,
+            line_bind_group,
+            line_width,
+            camera_bind_group_layout: camera_bind_group_layout.clone(),
+            line_bind_group_layout,
+            color_format,
+            depth_stencil_state,
+            depth_test,
+            depth_bias,
+            show_grid: true,
+            segments: SegmentBuffer::new("Segments Vertex Buffer"),
+            debug_segments: SegmentBuffer::new("Debug Segments Vertex Buffer"),
+        }
+    }
+
+    pub fn depth_test(&self) -> bool {
+        self.depth_test
+    }
+
+    /// Without the depth test the lines are drawn on top of the scene
+    pub fn set_depth_test(&mut self, depth_test: bool) {
+        if self.depth_test == depth_test {
+            return;
+        }
+
+        self.depth_test = depth_test;
+        let ctx = self.ctx.clone();
+        self.rebuild_pipelines(&ctx.borrow().device);
+    }
+
+    pub fn depth_bias(&self) -> wgpu::DepthBiasState {
+        self.depth_bias
+    }
+
+    /// Negative values move the lines towards the camera, positive ones away from it.
+    /// Only applies to thick lines, see [`Self::set_line_width`].
+    pub fn set_depth_bias(&mut self, device: &wgpu::Device, constant: i32, slope_scale: f32) {
+        self.depth_bias = wgpu::DepthBiasState {
+            constant,
+            slope_scale,
+            ..self.depth_bias
+        };
+        self.rebuild_pipelines(device);
+    }
+
+    fn rebuild_pipelines(&mut self, device: &wgpu::Device) {
+        (self.pipeline, self.thick_pipeline) = Self::create_pipelines(
+            device,
+            &self.camera_bind_group_layout,
+            &self.line_bind_group_layout,
+            self.color_format,
+            line_depth_states(
+                self.depth_stencil_state.clone(),
+                self.depth_test,
+                self.depth_bias,
+            ),
+        );
+    }
+
+    pub fn set_show_grid(&mut self, show_grid: bool) {
+        self.show_grid = show_grid;
+    }
+
+    /// Replaces the extra segments. Every two vertices make a segment.
+    pub fn set_segments(&mut self, vertices: &[Vertex]) {
+        self.segments.write(&self.ctx.borrow(), vertices);
+    }
+
+    /// Replaces the debug lines, see [`klgl::flush_debug_draw`]
+    pub fn set_debug_segments(&mut self, vertices: &[klgl::DebugVertex]) {
+        // Both vertex types have the same layout
+        self.debug_segments
+            .write(&self.ctx.borrow(), bytemuck::cast_slice(vertices));
+    }
+
+    /// Width of the lines in pixels. `0` draws cheap 1px lines with a `LineList`.
+    pub fn set_line_width(&mut self, line_width: f32) {
+        self.line_width = line_width.max(0.0);
+        self.write_line_uniform();
+    }
+
+    /// Keeps the line width in pixels after the surface was resized
+    pub fn on_resize(&self) {
+        self.write_line_uniform();
+    }
+
+    fn line_uniform(ctx: &klgl::RenderContext, line_width: f32) -> LineUniform {
+        LineUniform {
+            viewport_size: [ctx.config.width as f32, ctx.config.height as f32],
+            line_width,
+            _padding: 0.0,
         }
     }
 
+    fn write_line_uniform(&self) {
+        let ctx = self.ctx.borrow();
+        ctx.queue.write_buffer(
+            &self.line_buffer,
+            0,
+            bytemuck::cast_slice(&[Self::line_uniform(&ctx, self.line_width)]),
+        );
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        line_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        (thin_depth_state, thick_depth_state): (
+            Option<wgpu::DepthStencilState>,
+            Option<wgpu::DepthStencilState>,
+        ),
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let pipeline = Self::create_pipeline(
+            device,
+            camera_bind_group_layout,
+            color_format,
+            thin_depth_state,
+        );
+        let thick_pipeline = Self::create_thick_pipeline(
+            device,
+            &[camera_bind_group_layout, line_bind_group_layout],
+            color_format,
+            thick_depth_state,
+        );
+        (pipeline, thick_pipeline)
+    }
+
     fn create_pipeline(
         device: &wgpu::Device,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
@@ -80,15 +560,7 @@ impl LinesDrawPass {
                     push_constant_ranges: &[],
                 }),
             ),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::LineList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill, // others require Features::NON_FILL_POLYGON_MODE
-                unclipped_depth: false,                // Requires Features::DEPTH_CLIP_CONTROL
-                conservative: false, // Requires Features::CONSERVATIVE_RASTERIZATION
-            },
+            primitive: line_primitive_state(wgpu::PrimitiveTopology::LineList),
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: Some("vs_main"),
@@ -116,42 +588,102 @@ impl LinesDrawPass {
         })
     }
 
+    fn create_thick_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        texture_format: wgpu::TextureFormat,
+        depth_stencil_state: Option<wgpu::DepthStencilState>,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Solid Color Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                tutorial_embedded_content::COLORED_VERTICES_SHADER.into(),
+            ),
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Thick Lines Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Thick Lines Render Pipeline Layout"),
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                }),
+            ),
+            primitive: line_primitive_state(wgpu::PrimitiveTopology::TriangleList),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_thick_line"),
+                buffers: &[Segment::layout()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: texture_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            depth_stencil: depth_stencil_state,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
     pub fn render(&self, render_pass: &mut wgpu::RenderPass, camera_bind_group: &wgpu::BindGroup) {
-        if self.num_lines != 0 {
+        if self.show_grid {
+            self.draw_lines(
+                render_pass,
+                camera_bind_group,
+                &self.vertex_buffer,
+                self.num_lines,
+            );
+        }
+        for segments in [&self.segments, &self.debug_segments] {
+            if let Some((buffer, num_vertices)) = segments.get() {
+                self.draw_lines(render_pass, camera_bind_group, buffer, num_vertices);
+            }
+        }
+    }
+
+    fn draw_lines(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        camera_bind_group: &wgpu::BindGroup,
+        vertex_buffer: &wgpu::Buffer,
+        num_vertices: u32,
+    ) {
+        if num_vertices != 0 && self.line_width > 0.0 {
+            render_pass.set_pipeline(&self.thick_pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.line_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.draw(0..6, 0..num_vertices / 2);
+        } else if num_vertices != 0 {
             render_pass.set_pipeline(&self.pipeline);
             render_pass.set_bind_group(0, camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.draw(0..self.num_lines, 0..self.num_lines / 2);
-        }
-    }
-
-    fn make_lines_buffer(device: &wgpu::Device) -> (wgpu::Buffer, u32) {
-        let ranges: [(Vector3<f32>, Vector3<f32>, i32, [f32; 3]); 2] = [
-            (Vector3::unit_x(), Vector3::unit_y(), 51, [1.0, 0.0, 0.0]),
-            (Vector3::unit_y(), Vector3::unit_x(), 51, [0.0, 1.0, 0.0]),
-        ];
-
-        let vertices: Vec<Vertex> = ranges
-            .iter()
-            .map(|(spread_direction, line_direction, num_lines, color)| {
-                let h = num_lines / 2;
-                let hf = h as f32;
-                (-h..h)
-                    .map(move |x| {
-                        [
-                            (x as f32) * spread_direction + line_direction * hf,
-                            (x as f32) * spread_direction - line_direction * hf,
-                        ]
-                    })
-                    .flatten()
-                    .map(move |v| Vertex {
-                        position: v.into(),
-                        color: *color,
-                    })
-            })
-            .flatten()
-            .collect();
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.draw(0..num_vertices, 0..1);
+        }
+    }
+
+    /// Rebuilds the grid on the ground plane of `coordinate_system`
+    #[allow(dead_code)]
+    pub fn set_coordinate_system(&mut self, coordinate_system: klgl::CoordinateSystem) {
+        let (vertex_buffer, num_lines) =
+            Self::make_lines_buffer(&self.ctx.borrow().device, coordinate_system);
+        self.vertex_buffer = vertex_buffer;
+        self.num_lines = num_lines;
+    }
 
+    fn make_lines_buffer(
+        device: &wgpu::Device,
+        coordinate_system: klgl::CoordinateSystem,
+    ) -> (wgpu::Buffer, u32) {
+        let vertices = grid_vertices(coordinate_system.ground_axes());
         (
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Vertex Buffer"),
@@ -162,3 +694,223 @@ impl LinesDrawPass {
         )
     }
 }
+
+impl klgl::DrawPass for LinesDrawPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn per_view(&self) -> bool {
+        true
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let mut render_pass = targets.begin_render_pass(encoder, "Lines Render Pass");
+        self.render(&mut render_pass, targets.camera_or(&self.camera_bind_group));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    fn assert_vec_eq(a: Vector4<f32>, b: Vector4<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn test_segment_matches_vertex_pairs() {
+        assert_eq!(
+            std::mem::size_of::<Segment>(),
+            2 * std::mem::size_of::<Vertex>()
+        );
+        assert_eq!(std::mem::size_of::<LineUniform>() % 16, 0);
+    }
+
+    #[test]
+    fn test_depth_test_toggle() {
+        let depth_stencil_state = Some(wgpu::DepthStencilState {
+            format: klgl::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        });
+
+        let bias = wgpu::DepthBiasState::default();
+        let tested = line_depth_state(depth_stencil_state.clone(), true, bias).unwrap();
+        assert_eq!(tested.depth_compare, wgpu::CompareFunction::Less);
+        assert!(!tested.depth_write_enabled);
+
+        let overlay = line_depth_state(depth_stencil_state, false, bias).unwrap();
+        assert_eq!(overlay.depth_compare, wgpu::CompareFunction::Always);
+        assert!(!overlay.depth_write_enabled);
+        assert_eq!(overlay.format, klgl::Texture::DEPTH_FORMAT);
+
+        assert!(line_depth_state(None, true, bias).is_none());
+    }
+
+    #[test]
+    fn test_depth_bias_in_descriptor() {
+        let depth_stencil_state = Some(wgpu::DepthStencilState {
+            format: klgl::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        });
+        let bias = wgpu::DepthBiasState {
+            constant: -4,
+            slope_scale: -1.5,
+            clamp: 0.0,
+        };
+
+        let (thin, thick) = line_depth_states(depth_stencil_state, true, bias);
+        let thick = thick.unwrap();
+        assert_eq!(thick.bias.constant, -4);
+        assert_eq!(thick.bias.slope_scale, -1.5);
+        assert!(!thick.depth_write_enabled);
+
+        // Line topologies must not have a bias
+        assert!(!thin.unwrap().bias.is_enabled());
+    }
+
+    #[test]
+    fn test_normal_segment_of_a_vertex() {
+        let vertex = ModelVertex {
+            position: [1.0, 2.0, 3.0],
+            tex_coords: [0.0, 0.0],
+            normal: [0.0, 0.0, 2.0],
+            layer: 0,
+            color: [1.0, 1.0, 1.0],
+        };
+        let segments = normal_segments([&vertex], &Matrix4::from_scale(1.0), 0.5, [0.0, 1.0, 1.0]);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].position, [1.0, 2.0, 3.0]);
+        assert_eq!(segments[1].position, [1.0, 2.0, 3.5]);
+        assert_eq!(segments[1].color, [0.0, 1.0, 1.0]);
+
+        // The length is in world units however the model is scaled and turned
+        let transform = Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0))
+            * Matrix4::from_angle_x(cgmath::Deg(90.0))
+            * Matrix4::from_scale(0.1);
+        let segments = normal_segments([&vertex], &transform, 0.5, [0.0, 1.0, 1.0]);
+        let start = Vector3::from(segments[0].position);
+        let end = Vector3::from(segments[1].position);
+        assert!((start - Vector3::new(10.1, -0.3, 0.2)).magnitude() < 1e-5);
+        assert!((end - start - Vector3::new(0.0, -0.5, 0.0)).magnitude() < 1e-5);
+
+        let flat = ModelVertex {
+            normal: [0.0; 3],
+            ..vertex
+        };
+        assert!(normal_segments([&flat], &transform, 0.5, [0.0, 1.0, 1.0]).is_empty());
+    }
+
+    #[test]
+    fn test_lines_are_not_culled() {
+        for topology in [
+            wgpu::PrimitiveTopology::LineList,
+            wgpu::PrimitiveTopology::TriangleList,
+        ] {
+            let primitive = line_primitive_state(topology);
+            assert_eq!(primitive.topology, topology);
+            assert_eq!(primitive.cull_mode, None);
+        }
+    }
+
+    #[test]
+    fn test_unit_box_segments() {
+        let bounds = BoundingBox {
+            min: Point3::new(0.0, 0.0, 0.0),
+            max: Point3::new(1.0, 1.0, 1.0),
+        };
+        let vertices = box_segments(&bounds, &Matrix4::from_scale(1.0), [1.0, 1.0, 0.0]);
+        assert_eq!(vertices.len(), 24);
+
+        let mut edges: Vec<([f32; 3], [f32; 3])> = vertices
+            .chunks_exact(2)
+            .map(|pair| (pair[0].position, pair[1].position))
+            .collect();
+        for (start, end) in &edges {
+            // Every edge connects two corners along exactly one axis
+            let changed = (0..3).filter(|axis| start[*axis] != end[*axis]).count();
+            assert_eq!(changed, 1);
+            assert!(start.iter().chain(end).all(|v| *v == 0.0 || *v == 1.0));
+        }
+        edges.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        edges.dedup();
+        assert_eq!(edges.len(), 12);
+
+        // Moved by the instance transform
+        let moved = box_segments(
+            &bounds,
+            &Matrix4::from_translation(Vector3::new(2.0, 0.0, 0.0)),
+            [1.0, 1.0, 0.0],
+        );
+        assert_eq!(moved[0].position, [2.0, 0.0, 0.0]);
+        assert_eq!(moved[1].position, [3.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_expand_horizontal_segment() {
+        let corners = expand_segment(
+            Vector4::new(-0.5, 0.0, 0.5, 1.0),
+            Vector4::new(0.5, 0.0, 0.5, 1.0),
+            6.0,
+            [800.0, 600.0],
+        );
+
+        // 3 pixels on each side of a 600 pixels tall viewport spanning 2 units
+        assert_vec_eq(corners[0], Vector4::new(-0.5, -0.01, 0.5, 1.0));
+        assert_vec_eq(corners[1], Vector4::new(-0.5, 0.01, 0.5, 1.0));
+        assert_vec_eq(corners[2], Vector4::new(0.5, -0.01, 0.5, 1.0));
+        assert_vec_eq(corners[3], Vector4::new(0.5, 0.01, 0.5, 1.0));
+    }
+
+    #[test]
+    fn test_width_does_not_depend_on_distance() {
+        // A vertical segment further away, w scales the offset back to the same pixel width
+        let corners = expand_segment(
+            Vector4::new(0.0, -1.0, 1.0, 4.0),
+            Vector4::new(0.0, 1.0, 1.0, 4.0),
+            4.0,
+            [800.0, 600.0],
+        );
+
+        let ndc_x = |corner: Vector4<f32>| corner.x / corner.w;
+        assert!((ndc_x(corners[0]) - 0.005).abs() < 1e-6);
+        assert!((ndc_x(corners[1]) + 0.005).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_segment_is_clipped_to_near_plane() {
+        let corners = expand_segment(
+            Vector4::new(0.0, 0.0, -1.0, 0.5),
+            Vector4::new(0.0, 0.0, 1.0, 1.5),
+            2.0,
+            [800.0, 600.0],
+        );
+
+        // The start moves halfway to the end where z crosses zero
+        assert_eq!(corners[0].z, 0.0);
+        assert!((corners[0].w - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_grid_lies_on_the_ground() {
+        let z_up = grid_vertices(klgl::CoordinateSystem::ZUp.ground_axes());
+        let y_up = grid_vertices(klgl::CoordinateSystem::YUp.ground_axes());
+        assert_eq!(z_up.len(), y_up.len());
+        assert!(z_up.iter().all(|v| v.position[2] == 0.0));
+        assert!(y_up.iter().all(|v| v.position[1] == 0.0));
+
+        // Same layout, only the second axis changes
+        for (a, b) in z_up.iter().zip(&y_up) {
+            assert_eq!(a.position[0], b.position[0]);
+            assert_eq!(a.position[1], b.position[2]);
+            assert_eq!(a.color, b.color);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::model::MaterialUniform;
+
+// Change of ambient, diffuse and specular per step
+const STRENGTH_STEP: f32 = 0.1;
+// Shininess is multiplied or divided by this per step, so small and large values change alike
+const SHININESS_STEP: f32 = 1.25;
+const MAX_SHININESS: f32 = 1024.0;
+
+/// Lighting value of a material the editor changes
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MaterialField {
+    Ambient,
+    Diffuse,
+    Specular,
+    #[default]
+    Shininess,
+}
+
+impl MaterialField {
+    pub fn next(self) -> Self {
+        match self {
+            MaterialField::Ambient => MaterialField::Diffuse,
+            MaterialField::Diffuse => MaterialField::Specular,
+            MaterialField::Specular => MaterialField::Shininess,
+            MaterialField::Shininess => MaterialField::Ambient,
+        }
+    }
+}
+
+/// Changes the lighting of one material of the model at a time, to see what each value does
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MaterialEditor {
+    /// Index of the selected material, wraps around the materials of the model
+    pub material: usize,
+    pub field: MaterialField,
+}
+
+impl MaterialEditor {
+    /// Moves the selection by `offset` materials, wrapping around `count`
+    pub fn select(&mut self, offset: isize, count: usize) {
+        if count != 0 {
+            let current = (self.material % count) as isize;
+            self.material = (current + offset).rem_euclid(count as isize) as usize;
+        }
+    }
+
+    /// `uniform` with the selected field changed by `steps`, negative steps decrease it
+    pub fn adjusted(&self, uniform: MaterialUniform, steps: i32) -> MaterialUniform {
+        let strength = |value: f32| (value + steps as f32 * STRENGTH_STEP).max(0.0);
+        match self.field {
+            MaterialField::Ambient => MaterialUniform {
+                ambient: strength(uniform.ambient),
+                ..uniform
+            },
+            MaterialField::Diffuse => MaterialUniform {
+                diffuse: strength(uniform.diffuse),
+                ..uniform
+            },
+            MaterialField::Specular => MaterialUniform {
+                specular: strength(uniform.specular),
+                ..uniform
+            },
+            MaterialField::Shininess => MaterialUniform {
+                shininess: (uniform.shininess * SHININESS_STEP.powi(steps))
+                    .clamp(1.0, MAX_SHININESS),
+                ..uniform
+            },
+        }
+    }
+
+    /// Name and values of the selected material, e.g. for the window title
+    pub fn describe(&self, name: &str, uniform: &MaterialUniform) -> String {
+        format!(
+            "{name}: ambient {:.2}, diffuse {:.2}, specular {:.2}, shininess {:.1}, editing {:?}",
+            uniform.ambient, uniform.diffuse, uniform.specular, uniform.shininess, self.field
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shininess_updates_the_uniform_bytes() {
+        let editor = MaterialEditor::default();
+        let uniform = MaterialUniform::IDENTITY;
+        let adjusted = editor.adjusted(uniform, 1);
+        assert_eq!(adjusted.shininess, 40.0);
+
+        // Only the bytes of the shininess change, it is the last field of the uniform
+        let before = bytemuck::bytes_of(&uniform);
+        let after = bytemuck::bytes_of(&adjusted);
+        let offset = std::mem::offset_of!(MaterialUniform, shininess);
+        assert_eq!(offset + 4, std::mem::size_of::<MaterialUniform>());
+        assert_eq!(before[..offset], after[..offset]);
+        assert_eq!(after[offset..], 40.0f32.to_ne_bytes());
+
+        // Stays a valid exponent
+        assert_eq!(editor.adjusted(uniform, -100).shininess, 1.0);
+        assert_eq!(editor.adjusted(uniform, 100).shininess, MAX_SHININESS);
+    }
+
+    #[test]
+    fn test_strengths_and_selection() {
+        let mut editor = MaterialEditor {
+            material: 0,
+            field: MaterialField::Specular,
+        };
+        let uniform = editor.adjusted(MaterialUniform::IDENTITY, 3);
+        assert!((uniform.specular - 0.3).abs() < 1e-6);
+        assert_eq!(editor.adjusted(uniform, -10).specular, 0.0);
+
+        editor.field = editor.field.next();
+        assert_eq!(editor.field, MaterialField::Shininess);
+        assert_eq!(editor.field.next(), MaterialField::Ambient);
+
+        editor.select(-1, 3);
+        assert_eq!(editor.material, 2);
+        editor.select(1, 3);
+        assert_eq!(editor.material, 0);
+        // A model with fewer materials keeps the selection in range
+        editor.material = 5;
+        editor.select(0, 2);
+        assert_eq!(editor.material, 1);
+    }
+}
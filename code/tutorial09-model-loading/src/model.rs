@@ -1,13 +1,18 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
-    io::{BufReader, Cursor},
+    io::{BufReader, Cursor, Write},
     ops::Range,
     path::{Path, PathBuf},
 };
 
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3};
 use klgl::file_loader::FileDataHandle;
-use tutorial_embedded_content::ILLUMINATI_PNG;
-use wgpu::util::DeviceExt;
+use wgpu::util::{DeviceExt, DrawIndexedIndirectArgs};
+
+use crate::bounds::BoundingBox;
+use crate::bvh::{BoundingSphere, Bvh, visibility_mask};
+use crate::models_draw_pass::Instance;
 
 fn get_value_from_map<'map, Key, Value, Hasher, Query>(
     map: &'map HashMap<Key, Value, Hasher>,
@@ -27,266 +32,2627 @@ fn to_posix_path(path: &Path) -> String {
     path.to_string_lossy().replace('\\', "/")
 }
 
-pub trait Vertex {
-    fn layout() -> wgpu::VertexBufferLayout<'static>;
+// Directory of the obj file. Paths inside of obj and mtl files are relative to it
+fn root_path_of(obj_file_name: &str) -> PathBuf {
+    PathBuf::from({
+        match obj_file_name.rfind('/') {
+            Some(i) => String::from(&obj_file_name[0..i + 1]),
+            None => String::new(),
+        }
+    })
 }
 
-#[allow(dead_code)]
-pub struct Material {
-    pub name: String,
-    pub diffuse_texture: klgl::Texture,
-    pub bind_group: wgpu::BindGroup,
+#[cfg(not(target_arch = "wasm32"))]
+fn read_from_disk(path: &str) -> anyhow::Result<Cow<'static, [u8]>> {
+    std::fs::read(path)
+        .map(Cow::Owned)
+        .map_err(|err| anyhow::anyhow!("Failed to read {}. Error: {}", path, err))
+}
+
+// Obj files may come without materials, meshes without a valid material use a default one
+// that is appended after the materials of the obj file.
+fn resolve_material(material_id: Option<usize>, num_obj_materials: usize) -> usize {
+    match material_id {
+        Some(id) if id < num_obj_materials => id,
+        _ => num_obj_materials,
+    }
+}
+
+fn parse_obj<'a, GetFile>(
+    obj_file_name: &str,
+    get_file: &GetFile,
+) -> anyhow::Result<(Vec<tobj::Model>, Vec<tobj::Material>)>
+where
+    GetFile: Fn(&str) -> anyhow::Result<Cow<'a, [u8]>>,
+{
+    let obj_file_data = get_file(obj_file_name)?;
+    let obj_cursor = Cursor::new(&obj_file_data[..]);
+    let mut obj_reader = BufReader::new(obj_cursor);
+
+    let root_path = root_path_of(obj_file_name);
+
+    let (models, obj_materials) = tobj::load_obj_buf(
+        &mut obj_reader,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+        |p| {
+            let file_path = root_path.join(p);
+            let file_path_str = to_posix_path(&file_path);
+            match get_file(&file_path_str) {
+                Ok(file_data) => {
+                    tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(&file_data[..])))
+                }
+                Err(err) => {
+                    log::error!(
+                        "Failed to find a file {} required for model {}. It was expected that is was already preloaded by this point. Error: {}",
+                        file_path_str,
+                        obj_file_name,
+                        err
+                    );
+                    Err(tobj::LoadError::OpenFileFailed)
+                }
+            }
+        },
+    )?;
+
+    // The geometry is still usable without the material library
+    let obj_materials = obj_materials.unwrap_or_else(|err| {
+        log::warn!(
+            "Failed to load materials of {}: {}. Using the default material",
+            obj_file_name,
+            err
+        );
+        Vec::new()
+    });
+
+    Ok((models, obj_materials))
+}
+
+// Neutral grey shown until the texture of a material streams in
+const STREAMING_PLACEHOLDER_COLOR: [u8; 4] = [128, 128, 128, 255];
+
+// Where the diffuse texture of a material comes from
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum DiffuseSource {
+    File(String),
+    // Material without a diffuse texture, gets the fallback of the load options
+    Placeholder,
+    // Material added for meshes without one
+    Default,
+}
+
+// Decoded image of a texture array layer
+fn layer_image(
+    source: &DiffuseSource,
+    textures: &HashMap<String, TextureBytes>,
+    fallback: FallbackTexture,
+) -> anyhow::Result<image::DynamicImage> {
+    match source {
+        DiffuseSource::File(path) => Ok(image::load_from_memory(get_value_from_map(
+            textures, path,
+        )?)?),
+        DiffuseSource::Placeholder => fallback.image(),
+        DiffuseSource::Default => Ok(klgl::Texture::checkerboard_image()),
+    }
+}
+
+// Assigns a layer of the texture array to every source. Equal sources share a layer.
+// Returns the sources of the layers and the layer of every source.
+fn texture_array_layers(sources: &[DiffuseSource]) -> (Vec<DiffuseSource>, Vec<u32>) {
+    let mut layers = Vec::new();
+    let mut layer_of: HashMap<&DiffuseSource, u32> = HashMap::new();
+    let indices = sources
+        .iter()
+        .map(|source| {
+            *layer_of.entry(source).or_insert_with(|| {
+                layers.push(source.clone());
+                layers.len() as u32 - 1
+            })
+        })
+        .collect();
+    (layers, indices)
 }
 
+fn mesh_vertices(mesh: &tobj::Mesh, layer: u32) -> Vec<ModelVertex> {
+    (0..mesh.positions.len() / 3)
+        .map(|i| ModelVertex {
+            position: [
+                mesh.positions[i * 3],
+                mesh.positions[i * 3 + 1],
+                mesh.positions[i * 3 + 2],
+            ],
+            tex_coords: match mesh.texcoords.is_empty() {
+                true => [0.0, 0.0],
+                false => [mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1]],
+            },
+            normal: match mesh.normals.is_empty() {
+                true => [0.0, 0.0, 0.0],
+                false => [
+                    mesh.normals[i * 3],
+                    mesh.normals[i * 3 + 1],
+                    mesh.normals[i * 3 + 2],
+                ],
+            },
+            layer,
+            color: match mesh.vertex_color.is_empty() {
+                true => [1.0, 1.0, 1.0],
+                false => [
+                    mesh.vertex_color[i * 3],
+                    mesh.vertex_color[i * 3 + 1],
+                    mesh.vertex_color[i * 3 + 2],
+                ],
+            },
+        })
+        .collect()
+}
+
+/// Model file types, told apart by the file extension
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModelFormat {
+    Obj,
+    /// `.gltf` and `.glb`
+    Gltf,
+}
+
+impl ModelFormat {
+    /// None for files that are not models
+    pub fn of_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "obj" => Some(ModelFormat::Obj),
+            "gltf" | "glb" => Some(ModelFormat::Gltf),
+            _ => None,
+        }
+    }
+}
+
+/// Axis conventions of the tools models come from, converted to the Z-up world at load
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[allow(dead_code)]
-pub struct Mesh {
-    pub name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
-    pub num_elements: u32,
-    pub material: usize,
+pub enum ImportPreset {
+    /// Keep the file as it is
+    Identity,
+    /// Blender exports obj files Y-up with -Z forward, this turns them back to Z-up
+    Blender,
+    /// Turns +Z into +Y, for Z-up files shown in a Y-up scene
+    ZUpToYUp,
 }
 
-pub struct Model {
-    pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>,
+impl ImportPreset {
+    #[rustfmt::skip]
+    pub const fn matrix(self) -> Matrix4<f32> {
+        match self {
+            ImportPreset::Identity => Matrix4::new(
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                0.0, 0.0, 1.0, 0.0,
+                0.0, 0.0, 0.0, 1.0,
+            ),
+            // (x, y, z) -> (x, -z, y)
+            ImportPreset::Blender => Matrix4::new(
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, 1.0, 0.0,
+                0.0, -1.0, 0.0, 0.0,
+                0.0, 0.0, 0.0, 1.0,
+            ),
+            // (x, y, z) -> (x, z, -y)
+            ImportPreset::ZUpToYUp => Matrix4::new(
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, -1.0, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                0.0, 0.0, 0.0, 1.0,
+            ),
+        }
+    }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct ModelVertex {
-    pub position: [f32; 3],
-    pub tex_coords: [f32; 2],
-    pub normal: [f32; 3],
+// Bakes `transform` into the positions and normals. Mirroring transforms also flip the
+// winding of the triangles, so they stay front facing.
+fn apply_import_transform(
+    vertices: &mut [ModelVertex],
+    indices: &mut [u32],
+    transform: &Matrix4<f32>,
+) {
+    if *transform == Matrix4::identity() {
+        return;
+    }
+
+    let normal_matrix = klgl::normal_matrix(transform);
+    for vertex in vertices.iter_mut() {
+        vertex.position = transform
+            .transform_point(Point3::from(vertex.position))
+            .into();
+        let normal = normal_matrix * Vector3::from(vertex.normal);
+        // Missing normals stay zero, they are computed later if at all
+        if normal.magnitude2() > 0.0 {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+
+    if transform.determinant() < 0.0 {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
 }
 
-impl Vertex for ModelVertex {
-    fn layout() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x3,
+fn vertex_bounds(vertices: &[ModelVertex]) -> BoundingBox {
+    BoundingBox::from_points(vertices.iter().map(|v| Point3::from(v.position)))
+}
+
+fn union_bounds<'a>(bounds: impl Iterator<Item = &'a BoundingBox>) -> BoundingBox {
+    bounds.fold(BoundingBox::EMPTY, |union, bounds| union.union(bounds))
+}
+
+// Merges vertices that are equal bit for bit and remaps the indices to the merged ones
+fn dedup_vertices(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut unique = Vec::new();
+    let mut index_of: HashMap<[u32; 12], u32> = HashMap::new();
+    let remap = vertices
+        .iter()
+        .map(|vertex| {
+            *index_of.entry(bytemuck::cast(*vertex)).or_insert_with(|| {
+                unique.push(*vertex);
+                unique.len() as u32 - 1
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let indices = indices.iter().map(|&i| remap[i as usize]).collect();
+    (unique, indices)
+}
+
+// Relative positions are compared in steps of this, so copies still match after the float
+// error of moving them
+const SHARED_GEOMETRY_STEP: f32 = 1e-3;
+
+/// Meshes with the same material and the same geometry at different positions. The first mesh
+/// owns the buffers and all of them are drawn with one instanced draw of it.
+#[derive(Debug)]
+pub struct SharedGeometry {
+    pub meshes: Vec<usize>,
+    /// Offset of each mesh from the first one, in model space
+    pub offsets: Vec<Vector3<f32>>,
+    // Every instance of the model once per mesh, see `Model::write_shared_instances`
+    instances_buffer: Option<wgpu::Buffer>,
+}
+
+// Groups meshes that are copies of each other moved somewhere else. Each mesh is given as its
+// material, vertices and indices. Meshes without a copy are not in any group.
+fn shared_geometry<'a, Meshes>(meshes: Meshes) -> Vec<SharedGeometry>
+where
+    Meshes: Iterator<Item = (usize, &'a [ModelVertex], &'a [u32])>,
+{
+    let mut groups: Vec<(Vector3<f32>, SharedGeometry)> = Vec::new();
+    let mut group_of: HashMap<Vec<u8>, usize> = HashMap::new();
+    for (index, (material, vertices, indices)) in meshes.enumerate() {
+        let Some(first) = vertices.first() else {
+            continue;
+        };
+
+        // Positions relative to the first vertex are the same wherever the copy is
+        let origin = Vector3::from(first.position);
+        let mut key = (material as u64).to_ne_bytes().to_vec();
+        key.extend_from_slice(bytemuck::cast_slice(indices));
+        for vertex in vertices {
+            let relative = (Vector3::from(vertex.position) - origin) / SHARED_GEOMETRY_STEP;
+            for step in [relative.x, relative.y, relative.z] {
+                key.extend_from_slice(&(step.round() as i64).to_ne_bytes());
+            }
+            let attributes = ModelVertex {
+                position: [0.0; 3],
+                ..*vertex
+            };
+            key.extend_from_slice(bytemuck::bytes_of(&attributes));
+        }
+
+        let group = *group_of.entry(key).or_insert_with(|| {
+            groups.push((
+                origin,
+                SharedGeometry {
+                    meshes: Vec::new(),
+                    offsets: Vec::new(),
+                    instances_buffer: None,
                 },
-            ],
+            ));
+            groups.len() - 1
+        });
+        let (group_origin, group) = &mut groups[group];
+        group.meshes.push(index);
+        group.offsets.push(origin - *group_origin);
+    }
+
+    groups
+        .into_iter()
+        .map(|(_, group)| group)
+        .filter(|group| group.meshes.len() > 1)
+        .collect()
+}
+
+// Replaces the normals with ones computed from the triangles. A vertex gets the area weighted
+// average of the faces around its position that are within `smoothing_angle` of the face it
+// belongs to, so vertices on creases sharper than that are split and the creases stay sharp.
+fn compute_normals(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    smoothing_angle: Deg<f32>,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let position = |i: u32| Vector3::from(vertices[i as usize].position);
+    let triangles: Vec<[u32; 3]> = indices
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]])
+        .collect();
+
+    // Length is twice the area of the triangle
+    let face_normals: Vec<Vector3<f32>> = triangles
+        .iter()
+        .map(|&[a, b, c]| (position(b) - position(a)).cross(position(c) - position(a)))
+        .collect();
+
+    // Vertices that differ only in texture coordinates still share normals
+    let mut faces_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (face, triangle) in triangles.iter().enumerate() {
+        for &i in triangle {
+            let key = bytemuck::cast(vertices[i as usize].position);
+            faces_at.entry(key).or_default().push(face);
+        }
+    }
+
+    let min_cos = cgmath::Angle::cos(smoothing_angle) - 1e-5;
+    let unit = |n: Vector3<f32>| match n.magnitude2() > 0.0 {
+        true => n.normalize(),
+        false => n,
+    };
+
+    let mut corners = Vec::with_capacity(triangles.len() * 3);
+    for (face, triangle) in triangles.iter().enumerate() {
+        let face_normal = unit(face_normals[face]);
+        for &i in triangle {
+            let key: [u32; 3] = bytemuck::cast(vertices[i as usize].position);
+            let normal = faces_at[&key]
+                .iter()
+                .map(|&other| face_normals[other])
+                .filter(|&n| unit(n).dot(face_normal) >= min_cos)
+                .fold(Vector3::new(0.0, 0.0, 0.0), |sum, n| sum + n);
+
+            let mut vertex = vertices[i as usize];
+            vertex.normal = unit(normal).into();
+            corners.push(vertex);
         }
     }
+
+    let corner_indices: Vec<u32> = (0..corners.len() as u32).collect();
+    dedup_vertices(&corners, &corner_indices)
 }
 
-impl Mesh {
+// Writes meshes as obj objects. Every vertex gets its own v/vt/vn entries, so vertex indices
+// map one to one to the written ones.
+fn write_obj<'a, W, Meshes>(writer: &mut W, meshes: Meshes) -> std::io::Result<()>
+where
+    W: Write,
+    Meshes: IntoIterator<Item = (&'a str, &'a [ModelVertex], &'a [u32])>,
+{
+    // Obj indices are 1-based and global for the whole file
+    let mut first_index = 1;
+    for (name, vertices, indices) in meshes {
+        writeln!(writer, "o {}", name)?;
+        for v in vertices {
+            writeln!(
+                writer,
+                "v {} {} {}",
+                v.position[0], v.position[1], v.position[2]
+            )?;
+        }
+        for v in vertices {
+            // Undoes the flip done when loading
+            writeln!(writer, "vt {} {}", v.tex_coords[0], 1.0 - v.tex_coords[1])?;
+        }
+        for v in vertices {
+            writeln!(writer, "vn {} {} {}", v.normal[0], v.normal[1], v.normal[2])?;
+        }
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| triangle[k] + first_index);
+            writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        first_index += vertices.len() as u32;
+    }
+    Ok(())
+}
+
+/// Arguments of an indirect draw of each mesh with the given number of indices and placements.
+/// A mesh with shared geometry draws every instance once per mesh that shares it.
+fn indirect_args<Counts>(counts: Counts, instances: Range<u32>) -> Vec<DrawIndexedIndirectArgs>
+where
+    Counts: Iterator<Item = (u32, u32)>,
+{
+    counts
+        .map(|(index_count, placements)| DrawIndexedIndirectArgs {
+            index_count,
+            instance_count: instances.len() as u32 * placements,
+            first_index: 0,
+            base_vertex: 0,
+            first_instance: instances.start * placements,
+        })
+        .collect()
+}
+
+// Index count and placements of each mesh, see `indirect_args`
+fn draw_counts<'a>(
+    meshes: &'a [Mesh],
+    shared_geometry: &'a [SharedGeometry],
+) -> impl Iterator<Item = (u32, u32)> + 'a {
+    meshes.iter().map(|mesh| {
+        let placements = mesh
+            .shared
+            .map_or(1, |shared| shared_geometry[shared].meshes.len() as u32);
+        (mesh.num_elements, placements)
+    })
+}
+
+/// Mesh indices grouped by material, in load order within a group
+fn material_draw_order(materials: &[usize]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..materials.len()).collect();
+    order.sort_by_key(|index| materials[*index]);
+    order
+}
+
+/// Visibility of `count` meshes when only the mesh at `index` is shown
+fn solo_visibility(count: usize, index: usize) -> impl Iterator<Item = bool> {
+    (0..count).map(move |i| i == index)
+}
+
+/// `draw_order` without the meshes `is_visible` returns false for
+fn visible_draw_order<'a>(
+    draw_order: &'a [usize],
+    is_visible: impl Fn(usize) -> bool + 'a,
+) -> impl Iterator<Item = usize> + 'a {
+    draw_order
+        .iter()
+        .copied()
+        .filter(move |index| is_visible(*index))
+}
+
+/// How many times the material bind group changes when the meshes are drawn in `order`
+fn material_switches(materials: &[usize], order: &[usize]) -> usize {
+    let mut switches = 0;
+    let mut bound = None;
+    for index in order {
+        if bound != Some(materials[*index]) {
+            bound = Some(materials[*index]);
+            switches += 1;
+        }
+    }
+    switches
+}
+
+fn indirect_args_bytes(args: &[DrawIndexedIndirectArgs]) -> Vec<u8> {
+    args.iter()
+        .flat_map(|args| args.as_bytes())
+        .copied()
+        .collect()
+}
+
+/// Diffuse texture of materials that have none
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FallbackTexture {
+    /// One texel of this sRGB color
+    Color([u8; 4]),
+    /// Encoded image, e.g. the bytes of a png file
     #[allow(dead_code)]
-    pub fn draw(
-        &self,
-        render_pass: &mut wgpu::RenderPass,
-        camera_bind_group: &wgpu::BindGroup,
-        material: &Material,
-    ) {
-        self.draw_instanced(render_pass, camera_bind_group, material, 0..1);
+    Image(&'static [u8]),
+}
+
+impl FallbackTexture {
+    /// Neutral gray, lit like any other surface without standing out
+    pub const GRAY: FallbackTexture = FallbackTexture::Color([128, 128, 128, 255]);
+
+    fn image(self) -> anyhow::Result<image::DynamicImage> {
+        match self {
+            FallbackTexture::Color(color) => Ok(klgl::Texture::solid_color_image(color)),
+            FallbackTexture::Image(bytes) => Ok(image::load_from_memory(bytes)?),
+        }
     }
 
-    pub fn draw_instanced(
-        &self,
-        render_pass: &mut wgpu::RenderPass,
-        camera_bind_group: &wgpu::BindGroup,
-        material: &Material,
-        instances: Range<u32>,
-    ) {
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        render_pass.set_bind_group(0, &material.bind_group, &[]);
-        render_pass.set_bind_group(1, camera_bind_group, &[]);
-        render_pass.draw_indexed(0..self.num_elements, 0, instances);
+    fn texture(self, ctx: &klgl::RenderContext) -> anyhow::Result<klgl::Texture> {
+        match self {
+            FallbackTexture::Color(color) => Ok(klgl::Texture::solid_color(
+                &ctx.device,
+                &ctx.queue,
+                color,
+                "FALLBACK_COLOR",
+            )),
+            FallbackTexture::Image(bytes) => {
+                klgl::Texture::from_bytes(&ctx.device, &ctx.queue, bytes, "FALLBACK_IMAGE")
+            }
+        }
     }
 }
 
-impl Model {
-    pub fn draw_instanced(
-        &self,
-        render_pass: &mut wgpu::RenderPass,
-        camera_bind_group: &wgpu::BindGroup,
-        instances: Range<u32>,
-    ) {
-        for mesh in &self.meshes {
-            let material = &self.materials[mesh.material];
-            mesh.draw_instanced(render_pass, camera_bind_group, material, instances.clone());
+#[derive(Copy, Clone, Debug)]
+pub struct LoadOptions {
+    /// Merge vertices with identical position, texture coordinates and normal.
+    /// Obj files often repeat them under different indices, which inflates vertex buffers.
+    pub dedup_vertices: bool,
+    /// Pack all diffuse textures into one texture array so the whole model is drawn with one bind group.
+    /// The model has to be loaded with a bind group layout that matches [`TextureArray`].
+    pub texture_array: bool,
+    /// Replace the normals of the obj file with ones computed from the triangles
+    pub compute_normals: bool,
+    /// Faces meeting at a larger angle get separate normals when normals are computed
+    pub smoothing_angle_degrees: f32,
+    /// Diffuse maps of obj materials are usually tiled, so they repeat by default
+    pub diffuse_sampler: klgl::SamplerOptions,
+    /// Baked into positions and normals at load, e.g. [`ImportPreset::matrix`] to fix
+    /// the up axis of a file or a scale to convert its units
+    pub import_transform: Matrix4<f32>,
+    /// Diffuse textures that are not available yet get a placeholder, so the model can be
+    /// drawn before they arrive. See [`Model::stream_texture`]. Ignored with a texture array.
+    pub stream_textures: bool,
+    /// Diffuse texture of materials in the mtl file that have no `map_Kd`
+    pub fallback_texture: FallbackTexture,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            dedup_vertices: false,
+            texture_array: false,
+            compute_normals: false,
+            smoothing_angle_degrees: 60.0,
+            diffuse_sampler: klgl::SamplerOptions::REPEAT,
+            import_transform: ImportPreset::Identity.matrix(),
+            stream_textures: false,
+            fallback_texture: FallbackTexture::GRAY,
         }
     }
+}
 
-    pub fn load(
+/// Bytes of a texture file a parsed model refers to.
+/// Files of the file loader are shared with it instead of copied.
+#[derive(Clone, Debug)]
+pub enum TextureBytes {
+    Loaded(FileDataHandle),
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    Read(Vec<u8>),
+}
+
+impl std::ops::Deref for TextureBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            TextureBytes::Loaded(file) => &file.data,
+            TextureBytes::Read(bytes) => bytes,
+        }
+    }
+}
+
+// Everything about a material that is known before its texture is created
+#[derive(Debug)]
+struct MaterialData {
+    name: String,
+    source: DiffuseSource,
+    uv_transform: MaterialUniform,
+    double_sided: bool,
+}
+
+// Vertices of a mesh, ready to be copied to its buffers
+#[derive(Debug)]
+struct MeshData {
+    name: String,
+    material: usize,
+    layer: u32,
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+}
+
+/// A parsed model before anything of it is on the GPU. Parsing is the slow part of loading
+/// a large obj file and needs no device, so it can run on another thread.
+/// See [`Model::upload`].
+#[derive(Debug)]
+pub struct ModelData {
+    obj_file_name: String,
+    options: LoadOptions,
+    materials: Vec<MaterialData>,
+    // Sources of the texture array layers, None without a texture array
+    texture_layers: Option<Vec<DiffuseSource>>,
+    // Diffuse textures that were available at parse time, by path
+    textures: HashMap<String, TextureBytes>,
+    meshes: Vec<MeshData>,
+    shared_geometry: Vec<SharedGeometry>,
+}
+
+impl ModelData {
+    /// Parses the obj file in `obj_bytes`. The mtl file and the textures are looked up
+    /// in `file_map` relative to `obj_file_name`, textures are only referenced, not decoded.
+    pub fn parse(
         obj_file_name: &str,
+        obj_bytes: &[u8],
         file_map: &HashMap<String, FileDataHandle>,
-        ctx: &klgl::RenderContext,
-        layout: &wgpu::BindGroupLayout,
-    ) -> anyhow::Result<Model> {
-        let obj_file_handle = get_value_from_map(file_map, obj_file_name)?;
-        let obj_cursor = Cursor::new(&obj_file_handle.data);
-        let mut obj_reader = BufReader::new(obj_cursor);
-
-        let root_path = PathBuf::from({
-            match obj_file_name.rfind('/') {
-                Some(i) => String::from(&obj_file_name[0..i + 1]),
-                None => String::new(),
-            }
-        });
-
-        let (models, obj_materials) = tobj::load_obj_buf(
-            &mut obj_reader,
-            &tobj::LoadOptions {
-                triangulate: true,
-                single_index: true,
-                ..Default::default()
-            },
-            |p| {
-                let file_path = root_path.join(p);
-                let file_path_str = to_posix_path(&file_path);
-                match get_value_from_map(file_map, &file_path_str) {
-                    Ok(file_data) => {
-                        tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(&file_data.data)))
-                    }
-                    Err(err) => {
-                        log::error!(
-                            "Failed to find a file {} required for model {}. It was expected that is was already preloaded by this point. Error: {}",
-                            file_path_str,
-                            obj_file_name,
-                            err
-                        );
-                        Err(tobj::LoadError::OpenFileFailed)
-                    }
-                }
+        options: LoadOptions,
+    ) -> anyhow::Result<Self> {
+        Self::parse_with(
+            obj_file_name,
+            |path| match path == obj_file_name {
+                true => Ok(Cow::Borrowed(obj_bytes)),
+                false => get_value_from_map(file_map, path).map(|x| Cow::Borrowed(&x.data[..])),
             },
-        )?;
+            |path| file_map.get(path).cloned().map(TextureBytes::Loaded),
+            options,
+        )
+    }
 
-        let mut materials = Vec::new();
-        for m in obj_materials? {
-            let diffuse_texture = {
-                match &m.diffuse_texture {
-                    Some(diffuse_texture_path) => {
-                        let diffuse_texture_path = root_path.join(&diffuse_texture_path);
-                        let diffuse_texture_path_str = to_posix_path(&diffuse_texture_path);
-                        let diffuse_texture_file_handle =
-                            get_value_from_map(file_map, &diffuse_texture_path_str)?;
-                        klgl::Texture::from_bytes(
-                            &ctx.device,
-                            &ctx.queue,
-                            &diffuse_texture_file_handle.data,
-                            &diffuse_texture_path_str,
-                        )?
+    /// Parses the obj file at `obj_path` and reads the mtl file and the textures it references
+    /// from the file system, relative to the obj's directory. Missing textures are streamed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn parse_from_disk(obj_path: &Path, options: LoadOptions) -> anyhow::Result<Self> {
+        Self::parse_with(
+            &to_posix_path(obj_path),
+            read_from_disk,
+            |path| Some(TextureBytes::Read(read_from_disk(path).ok()?.into_owned())),
+            options,
+        )
+    }
+
+    // `get_file` returns the obj and mtl files, `get_texture` the textures that are available
+    fn parse_with<'a, GetFile, GetTexture>(
+        obj_file_name: &str,
+        get_file: GetFile,
+        get_texture: GetTexture,
+        options: LoadOptions,
+    ) -> anyhow::Result<Self>
+    where
+        GetFile: Fn(&str) -> anyhow::Result<Cow<'a, [u8]>>,
+        GetTexture: Fn(&str) -> Option<TextureBytes>,
+    {
+        let (models, obj_materials) = parse_obj(obj_file_name, &get_file)?;
+        let root_path = root_path_of(obj_file_name);
+        let num_obj_materials = obj_materials.len();
+
+        let mut materials: Vec<MaterialData> = obj_materials
+            .into_iter()
+            .map(|m| {
+                let (source, uv_transform) = match &m.diffuse_texture {
+                    Some(map) => {
+                        let (path, uv_transform) = parse_texture_map(map);
+                        let path = to_posix_path(&root_path.join(path));
+                        (DiffuseSource::File(path), uv_transform)
                     }
                     None => {
                         log::warn!(
-                            "obj file {} has a material {} without diffuse texture. Using placeholder",
+                            "obj file {} has a material {} without diffuse texture. Using the fallback texture",
                             obj_file_name,
                             m.name
                         );
-                        klgl::Texture::from_bytes(
-                            &ctx.device,
-                            &ctx.queue,
-                            &ILLUMINATI_PNG,
-                            &"PLACEHOLDER",
-                        )?
+                        (DiffuseSource::Placeholder, MaterialUniform::IDENTITY)
                     }
+                };
+                MaterialData {
+                    name: m.name,
+                    source,
+                    uv_transform,
+                    // Materials with an alpha mask are cut out of single sheets that have to be visible from both sides
+                    double_sided: m.dissolve_texture.is_some(),
                 }
-            };
-            let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    },
-                ],
-                label: None,
-            });
-
-            materials.push(Material {
-                name: m.name,
-                diffuse_texture,
-                bind_group,
             })
+            .collect();
+
+        let needs_default_material = models
+            .iter()
+            .any(|m| resolve_material(m.mesh.material_id, num_obj_materials) == num_obj_materials);
+        if needs_default_material {
+            log::warn!(
+                "obj file {} has meshes without material. Using the default material",
+                obj_file_name
+            );
+            materials.push(MaterialData {
+                name: "default".to_string(),
+                source: DiffuseSource::Default,
+                uv_transform: MaterialUniform::IDENTITY,
+                double_sided: false,
+            });
         }
 
+        let (texture_layers, material_layers) = match options.texture_array {
+            true => {
+                if materials
+                    .iter()
+                    .any(|m| m.uv_transform != MaterialUniform::IDENTITY)
+                {
+                    log::warn!(
+                        "{}: texture scale and offset of materials are ignored with a texture array",
+                        obj_file_name
+                    );
+                }
+                let sources: Vec<DiffuseSource> =
+                    materials.iter().map(|m| m.source.clone()).collect();
+                let (layers, layer_of_material) = texture_array_layers(&sources);
+                (Some(layers), layer_of_material)
+            }
+            false => (None, vec![0; materials.len()]),
+        };
+
+        let mut vertex_counts = (0, 0);
         let meshes = models
             .into_iter()
             .map(|m| {
-                let vertices = (0..m.mesh.positions.len() / 3)
-                    .map(|i| {
-                        if m.mesh.normals.is_empty() {
-                            ModelVertex {
-                                position: [
-                                    m.mesh.positions[i * 3],
-                                    m.mesh.positions[i * 3 + 1],
-                                    m.mesh.positions[i * 3 + 2],
-                                ],
-                                tex_coords: [
-                                    m.mesh.texcoords[i * 2],
-                                    1.0 - m.mesh.texcoords[i * 2 + 1],
-                                ],
-                                normal: [0.0, 0.0, 0.0],
-                            }
-                        } else {
-                            ModelVertex {
-                                position: [
-                                    m.mesh.positions[i * 3],
-                                    m.mesh.positions[i * 3 + 1],
-                                    m.mesh.positions[i * 3 + 2],
-                                ],
-                                tex_coords: [
-                                    m.mesh.texcoords[i * 2],
-                                    1.0 - m.mesh.texcoords[i * 2 + 1],
-                                ],
-                                normal: [
-                                    m.mesh.normals[i * 3],
-                                    m.mesh.normals[i * 3 + 1],
-                                    m.mesh.normals[i * 3 + 2],
-                                ],
-                            }
-                        }
-                    })
-                    .collect::<Vec<_>>();
-
-                let vertex_buffer =
-                    ctx.device
-                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some(&format!("{:?} Vertex Buffer", obj_file_name)),
-                            contents: bytemuck::cast_slice(&vertices),
-                            usage: wgpu::BufferUsages::VERTEX,
-                        });
-                let index_buffer =
-                    ctx.device
-                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some(&format!("{:?} Index Buffer", obj_file_name)),
-                            contents: bytemuck::cast_slice(&m.mesh.indices),
-                            usage: wgpu::BufferUsages::INDEX,
-                        });
-
-                Mesh {
-                    name: obj_file_name.to_string(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_elements: m.mesh.indices.len() as u32,
-                    material: m.mesh.material_id.unwrap_or(0),
+                let material = resolve_material(m.mesh.material_id, num_obj_materials);
+                let layer = material_layers[material];
+                let mut vertices = mesh_vertices(&m.mesh, layer);
+                let mut indices = m.mesh.indices;
+                apply_import_transform(&mut vertices, &mut indices, &options.import_transform);
+                let (vertices, indices) = match options.compute_normals {
+                    true => {
+                        compute_normals(&vertices, &indices, Deg(options.smoothing_angle_degrees))
+                    }
+                    false => (vertices, indices),
+                };
+                let (vertices, indices) = match options.dedup_vertices {
+                    true => dedup_vertices(&vertices, &indices),
+                    false => (vertices, indices),
+                };
+                vertex_counts.0 += m.mesh.positions.len() / 3;
+                vertex_counts.1 += vertices.len();
+                MeshData {
+                    name: m.name,
+                    material,
+                    layer,
+                    vertices,
+                    indices,
                 }
             })
             .collect::<Vec<_>>();
 
-        Ok(Model { meshes, materials })
+        if options.dedup_vertices {
+            log::info!(
+                "{}: deduplicated {} vertices to {}",
+                obj_file_name,
+                vertex_counts.0,
+                vertex_counts.1
+            );
+        }
+
+        let shared_geometry = shared_geometry(
+            meshes
+                .iter()
+                .map(|mesh| (mesh.material, &mesh.vertices[..], &mesh.indices[..])),
+        );
+
+        let textures = materials
+            .iter()
+            .filter_map(|m| match &m.source {
+                DiffuseSource::File(path) => Some(path),
+                _ => None,
+            })
+            .filter_map(|path| Some((path.clone(), get_texture(path)?)))
+            .collect();
+
+        Ok(Self {
+            obj_file_name: obj_file_name.to_string(),
+            options,
+            materials,
+            texture_layers,
+            textures,
+            meshes,
+            shared_geometry,
+        })
+    }
+}
+
+pub trait Vertex {
+    fn layout() -> wgpu::VertexBufferLayout<'static>;
+}
+
+/// Transform of the texture coordinates of a material: `uv * tex_scale + tex_offset`,
+/// and how it reflects light. Bound at binding 3 of the texture bind group.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub tex_scale: [f32; 2],
+    pub tex_offset: [f32; 2],
+    /// Scales the light that reaches the surface without the point lights
+    pub ambient: f32,
+    /// Scales the light of the point lights
+    pub diffuse: f32,
+    /// Strength of the point light highlights, none by default
+    pub specular: f32,
+    /// Exponent of the highlights, higher values make them smaller and sharper
+    pub shininess: f32,
+}
+
+impl MaterialUniform {
+    /// Texture coordinates as they are and the lighting models had before materials had any
+    pub const IDENTITY: Self = Self {
+        tex_scale: [1.0, 1.0],
+        tex_offset: [0.0, 0.0],
+        ambient: 1.0,
+        diffuse: 1.0,
+        specular: 0.0,
+        shininess: 32.0,
+    };
+}
+
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+// map_Kd may start with options, e.g. `-s 4 4 1 -o 0.5 0 0 bricks.png`. Scale and offset
+// are kept, other options are skipped. What is left is the path of the texture.
+fn parse_texture_map(map: &str) -> (String, MaterialUniform) {
+    let mut uniform = MaterialUniform::IDENTITY;
+    let mut tokens = map.split_whitespace().peekable();
+    while let Some(option) = tokens.next_if(|token| token.starts_with('-')) {
+        let mut values = Vec::new();
+        while let Some(token) = tokens
+            .next_if(|token| token.parse::<f32>().is_ok() || *token == "on" || *token == "off")
+        {
+            values.extend(token.parse::<f32>().ok());
+        }
+
+        match (option, values.as_slice()) {
+            ("-s", [u, rest @ ..]) => {
+                uniform.tex_scale = [*u, rest.first().copied().unwrap_or(1.0)]
+            }
+            ("-o", [u, rest @ ..]) => {
+                uniform.tex_offset = [*u, rest.first().copied().unwrap_or(0.0)]
+            }
+            _ => {}
+        }
+    }
+
+    (tokens.collect::<Vec<_>>().join(" "), uniform)
+}
+
+/// The render pass commands that draw the meshes of a model. Tests record them instead.
+pub trait MeshPass {
+    fn set_bind_group(&mut self, index: u32, bind_group: &wgpu::BindGroup);
+    fn set_vertex_buffer(&mut self, slot: u32, buffer: &wgpu::Buffer);
+    fn set_index_buffer(&mut self, buffer: &wgpu::Buffer);
+    fn draw_indexed(&mut self, indices: Range<u32>, instances: Range<u32>);
+    fn draw_indexed_indirect(&mut self, args_buffer: &wgpu::Buffer, offset: wgpu::BufferAddress);
+}
+
+impl MeshPass for wgpu::RenderPass<'_> {
+    fn set_bind_group(&mut self, index: u32, bind_group: &wgpu::BindGroup) {
+        wgpu::RenderPass::set_bind_group(self, index, bind_group, &[]);
+    }
+
+    fn set_vertex_buffer(&mut self, slot: u32, buffer: &wgpu::Buffer) {
+        wgpu::RenderPass::set_vertex_buffer(self, slot, buffer.slice(..));
+    }
+
+    fn set_index_buffer(&mut self, buffer: &wgpu::Buffer) {
+        wgpu::RenderPass::set_index_buffer(self, buffer.slice(..), wgpu::IndexFormat::Uint32);
+    }
+
+    fn draw_indexed(&mut self, indices: Range<u32>, instances: Range<u32>) {
+        wgpu::RenderPass::draw_indexed(self, indices, 0, instances);
+    }
+
+    fn draw_indexed_indirect(&mut self, args_buffer: &wgpu::Buffer, offset: wgpu::BufferAddress) {
+        wgpu::RenderPass::draw_indexed_indirect(self, args_buffer, offset);
+    }
+}
+
+#[allow(dead_code)]
+pub struct Material {
+    pub name: String,
+    pub diffuse_texture: klgl::Texture,
+    pub uniform: MaterialUniform,
+    pub uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+#[allow(dead_code)]
+pub struct Mesh {
+    pub name: String,
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_elements: u32,
+    pub material: usize,
+    /// Layer of the material's texture when the model uses a texture array
+    pub layer: u32,
+    /// Drawn without back-face culling, e.g. foliage and curtains that are a single sheet of triangles
+    pub double_sided: bool,
+    /// Copy of the uploaded geometry, kept to inspect what was loaded
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    /// Index in `Model::shared_geometry` when other meshes are copies of this one
+    pub shared: Option<usize>,
+    /// Hidden meshes are skipped by every draw, see [`Model::solo`]
+    pub visible: bool,
+    bounds: BoundingBox,
+}
+
+/// Diffuse textures of all materials of a model as layers of one texture.
+/// Bound at binding 1 (sampler) and 2 (`texture_2d_array`) of the texture bind group.
+#[allow(dead_code)]
+pub struct TextureArray {
+    pub texture: klgl::Texture,
+    pub bind_group: wgpu::BindGroup,
+}
+
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    /// Empty when the model uses a texture array
+    pub materials: Vec<Material>,
+    pub texture_array: Option<TextureArray>,
+    /// `DrawIndexedIndirectArgs` for every mesh in order, see [`Model::write_indirect_args`]
+    pub indirect_buffer: wgpu::Buffer,
+    /// Bounding spheres of the meshes in model space, for culling
+    pub bvh: Bvh,
+    /// Mesh indices grouped by material, the meshes are drawn in this order
+    pub draw_order: Vec<usize>,
+    /// Materials that show a placeholder until their diffuse texture file arrives
+    pub pending_textures: HashMap<String, Vec<usize>>,
+    /// Meshes that are drawn as instances of one of them
+    pub shared_geometry: Vec<SharedGeometry>,
+    // Instances the shared geometry buffers were last written for
+    shared_instances: Vec<Instance>,
+}
+
+impl TextureArray {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: klgl::Texture,
+    ) -> Self {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+            ],
+            label: Some("texture_array_bind_group"),
+        });
+
+        Self {
+            texture,
+            bind_group,
+        }
+    }
+}
+
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: String,
+        diffuse_texture: klgl::Texture,
+        uniform: MaterialUniform,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = Self::create_bind_group(device, layout, &diffuse_texture, &uniform_buffer);
+
+        Self {
+            name,
+            diffuse_texture,
+            uniform,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        diffuse_texture: &klgl::Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: None,
+        })
+    }
+
+    /// Swaps the diffuse texture, the uniform is kept
+    pub fn set_diffuse_texture(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        diffuse_texture: klgl::Texture,
+    ) {
+        self.bind_group =
+            Self::create_bind_group(device, layout, &diffuse_texture, &self.uniform_buffer);
+        self.diffuse_texture = diffuse_texture;
+    }
+
+    pub fn set_uv_transform(&mut self, queue: &wgpu::Queue, scale: [f32; 2], offset: [f32; 2]) {
+        let uniform = MaterialUniform {
+            tex_scale: scale,
+            tex_offset: offset,
+            ..self.uniform
+        };
+        self.set_uniform(queue, uniform);
+    }
+
+    pub fn set_uniform(&mut self, queue: &wgpu::Queue, uniform: MaterialUniform) {
+        self.uniform = uniform;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ModelVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub layer: u32,
+    /// White when the obj file has no vertex colors
+    pub color: [f32; 3],
+}
+
+impl Vertex for ModelVertex {
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+impl Mesh {
+    /// Bounds of the vertices in model space
+    pub fn bounds(&self) -> &BoundingBox {
+        &self.bounds
+    }
+
+    #[allow(dead_code)]
+    pub fn draw(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        camera_bind_group: &wgpu::BindGroup,
+        material: &Material,
+    ) {
+        self.draw_instanced(render_pass, camera_bind_group, material, 0..1);
+    }
+
+    pub fn draw_instanced(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        camera_bind_group: &wgpu::BindGroup,
+        material: &Material,
+        instances: Range<u32>,
+    ) {
+        render_pass.set_bind_group(0, &material.bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        self.draw_geometry(render_pass, instances);
+    }
+
+    /// Draws with the bind groups that are already set on the render pass
+    pub fn draw_geometry(&self, render_pass: &mut dyn MeshPass, instances: Range<u32>) {
+        render_pass.set_vertex_buffer(0, &self.vertex_buffer);
+        render_pass.set_index_buffer(&self.index_buffer);
+        render_pass.draw_indexed(0..self.num_elements, instances);
+    }
+
+    /// Same as `draw_geometry` with the counts read from `args_buffer` at `offset`
+    pub fn draw_geometry_indirect(
+        &self,
+        render_pass: &mut dyn MeshPass,
+        args_buffer: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+    ) {
+        render_pass.set_vertex_buffer(0, &self.vertex_buffer);
+        render_pass.set_index_buffer(&self.index_buffer);
+        render_pass.draw_indexed_indirect(args_buffer, offset);
+    }
+}
+
+impl Model {
+    /// Union of the mesh bounds. Empty if the model has no vertices.
+    #[allow(dead_code)]
+    pub fn bounds(&self) -> BoundingBox {
+        union_bounds(self.meshes.iter().map(Mesh::bounds))
+    }
+
+    /// Draws the meshes with the indices in `visible`, e.g. the result of [`Bvh::cull`]
+    #[allow(dead_code)]
+    pub fn draw_instanced(
+        &self,
+        render_pass: &mut dyn MeshPass,
+        camera_bind_group: &wgpu::BindGroup,
+        instances_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+        visible: &[usize],
+    ) {
+        let mask = visibility_mask(self.meshes.len(), visible);
+        self.draw_instanced_filtered(
+            render_pass,
+            camera_bind_group,
+            instances_buffer,
+            instances,
+            |index, _| mask[index],
+        );
+    }
+
+    /// Draws only the meshes `filter` returns true for. It gets the mesh index and the mesh.
+    pub fn draw_instanced_filtered<Filter>(
+        &self,
+        render_pass: &mut dyn MeshPass,
+        camera_bind_group: &wgpu::BindGroup,
+        instances_buffer: &wgpu::Buffer,
+        instances: Range<u32>,
+        filter: Filter,
+    ) where
+        Filter: Fn(usize, &Mesh) -> bool,
+    {
+        self.draw_meshes(
+            render_pass,
+            camera_bind_group,
+            instances_buffer,
+            filter,
+            |render_pass, _, mesh, placements| {
+                let instances = instances.start * placements..instances.end * placements;
+                mesh.draw_geometry(render_pass, instances)
+            },
+        );
+    }
+
+    /// Draws every mesh with one `draw_indexed_indirect` that reads its counts from
+    /// `indirect_buffer`. Needs `DownlevelFlags::INDIRECT_EXECUTION`.
+    #[allow(dead_code)]
+    pub fn draw_indirect(
+        &self,
+        render_pass: &mut dyn MeshPass,
+        camera_bind_group: &wgpu::BindGroup,
+        instances_buffer: &wgpu::Buffer,
+    ) {
+        self.draw_indirect_filtered(render_pass, camera_bind_group, instances_buffer, |_, _| {
+            true
+        });
+    }
+
+    /// Draws only the meshes `filter` returns true for. It gets the mesh index and the mesh.
+    pub fn draw_indirect_filtered<Filter>(
+        &self,
+        render_pass: &mut dyn MeshPass,
+        camera_bind_group: &wgpu::BindGroup,
+        instances_buffer: &wgpu::Buffer,
+        filter: Filter,
+    ) where
+        Filter: Fn(usize, &Mesh) -> bool,
+    {
+        let stride = std::mem::size_of::<DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+        self.draw_meshes(
+            render_pass,
+            camera_bind_group,
+            instances_buffer,
+            filter,
+            |render_pass, index, mesh, _| {
+                mesh.draw_geometry_indirect(
+                    render_pass,
+                    &self.indirect_buffer,
+                    index as wgpu::BufferAddress * stride,
+                )
+            },
+        );
+    }
+
+    /// Draws that one instanced draw of shared geometry replaces
+    pub fn instanced_mesh_savings(&self) -> usize {
+        self.shared_geometry
+            .iter()
+            .map(|geometry| geometry.meshes.len() - 1)
+            .sum()
+    }
+
+    // Binds the material and instances of every mesh that passes the filter and lets `draw`
+    // issue the draw call. It also gets how many meshes the geometry is drawn for.
+    fn draw_meshes<Filter, Draw>(
+        &self,
+        render_pass: &mut dyn MeshPass,
+        camera_bind_group: &wgpu::BindGroup,
+        instances_buffer: &wgpu::Buffer,
+        filter: Filter,
+        draw: Draw,
+    ) where
+        Filter: Fn(usize, &Mesh) -> bool,
+        Draw: Fn(&mut dyn MeshPass, usize, &Mesh, u32),
+    {
+        // Shared geometry is drawn for all of its meshes when the first one that passes comes,
+        // so a visible copy shows the hidden ones too.
+        // Its buffer is missing until `write_shared_instances` was called.
+        let mut shared_drawn = vec![false; self.shared_geometry.len()];
+        let draws = visible_draw_order(&self.draw_order, |index| self.meshes[index].visible)
+            .map(|index| (index, &self.meshes[index]))
+            .filter(|(index, mesh)| filter(*index, mesh))
+            .filter_map(|(index, mesh)| {
+                let Some(shared) = mesh.shared else {
+                    return Some((index, mesh, instances_buffer, 1));
+                };
+                let geometry = &self.shared_geometry[shared];
+                match (shared_drawn[shared], &geometry.instances_buffer) {
+                    (false, Some(buffer)) => {
+                        shared_drawn[shared] = true;
+                        let owner = geometry.meshes[0];
+                        let placements = geometry.meshes.len() as u32;
+                        Some((owner, &self.meshes[owner], buffer, placements))
+                    }
+                    _ => None,
+                }
+            });
+
+        render_pass.set_bind_group(1, camera_bind_group);
+        if let Some(texture_array) = &self.texture_array {
+            // Vertices know their layer, so the texture bind group is set once for all meshes
+            render_pass.set_bind_group(0, &texture_array.bind_group);
+        }
+
+        // Meshes of one material come one after another, so it is bound once for all of them
+        let mut bound_material = None;
+        let mut bound_instances: Option<&wgpu::Buffer> = None;
+        for (index, mesh, instances, placements) in draws {
+            if self.texture_array.is_none() && bound_material != Some(mesh.material) {
+                let material = &self.materials[mesh.material];
+                render_pass.set_bind_group(0, &material.bind_group);
+                bound_material = Some(mesh.material);
+            }
+            if !bound_instances.is_some_and(|bound| std::ptr::eq(bound, instances)) {
+                render_pass.set_vertex_buffer(1, instances);
+                bound_instances = Some(instances);
+            }
+            draw(render_pass, index, mesh, placements);
+        }
+    }
+
+    fn mesh_materials(&self) -> Vec<usize> {
+        self.meshes.iter().map(|mesh| mesh.material).collect()
+    }
+
+    /// Material bind group changes to draw every mesh, for benchmarking the draw order.
+    /// The second value is the same in load order.
+    pub fn bind_group_switches(&self) -> (usize, usize) {
+        if self.texture_array.is_some() {
+            return (1, 1);
+        }
+
+        let materials = self.mesh_materials();
+        let load_order: Vec<usize> = (0..materials.len()).collect();
+        (
+            material_switches(&materials, &self.draw_order),
+            material_switches(&materials, &load_order),
+        )
+    }
+
+    /// Writes the geometry as it was uploaded, after deduplication and normal computation,
+    /// as an obj file with an object per mesh. Materials are not written.
+    pub fn export_obj(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        write_obj(
+            writer,
+            self.meshes
+                .iter()
+                .map(|mesh| (&mesh.name[..], &mesh.vertices[..], &mesh.indices[..])),
+        )
+    }
+
+    /// Adds a material that was not part of the obj file. Returns its index.
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    /// Makes the mesh use another material, e.g. a debug one added with `add_material`.
+    /// Meshes that share geometry with it change too.
+    pub fn set_mesh_material(
+        &mut self,
+        mesh_index: usize,
+        material_index: usize,
+    ) -> anyhow::Result<()> {
+        if material_index >= self.materials.len() {
+            return Err(anyhow::anyhow!(
+                "Material index {} is out of range. Model has {} materials",
+                material_index,
+                self.materials.len()
+            ));
+        }
+
+        let num_meshes = self.meshes.len();
+        let mesh = self.meshes.get_mut(mesh_index).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Mesh index {} is out of range. Model has {} meshes",
+                mesh_index,
+                num_meshes
+            )
+        })?;
+        mesh.material = material_index;
+        // Copies are drawn with the geometry of the first mesh, so they change together
+        if let Some(shared) = mesh.shared {
+            for index in &self.shared_geometry[shared].meshes {
+                self.meshes[*index].material = material_index;
+            }
+        }
+        self.draw_order = material_draw_order(&self.mesh_materials());
+        Ok(())
+    }
+
+    /// Shows or hides one mesh in every draw. Out of range indices are ignored.
+    pub fn set_mesh_visible(&mut self, index: usize, visible: bool) {
+        if let Some(mesh) = self.meshes.get_mut(index) {
+            mesh.visible = visible;
+        }
+    }
+
+    /// Hides every mesh but the one at `index`, to inspect it alone
+    pub fn solo(&mut self, index: usize) {
+        for (mesh_index, visible) in solo_visibility(self.meshes.len(), index).enumerate() {
+            self.set_mesh_visible(mesh_index, visible);
+        }
+    }
+
+    /// Shows all meshes again after [`Self::solo`]
+    pub fn show_all_meshes(&mut self) {
+        for index in 0..self.meshes.len() {
+            self.set_mesh_visible(index, true);
+        }
+    }
+    /// Sets the instances every indirect draw renders. An instance range that does not
+    /// start at 0 needs `Features::INDIRECT_FIRST_INSTANCE`.
+    pub fn write_indirect_args(
+        &self,
+        ctx: &klgl::RenderContext,
+        instances: Range<u32>,
+    ) -> anyhow::Result<()> {
+        if instances.start != 0
+            && !ctx
+                .device
+                .features()
+                .contains(wgpu::Features::INDIRECT_FIRST_INSTANCE)
+        {
+            return Err(anyhow::anyhow!(
+                "Instances {:?} do not start at 0 and the device does not support INDIRECT_FIRST_INSTANCE",
+                instances
+            ));
+        }
+
+        let args = indirect_args(draw_counts(&self.meshes, &self.shared_geometry), instances);
+        ctx.queue
+            .write_buffer(&self.indirect_buffer, 0, &indirect_args_bytes(&args));
+        Ok(())
+    }
+
+    /// Writes the instances the meshes with shared geometry are drawn with: every one of
+    /// `instances` once per mesh, moved to that mesh. Does nothing if they did not change.
+    pub fn write_shared_instances(&mut self, ctx: &klgl::RenderContext, instances: &[Instance]) {
+        let written: &[u8] = bytemuck::cast_slice(&self.shared_instances);
+        if self.shared_geometry.is_empty() || bytemuck::cast_slice::<_, u8>(instances) == written {
+            return;
+        }
+
+        for geometry in &mut self.shared_geometry {
+            let placed: Vec<Instance> = instances
+                .iter()
+                .flat_map(|instance| {
+                    geometry
+                        .offsets
+                        .iter()
+                        .map(|offset| instance.translated(*offset))
+                })
+                .collect();
+            let size = std::mem::size_of_val(&placed[..]).max(std::mem::size_of::<Instance>())
+                as wgpu::BufferAddress;
+            if geometry
+                .instances_buffer
+                .as_ref()
+                .is_none_or(|buffer| buffer.size() < size)
+            {
+                geometry.instances_buffer =
+                    Some(ctx.device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Shared Geometry Instance Buffer"),
+                        size,
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }));
+            }
+            if let Some(buffer) = &geometry.instances_buffer {
+                ctx.queue
+                    .write_buffer(buffer, 0, bytemuck::cast_slice(&placed));
+            }
+        }
+        self.shared_instances = instances.to_vec();
+    }
+
+    /// Tiles and shifts the texture of a material, e.g. to repeat bricks over a large wall.
+    /// Models that use a texture array have no materials to change.
+    #[allow(dead_code)]
+    pub fn set_material_uv_transform(
+        &mut self,
+        queue: &wgpu::Queue,
+        material_index: usize,
+        scale: [f32; 2],
+        offset: [f32; 2],
+    ) -> anyhow::Result<()> {
+        let num_materials = self.materials.len();
+        let material = self.materials.get_mut(material_index).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Material index {} is out of range. Model has {} materials",
+                material_index,
+                num_materials
+            )
+        })?;
+        material.set_uv_transform(queue, scale, offset);
+        Ok(())
+    }
+
+    /// Replaces the placeholder of the materials that wait for the texture at `path`.
+    /// Returns false if no material waits for it.
+    pub fn stream_texture(
+        &mut self,
+        ctx: &klgl::RenderContext,
+        layout: &wgpu::BindGroupLayout,
+        path: &str,
+        bytes: &[u8],
+        sampler: klgl::SamplerOptions,
+    ) -> anyhow::Result<bool> {
+        let Some(material_indices) = self.pending_textures.remove(path) else {
+            return Ok(false);
+        };
+        for material_index in material_indices {
+            let texture = klgl::Texture::from_bytes_with_sampler(
+                &ctx.device,
+                &ctx.queue,
+                bytes,
+                path,
+                sampler,
+                ctx.max_sampler_anisotropy(),
+                false,
+            )?;
+            self.materials[material_index].set_diffuse_texture(&ctx.device, layout, texture);
+        }
+        Ok(true)
+    }
+
+    #[allow(dead_code)]
+    pub fn load(
+        obj_file_name: &str,
+        file_map: &HashMap<String, FileDataHandle>,
+        ctx: &klgl::RenderContext,
+        layout: &wgpu::BindGroupLayout,
+        options: LoadOptions,
+    ) -> anyhow::Result<Model> {
+        let obj_bytes = &get_value_from_map(file_map, obj_file_name)?.data;
+        let data = ModelData::parse(obj_file_name, obj_bytes, file_map, options)?;
+        Self::upload(data, ctx, layout)
+    }
+
+    /// Loads the model and everything it references directly from the file system.
+    /// Relative paths in the obj and mtl files are resolved against the obj's directory.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_from_disk(
+        obj_path: &Path,
+        ctx: &klgl::RenderContext,
+        layout: &wgpu::BindGroupLayout,
+        options: LoadOptions,
+    ) -> anyhow::Result<Model> {
+        let data = ModelData::parse_from_disk(obj_path, options)?;
+        Self::upload(data, ctx, layout)
+    }
+
+    /// Creates the textures and buffers of a parsed model. Textures that were missing when it was
+    /// parsed get a placeholder if the options stream textures, see [`Self::stream_texture`].
+    pub fn upload(
+        data: ModelData,
+        ctx: &klgl::RenderContext,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Model> {
+        let ModelData {
+            obj_file_name,
+            options,
+            materials: material_data,
+            texture_layers,
+            textures,
+            meshes: mesh_data,
+            shared_geometry,
+        } = data;
+        let get_file = |path: &str| get_value_from_map(&textures, path);
+
+        let mut materials = Vec::new();
+        let mut pending_textures: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut texture_array = None;
+        if let Some(layers) = texture_layers {
+            let images = layers
+                .iter()
+                .map(|source| layer_image(source, &textures, options.fallback_texture))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            log::info!(
+                "{}: packed {} materials into {} texture array layers",
+                obj_file_name,
+                material_data.len(),
+                images.len()
+            );
+
+            let texture = klgl::Texture::array_from_images(
+                &ctx.device,
+                &ctx.queue,
+                &images,
+                &format!("{} texture array", obj_file_name),
+                options.diffuse_sampler,
+                ctx.max_sampler_anisotropy(),
+            )?;
+            texture_array = Some(TextureArray::new(&ctx.device, layout, texture));
+        } else {
+            for material in &material_data {
+                let diffuse_texture = match &material.source {
+                    DiffuseSource::File(path) if options.stream_textures => match get_file(path) {
+                        Ok(bytes) => klgl::Texture::from_bytes_with_sampler(
+                            &ctx.device,
+                            &ctx.queue,
+                            bytes,
+                            path,
+                            options.diffuse_sampler,
+                            ctx.max_sampler_anisotropy(),
+                            false,
+                        )?,
+                        Err(_) => {
+                            pending_textures
+                                .entry(path.clone())
+                                .or_default()
+                                .push(materials.len());
+                            klgl::Texture::solid_color(
+                                &ctx.device,
+                                &ctx.queue,
+                                STREAMING_PLACEHOLDER_COLOR,
+                                "STREAMING_PLACEHOLDER",
+                            )
+                        }
+                    },
+                    DiffuseSource::File(path) => klgl::Texture::from_bytes_with_sampler(
+                        &ctx.device,
+                        &ctx.queue,
+                        get_file(path)?,
+                        path,
+                        options.diffuse_sampler,
+                        ctx.max_sampler_anisotropy(),
+                        false,
+                    )?,
+                    DiffuseSource::Placeholder => options.fallback_texture.texture(ctx)?,
+                    DiffuseSource::Default => klgl::Texture::create_checkerboard(
+                        &ctx.device,
+                        &ctx.queue,
+                        "DEFAULT_MATERIAL",
+                    ),
+                };
+                materials.push(Material::new(
+                    &ctx.device,
+                    layout,
+                    material.name.clone(),
+                    diffuse_texture,
+                    material.uv_transform,
+                ));
+            }
+        }
+
+        let mut shared_of = vec![None; mesh_data.len()];
+        for (shared, group) in shared_geometry.iter().enumerate() {
+            for index in &group.meshes {
+                shared_of[*index] = Some(shared);
+            }
+        }
+
+        let mut meshes: Vec<Mesh> = Vec::with_capacity(mesh_data.len());
+        for (index, mesh) in mesh_data.into_iter().enumerate() {
+            // Copies use the buffers of the first mesh of their group, which comes before them
+            let owner = shared_of[index].map(|shared| &meshes[shared_geometry[shared].meshes[0]]);
+            let (vertex_buffer, index_buffer) = match owner {
+                Some(owner) => (owner.vertex_buffer.clone(), owner.index_buffer.clone()),
+                None => (
+                    ctx.device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some(&format!("{:?} Vertex Buffer", obj_file_name)),
+                            contents: bytemuck::cast_slice(&mesh.vertices),
+                            usage: wgpu::BufferUsages::VERTEX,
+                        }),
+                    ctx.device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some(&format!("{:?} Index Buffer", obj_file_name)),
+                            contents: bytemuck::cast_slice(&mesh.indices),
+                            usage: wgpu::BufferUsages::INDEX,
+                        }),
+                ),
+            };
+
+            meshes.push(Mesh {
+                name: mesh.name,
+                vertex_buffer,
+                index_buffer,
+                num_elements: mesh.indices.len() as u32,
+                material: mesh.material,
+                layer: mesh.layer,
+                double_sided: material_data[mesh.material].double_sided,
+                bounds: vertex_bounds(&mesh.vertices),
+                vertices: mesh.vertices,
+                indices: mesh.indices,
+                shared: shared_of[index],
+                visible: true,
+            });
+        }
+
+        // One instance until the owner writes its own counts
+        let indirect_buffer = ctx
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{:?} Indirect Buffer", obj_file_name)),
+                contents: &indirect_args_bytes(&indirect_args(
+                    draw_counts(&meshes, &shared_geometry),
+                    0..1,
+                )),
+                usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let bvh = Bvh::new(
+            &meshes
+                .iter()
+                .map(|mesh| {
+                    BoundingSphere::around(
+                        mesh.bounds(),
+                        mesh.vertices.iter().map(|v| Point3::from(v.position)),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let mesh_materials: Vec<usize> = meshes.iter().map(|mesh| mesh.material).collect();
+        let draw_order = material_draw_order(&mesh_materials);
+        if texture_array.is_none() {
+            log::info!(
+                "{}: {} material switches per draw, {} in load order",
+                obj_file_name,
+                material_switches(&mesh_materials, &draw_order),
+                material_switches(&mesh_materials, &(0..meshes.len()).collect::<Vec<_>>())
+            );
+        }
+
+        if !pending_textures.is_empty() {
+            log::info!(
+                "{}: {} textures are streamed in after the geometry",
+                obj_file_name,
+                pending_textures.len()
+            );
+        }
+
+        let model = Model {
+            meshes,
+            materials,
+            texture_array,
+            indirect_buffer,
+            bvh,
+            draw_order,
+            pending_textures,
+            shared_geometry,
+            shared_instances: Vec::new(),
+        };
+        if !model.shared_geometry.is_empty() {
+            log::info!(
+                "{}: {} geometries are shared by copies, {} draws fewer per instance",
+                obj_file_name,
+                model.shared_geometry.len(),
+                model.instanced_mesh_savings()
+            );
+        }
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_format_of_path() {
+        let format = |path: &str| ModelFormat::of_path(Path::new(path));
+        assert_eq!(format("models/sponza/sponza.obj"), Some(ModelFormat::Obj));
+        assert_eq!(format("C:/Models/Crate.OBJ"), Some(ModelFormat::Obj));
+        assert_eq!(format("scene.gltf"), Some(ModelFormat::Gltf));
+        assert_eq!(format("/tmp/scene.glb"), Some(ModelFormat::Gltf));
+        assert_eq!(format("sponza.mtl"), None);
+        assert_eq!(format("texture.png"), None);
+        assert_eq!(format("obj"), None);
+        assert_eq!(format("models/"), None);
+    }
+
+    #[test]
+    fn test_read_from_disk_relative_to_obj() {
+        let dir =
+            std::env::temp_dir().join(format!("tutorial09_read_from_disk_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("textures")).unwrap();
+        std::fs::write(dir.join("textures").join("diffuse.png"), [1, 2, 3]).unwrap();
+
+        let root_path = root_path_of(&to_posix_path(&dir.join("model.obj")));
+        let texture_path = to_posix_path(&root_path.join("textures/diffuse.png"));
+
+        let texture = read_from_disk(&texture_path);
+        let missing = read_from_disk(&to_posix_path(&root_path.join("missing.png")));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(&texture.unwrap()[..], &[1, 2, 3]);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_parse_from_disk() {
+        let dir =
+            std::env::temp_dir().join(format!("tutorial09_parse_from_disk_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("textures")).unwrap();
+        let obj = "mtllib crate.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl wood\nf 1 2 3\n";
+        let mtl = "newmtl wood\nmap_Kd textures/wood.png\n";
+        std::fs::write(dir.join("crate.obj"), obj).unwrap();
+        std::fs::write(dir.join("crate.mtl"), mtl).unwrap();
+        std::fs::write(dir.join("textures").join("wood.png"), [1, 2, 3]).unwrap();
+
+        let data = ModelData::parse_from_disk(&dir.join("crate.obj"), LoadOptions::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+        let data = data.unwrap();
+
+        let texture_path = to_posix_path(&dir.join("textures").join("wood.png"));
+        assert_eq!(
+            data.materials[0].source,
+            DiffuseSource::File(texture_path.clone())
+        );
+        assert_eq!(&data.textures[&texture_path][..], [1, 2, 3]);
+        assert_eq!(data.meshes.len(), 1);
+        assert_eq!(data.meshes[0].indices.len(), 3);
+
+        // Nothing to read from a directory that is gone
+        assert!(
+            ModelData::parse_from_disk(&dir.join("crate.obj"), LoadOptions::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_obj_without_materials() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nf 1 2 3\nf 2 4 3\n";
+        let files = HashMap::from([("models/plain.obj".to_string(), obj.as_bytes())]);
+        let get_file = |path: &str| get_value_from_map(&files, path).map(|x| Cow::Borrowed(*x));
+
+        let (models, materials) = parse_obj("models/plain.obj", &get_file).unwrap();
+        assert!(materials.is_empty());
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].mesh.indices.len(), 6);
+
+        let vertices = mesh_vertices(&models[0].mesh, 0);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(vertices[3].position, [1.0, 1.0, 0.0]);
+
+        // Every mesh maps to the default material appended after the obj materials
+        assert_eq!(resolve_material(models[0].mesh.material_id, 0), 0);
+        assert_eq!(resolve_material(Some(1), 2), 1);
+        assert_eq!(resolve_material(Some(5), 2), 2);
+    }
+
+    #[test]
+    fn test_vertex_colors() {
+        let obj = "v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 0 1 0 0 0 1\nf 1 2 3\n";
+        let colored_files = HashMap::from([("colored.obj".to_string(), obj.as_bytes())]);
+        let get_file =
+            |path: &str| get_value_from_map(&colored_files, path).map(|x| Cow::Borrowed(*x));
+        let (models, _) = parse_obj("colored.obj", &get_file).unwrap();
+        let colors: Vec<[f32; 3]> = mesh_vertices(&models[0].mesh, 0)
+            .iter()
+            .map(|v| v.color)
+            .collect();
+        assert_eq!(colors, [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+        let plain_files = HashMap::from([("plain.obj".to_string(), obj.as_bytes())]);
+        let get_file =
+            |path: &str| get_value_from_map(&plain_files, path).map(|x| Cow::Borrowed(*x));
+        let (models, _) = parse_obj("plain.obj", &get_file).unwrap();
+        for vertex in mesh_vertices(&models[0].mesh, 0) {
+            assert_eq!(vertex.color, [1.0, 1.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn test_import_transform() {
+        let vertex = |position: [f32; 3], normal: [f32; 3]| ModelVertex {
+            position,
+            tex_coords: [0.0, 0.0],
+            normal,
+            layer: 0,
+            color: [1.0, 1.0, 1.0],
+        };
+        // Triangle facing +Z, counter-clockwise seen from above
+        let mut vertices = vec![
+            vertex([0.0, 0.0, 1.0], [0.0, 0.0, 1.0]),
+            vertex([1.0, 0.0, 1.0], [0.0, 0.0, 1.0]),
+            vertex([0.0, 1.0, 1.0], [0.0, 0.0, 0.0]),
+        ];
+        let mut indices = vec![0, 1, 2];
+        apply_import_transform(
+            &mut vertices,
+            &mut indices,
+            &ImportPreset::ZUpToYUp.matrix(),
+        );
+        assert_eq!(vertices[0].position, [0.0, 1.0, 0.0]);
+        assert_eq!(vertices[0].normal, [0.0, 1.0, 0.0]);
+        assert_eq!(vertices[2].position, [0.0, 1.0, -1.0]);
+        // Missing normals are not made up
+        assert_eq!(vertices[2].normal, [0.0, 0.0, 0.0]);
+        // A rotation keeps the winding
+        assert_eq!(indices, [0, 1, 2]);
+
+        // Blender undoes it
+        apply_import_transform(&mut vertices, &mut indices, &ImportPreset::Blender.matrix());
+        assert_eq!(vertices[0].position, [0.0, 0.0, 1.0]);
+        assert_eq!(vertices[1].normal, [0.0, 0.0, 1.0]);
+
+        // Mirroring and scaling keeps the normals unit length and the triangles front facing
+        let mirror = Matrix4::from_nonuniform_scale(-2.0, 2.0, 2.0);
+        apply_import_transform(&mut vertices, &mut indices, &mirror);
+        assert_eq!(vertices[1].position, [-2.0, 0.0, 2.0]);
+        assert_eq!(vertices[1].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(indices, [0, 2, 1]);
+        let corner = |i: u32| Vector3::from(vertices[i as usize].position);
+        let face_normal = (corner(indices[1]) - corner(indices[0]))
+            .cross(corner(indices[2]) - corner(indices[0]));
+        assert!(face_normal.z > 0.0);
+    }
+
+    #[test]
+    fn test_dedup_vertices() {
+        // Two triangles of a quad where the shared edge is stored twice
+        let vertex = |x: f32, y: f32| ModelVertex {
+            position: [x, y, 0.0],
+            tex_coords: [x, y],
+            normal: [0.0, 0.0, 1.0],
+            layer: 0,
+            color: [1.0, 1.0, 1.0],
+        };
+        let vertices = [
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(0.0, 1.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
+        ];
+        let indices = [0, 1, 2, 3, 4, 5];
+
+        let (unique, remapped) = dedup_vertices(&vertices, &indices);
+        assert_eq!(unique.len(), 4);
+        assert_eq!(remapped.len(), indices.len());
+        for (&old, &new) in indices.iter().zip(&remapped) {
+            let expected: [u32; 12] = bytemuck::cast(vertices[old as usize]);
+            let actual: [u32; 12] = bytemuck::cast(unique[new as usize]);
+            assert_eq!(actual, expected);
+        }
+
+        // Vertices that differ in any attribute are kept apart
+        let mut flipped = vertex(0.0, 0.0);
+        flipped.normal = [0.0, 0.0, -1.0];
+        let (unique, _) = dedup_vertices(&[vertex(0.0, 0.0), flipped], &[0, 1]);
+        assert_eq!(unique.len(), 2);
+    }
+
+    // Unit cube with 8 shared corners and two triangles per side
+    fn cube() -> (Vec<ModelVertex>, Vec<u32>) {
+        let vertices = (0..8)
+            .map(|i| ModelVertex {
+                position: [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32],
+                tex_coords: [0.0, 0.0],
+                normal: [0.0, 0.0, 0.0],
+                layer: 0,
+                color: [1.0, 1.0, 1.0],
+            })
+            .collect();
+        #[rustfmt::skip]
+        let indices = vec![
+            0, 2, 3, 0, 3, 1, // -z
+            4, 5, 7, 4, 7, 6, // +z
+            0, 1, 5, 0, 5, 4, // -y
+            2, 6, 7, 2, 7, 3, // +y
+            0, 4, 6, 0, 6, 2, // -x
+            1, 3, 7, 1, 7, 5, // +x
+        ];
+        (vertices, indices)
+    }
+
+    #[test]
+    fn test_compute_normals_keeps_sharp_edges() {
+        let (vertices, indices) = cube();
+        let (vertices, indices) = compute_normals(&vertices, &indices, Deg(30.0));
+
+        // Every side gets its own 4 vertices with the normal of the side
+        assert_eq!(vertices.len(), 24);
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] =
+                [0, 1, 2].map(|k| Vector3::from(vertices[triangle[k] as usize].position));
+            let face_normal = (b - a).cross(c - a).normalize();
+            for &i in triangle {
+                let normal = Vector3::from(vertices[i as usize].normal);
+                assert!((normal - face_normal).magnitude() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compute_normals_smooths_below_threshold() {
+        let (vertices, indices) = cube();
+        let (vertices, _) = compute_normals(&vertices, &indices, Deg(180.0));
+
+        // Corners are shared and their normals point away from the center of the cube
+        assert_eq!(vertices.len(), 8);
+        for vertex in &vertices {
+            let normal = Vector3::from(vertex.normal);
+            let outward = Vector3::from(vertex.position) - Vector3::new(0.5, 0.5, 0.5);
+            assert!((normal.magnitude() - 1.0).abs() < 1e-5);
+            assert!(normal.dot(outward.normalize()) > 0.5);
+        }
+    }
+
+    #[test]
+    fn test_export_obj_round_trip() {
+        let (vertices, indices) = cube();
+        let (vertices, indices) = compute_normals(&vertices, &indices, Deg(30.0));
+        let quad = &vertices[..4];
+
+        let mut bytes = Vec::new();
+        write_obj(
+            &mut bytes,
+            [
+                ("cube", &vertices[..], &indices[..]),
+                ("quad", quad, &[0, 1, 2, 0, 2, 3][..]),
+            ],
+        )
+        .unwrap();
+
+        let obj = String::from_utf8(bytes).unwrap();
+        let files = HashMap::from([("dump.obj".to_string(), obj.as_bytes())]);
+        let get_file = |path: &str| get_value_from_map(&files, path).map(|x| Cow::Borrowed(*x));
+        let (models, _) = parse_obj("dump.obj", &get_file).unwrap();
+
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].name, "cube");
+        let parsed = mesh_vertices(&models[0].mesh, 0);
+        assert_eq!(parsed.len(), vertices.len());
+        assert_eq!(models[0].mesh.indices, indices);
+        for (parsed, original) in parsed.iter().zip(&vertices) {
+            assert_eq!(parsed.position, original.position);
+            assert_eq!(parsed.tex_coords, original.tex_coords);
+            assert_eq!(parsed.normal, original.normal);
+        }
+
+        // Indices of the second object start after the vertices of the first one
+        assert_eq!(mesh_vertices(&models[1].mesh, 0).len(), 4);
+        assert_eq!(models[1].mesh.indices, [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn test_texture_array_has_a_layer_per_unique_texture() {
+        let file = |path: &str| DiffuseSource::File(path.to_string());
+        let sources = [
+            file("bricks.png"),
+            file("floor.png"),
+            file("bricks.png"),
+            DiffuseSource::Placeholder,
+            DiffuseSource::Placeholder,
+            DiffuseSource::Default,
+        ];
+
+        let (layers, layer_of_material) = texture_array_layers(&sources);
+        assert_eq!(
+            layers,
+            [
+                file("bricks.png"),
+                file("floor.png"),
+                DiffuseSource::Placeholder,
+                DiffuseSource::Default
+            ]
+        );
+        assert_eq!(layer_of_material, [0, 1, 0, 2, 2, 3]);
+
+        // Every material samples the layer made from its own texture
+        for (source, &layer) in sources.iter().zip(&layer_of_material) {
+            assert_eq!(&layers[layer as usize], source);
+        }
+    }
+
+    #[test]
+    fn test_obj_with_missing_mtl_file() {
+        let obj = "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl stone\nf 1 2 3\n";
+        let files = HashMap::from([("plain.obj".to_string(), obj.as_bytes())]);
+        let get_file = |path: &str| get_value_from_map(&files, path).map(|x| Cow::Borrowed(*x));
+
+        let (models, materials) = parse_obj("plain.obj", &get_file).unwrap();
+        assert!(materials.is_empty());
+        assert_eq!(resolve_material(models[0].mesh.material_id, 0), 0);
+    }
+
+    #[test]
+    fn test_parse_without_a_device() {
+        let obj = "mtllib box.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\n\
+                   o textured\nusemtl wood\nf 1 2 3\n\
+                   o plain\nusemtl paint\nf 1 3 4\n";
+        let mtl = "newmtl wood\nmap_Kd textures/wood.png\nnewmtl paint\nKd 1 0 0\n";
+        // The texture is not needed to parse, a missing one is streamed in after the upload
+        let files = HashMap::from([
+            ("models/box.obj".to_string(), obj.as_bytes()),
+            ("models/box.mtl".to_string(), mtl.as_bytes()),
+        ]);
+        let get_file = |path: &str| get_value_from_map(&files, path).map(|x| Cow::Borrowed(*x));
+
+        let no_texture = |_: &str| None;
+
+        let data = ModelData::parse_with(
+            "models/box.obj",
+            get_file,
+            no_texture,
+            LoadOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(data.obj_file_name, "models/box.obj");
+        assert!(data.texture_layers.is_none());
+        assert!(data.textures.is_empty());
+
+        let sources: Vec<&DiffuseSource> = data.materials.iter().map(|m| &m.source).collect();
+        assert_eq!(
+            sources,
+            [
+                &DiffuseSource::File("models/textures/wood.png".to_string()),
+                &DiffuseSource::Placeholder
+            ]
+        );
+
+        let names: Vec<&str> = data.meshes.iter().map(|mesh| mesh.name.as_str()).collect();
+        assert_eq!(names, ["textured", "plain"]);
+        assert_eq!(data.meshes[1].material, 1);
+        let positions: Vec<[f32; 3]> = data.meshes[1]
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position)
+            .collect();
+        assert_eq!(
+            positions,
+            [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+        );
+        assert_eq!(data.meshes[1].indices, [0, 1, 2]);
+        assert!(data.shared_geometry.is_empty());
+
+        // With a texture array every material gets a layer
+        let options = LoadOptions {
+            texture_array: true,
+            ..Default::default()
+        };
+        let data = ModelData::parse_with("models/box.obj", get_file, no_texture, options).unwrap();
+        assert_eq!(data.texture_layers.map(|layers| layers.len()), Some(2));
+        assert_eq!(data.meshes[1].layer, 1);
+        assert!(
+            data.meshes[1]
+                .vertices
+                .iter()
+                .all(|vertex| vertex.layer == 1)
+        );
+    }
+
+    #[test]
+    fn test_parse_in_memory_obj() {
+        let obj = "mtllib quad.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                   vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                   usemtl brick\nf 1/1 2/2 3/3\nf 1/1 3/3 4/4\n";
+        let mtl = "newmtl brick\nmap_Kd -s 2 2 brick.png\n";
+        let files = HashMap::from([
+            ("quad.obj".to_string(), obj.as_bytes()),
+            ("quad.mtl".to_string(), mtl.as_bytes()),
+        ]);
+        let get_file = |path: &str| get_value_from_map(&files, path).map(|x| Cow::Borrowed(*x));
+        // Only the bytes of the texture are kept, nothing is decoded yet
+        let get_texture = |path: &str| {
+            assert_eq!(path, "brick.png");
+            Some(TextureBytes::Read(vec![1, 2, 3]))
+        };
+        let options = LoadOptions {
+            compute_normals: true,
+            ..Default::default()
+        };
+
+        let data = ModelData::parse_with("quad.obj", get_file, get_texture, options).unwrap();
+        assert_eq!(&data.textures["brick.png"][..], [1, 2, 3]);
+        assert_eq!(data.materials.len(), 1);
+        assert_eq!(data.materials[0].uv_transform.tex_scale, [2.0, 2.0]);
+        assert!(!data.materials[0].double_sided);
+
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.indices.len(), 6);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.vertices[2].position, [1.0, 1.0, 0.0]);
+        assert_eq!(mesh.vertices[2].tex_coords, [1.0, 0.0]);
+        // The quad is flat, so all computed normals face +Z
+        for vertex in &mesh.vertices {
+            assert!((Vector3::from(vertex.normal) - Vector3::unit_z()).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_material_without_texture_gets_the_fallback() {
+        let obj = "mtllib plain.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl paint\nf 1 2 3\n";
+        let mtl = "newmtl paint\nKd 1 0 0\n";
+        let files = HashMap::from([
+            ("plain.obj".to_string(), obj.as_bytes()),
+            ("plain.mtl".to_string(), mtl.as_bytes()),
+        ]);
+        let get_file = |path: &str| get_value_from_map(&files, path).map(|x| Cow::Borrowed(*x));
+        let options = LoadOptions {
+            texture_array: true,
+            fallback_texture: FallbackTexture::Color([10, 20, 30, 255]),
+            ..Default::default()
+        };
+
+        let data = ModelData::parse_with("plain.obj", get_file, |_| None, options).unwrap();
+        assert_eq!(data.materials[0].source, DiffuseSource::Placeholder);
+        let layers = data.texture_layers.unwrap();
+        let image = layer_image(&layers[0], &data.textures, data.options.fallback_texture)
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(image.dimensions(), (1, 1));
+        assert_eq!(image.get_pixel(0, 0).0, [10, 20, 30, 255]);
+
+        // Neutral gray unless configured
+        let gray = layer_image(
+            &DiffuseSource::Placeholder,
+            &HashMap::new(),
+            LoadOptions::default().fallback_texture,
+        )
+        .unwrap()
+        .to_rgba8();
+        assert_eq!(gray.get_pixel(0, 0).0, [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn test_mesh_bounds() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 2 0\nv 5 5 5\nv 6 5 5\nv 5 5 -3\n\
+                   o first\nf 1 2 3\no second\nf 4 5 6\n";
+        let files = HashMap::from([("two.obj".to_string(), obj.as_bytes())]);
+        let get_file = |path: &str| get_value_from_map(&files, path).map(|x| Cow::Borrowed(*x));
+
+        let (models, _) = parse_obj("two.obj", &get_file).unwrap();
+        assert_eq!(models.len(), 2);
+        let vertices: Vec<Vec<ModelVertex>> = models
+            .iter()
+            .map(|model| mesh_vertices(&model.mesh, 0))
+            .collect();
+        let bounds: Vec<BoundingBox> = vertices.iter().map(|v| vertex_bounds(v)).collect();
+
+        assert_eq!(bounds[0].min, Point3::new(0.0, 0.0, 0.0));
+        assert_eq!(bounds[0].max, Point3::new(1.0, 2.0, 0.0));
+        assert_eq!(bounds[1].min, Point3::new(5.0, 5.0, -3.0));
+        assert_eq!(bounds[1].max, Point3::new(6.0, 5.0, 5.0));
+
+        let all_vertices: Vec<ModelVertex> = vertices.concat();
+        assert_eq!(union_bounds(bounds.iter()), vertex_bounds(&all_vertices));
+    }
+
+    #[test]
+    fn test_copies_share_geometry() {
+        let quad = |offset: [f32; 3]| -> Vec<ModelVertex> {
+            [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]
+                .iter()
+                .map(|[x, y]| ModelVertex {
+                    position: [x + offset[0], y + offset[1], offset[2]],
+                    tex_coords: [*x, *y],
+                    normal: [0.0, 0.0, 1.0],
+                    layer: 0,
+                    color: [1.0; 3],
+                })
+                .collect()
+        };
+        let indices = [0, 1, 2, 0, 2, 3];
+        let at_origin = quad([0.0, 0.0, 0.0]);
+        let moved = quad([5.0, -2.0, 3.0]);
+        let mut stretched = quad([0.0, 0.0, 0.0]);
+        stretched[2].position[0] = 2.0;
+
+        // Two identical quads at different positions make one geometry drawn as two instances
+        let meshes = [
+            (0, &at_origin[..], &indices[..]),
+            (0, &stretched[..], &indices[..]),
+            (0, &moved[..], &indices[..]),
+            (1, &moved[..], &indices[..]),
+        ];
+        let shared = shared_geometry(meshes.into_iter());
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].meshes, [0, 2]);
+        assert_eq!(
+            shared[0].offsets,
+            [Vector3::new(0.0, 0.0, 0.0), Vector3::new(5.0, -2.0, 3.0)]
+        );
+        assert!(shared[0].instances_buffer.is_none());
+
+        // The same quad with another material or other indices is not a copy
+        let flipped = [0, 2, 1, 0, 3, 2];
+        let meshes = [
+            (0, &at_origin[..], &indices[..]),
+            (0, &moved[..], &flipped[..]),
+        ];
+        assert!(shared_geometry(meshes.into_iter()).is_empty());
+    }
+
+    #[test]
+    fn test_indirect_args_match_meshes() {
+        let args = indirect_args([(36, 1), (6, 1), (3, 1)].into_iter(), 0..25);
+        let words: Vec<u32> = indirect_args_bytes(&args)
+            .chunks_exact(4)
+            .map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        // index_count, instance_count, first_index, base_vertex, first_instance per mesh
+        assert_eq!(words, [36, 25, 0, 0, 0, 6, 25, 0, 0, 0, 3, 25, 0, 0, 0],);
+
+        let args = indirect_args([(12, 1)].into_iter(), 4..6);
+        assert_eq!(args[0].instance_count, 2);
+        assert_eq!(args[0].first_instance, 4);
+
+        // Three meshes share the geometry, so each instance is drawn three times
+        let args = indirect_args([(12, 3)].into_iter(), 4..6);
+        assert_eq!(args[0].instance_count, 6);
+        assert_eq!(args[0].first_instance, 12);
+    }
+
+    #[test]
+    fn test_texture_map_uv_transform() {
+        assert_eq!(
+            parse_texture_map("textures/bricks.png"),
+            ("textures/bricks.png".to_string(), MaterialUniform::IDENTITY)
+        );
+        assert_eq!(MaterialUniform::default(), MaterialUniform::IDENTITY);
+
+        let (path, uniform) =
+            parse_texture_map("-bm 0.5 -s 4 2 1 -clamp on -o -0.5 0.25 0 my bricks.png");
+        assert_eq!(path, "my bricks.png");
+        assert_eq!(uniform.tex_scale, [4.0, 2.0]);
+        assert_eq!(uniform.tex_offset, [-0.5, 0.25]);
+
+        // The uniform buffer gets the values in this order
+        let bytes: &[f32] = bytemuck::cast_slice(std::slice::from_ref(&uniform));
+        assert_eq!(bytes, [4.0, 2.0, -0.5, 0.25, 1.0, 1.0, 0.0, 32.0]);
+    }
+
+    #[test]
+    fn test_draw_order_groups_materials() {
+        let materials = [2, 0, 1, 0, 2, 1, 0];
+        let order = material_draw_order(&materials);
+
+        // Every mesh once, in load order within a material
+        assert_eq!(order, [1, 3, 6, 2, 5, 0, 4]);
+
+        // Each material is a single contiguous run
+        let sorted: Vec<usize> = order.iter().map(|index| materials[*index]).collect();
+        let mut seen = vec![];
+        for run in sorted.chunk_by(|a, b| a == b) {
+            assert!(!seen.contains(&run[0]));
+            seen.push(run[0]);
+        }
+
+        let load_order: Vec<usize> = (0..materials.len()).collect();
+        assert_eq!(material_switches(&materials, &order), 3);
+        assert_eq!(material_switches(&materials, &load_order), 7);
+        assert_eq!(material_switches(&[], &[]), 0);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Command {
+        BindGroup(u32, wgpu::BindGroup),
+        VertexBuffer(u32, wgpu::Buffer),
+        IndexBuffer(wgpu::Buffer),
+        Draw(Range<u32>, Range<u32>),
+        DrawIndirect(wgpu::Buffer, wgpu::BufferAddress),
+    }
+
+    #[derive(Default)]
+    struct RecordingPass {
+        commands: Vec<Command>,
+    }
+
+    impl MeshPass for RecordingPass {
+        fn set_bind_group(&mut self, index: u32, bind_group: &wgpu::BindGroup) {
+            self.commands
+                .push(Command::BindGroup(index, bind_group.clone()));
+        }
+
+        fn set_vertex_buffer(&mut self, slot: u32, buffer: &wgpu::Buffer) {
+            self.commands
+                .push(Command::VertexBuffer(slot, buffer.clone()));
+        }
+
+        fn set_index_buffer(&mut self, buffer: &wgpu::Buffer) {
+            self.commands.push(Command::IndexBuffer(buffer.clone()));
+        }
+
+        fn draw_indexed(&mut self, indices: Range<u32>, instances: Range<u32>) {
+            self.commands.push(Command::Draw(indices, instances));
+        }
+
+        fn draw_indexed_indirect(
+            &mut self,
+            args_buffer: &wgpu::Buffer,
+            offset: wgpu::BufferAddress,
+        ) {
+            self.commands
+                .push(Command::DrawIndirect(args_buffer.clone(), offset));
+        }
+    }
+
+    impl RecordingPass {
+        // Names of each drawn mesh, found by its index buffer, and the material bound at the time
+        fn drawn_materials<'a>(&self, model: &'a Model) -> Vec<(&'a str, &'a str)> {
+            let mut material = "";
+            let mut drawn = Vec::new();
+            for command in &self.commands {
+                match command {
+                    Command::BindGroup(0, bind_group) => {
+                        material = model
+                            .materials
+                            .iter()
+                            .find(|material| material.bind_group == *bind_group)
+                            .map_or("", |material| &material.name[..]);
+                    }
+                    Command::IndexBuffer(buffer) => {
+                        let mesh = model
+                            .meshes
+                            .iter()
+                            .find(|mesh| mesh.index_buffer == *buffer)
+                            .unwrap();
+                        drawn.push((&mesh.name[..], material));
+                    }
+                    _ => {}
+                }
+            }
+            drawn
+        }
+    }
+
+    // Draws every mesh of the model into a recording pass
+    struct DrawFixture {
+        ctx: std::rc::Rc<std::cell::RefCell<klgl::RenderContext>>,
+        layout: wgpu::BindGroupLayout,
+        camera: wgpu::BindGroup,
+        instances: wgpu::Buffer,
+    }
+
+    impl DrawFixture {
+        fn new() -> Self {
+            let ctx = crate::test_utils::gpu_context(4, 4);
+            let (layout, camera, instances) = {
+                let ctx = ctx.borrow();
+                let layout =
+                    ctx.device
+                        .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                            entries: &crate::models_draw_pass::texture_bind_group_layout_entries(
+                                false,
+                            ),
+                            label: None,
+                        });
+                let camera = klgl::Camera::new(
+                    cgmath::Point3::new(0.0, 0.0, 0.0),
+                    klgl::Rotator::from_direction(Vector3::unit_x()),
+                    1.0,
+                    45.0,
+                    0.1,
+                    100.0,
+                );
+                let (_, camera) = crate::test_utils::camera_binding(&ctx.device, &camera);
+                let instances = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("instances"),
+                    size: 256,
+                    usage: wgpu::BufferUsages::VERTEX,
+                    mapped_at_creation: false,
+                });
+                (layout, camera, instances)
+            };
+            Self {
+                ctx,
+                layout,
+                camera,
+                instances,
+            }
+        }
+
+        fn material(&self, name: &str) -> Material {
+            let ctx = self.ctx.borrow();
+            let texture = klgl::Texture::solid_color(&ctx.device, &ctx.queue, [255; 4], name);
+            Material::new(
+                &ctx.device,
+                &self.layout,
+                name.to_string(),
+                texture,
+                MaterialUniform::IDENTITY,
+            )
+        }
+
+        // A triangle per entry of `mesh_materials` named by its index, nothing is shared
+        fn model(&self, materials: &[&str], mesh_materials: &[usize]) -> Model {
+            let ctx = self.ctx.borrow();
+            let buffer = |label: &str, usage| {
+                ctx.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(label),
+                    size: 64,
+                    usage,
+                    mapped_at_creation: false,
+                })
+            };
+            let meshes = mesh_materials
+                .iter()
+                .enumerate()
+                .map(|(index, material)| Mesh {
+                    name: format!("mesh {}", index),
+                    vertex_buffer: buffer("vertices", wgpu::BufferUsages::VERTEX),
+                    index_buffer: buffer("indices", wgpu::BufferUsages::INDEX),
+                    num_elements: 3,
+                    material: *material,
+                    layer: 0,
+                    double_sided: false,
+                    vertices: Vec::new(),
+                    indices: Vec::new(),
+                    shared: None,
+                    visible: true,
+                    bounds: vertex_bounds(&[]),
+                })
+                .collect();
+            Model {
+                meshes,
+                materials: materials.iter().map(|name| self.material(name)).collect(),
+                texture_array: None,
+                indirect_buffer: buffer("indirect", wgpu::BufferUsages::INDIRECT),
+                bvh: Bvh::new(&[]),
+                draw_order: material_draw_order(mesh_materials),
+                pending_textures: HashMap::new(),
+                shared_geometry: Vec::new(),
+                shared_instances: Vec::new(),
+            }
+        }
+
+        fn draw(&self, model: &Model) -> RecordingPass {
+            let all: Vec<usize> = (0..model.meshes.len()).collect();
+            let mut pass = RecordingPass::default();
+            model.draw_instanced(&mut pass, &self.camera, &self.instances, 0..1, &all);
+            pass
+        }
+
+        // Meshes a draw of every mesh records, in draw order
+        fn drawn_meshes(&self, model: &Model) -> Vec<String> {
+            self.draw(model)
+                .drawn_materials(model)
+                .into_iter()
+                .map(|(mesh, _)| mesh.to_string())
+                .collect()
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_overridden_material_is_bound_for_the_mesh() {
+        let fixture = DrawFixture::new();
+        let mut model = fixture.model(&["brick", "wood"], &[0, 1, 0]);
+        let highlight = model.add_material(fixture.material("highlight"));
+        assert_eq!(highlight, 2);
+        model.set_mesh_material(2, highlight).unwrap();
+        assert!(model.set_mesh_material(2, 3).is_err());
+        assert!(model.set_mesh_material(3, highlight).is_err());
+
+        let pass = fixture.draw(&model);
+        assert_eq!(
+            pass.drawn_materials(&model),
+            [
+                ("mesh 0", "brick"),
+                ("mesh 1", "wood"),
+                ("mesh 2", "highlight")
+            ]
+        );
+        assert_eq!(
+            pass.commands[0],
+            Command::BindGroup(1, fixture.camera.clone())
+        );
+        assert_eq!(pass.commands.last(), Some(&Command::Draw(0..3, 0..1)));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_bind_group_switches_match_the_recorded_draws() {
+        let fixture = DrawFixture::new();
+        let model = fixture.model(&["brick", "wood"], &[0, 1, 0, 1]);
+        let material_binds = fixture
+            .draw(&model)
+            .commands
+            .iter()
+            .filter(|command| matches!(command, Command::BindGroup(0, _)))
+            .count();
+        assert_eq!(model.bind_group_switches(), (material_binds, 4));
+        assert_eq!(material_binds, 2);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_solo_draws_only_one_mesh() {
+        let fixture = DrawFixture::new();
+        let mut model = fixture.model(&["brick", "wood", "glass"], &[2, 0, 1, 0]);
+        model.solo(1);
+        assert_eq!(fixture.drawn_meshes(&model), ["mesh 1"]);
+
+        // All meshes are back in material order once the solo is cleared
+        model.show_all_meshes();
+        assert_eq!(
+            fixture.drawn_meshes(&model),
+            ["mesh 1", "mesh 3", "mesh 2", "mesh 0"]
+        );
+
+        // Soloing a mesh that does not exist hides everything
+        model.solo(5);
+        assert!(fixture.drawn_meshes(&model).is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_hidden_mesh_is_not_drawn() {
+        let fixture = DrawFixture::new();
+        let mut model = fixture.model(&["brick", "wood"], &[0, 1, 0]);
+        model.set_mesh_visible(2, false);
+        model.set_mesh_visible(7, false);
+        assert_eq!(fixture.drawn_meshes(&model), ["mesh 0", "mesh 1"]);
+
+        model.set_mesh_visible(2, true);
+        model.set_mesh_visible(0, false);
+        assert_eq!(fixture.drawn_meshes(&model), ["mesh 2", "mesh 1"]);
     }
 }
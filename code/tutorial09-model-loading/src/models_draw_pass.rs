@@ -1,4 +1,9 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    rc::Rc,
+};
 
 use cgmath::Deg;
 use klgl::{
@@ -7,16 +12,125 @@ use klgl::{
 };
 use wgpu::util::DeviceExt;
 
-use crate::model::{Model, ModelVertex, Vertex};
+use crate::bounds::BoundingBox;
+use crate::frame_ring::FrameRing;
+use crate::lights::LightManager;
+use crate::lines_draw_pass::{self, box_segments, normal_segments};
+use crate::model::{
+    FallbackTexture, ImportPreset, LoadOptions, Mesh, Model, ModelData, ModelVertex, Vertex,
+};
+use crate::occlusion_query_pass::OcclusionQueryPass;
+use crate::scene::Scene;
+use crate::shadow_draw_pass::ShadowBinding;
+
+// Distance between neighbour instances. Large enough to fit the scaled down sponza
+const SPACING: f32 = 400.0;
+// Approximate radius of the scaled down sponza
+const MODEL_RADIUS: f32 = 250.0;
+// Color of the mesh bounds lines
+const BOUNDS_COLOR: [f32; 3] = [1.0, 1.0, 0.0];
+const NORMALS_COLOR: [f32; 3] = [0.0, 1.0, 1.0];
+// Normal lines of all instances together, larger models are subsampled to stay below it
+const MAX_NORMAL_SEGMENTS: usize = 200_000;
+
+const LOAD_OPTIONS: LoadOptions = LoadOptions {
+    dedup_vertices: true,
+    // Draws sponza with one texture bind group instead of one per material
+    texture_array: false,
+    compute_normals: false,
+    smoothing_angle_degrees: 60.0,
+    // Keeps the floor sharp at grazing angles where the adapter supports it
+    diffuse_sampler: klgl::SamplerOptions::REPEAT
+        .with_anisotropy(klgl::SamplerOptions::MAX_ANISOTROPY),
+    import_transform: ImportPreset::Identity.matrix(),
+    // Shows the geometry of sponza before its textures arrive
+    stream_textures: true,
+    fallback_texture: FallbackTexture::GRAY,
+};
+
+// Texture array mode samples the layer of each vertex in place of the material texture.
+// Debug modes do not sample textures, so they work with both.
+// Vertex colors are read by vs_colored and vs_array.
+fn entry_points(
+    fragment_entry_point: &'static str,
+    texture_array: bool,
+) -> (&'static str, &'static str) {
+    match (texture_array, fragment_entry_point) {
+        (true, "fs_lit") => ("vs_array", "fs_lit_array"),
+        (true, "fs_shadowed") => ("vs_array", "fs_shadowed_array"),
+        (_, fragment_entry_point) => ("vs_colored", fragment_entry_point),
+    }
+}
+
+pub(crate) fn texture_bind_group_layout_entries(
+    texture_array: bool,
+) -> Vec<wgpu::BindGroupLayoutEntry> {
+    let (binding, view_dimension) = match texture_array {
+        true => (2, wgpu::TextureViewDimension::D2Array),
+        false => (0, wgpu::TextureViewDimension::D2),
+    };
+    let mut entries = vec![
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            // This should match the filterable field of the
+            // corresponding Texture entry above.
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+    ];
+
+    // A texture array has no materials, so the material uniform is only there for single textures
+    if !texture_array {
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+    }
+    entries
+}
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Instance {
+pub struct Instance {
     model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
 }
 
 impl Instance {
-    fn layout() -> wgpu::VertexBufferLayout<'static> {
+    pub fn new(model: cgmath::Matrix4<f32>) -> Self {
+        Self {
+            model: model.into(),
+            normal: klgl::normal_matrix(&model).into(),
+        }
+    }
+
+    /// Same instance with its model moved by `offset` first. The normal matrix stays the same.
+    pub fn translated(&self, offset: cgmath::Vector3<f32>) -> Self {
+        Self {
+            model: (cgmath::Matrix4::from(self.model) * cgmath::Matrix4::from_translation(offset))
+                .into(),
+            normal: self.normal,
+        }
+    }
+
+    pub fn layout() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<Instance>() as wgpu::BufferAddress,
@@ -49,26 +163,184 @@ impl Instance {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // The normal matrix follows as three vec3 columns
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DebugMode {
+    Textured,
+    Normals,
+    TexCoords,
+    Depth,
+}
+
+impl DebugMode {
+    pub const ALL: [DebugMode; 4] = [
+        DebugMode::Textured,
+        DebugMode::Normals,
+        DebugMode::TexCoords,
+        DebugMode::Depth,
+    ];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|x| *x == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    fn fragment_entry_point(self) -> &'static str {
+        match self {
+            DebugMode::Textured => "fs_lit",
+            DebugMode::Normals => "fs_normals",
+            DebugMode::TexCoords => "fs_tex_coords",
+            DebugMode::Depth => "fs_depth",
+        }
+    }
+}
+
+/// Back, front and no culling, in the order they are cycled through
+pub fn next_cull_mode(cull_mode: Option<wgpu::Face>) -> Option<wgpu::Face> {
+    match cull_mode {
+        Some(wgpu::Face::Back) => Some(wgpu::Face::Front),
+        Some(wgpu::Face::Front) => None,
+        None => Some(wgpu::Face::Back),
+    }
+}
+
+fn primitive_state(cull_mode: Option<wgpu::Face>) -> wgpu::PrimitiveState {
+    wgpu::PrimitiveState {
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        strip_index_format: None,
+        front_face: wgpu::FrontFace::Ccw,
+        cull_mode,
+        // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+        polygon_mode: wgpu::PolygonMode::Fill,
+        // Requires Features::DEPTH_CLIP_CONTROL
+        unclipped_depth: false,
+        // Requires Features::CONSERVATIVE_RASTERIZATION
+        conservative: false,
+    }
+}
+
+// Double sided meshes are drawn without culling whatever the cull mode is
+struct MeshPipelines {
+    culled: wgpu::RenderPipeline,
+    double_sided: wgpu::RenderPipeline,
+}
+
 pub struct ModelsDrawPass {
     ctx: Rc<RefCell<klgl::RenderContext>>,
-    pipeline: wgpu::RenderPipeline,
+    pipeline: MeshPipelines,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group: wgpu::BindGroup,
+    lights_bind_group_layout: wgpu::BindGroupLayout,
+    lights_bind_group: wgpu::BindGroup,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    color_format: wgpu::TextureFormat,
+    depth_stencil_state: Option<wgpu::DepthStencilState>,
+    debug_mode: DebugMode,
+    // Pipelines for debug modes are created on first use
+    debug_pipelines: HashMap<DebugMode, MeshPipelines>,
+    // Replaces the textured pipeline while shadows are enabled
+    shadows: Option<(ShadowBinding, MeshPipelines)>,
+    cull_mode: Option<wgpu::Face>,
+    // Draw calls read their counts from the model's indirect buffer
+    indirect: bool,
+    // Queries per instance and pipelines that test hidden instances without drawing them
+    occlusion: Option<(OcclusionQueryPass, MeshPipelines)>,
+    // Meshes inside the frustum for at least one instance. None draws every mesh.
+    visible_meshes: Option<Vec<bool>>,
+    show_bounds: bool,
+    // Length of the normal lines while they are shown
+    show_normals: Option<f32>,
+    // Every n-th vertex gets a normal line
+    normals_stride: usize,
+    // Bounds and normal lines that changed and were not taken by the lines pass yet
+    segments: Option<Vec<lines_draw_pass::Vertex>>,
     instances: Vec<Instance>,
-    instances_buffer: wgpu::Buffer,
+    instances_per_row: u32,
+    // Written in turn, so the buffer a frame in flight reads is not overwritten
+    instances_buffers: FrameRing<wgpu::Buffer>,
+    // Files of the current model, requested again on reload
+    model_path: String,
+    model_requirements: Vec<String>,
+    // Set while the model was loaded from the file system instead of the assets
+    disk_path: Option<PathBuf>,
     loading_model: Option<LoadingModel>,
     model: Option<Model>,
+    // Models of a scene file, drawn next to the model
+    scene: Option<Scene>,
 }
 
-struct LoadingModel {
+// Files of a model that is loading. The geometry only needs the obj and mtl files,
+// streamed textures arrive after the model is drawable.
+#[derive(Debug)]
+struct LoadProgress {
+    pending_geometry: HashSet<String>,
+    pending_textures: HashSet<String>,
+}
+
+impl LoadProgress {
+    fn new(obj_path: &str, requirements: &[String], stream_textures: bool) -> Self {
+        let (pending_textures, mut pending_geometry): (HashSet<String>, HashSet<String>) =
+            requirements
+                .iter()
+                .cloned()
+                .partition(|path| stream_textures && is_texture_path(path));
+        pending_geometry.insert(obj_path.to_string());
+        Self {
+            pending_geometry,
+            pending_textures,
+        }
+    }
+
+    fn receive(&mut self, path: &str) {
+        self.pending_geometry.remove(path);
+        self.pending_textures.remove(path);
+    }
+
+    /// The model can be built and drawn
+    fn geometry_ready(&self) -> bool {
+        self.pending_geometry.is_empty()
+    }
+
+    fn finished(&self) -> bool {
+        self.pending_geometry.is_empty() && self.pending_textures.is_empty()
+    }
+}
+
+fn is_texture_path(path: &str) -> bool {
+    image::ImageFormat::from_path(path).is_ok()
+}
+
+pub struct LoadingModel {
     endpoint: FileLoaderEndpoint,
     received_files: HashMap<String, FileDataHandle>,
-    remaining: u16,
+    progress: LoadProgress,
+    // The model was built from the geometry, textures that arrive now are streamed into it
+    built: bool,
     obj_path: String,
     bind_group_layout: wgpu::BindGroupLayout,
+    // Parses the obj file while the frames go on, large ones take long enough to stutter
+    #[cfg(not(target_arch = "wasm32"))]
+    parsing: Option<std::thread::JoinHandle<anyhow::Result<ModelData>>>,
 }
 
 impl LoadingModel {
@@ -76,110 +348,188 @@ impl LoadingModel {
         file_loader: &mut FileLoader,
         obj_path: &str,
         bind_group_layout: wgpu::BindGroupLayout,
-        requirements: &[&str],
+        requirements: &[String],
     ) -> Self {
         let mut endpoint = file_loader.make_endpoint();
-        let remaining = (requirements.len() as u16) + 1;
         endpoint.request(obj_path);
         for requirement in requirements {
-            endpoint.request(&requirement);
+            endpoint.request(requirement);
         }
 
         Self {
             endpoint,
             obj_path: obj_path.into(),
-            remaining,
+            progress: LoadProgress::new(obj_path, requirements, LOAD_OPTIONS.stream_textures),
+            built: false,
             received_files: HashMap::new(),
             bind_group_layout,
+            #[cfg(not(target_arch = "wasm32"))]
+            parsing: None,
         }
     }
 
-    pub fn ready(&self) -> bool {
-        self.remaining == 0
+    /// Paths of the files that arrived since the last call
+    pub fn update(&mut self) -> Vec<String> {
+        let mut arrived = Vec::new();
+        while let Ok((file_id, file_handle)) = self.endpoint.receiver.try_recv() {
+            let path = self.endpoint.loader.path_by_id(file_id).unwrap();
+            self.progress.receive(&path);
+            self.received_files.insert(path.clone(), file_handle);
+            arrived.push(path);
+        }
+        arrived
     }
 
-    pub fn update(&mut self) {
-        while let Ok(file_handle) = self.endpoint.receiver.try_recv() {
-            let path = self.endpoint.loader.path_by_id(file_handle.id).unwrap();
-            self.received_files.insert(path, file_handle);
-            if self.remaining > 0 {
-                self.remaining -= 1;
-            }
-        }
+    /// The model was built and every file of it arrived
+    pub fn finished(&self) -> bool {
+        self.built && self.progress.finished()
     }
 
-    pub fn get(&self, ctx: &klgl::RenderContext) -> Option<anyhow::Result<Model>> {
-        if !self.ready() {
+    /// Builds the model once its geometry arrived. Textures that are still loading
+    /// have placeholders until [`Self::stream`] gets them.
+    /// On native the obj file is parsed on another thread first, so the model comes a few calls later.
+    pub fn build(&mut self, ctx: &klgl::RenderContext) -> Option<anyhow::Result<Model>> {
+        if self.built || !self.progress.geometry_ready() {
             return None;
         }
 
-        Some(Model::load(
-            &self.obj_path,
-            &self.received_files,
-            ctx,
-            &self.bind_group_layout,
-        ))
+        let data = self.parse()?;
+        self.built = true;
+        Some(
+            data.and_then(|data| Model::upload(data, ctx, &self.bind_group_layout))
+                .map(|mut model| {
+                    // Textures that arrived while the obj file was parsed
+                    let arrived: Vec<String> = model
+                        .pending_textures
+                        .keys()
+                        .filter(|path| self.received_files.contains_key(*path))
+                        .cloned()
+                        .collect();
+                    self.stream(ctx, &mut model, &arrived);
+                    model
+                }),
+        )
+    }
+
+    fn parse_files(
+        obj_path: &str,
+        files: &HashMap<String, FileDataHandle>,
+    ) -> anyhow::Result<ModelData> {
+        let obj = files
+            .get(obj_path)
+            .ok_or_else(|| anyhow::anyhow!("{} was not loaded", obj_path))?;
+        ModelData::parse(obj_path, &obj.data, files, LOAD_OPTIONS)
+    }
+
+    // Starts parsing on the first call, then returns the data once the thread is done
+    #[cfg(not(target_arch = "wasm32"))]
+    fn parse(&mut self) -> Option<anyhow::Result<ModelData>> {
+        match self.parsing.take() {
+            Some(parsing) if parsing.is_finished() => Some(
+                parsing
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("Parsing {} panicked", self.obj_path))),
+            ),
+            Some(parsing) => {
+                self.parsing = Some(parsing);
+                None
+            }
+            None => {
+                // The thread gets its own handles of the files, not copies of their bytes
+                let files = self.received_files.clone();
+                let obj_path = self.obj_path.clone();
+                self.parsing = Some(std::thread::spawn(move || {
+                    Self::parse_files(&obj_path, &files)
+                }));
+                None
+            }
+        }
+    }
+
+    // The web has no threads here, the model is parsed in place
+    #[cfg(target_arch = "wasm32")]
+    fn parse(&mut self) -> Option<anyhow::Result<ModelData>> {
+        Some(Self::parse_files(&self.obj_path, &self.received_files))
+    }
+
+    /// Puts the textures that arrived after the model was built into it
+    pub fn stream(&self, ctx: &klgl::RenderContext, model: &mut Model, arrived: &[String]) {
+        for path in arrived {
+            let Some(file) = self.received_files.get(path) else {
+                continue;
+            };
+            let result = model.stream_texture(
+                ctx,
+                &self.bind_group_layout,
+                path,
+                &file.data,
+                LOAD_OPTIONS.diffuse_sampler,
+            );
+            if let Err(err) = result {
+                log::error!("Failed to load texture {}. Error: {}", path, err);
+            }
+        }
     }
 }
 
 impl ModelsDrawPass {
+    pub const NAME: &str = "models";
+
     pub async fn new(
         file_loader: &mut FileLoader,
         render_context: Rc<RefCell<klgl::RenderContext>>,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group: &wgpu::BindGroup,
+        lights: &LightManager,
+        color_format: wgpu::TextureFormat,
         depth_stencil_state: Option<wgpu::DepthStencilState>,
     ) -> Self {
         let texture_bind_group_layout = {
             let ctx = render_context.borrow();
             ctx.device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                multisampled: false,
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            // This should match the filterable field of the
-                            // corresponding Texture entry above.
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                    ],
+                    entries: &texture_bind_group_layout_entries(LOAD_OPTIONS.texture_array),
                     label: Some("model_draw_pass_texture_bind_group_layout"),
                 })
         };
 
+        let cull_mode = Some(wgpu::Face::Back);
         let models_pipeline = {
             let ctx = render_context.borrow();
-            ModelsDrawPass::create_render_pipeline(
+            ModelsDrawPass::create_pipelines(
                 &ctx.device,
-                &camera_bind_group_layout,
-                &texture_bind_group_layout,
-                ctx.config.format,
-                depth_stencil_state,
+                &[
+                    &texture_bind_group_layout,
+                    camera_bind_group_layout,
+                    lights.bind_group_layout(),
+                ],
+                color_format,
+                depth_stencil_state.clone(),
+                entry_points("fs_lit", LOAD_OPTIONS.texture_array),
+                cull_mode,
+                wgpu::ColorWrites::ALL,
             )
         };
 
+        let instances_per_row = 1;
         let mut model_instances: Vec<Instance> = vec![];
-        Self::compute_model_instances(&mut model_instances, Deg(45.0));
+        Self::compute_model_instances(&mut model_instances, Deg(45.0), instances_per_row);
 
-        let model_instances_buffer =
-            render_context
-                .borrow()
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Instance Buffer"),
-                    contents: bytemuck::cast_slice(&model_instances),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                });
+        // One more than the frames that can be queued, so one buffer is always free to write
+        let model_instances_buffers = {
+            let ctx = render_context.borrow();
+            FrameRing::new(
+                ctx.config.desired_maximum_frame_latency as usize + 1,
+                |_| {
+                    ctx.device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("Instance Buffer"),
+                            contents: bytemuck::cast_slice(&model_instances),
+                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        })
+                },
+            )
+        };
 
         // let model_path = "models/cube/cube.obj";
         // let model_requirements = [
@@ -262,6 +612,10 @@ impl ModelsDrawPass {
             "models/sponza/vase_round_spec.png",
         ];
 
+        let model_requirements: Vec<String> = model_requirements
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
         let loading_model = Some(LoadingModel::new(
             &mut file_loader.clone(),
             model_path,
@@ -272,48 +626,80 @@ impl ModelsDrawPass {
         Self {
             ctx: render_context,
             pipeline: models_pipeline,
+            camera_bind_group_layout: camera_bind_group_layout.clone(),
+            camera_bind_group: camera_bind_group.clone(),
+            lights_bind_group_layout: lights.bind_group_layout().clone(),
+            lights_bind_group: lights.bind_group().clone(),
+            texture_bind_group_layout,
+            color_format,
+            depth_stencil_state,
+            debug_mode: DebugMode::Textured,
+            debug_pipelines: HashMap::new(),
+            shadows: None,
+            cull_mode,
+            indirect: false,
+            occlusion: None,
+            visible_meshes: None,
+            show_bounds: false,
+            show_normals: None,
+            normals_stride: 1,
+            segments: None,
             instances: model_instances,
-            instances_buffer: model_instances_buffer,
+            instances_per_row,
+            instances_buffers: model_instances_buffers,
+            model_path: model_path.into(),
+            model_requirements,
+            disk_path: None,
             loading_model,
             model: None,
+            scene: None,
         }
     }
 
-    fn compute_model_instances(v: &mut Vec<Instance>, angle: Deg<f32>) {
-        const NUM_INSTANCES_PER_ROW: u32 = 1;
+    fn compute_model_instances(v: &mut Vec<Instance>, angle: Deg<f32>, instances_per_row: u32) {
+        // Offset that keeps the center of the grid at the origin
+        let center = (instances_per_row as f32 - 1.0) / 2.0;
+
         v.clear();
-        v.extend((0..NUM_INSTANCES_PER_ROW).flat_map(|y| {
-            (0..NUM_INSTANCES_PER_ROW).map(move |x| {
+        v.extend((0..instances_per_row).flat_map(|y| {
+            (0..instances_per_row).map(move |x| {
                 let rotation = Rotator {
-                    yaw: angle * (-0.5 + ((x + 1) as f32 / NUM_INSTANCES_PER_ROW as f32)),
-                    pitch: angle * (-0.5 + ((y + 1) as f32 / NUM_INSTANCES_PER_ROW as f32)),
+                    yaw: angle * (-0.5 + ((x + 1) as f32 / instances_per_row as f32)),
+                    pitch: angle * (-0.5 + ((y + 1) as f32 / instances_per_row as f32)),
                     roll: Deg(90.0),
                 };
 
                 let scale = cgmath::Matrix4::from_scale(0.1);
 
-                Instance {
-                    model: (cgmath::Matrix4::from_translation(cgmath::Vector3 {
-                        x: (x as f32),
-                        y: (y as f32),
+                Instance::new(
+                    cgmath::Matrix4::from_translation(cgmath::Vector3 {
+                        x: (x as f32 - center) * SPACING,
+                        y: (y as f32 - center) * SPACING,
                         z: 1.0,
                     }) * rotation.to_matrix()
-                        * scale)
-                        .into(),
-                }
+                        * scale,
+                )
             })
         }));
     }
 
     pub fn update(&mut self) {
+        let mut load_finished = false;
         if let Some(loading_model) = &mut self.loading_model {
-            loading_model.update();
-            self.model = match loading_model.get(&self.ctx.borrow_mut()) {
-                Some(model_result) => match model_result {
+            let mut failed = false;
+            let arrived = loading_model.update();
+            let ctx = self.ctx.borrow();
+            if loading_model.built {
+                if let Some(model) = &mut self.model {
+                    loading_model.stream(&ctx, model, &arrived);
+                }
+            } else if let Some(model_result) = loading_model.build(&ctx) {
+                // The current model stays until the new one is ready, so reloading does not flicker
+                match model_result {
                     Ok(model) => {
                         log::info!("Model successfully loaded: {}", loading_model.obj_path);
-                        self.loading_model = None;
-                        Some(model)
+                        self.model = Some(model);
+                        self.visible_meshes = None;
                     }
                     Err(err) => {
                         log::error!(
@@ -321,29 +707,97 @@ impl ModelsDrawPass {
                             loading_model.obj_path,
                             err
                         );
-                        self.loading_model = None;
-                        None
+                        failed = true;
                     }
-                },
-                None => None,
+                }
+                load_finished = true;
+            }
+            if failed || loading_model.finished() {
+                self.loading_model = None;
             }
         }
+        if load_finished && self.shows_segments() {
+            self.update_segments();
+        }
+        if let Some(scene) = &mut self.scene {
+            scene.update();
+        }
+
+        Self::compute_model_instances(&mut self.instances, Deg(0.0), self.instances_per_row);
+        // Self::compute_model_instances(&mut self.instances, angle, self.instances_per_row);
 
-        Self::compute_model_instances(&mut self.instances, Deg(0.0));
-        // Self::compute_model_instances(&mut self.instances, angle);
-        self.ctx.borrow().queue.write_buffer(
-            &self.instances_buffer,
+        if let Some(model) = &mut self.model {
+            model.write_shared_instances(&self.ctx.borrow(), &self.instances);
+        }
+
+        if let Some((queries, _)) = &mut self.occlusion {
+            queries.poll(&self.ctx.borrow().device);
+        }
+
+        if let (true, Some(model)) = (self.indirect, &self.model) {
+            // Instances always start at 0, so INDIRECT_FIRST_INSTANCE is not needed
+            let ctx = self.ctx.borrow();
+            model
+                .write_indirect_args(&ctx, 0..self.instances.len() as u32)
+                .unwrap();
+        }
+    }
+
+    /// Records the copy of this frame's instances, before the passes that draw them
+    pub fn upload_instances(
+        &mut self,
+        uploader: &mut klgl::FrameUploader,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        uploader.write(
+            encoder,
+            &self.ctx.borrow().device,
+            self.instances_buffers.advance(),
             0,
             bytemuck::cast_slice(&self.instances[..]),
         );
     }
 
+    fn create_pipelines(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        depth_stencil_state: Option<wgpu::DepthStencilState>,
+        entry_points: (&str, &str),
+        cull_mode: Option<wgpu::Face>,
+        write_mask: wgpu::ColorWrites,
+    ) -> MeshPipelines {
+        let create = |cull_mode| {
+            Self::create_render_pipeline(
+                device,
+                bind_group_layouts,
+                color_format,
+                depth_stencil_state.clone(),
+                entry_points,
+                cull_mode,
+                write_mask,
+            )
+        };
+
+        let double_sided = create(None);
+        let culled = match cull_mode {
+            Some(_) => create(cull_mode),
+            None => double_sided.clone(),
+        };
+        MeshPipelines {
+            culled,
+            double_sided,
+        }
+    }
+
     fn create_render_pipeline(
         device: &wgpu::Device,
-        camera_bind_group_layout: &wgpu::BindGroupLayout,
-        texture_bind_group_layout: &wgpu::BindGroupLayout,
-        surface_format: wgpu::TextureFormat,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
         depth_stencil_state: Option<wgpu::DepthStencilState>,
+        (vertex_entry_point, fragment_entry_point): (&str, &str),
+        cull_mode: Option<wgpu::Face>,
+        write_mask: wgpu::ColorWrites,
     ) -> wgpu::RenderPipeline {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Model Shader"),
@@ -355,38 +809,27 @@ impl ModelsDrawPass {
             layout: Some(
                 &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     label: Some("Triangle Strip Render Pipeline Layout"),
-                    bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+                    bind_group_layouts,
                     push_constant_ranges: &[],
                 }),
             ),
             vertex: wgpu::VertexState {
                 module: &shader,
-                entry_point: Some("vs_main"),
+                entry_point: Some(vertex_entry_point),
                 buffers: &[ModelVertex::layout(), Instance::layout()],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: Some("fs_main"),
+                entry_point: Some(fragment_entry_point),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
+                    format: color_format,
                     blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
+                    write_mask,
                 })],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
+            primitive: primitive_state(cull_mode),
             depth_stencil: depth_stencil_state.clone(),
             multisample: wgpu::MultisampleState {
                 count: 1,
@@ -400,15 +843,842 @@ impl ModelsDrawPass {
 
     pub fn swap_model(&mut self) {}
 
-    pub fn render(&self, render_pass: &mut wgpu::RenderPass, camera_bind_group: &wgpu::BindGroup) {
+    /// Replaces the model with an obj file from the file system, e.g. one dropped on the window.
+    /// Textures are looked up relative to the directory of the file.
+    /// The current model stays if loading fails.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_from_disk(&mut self, path: &std::path::Path) -> anyhow::Result<()> {
+        let model = Model::load_from_disk(
+            path,
+            &self.ctx.borrow(),
+            &self.texture_bind_group_layout,
+            LOAD_OPTIONS,
+        )?;
+        log::info!("Model successfully loaded: {}", path.display());
+
+        // A model that is still loading from the assets would replace this one
+        self.loading_model = None;
+        self.disk_path = Some(path.to_path_buf());
+        self.model = Some(model);
+        self.visible_meshes = None;
+        if self.shows_segments() {
+            self.update_segments();
+        }
+        Ok(())
+    }
+
+    /// Loads the files of the current model again, bypassing the cache of `file_loader`,
+    /// and replaces the model once they arrive. Useful while editing the model externally.
+    pub fn reload_current(&mut self, file_loader: &mut FileLoader) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(path) = self.disk_path.clone() {
+            log::info!("Reloading {}", path.display());
+            if let Err(err) = self.load_from_disk(&path) {
+                log::error!("Failed to reload {}. Error: {:#}", path.display(), err);
+            }
+            return;
+        }
+
+        if self.loading_model.is_some() {
+            log::warn!("{} is still loading", self.model_path);
+            return;
+        }
+
+        log::info!("Reloading {}", self.model_path);
+        file_loader.invalidate(&self.model_path);
+        for path in &self.model_requirements {
+            file_loader.invalidate(path);
+        }
+
+        self.loading_model = Some(LoadingModel::new(
+            file_loader,
+            &self.model_path,
+            self.texture_bind_group_layout.clone(),
+            &self.model_requirements,
+        ));
+    }
+
+    pub fn instances_per_row(&self) -> u32 {
+        self.instances_per_row
+    }
+
+    /// Keeps one instance buffer more than the frames that can be queued.
+    /// Call after [`klgl::RenderContext::set_frame_latency`].
+    pub fn set_frame_latency(&mut self, device: &wgpu::Device, latency: u32) {
+        let len = latency as usize + 1;
+        if len == self.instances_buffers.len() {
+            return;
+        }
+
+        let size = self.instances_buffers.current().size();
+        self.instances_buffers.resize(len, |_| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Instance Buffer"),
+                size,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+    }
+
+    /// Draws the model as a centered grid of `n` x `n` instances
+    pub fn set_instance_grid(&mut self, device: &wgpu::Device, n: u32) {
+        let n = n.max(1);
+        self.instances_per_row = n;
+        Self::compute_model_instances(&mut self.instances, Deg(0.0), n);
+        if self.shows_segments() {
+            self.update_segments();
+        }
+
+        let required_size = std::mem::size_of_val(&self.instances[..]) as wgpu::BufferAddress;
+        if required_size > self.instances_buffers.current().size() {
+            self.instances_buffers.rebuild(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Instance Buffer"),
+                    size: required_size,
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            });
+        }
+
+        let num_instances = self.instances.len() as u32;
+        if let Some((queries, _)) = self
+            .occlusion
+            .as_mut()
+            .filter(|(queries, _)| queries.capacity() < num_instances)
+        {
+            *queries = OcclusionQueryPass::new(device, num_instances);
+        }
+    }
+
+    pub fn debug_mode(&self) -> DebugMode {
+        self.debug_mode
+    }
+
+    pub fn set_debug_mode(&mut self, debug_mode: DebugMode) {
+        self.debug_mode = debug_mode;
+        if debug_mode == DebugMode::Textured || self.debug_pipelines.contains_key(&debug_mode) {
+            return;
+        }
+
+        let pipeline = {
+            let ctx = self.ctx.borrow();
+            Self::create_pipelines(
+                &ctx.device,
+                &[
+                    &self.texture_bind_group_layout,
+                    &self.camera_bind_group_layout,
+                ],
+                self.color_format,
+                self.depth_stencil_state.clone(),
+                entry_points(
+                    debug_mode.fragment_entry_point(),
+                    LOAD_OPTIONS.texture_array,
+                ),
+                self.cull_mode,
+                wgpu::ColorWrites::ALL,
+            )
+        };
+        self.debug_pipelines.insert(debug_mode, pipeline);
+    }
+
+    /// None until the model finishes loading
+    pub fn model(&self) -> Option<&Model> {
+        self.model.as_ref()
+    }
+
+    pub fn model_mut(&mut self) -> Option<&mut Model> {
+        self.model.as_mut()
+    }
+
+    /// Replaces the models of the previous scene, the model stays
+    pub fn set_scene(&mut self, scene: Option<Scene>) {
+        self.scene = scene;
+    }
+
+    pub fn cull_mode(&self) -> Option<wgpu::Face> {
+        self.cull_mode
+    }
+
+    pub fn indirect(&self) -> bool {
+        self.indirect
+    }
+
+    /// Switches between draw calls with counts from the CPU and from the model's indirect buffer.
+    /// Stays off where indirect execution is not supported (WebGL2).
+    pub fn set_indirect(&mut self, indirect: bool) {
+        let supported = self
+            .ctx
+            .borrow()
+            .adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION);
+        if indirect && !supported {
+            log::warn!("Indirect draws are not supported");
+            return;
+        }
+        self.indirect = indirect;
+    }
+
+    /// Rebuilds the pipelines with the new cull mode. Double sided meshes are never culled.
+    pub fn set_cull_mode(&mut self, device: &wgpu::Device, cull_mode: Option<wgpu::Face>) {
+        self.cull_mode = cull_mode;
+        self.pipeline = Self::create_pipelines(
+            device,
+            &[
+                &self.texture_bind_group_layout,
+                &self.camera_bind_group_layout,
+                &self.lights_bind_group_layout,
+            ],
+            self.color_format,
+            self.depth_stencil_state.clone(),
+            entry_points("fs_lit", LOAD_OPTIONS.texture_array),
+            cull_mode,
+            wgpu::ColorWrites::ALL,
+        );
+
+        self.debug_pipelines.clear();
+        self.set_debug_mode(self.debug_mode);
+        let shadows = self.shadows.take().map(|(binding, _)| binding);
+        self.set_shadows(shadows);
+        if self.occlusion.take().is_some() {
+            self.set_occlusion_culling(true);
+        }
+    }
+
+    pub fn occlusion_culling(&self) -> bool {
+        self.occlusion.is_some()
+    }
+
+    /// Skips instances that had no visible samples in the latest occlusion query results.
+    /// Skipped instances are still tested every frame so they reappear once uncovered.
+    pub fn set_occlusion_culling(&mut self, enabled: bool) {
+        if !enabled {
+            self.occlusion = None;
+            return;
+        }
+
+        if self.occlusion.is_some() {
+            return;
+        }
+
+        let ctx = self.ctx.borrow();
+        let queries = OcclusionQueryPass::new(&ctx.device, self.instances.len() as u32);
+        // Tests only need the depth, so they use the cheapest fragment shader and write nothing
+        let depth_stencil_state =
+            self.depth_stencil_state
+                .clone()
+                .map(|state| wgpu::DepthStencilState {
+                    depth_write_enabled: false,
+                    ..state
+                });
+        let pipelines = Self::create_pipelines(
+            &ctx.device,
+            &[
+                &self.texture_bind_group_layout,
+                &self.camera_bind_group_layout,
+            ],
+            self.color_format,
+            depth_stencil_state,
+            entry_points(
+                DebugMode::Depth.fragment_entry_point(),
+                LOAD_OPTIONS.texture_array,
+            ),
+            self.cull_mode,
+            wgpu::ColorWrites::empty(),
+        );
+        self.occlusion = Some((queries, pipelines));
+    }
+
+    /// Samples that passed the depth test per instance, if occlusion culling is enabled
+    pub fn last_visible_samples(&self) -> Option<&[u64]> {
+        self.occlusion
+            .as_ref()
+            .map(|(queries, _)| queries.last_visible_samples())
+    }
+
+    /// Skips the meshes that are outside of every frustum for every instance,
+    /// one frustum per view that draws the models
+    pub fn cull(&mut self, frustums: &[klgl::Frustum]) {
+        let Some(model) = &self.model else {
+            return;
+        };
+
+        let mut mask = vec![false; model.meshes.len()];
+        for instance in &self.instances {
+            for frustum in frustums {
+                let local = frustum.to_model_space(cgmath::Matrix4::from(instance.model));
+                for index in model.bvh.cull(&local) {
+                    mask[index] = true;
+                }
+            }
+        }
+        self.visible_meshes = Some(mask);
+    }
+
+    /// Number of meshes that passed the last `cull`, None before the first one
+    pub fn num_visible_meshes(&self) -> Option<usize> {
+        self.visible_meshes
+            .as_ref()
+            .map(|mask| mask.iter().filter(|visible| **visible).count())
+    }
+
+    fn is_mesh_visible(&self, index: usize) -> bool {
+        self.visible_meshes
+            .as_ref()
+            .is_none_or(|mask| mask.get(index).copied().unwrap_or(true))
+    }
+
+    /// World positions of the instances
+    pub fn instance_origins(&self) -> impl Iterator<Item = [f32; 3]> + '_ {
+        self.instances.iter().map(|instance| {
+            let [x, y, z, _] = instance.model[3];
+            [x, y, z]
+        })
+    }
+
+    pub fn show_bounds(&self) -> bool {
+        self.show_bounds
+    }
+
+    /// Outlines the bounding box of every mesh of every instance
+    pub fn set_show_bounds(&mut self, show_bounds: bool) {
+        self.show_bounds = show_bounds;
+        self.update_segments();
+    }
+
+    pub fn show_normals(&self) -> bool {
+        self.show_normals.is_some()
+    }
+
+    /// Draws a line of `length` world units from the vertices along their normals, to check
+    /// the normals the model was loaded with. Large models only show some of the vertices,
+    /// see [`Self::set_normals_stride`].
+    pub fn set_show_normals(&mut self, show_normals: bool, length: f32) {
+        self.show_normals = show_normals.then_some(length);
+        self.update_segments();
+    }
+
+    /// Shows the normal of every `stride`-th vertex. The stride grows further when the lines
+    /// would exceed `MAX_NORMAL_SEGMENTS`.
+    #[allow(dead_code)]
+    pub fn set_normals_stride(&mut self, stride: usize) {
+        self.normals_stride = stride.max(1);
+        if self.show_normals.is_some() {
+            self.update_segments();
+        }
+    }
+
+    fn shows_segments(&self) -> bool {
+        self.show_bounds || self.show_normals.is_some()
+    }
+
+    fn update_segments(&mut self) {
+        let Some(model) = &self.model else {
+            self.segments = Some(Vec::new());
+            return;
+        };
+
+        let mut segments = Vec::new();
+        if self.show_bounds {
+            segments.extend(self.instances.iter().flat_map(|instance| {
+                let transform = cgmath::Matrix4::from(instance.model);
+                model
+                    .meshes
+                    .iter()
+                    .flat_map(move |mesh| box_segments(mesh.bounds(), &transform, BOUNDS_COLOR))
+            }));
+        }
+        if let Some(length) = self.show_normals {
+            let num_vertices: usize = model.meshes.iter().map(|mesh| mesh.vertices.len()).sum();
+            let total = num_vertices * self.instances.len();
+            let stride = self.normals_stride.max(total.div_ceil(MAX_NORMAL_SEGMENTS));
+            for instance in &self.instances {
+                let transform = cgmath::Matrix4::from(instance.model);
+                for mesh in &model.meshes {
+                    segments.extend(normal_segments(
+                        mesh.vertices.iter().step_by(stride),
+                        &transform,
+                        length,
+                        NORMALS_COLOR,
+                    ));
+                }
+            }
+        }
+        self.segments = Some(segments);
+    }
+
+    /// Bounds and normal lines that changed since the last call, for [`LinesDrawPass::set_segments`]
+    ///
+    /// [`LinesDrawPass::set_segments`]: crate::lines_draw_pass::LinesDrawPass::set_segments
+    pub fn take_segments(&mut self) -> Option<Vec<lines_draw_pass::Vertex>> {
+        self.segments.take()
+    }
+
+    /// Starts reading the occlusion query results of the submitted frame
+    pub fn after_submit(&self) {
+        if let Some((queries, _)) = &self.occlusion {
+            queries.after_submit();
+        }
+    }
+
+    pub fn texture_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.texture_bind_group_layout
+    }
+
+    pub fn camera_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.camera_bind_group_layout
+    }
+
+    /// Camera of the main view, for passes that draw the models with their own pipeline
+    pub fn camera_bind_group(&self) -> &wgpu::BindGroup {
+        &self.camera_bind_group
+    }
+
+    /// True once the model is built and gets drawn
+    pub fn is_loaded(&self) -> bool {
+        self.model.is_some()
+    }
+
+    /// World space box around all instances of the model, None until the model is loaded
+    pub fn world_bounds(&self) -> Option<BoundingBox> {
+        let bounds = self.model.as_ref()?.bounds();
+        let world = self
+            .instances
+            .iter()
+            .map(|instance| bounds.transformed(&cgmath::Matrix4::from(instance.model)))
+            .fold(BoundingBox::EMPTY, |a, b| a.union(&b));
+        (!world.is_empty()).then_some(world)
+    }
+
+    /// Radius of a sphere around the origin that contains all instances
+    pub fn bounding_radius(&self) -> f32 {
+        let center = (self.instances_per_row as f32 - 1.0) / 2.0;
+        center * SPACING * std::f32::consts::SQRT_2 + MODEL_RADIUS
+    }
+
+    /// Textured mode samples the shadow map while shadows are set
+    pub fn set_shadows(&mut self, shadows: Option<ShadowBinding>) {
+        self.shadows = shadows.map(|binding| {
+            let ctx = self.ctx.borrow();
+            let pipeline = Self::create_pipelines(
+                &ctx.device,
+                &[
+                    &self.texture_bind_group_layout,
+                    &self.camera_bind_group_layout,
+                    &self.lights_bind_group_layout,
+                    &binding.layout,
+                ],
+                self.color_format,
+                self.depth_stencil_state.clone(),
+                entry_points("fs_shadowed", LOAD_OPTIONS.texture_array),
+                self.cull_mode,
+                wgpu::ColorWrites::ALL,
+            );
+            (binding, pipeline)
+        });
+    }
+
+    /// Draws the instances with whatever pipeline is already set on the render pass
+    pub fn draw_geometry(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        self.draw_meshes(render_pass, camera_bind_group, |_, _| true);
+        if let Some(scene) = &self.scene {
+            scene.render(render_pass, camera_bind_group);
+        }
+    }
+
+    fn draw_meshes<Filter>(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        camera_bind_group: &wgpu::BindGroup,
+        filter: Filter,
+    ) where
+        Filter: Fn(usize, &Mesh) -> bool,
+    {
         if let Some(model) = &self.model {
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_vertex_buffer(1, self.instances_buffer.slice(..));
-            model.draw_instanced(
+            let instances_buffer = self.instances_buffers.current();
+            match self.indirect && self.occlusion.is_none() {
+                true => model.draw_indirect_filtered(
+                    render_pass,
+                    camera_bind_group,
+                    instances_buffer,
+                    filter,
+                ),
+                false => model.draw_instanced_filtered(
+                    render_pass,
+                    camera_bind_group,
+                    instances_buffer,
+                    0..self.instances.len() as u32,
+                    filter,
+                ),
+            }
+        }
+    }
+
+    pub fn render(&self, render_pass: &mut wgpu::RenderPass, camera_bind_group: &wgpu::BindGroup) {
+        if self.debug_mode == DebugMode::Textured {
+            render_pass.set_bind_group(2, &self.lights_bind_group, &[]);
+        }
+
+        let pipelines = match (self.debug_mode, &self.shadows) {
+            (DebugMode::Textured, Some((binding, pipelines))) => {
+                render_pass.set_bind_group(3, &binding.bind_group, &[]);
+                pipelines
+            }
+            (DebugMode::Textured, None) => &self.pipeline,
+            (mode, _) => &self.debug_pipelines[&mode],
+        };
+
+        // Scene models are not occlusion culled, they are drawn first to occlude the instances
+        if let Some(scene) = &self.scene {
+            render_pass.set_pipeline(&pipelines.culled);
+            scene.render_filtered(render_pass, camera_bind_group, |_, mesh| !mesh.double_sided);
+            render_pass.set_pipeline(&pipelines.double_sided);
+            scene.render_filtered(render_pass, camera_bind_group, |_, mesh| mesh.double_sided);
+        }
+
+        if let Some((queries, query_pipelines)) = &self.occlusion {
+            self.render_occlusion_culled(
                 render_pass,
                 camera_bind_group,
-                0..self.instances.len() as u32,
+                pipelines,
+                queries,
+                query_pipelines,
+            );
+            return;
+        }
+
+        render_pass.set_pipeline(&pipelines.culled);
+        self.draw_meshes(render_pass, camera_bind_group, |index, mesh| {
+            !mesh.double_sided && self.is_mesh_visible(index)
+        });
+        render_pass.set_pipeline(&pipelines.double_sided);
+        self.draw_meshes(render_pass, camera_bind_group, |index, mesh| {
+            mesh.double_sided && self.is_mesh_visible(index)
+        });
+    }
+
+    // Every instance is drawn inside its own query. Visible instances go first so that
+    // the hidden ones are tested against a complete depth buffer.
+    fn render_occlusion_culled(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        camera_bind_group: &wgpu::BindGroup,
+        pipelines: &MeshPipelines,
+        queries: &OcclusionQueryPass,
+        query_pipelines: &MeshPipelines,
+    ) {
+        let Some(model) = &self.model else {
+            return;
+        };
+
+        let num_instances = (self.instances.len() as u32).min(queries.capacity());
+        let (visible, hidden): (Vec<u32>, Vec<u32>) =
+            (0..num_instances).partition(|index| queries.is_visible(*index as usize));
+
+        let instances_buffer = self.instances_buffers.current();
+        for (indices, pipelines) in [(visible, pipelines), (hidden, query_pipelines)] {
+            for index in indices {
+                render_pass.begin_occlusion_query(index);
+                render_pass.set_pipeline(&pipelines.culled);
+                model.draw_instanced_filtered(
+                    render_pass,
+                    camera_bind_group,
+                    instances_buffer,
+                    index..index + 1,
+                    |mesh_index, mesh| !mesh.double_sided && self.is_mesh_visible(mesh_index),
+                );
+                render_pass.set_pipeline(&pipelines.double_sided);
+                model.draw_instanced_filtered(
+                    render_pass,
+                    camera_bind_group,
+                    instances_buffer,
+                    index..index + 1,
+                    |mesh_index, mesh| mesh.double_sided && self.is_mesh_visible(mesh_index),
+                );
+                render_pass.end_occlusion_query();
+            }
+        }
+    }
+}
+
+impl klgl::DrawPass for ModelsDrawPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn per_view(&self) -> bool {
+        true
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let Some((queries, _)) = &self.occlusion else {
+            let mut render_pass = targets.begin_render_pass(encoder, "Models Render Pass");
+            self.render(&mut render_pass, targets.camera_or(&self.camera_bind_group));
+            return;
+        };
+
+        {
+            let mut render_pass = targets.begin_render_pass_with_queries(
+                encoder,
+                "Models Render Pass",
+                Some(queries.query_set()),
+            );
+            self.render(&mut render_pass, targets.camera_or(&self.camera_bind_group));
+        }
+        // Nothing was queried before the model finished loading
+        if self.model.is_some() {
+            queries.resolve(encoder, self.instances.len() as u32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_is_drawable_before_its_textures() {
+        let requirements = [
+            "models/crate/crate.mtl".to_string(),
+            "models/crate/diffuse.png".to_string(),
+            "models/crate/bump.jpg".to_string(),
+        ];
+        let mut progress = LoadProgress::new("models/crate/crate.obj", &requirements, true);
+        assert!(!progress.geometry_ready());
+
+        progress.receive("models/crate/crate.obj");
+        progress.receive("models/crate/diffuse.png");
+        assert!(!progress.geometry_ready(), "the mtl file is still missing");
+
+        progress.receive("models/crate/crate.mtl");
+        assert!(progress.geometry_ready());
+        assert!(!progress.finished());
+
+        progress.receive("models/crate/bump.jpg");
+        assert!(progress.finished());
+
+        // Without streaming the textures are part of the geometry
+        let mut progress = LoadProgress::new("models/crate/crate.obj", &requirements, false);
+        progress.receive("models/crate/crate.obj");
+        progress.receive("models/crate/crate.mtl");
+        assert!(!progress.geometry_ready());
+    }
+
+    #[test]
+    fn test_instance_layout_covers_the_struct() {
+        let layout = Instance::layout();
+        let last = layout.attributes.last().unwrap();
+        assert_eq!(
+            last.offset + last.format.size(),
+            std::mem::size_of::<Instance>() as wgpu::BufferAddress
+        );
+        assert_eq!(layout.array_stride % wgpu::VERTEX_STRIDE_ALIGNMENT, 0);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_instance_grid_size() {
+        use crate::test_utils::{camera_binding, cube_models, depth_stencil_state};
+
+        let ctx = crate::test_utils::gpu_context(4, 4);
+        let camera = klgl::Camera::new(
+            cgmath::Point3::new(0.0, -400.0, 1.0),
+            Rotator::from_direction(cgmath::Vector3::unit_y()),
+            1.0,
+            45.0,
+            1.0,
+            1000.0,
+        );
+        let (camera_layout, camera_bind_group) = camera_binding(&ctx.borrow().device, &camera);
+        let mut models = cube_models(
+            &ctx,
+            &camera_layout,
+            &camera_bind_group,
+            wgpu::TextureFormat::Rgba8Unorm,
+            depth_stencil_state(),
+            "instance_grid",
+        );
+        let ctx = ctx.borrow();
+        let grid_size = |models: &mut ModelsDrawPass| {
+            let sizes: Vec<wgpu::BufferAddress> = (0..models.instances_buffers.len())
+                .map(|_| models.instances_buffers.advance().size())
+                .collect();
+            (models.instances_per_row(), models.instances.len(), sizes)
+        };
+
+        // Every instance buffer grows to hold the whole grid
+        models.set_instance_grid(&ctx.device, 5);
+        let grid_bytes = 25 * std::mem::size_of::<Instance>() as wgpu::BufferAddress;
+        let (per_row, len, sizes) = grid_size(&mut models);
+        assert_eq!((per_row, len), (5, 25));
+        assert!(sizes.iter().all(|size| *size >= grid_bytes), "{sizes:?}");
+
+        // The middle instance of an odd sized grid is at the origin
+        let middle = models.instances[12].model[3];
+        assert_eq!([middle[0], middle[1]], [0.0, 0.0]);
+
+        // A smaller grid keeps the buffers and is centered again
+        models.set_instance_grid(&ctx.device, 2);
+        let (per_row, len, smaller_sizes) = grid_size(&mut models);
+        assert_eq!((per_row, len), (2, 4));
+        assert_eq!(smaller_sizes, sizes);
+        let first = models.instances[0].model[3];
+        let last = models.instances[3].model[3];
+        assert_eq!([first[0], first[1]], [-SPACING / 2.0, -SPACING / 2.0]);
+        assert_eq!([last[0], last[1]], [SPACING / 2.0, SPACING / 2.0]);
+    }
+
+    #[test]
+    fn test_debug_mode_cycles_through_all_variants() {
+        let mut mode = DebugMode::Textured;
+        let mut visited = vec![];
+        for _ in 0..DebugMode::ALL.len() {
+            visited.push(mode);
+            mode = mode.next();
+        }
+
+        assert_eq!(mode, DebugMode::Textured);
+        assert_eq!(visited, DebugMode::ALL);
+    }
+
+    #[test]
+    fn test_pipeline_cull_mode() {
+        for cull_mode in [Some(wgpu::Face::Back), Some(wgpu::Face::Front), None] {
+            assert_eq!(primitive_state(cull_mode).cull_mode, cull_mode);
+        }
+    }
+
+    #[test]
+    fn test_cull_mode_cycles() {
+        let start = Some(wgpu::Face::Back);
+        let mut cull_mode = start;
+        let mut visited = vec![];
+        for _ in 0..3 {
+            visited.push(cull_mode);
+            cull_mode = next_cull_mode(cull_mode);
+        }
+        assert_eq!(cull_mode, start);
+        assert_eq!(
+            visited,
+            [Some(wgpu::Face::Back), Some(wgpu::Face::Front), None]
+        );
+    }
+
+    #[test]
+    fn test_texture_array_entry_points() {
+        assert_eq!(entry_points("fs_lit", false), ("vs_colored", "fs_lit"));
+        assert_eq!(entry_points("fs_lit", true), ("vs_array", "fs_lit_array"));
+        assert_eq!(
+            entry_points("fs_shadowed", true),
+            ("vs_array", "fs_shadowed_array")
+        );
+        for mode in DebugMode::ALL.into_iter().skip(1) {
+            let fragment_entry_point = mode.fragment_entry_point();
+            assert_eq!(
+                entry_points(fragment_entry_point, true),
+                ("vs_colored", fragment_entry_point)
             );
         }
     }
+
+    // Draws the models once with occlusion culling and waits for the query results
+    fn render_culled(
+        ctx: &klgl::RenderContext,
+        models: &mut ModelsDrawPass,
+        camera_bind_group: &wgpu::BindGroup,
+    ) -> Vec<[u8; 4]> {
+        use klgl::DrawPass;
+
+        let color = klgl::Texture::create_render_target(
+            &ctx.device,
+            32,
+            32,
+            wgpu::TextureFormat::Rgba8Unorm,
+            "color",
+        );
+        let depth = klgl::Texture::create_depth_texture(&ctx.device, 32, 32, "depth");
+        let targets = klgl::PassTargets {
+            color: &color.view,
+            depth: Some(&depth.view),
+            surface: &color.view,
+            viewport: None,
+            camera: Some(camera_bind_group),
+        };
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        klgl::ClearPass::new(wgpu::Color::BLACK).record(&mut encoder, &targets);
+        models.record(&mut encoder, &targets);
+        ctx.queue.submit([encoder.finish()]);
+        models.after_submit();
+
+        ctx.device.poll(wgpu::Maintain::Wait);
+        let (queries, _) = models.occlusion.as_mut().unwrap();
+        queries.poll(&ctx.device);
+        crate::test_utils::read_rgba8(ctx, &color.texture)
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_occluded_instance_is_skipped() {
+        use crate::test_utils::{camera_binding, cube_models, depth_stencil_state};
+
+        let ctx = crate::test_utils::gpu_context(32, 32);
+
+        // Looks along the first row of a 2 x 2 grid, the first cube hides the second one
+        let mut camera = klgl::Camera::new(
+            cgmath::Point3::new(-400.0, -200.0, 1.0),
+            Rotator::from_direction(cgmath::Vector3::unit_x()),
+            1.0,
+            30.0,
+            1.0,
+            1000.0,
+        );
+        let (camera_layout, behind_first) = camera_binding(&ctx.borrow().device, &camera);
+        let mut models = cube_models(
+            &ctx,
+            &camera_layout,
+            &behind_first,
+            wgpu::TextureFormat::Rgba8Unorm,
+            depth_stencil_state(),
+            "occlusion",
+        );
+        models.set_debug_mode(DebugMode::Depth);
+        models.set_instance_grid(&ctx.borrow().device, 2);
+        models.set_occlusion_culling(true);
+
+        let ctx = ctx.borrow();
+        let mut uploader = klgl::FrameUploader::new();
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        models.upload_instances(&mut uploader, &mut encoder);
+        uploader.finish();
+        ctx.queue.submit([encoder.finish()]);
+
+        let center = 16 * 32 + 16;
+        let pixels = render_culled(&ctx, &mut models, &behind_first);
+        assert_ne!(pixels[center], [0, 0, 0, 255]);
+        let samples = models.last_visible_samples().unwrap();
+        assert!(samples[0] > 0, "{samples:?}");
+        assert_eq!(samples[1], 0, "{samples:?}");
+
+        // Between the cubes only the second one is in view. Its draw is skipped
+        // until the query reports it visible again, one frame later.
+        camera.set_eye(cgmath::Point3::new(0.0, -200.0, 1.0));
+        let (_, between) = camera_binding(&ctx.device, &camera);
+        let pixels = render_culled(&ctx, &mut models, &between);
+        assert_eq!(pixels[center], [0, 0, 0, 255]);
+        assert!(models.last_visible_samples().unwrap()[1] > 0);
+
+        let pixels = render_culled(&ctx, &mut models, &between);
+        assert_ne!(pixels[center], [0, 0, 0, 255]);
+    }
 }
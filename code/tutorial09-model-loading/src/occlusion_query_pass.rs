@@ -0,0 +1,184 @@
+use std::{
+    cell::Cell,
+    sync::{Arc, Mutex},
+};
+
+// Every occlusion query resolves to one u64 sample count
+const QUERY_SIZE: wgpu::BufferAddress = std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Readback {
+    /// The readback buffer can receive new results
+    Idle,
+    /// Results of this many queries were copied and wait for the submit
+    Copied(u32),
+    /// Waiting for the readback buffer to be mapped
+    Mapping(u32),
+}
+
+type MapResult = Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>;
+
+fn decode_samples(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks_exact(QUERY_SIZE as usize)
+        .map(|bytes| u64::from_ne_bytes(bytes.try_into().unwrap()))
+        .collect()
+}
+
+// Objects without a result yet count as visible, so they get drawn and queried
+fn is_visible(samples: &[u64], index: usize) -> bool {
+    samples.get(index).is_none_or(|samples| *samples > 0)
+}
+
+/// Counts the samples that pass the depth test for up to `capacity` objects per frame.
+///
+/// Wrap each object's draw in `begin_occlusion_query(index)`/`end_occlusion_query` inside a
+/// render pass that uses [`OcclusionQueryPass::query_set`], then call `resolve` after the pass
+/// and `after_submit` after the commands were submitted. Results arrive a few frames later,
+/// `poll` picks them up.
+pub struct OcclusionQueryPass {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    capacity: u32,
+    readback: Cell<Readback>,
+    map_result: MapResult,
+    visible_samples: Vec<u64>,
+}
+
+impl OcclusionQueryPass {
+    pub fn new(device: &wgpu::Device, capacity: u32) -> Self {
+        let capacity = capacity.max(1);
+        let size = capacity as wgpu::BufferAddress * QUERY_SIZE;
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Occlusion Query Set"),
+            ty: wgpu::QueryType::Occlusion,
+            count: capacity,
+        });
+
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            capacity,
+            readback: Cell::new(Readback::Idle),
+            map_result: Arc::new(Mutex::new(None)),
+            visible_samples: Vec::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn query_set(&self) -> &wgpu::QuerySet {
+        &self.query_set
+    }
+
+    /// Sample counts of the most recent results, indexed by query
+    pub fn last_visible_samples(&self) -> &[u64] {
+        &self.visible_samples
+    }
+
+    /// False only if the latest result says no sample of the object passed the depth test
+    pub fn is_visible(&self, index: usize) -> bool {
+        is_visible(&self.visible_samples, index)
+    }
+
+    /// Copies the results of the first `count` queries for reading.
+    /// Skipped while the previous results are still being read.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder, count: u32) {
+        if self.readback.get() != Readback::Idle {
+            return;
+        }
+
+        let count = count.min(self.capacity);
+        if count == 0 {
+            return;
+        }
+
+        let size = count as wgpu::BufferAddress * QUERY_SIZE;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, size);
+        self.readback.set(Readback::Copied(count));
+    }
+
+    /// Starts reading the results copied by `resolve`. Has to be called after the submit.
+    pub fn after_submit(&self) {
+        let Readback::Copied(count) = self.readback.get() else {
+            return;
+        };
+
+        let map_result = self.map_result.clone();
+        self.readback_buffer
+            .slice(..count as wgpu::BufferAddress * QUERY_SIZE)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *map_result.lock().unwrap() = Some(result);
+            });
+        self.readback.set(Readback::Mapping(count));
+    }
+
+    /// Takes the results once the readback buffer is mapped
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        let Readback::Mapping(count) = self.readback.get() else {
+            return;
+        };
+
+        let _ = device.poll(wgpu::Maintain::Poll);
+        let Some(result) = self.map_result.lock().unwrap().take() else {
+            return;
+        };
+
+        match result {
+            Ok(()) => {
+                let slice = self
+                    .readback_buffer
+                    .slice(..count as wgpu::BufferAddress * QUERY_SIZE);
+                self.visible_samples = decode_samples(&slice.get_mapped_range());
+                self.readback_buffer.unmap();
+            }
+            Err(err) => log::error!("Failed to read occlusion queries: {}", err),
+        }
+        self.readback.set(Readback::Idle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_samples() {
+        let bytes: Vec<u8> = [1200u64, 0, u64::MAX]
+            .iter()
+            .flat_map(|samples| samples.to_ne_bytes())
+            .collect();
+        assert_eq!(decode_samples(&bytes), [1200, 0, u64::MAX]);
+    }
+
+    #[test]
+    fn test_objects_without_results_are_visible() {
+        let samples = [1200, 0];
+        assert!(is_visible(&samples, 0));
+        assert!(!is_visible(&samples, 1));
+
+        // Objects added after the last readback are drawn until they have results
+        assert!(is_visible(&samples, 2));
+        assert!(is_visible(&[], 0));
+    }
+}
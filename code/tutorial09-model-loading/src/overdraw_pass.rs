@@ -0,0 +1,356 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::model::{ModelVertex, Vertex};
+use crate::models_draw_pass::{Instance, ModelsDrawPass};
+
+// Counts fragments per pixel. Float formats can be blended, a normalized one would clamp at 1.
+const OVERDRAW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+// Every fragment adds what `fs_overdraw` writes to what is already in the target
+const OVERDRAW_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// Replaces the image with a heat map of how many fragments the models draw to each pixel,
+/// blue for one through green to red for eight or more. Nothing is depth tested,
+/// so hidden surfaces count as well. That is the fill rate the scene costs without a depth prepass.
+pub struct OverdrawPass {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    models: Rc<RefCell<ModelsDrawPass>>,
+    accumulate_pipeline: wgpu::RenderPipeline,
+    colorize_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    // Fragments drawn to each pixel
+    target: klgl::Texture,
+}
+
+impl OverdrawPass {
+    pub const NAME: &str = "overdraw";
+
+    pub fn new(ctx: Rc<RefCell<klgl::RenderContext>>, models: Rc<RefCell<ModelsDrawPass>>) -> Self {
+        let (accumulate_pipeline, colorize_pipeline, bind_group_layout, bind_group, target) = {
+            let ctx = ctx.borrow();
+            let models = models.borrow();
+            let device = &ctx.device;
+            let target = Self::create_target(&ctx);
+
+            let bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    }],
+                    label: Some("overdraw_bind_group_layout"),
+                });
+
+            let bind_group = Self::create_bind_group(device, &bind_group_layout, &target);
+            let accumulate_pipeline = Self::create_accumulate_pipeline(
+                device,
+                models.texture_bind_group_layout(),
+                models.camera_bind_group_layout(),
+            );
+            let colorize_pipeline =
+                Self::create_colorize_pipeline(device, &bind_group_layout, ctx.config.format);
+            (
+                accumulate_pipeline,
+                colorize_pipeline,
+                bind_group_layout,
+                bind_group,
+                target,
+            )
+        };
+
+        Self {
+            ctx,
+            models,
+            accumulate_pipeline,
+            colorize_pipeline,
+            bind_group_layout,
+            bind_group,
+            target,
+        }
+    }
+
+    /// Keeps the target the size of the surface
+    pub fn on_resize(&mut self) {
+        let ctx = self.ctx.borrow();
+        self.target = Self::create_target(&ctx);
+        self.bind_group =
+            Self::create_bind_group(&ctx.device, &self.bind_group_layout, &self.target);
+    }
+
+    fn create_target(ctx: &klgl::RenderContext) -> klgl::Texture {
+        klgl::Texture::create_render_target(
+            &ctx.device,
+            ctx.config.width,
+            ctx.config.height,
+            OVERDRAW_FORMAT,
+            "overdraw_texture",
+        )
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        target: &klgl::Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&target.view),
+            }],
+            label: Some("overdraw_bind_group"),
+        })
+    }
+
+    fn create_accumulate_pipeline(
+        device: &wgpu::Device,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Overdraw Accumulate Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::TUTORIAL_9_SHADER.into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overdraw Accumulate Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Overdraw Accumulate Pipeline Layout"),
+                    bind_group_layouts: &[texture_bind_group_layout, camera_bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_shadow"),
+                buffers: &[ModelVertex::layout(), Instance::layout()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_overdraw"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: OVERDRAW_FORMAT,
+                    blend: Some(OVERDRAW_BLEND),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            // Back faces are culled like in the scene. Double sided meshes only count their front.
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    fn create_colorize_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        surface_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Overdraw Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::OVERDRAW_SHADER.into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overdraw Colorize Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Overdraw Colorize Pipeline Layout"),
+                    bind_group_layouts: &[bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+impl klgl::DrawPass for OverdrawPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overdraw Accumulate Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.target.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            if let Some(viewport) = targets.viewport {
+                render_pass.set_viewport(
+                    viewport.x as f32,
+                    viewport.y as f32,
+                    viewport.width as f32,
+                    viewport.height as f32,
+                    0.0,
+                    1.0,
+                );
+                render_pass.set_scissor_rect(
+                    viewport.x,
+                    viewport.y,
+                    viewport.width,
+                    viewport.height,
+                );
+            }
+
+            let models = self.models.borrow();
+            render_pass.set_pipeline(&self.accumulate_pipeline);
+            models.draw_geometry(
+                &mut render_pass,
+                targets.camera_or(models.camera_bind_group()),
+            );
+        }
+
+        let mut render_pass = targets
+            .surface_only()
+            .begin_render_pass(encoder, "Overdraw Colorize Pass");
+        render_pass.set_pipeline(&self.colorize_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use klgl::DrawPass;
+
+    const SIZE: u32 = 32;
+
+    // Two triangles facing -X, the nearer one moved aside so they overlap in part
+    const TRIANGLES_OBJ: &str = "v 0 -100 -100\nv 0 100 -100\nv 0 0 100\n\
+                                 v -10 -50 -100\nv -10 150 -100\nv -10 50 100\n\
+                                 f 1 3 2\nf 4 6 5\n";
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_pixel_covered_twice_is_hotter() {
+        let ctx = crate::test_utils::gpu_context(SIZE, SIZE);
+
+        let camera = klgl::Camera::new(
+            cgmath::Point3::new(-100.0, 0.0, 1.0),
+            klgl::Rotator::from_direction(cgmath::Vector3::unit_x()),
+            1.0,
+            45.0,
+            1.0,
+            1000.0,
+        );
+        let (camera_layout, camera_bind_group) =
+            crate::test_utils::camera_binding(&ctx.borrow().device, &camera);
+        let mut models = crate::test_utils::obj_models(
+            &ctx,
+            &camera_layout,
+            &camera_bind_group,
+            wgpu::TextureFormat::Rgba16Float,
+            None,
+            "overdraw",
+            TRIANGLES_OBJ,
+        );
+        models.set_instance_grid(&ctx.borrow().device, 1);
+        let models = Rc::new(RefCell::new(models));
+        let pass = OverdrawPass::new(ctx.clone(), models.clone());
+
+        let ctx = ctx.borrow();
+        let surface = klgl::Texture::create_render_target(
+            &ctx.device,
+            SIZE,
+            SIZE,
+            ctx.config.format,
+            "surface",
+        );
+        let targets = klgl::PassTargets {
+            color: &surface.view,
+            depth: None,
+            surface: &surface.view,
+            viewport: None,
+            camera: None,
+        };
+        let mut uploader = klgl::FrameUploader::new();
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        models
+            .borrow_mut()
+            .upload_instances(&mut uploader, &mut encoder);
+        uploader.finish();
+        pass.record(&mut encoder, &targets);
+        ctx.queue.submit([encoder.finish()]);
+
+        // Every fragment adds one, hidden or not
+        let layers = crate::test_utils::read_r16f(&ctx, &pass.target.texture);
+        let pixels = crate::test_utils::read_rgba8(&ctx, &surface.texture);
+        let covered = |count: f32| layers.iter().position(|layers| *layers == count);
+        let (Some(none), Some(once), Some(twice)) = (covered(0.0), covered(1.0), covered(2.0))
+        else {
+            panic!("Expected pixels covered zero, one and two times: {layers:?}");
+        };
+        assert!(layers.iter().all(|layers| [0.0, 1.0, 2.0].contains(layers)));
+
+        // Black where nothing was drawn, one layer is blue and more turn it towards red
+        assert_eq!(pixels[none], [0, 0, 0, 255]);
+        assert_eq!(pixels[once], [0, 0, 255, 255]);
+        let [_, twice_green, twice_blue, _] = pixels[twice];
+        assert!(twice_blue < 255 && twice_green > 0, "{:?}", pixels[twice]);
+    }
+}
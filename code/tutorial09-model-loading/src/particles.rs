@@ -0,0 +1,519 @@
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+use wgpu::util::DeviceExt;
+
+// Has to match the workgroup size of cs_update
+const WORKGROUP_SIZE: u32 = 64;
+// The scene is Z up and measured in large units, so gravity is stronger than on earth
+const GRAVITY: [f32; 3] = [0.0, 0.0, -30.0];
+// Half size of a billboard in clip space at a distance of one unit from the camera
+const PARTICLE_SIZE: f32 = 0.5;
+
+const ADDITIVE_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent::REPLACE,
+};
+
+/// Has to match `Particle` in the shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct Particle {
+    position: [f32; 3],
+    // Seconds left. The slot is free when it is not positive.
+    life: f32,
+    velocity: [f32; 3],
+    lifetime: f32,
+    color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SimParams {
+    gravity: [f32; 3],
+    dt: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BillboardUniform {
+    half_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+impl BillboardUniform {
+    fn new(aspect: f32) -> Self {
+        Self {
+            half_size: [PARTICLE_SIZE / aspect, PARTICLE_SIZE],
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// How new particles start
+#[derive(Copy, Clone, Debug)]
+pub struct EmitParams {
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    /// Largest random velocity added to `velocity` in any direction
+    pub spread: f32,
+    /// Seconds a particle lives
+    pub lifetime: f32,
+    /// Linear color, may exceed 1 to feed the bloom pass
+    pub color: [f32; 4],
+}
+
+// Xorshift is plenty for scattering particles and needs no extra dependency
+struct Rng(u32);
+
+impl Rng {
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// Uniform value in [-1, 1)
+    fn next_signed(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+}
+
+fn spawn_particle(params: &EmitParams, rng: &mut Rng) -> Particle {
+    // Rejection sampling keeps the spread inside a sphere instead of a cube
+    let direction = loop {
+        let v = [rng.next_signed(), rng.next_signed(), rng.next_signed()];
+        if v.iter().map(|x| x * x).sum::<f32>() <= 1.0 {
+            break v;
+        }
+    };
+
+    Particle {
+        position: params.position,
+        life: params.lifetime,
+        velocity: std::array::from_fn(|i| params.velocity[i] + direction[i] * params.spread),
+        lifetime: params.lifetime,
+        color: params.color,
+    }
+}
+
+/// Slots for `count` new particles starting at `cursor`. The second range is empty unless the
+/// slots wrap around to the start of the buffer, recycling the oldest particles first.
+fn emit_ranges(cursor: u32, count: u32, max_particles: u32) -> (Range<u32>, Range<u32>) {
+    let count = count.min(max_particles);
+    let end = cursor + count;
+    match end <= max_particles {
+        true => (cursor..end, 0..0),
+        false => (cursor..max_particles, 0..end - max_particles),
+    }
+}
+
+/// Particles live in a storage buffer that a compute shader advances every frame.
+/// They are drawn as instanced billboards, one instance per slot.
+pub struct ParticleSystem {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    particles_buffer: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+    billboard_buffer: wgpu::Buffer,
+    compute_pipeline: wgpu::ComputePipeline,
+    compute_bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+    render_bind_group: wgpu::BindGroup,
+    camera_bind_group: wgpu::BindGroup,
+    max_particles: u32,
+    // Next slot to emit into
+    cursor: u32,
+    rng: Rng,
+}
+
+impl ParticleSystem {
+    pub const NAME: &str = "particles";
+
+    /// Compute shaders and storage buffers in vertex shaders are not available on WebGL2
+    pub fn is_supported(ctx: &klgl::RenderContext) -> bool {
+        ctx.adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::VERTEX_STORAGE)
+    }
+
+    pub fn new(
+        ctx: Rc<RefCell<klgl::RenderContext>>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group: &wgpu::BindGroup,
+        color_format: wgpu::TextureFormat,
+        max_particles: u32,
+    ) -> Self {
+        let max_particles = max_particles.max(1);
+        let (
+            particles_buffer,
+            params_buffer,
+            billboard_buffer,
+            compute_pipeline,
+            compute_bind_group,
+            render_pipeline,
+            render_bind_group,
+        ) = {
+            let ctx = ctx.borrow();
+            let device = &ctx.device;
+
+            // Zeroed particles have no life left, so every slot starts free
+            let particles_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Particles Buffer"),
+                contents: bytemuck::cast_slice(&vec![Particle::default(); max_particles as usize]),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            });
+
+            let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Particle Params Buffer"),
+                contents: bytemuck::cast_slice(&[SimParams {
+                    gravity: GRAVITY,
+                    dt: 0.0,
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+            let billboard_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Particle Billboard Buffer"),
+                contents: bytemuck::cast_slice(&[BillboardUniform::new(ctx.aspect())]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Particles Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    tutorial_embedded_content::PARTICLES_SHADER.into(),
+                ),
+            });
+
+            let compute_bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("particles_compute_bind_group_layout"),
+                });
+
+            let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &compute_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: particles_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("particles_compute_bind_group"),
+            });
+
+            let compute_pipeline =
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("Particles Compute Pipeline"),
+                    layout: Some(
+                        &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                            label: Some("Particles Compute Pipeline Layout"),
+                            bind_group_layouts: &[&compute_bind_group_layout],
+                            push_constant_ranges: &[],
+                        }),
+                    ),
+                    module: &shader,
+                    entry_point: Some("cs_update"),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    cache: None,
+                });
+
+            let render_bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("particles_render_bind_group_layout"),
+                });
+
+            let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &render_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: particles_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: billboard_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("particles_render_bind_group"),
+            });
+
+            let render_pipeline = Self::create_render_pipeline(
+                device,
+                &shader,
+                &[camera_bind_group_layout, &render_bind_group_layout],
+                color_format,
+            );
+
+            (
+                particles_buffer,
+                params_buffer,
+                billboard_buffer,
+                compute_pipeline,
+                compute_bind_group,
+                render_pipeline,
+                render_bind_group,
+            )
+        };
+
+        Self {
+            ctx,
+            particles_buffer,
+            params_buffer,
+            billboard_buffer,
+            compute_pipeline,
+            compute_bind_group,
+            render_pipeline,
+            render_bind_group,
+            camera_bind_group: camera_bind_group.clone(),
+            max_particles,
+            cursor: 0,
+            rng: Rng(0x9E37_79B9),
+        }
+    }
+
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        texture_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particles Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Particles Render Pipeline Layout"),
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: texture_format,
+                    blend: Some(ADDITIVE_BLEND),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Additive particles do not need sorting as long as they do not write depth
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: klgl::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Starts `count` particles. When the buffer is full the oldest ones are replaced.
+    pub fn emit(&mut self, count: u32, params: &EmitParams) {
+        let (first, wrapped) = emit_ranges(self.cursor, count, self.max_particles);
+        self.cursor = match wrapped.is_empty() {
+            true => first.end % self.max_particles,
+            false => wrapped.end,
+        };
+
+        let ctx = self.ctx.borrow();
+        for range in [first, wrapped] {
+            if range.is_empty() {
+                continue;
+            }
+
+            let particles: Vec<Particle> = range
+                .clone()
+                .map(|_| spawn_particle(params, &mut self.rng))
+                .collect();
+            let offset = range.start as u64 * std::mem::size_of::<Particle>() as u64;
+            ctx.queue.write_buffer(
+                &self.particles_buffer,
+                offset,
+                bytemuck::cast_slice(&particles),
+            );
+        }
+    }
+
+    /// Records the compute dispatch that advances every particle by `dt` seconds
+    pub fn update(&self, encoder: &mut wgpu::CommandEncoder, dt: f32) {
+        self.ctx.borrow().queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[SimParams {
+                gravity: GRAVITY,
+                dt,
+            }]),
+        );
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particles Compute Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.max_particles.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    pub fn render(&self, render_pass: &mut wgpu::RenderPass, camera_bind_group: &wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.render_bind_group, &[]);
+        render_pass.draw(0..6, 0..self.max_particles);
+    }
+
+    /// Keeps the billboards square after the surface was resized
+    pub fn on_resize(&self) {
+        let ctx = self.ctx.borrow();
+        ctx.queue.write_buffer(
+            &self.billboard_buffer,
+            0,
+            bytemuck::cast_slice(&[BillboardUniform::new(ctx.aspect())]),
+        );
+    }
+}
+
+impl klgl::DrawPass for ParticleSystem {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn per_view(&self) -> bool {
+        true
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let mut render_pass = targets.begin_render_pass(encoder, "Particles Render Pass");
+        self.render(&mut render_pass, targets.camera_or(&self.camera_bind_group));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> EmitParams {
+        EmitParams {
+            position: [1.0, 2.0, 3.0],
+            velocity: [0.0, 0.0, 10.0],
+            spread: 2.0,
+            lifetime: 1.5,
+            color: [1.0, 0.5, 0.25, 1.0],
+        }
+    }
+
+    #[test]
+    fn test_particle_matches_shader_layout() {
+        // vec3 + f32, vec3 + f32, vec4
+        assert_eq!(std::mem::size_of::<Particle>(), 48);
+    }
+
+    #[test]
+    fn test_emit_ranges() {
+        assert_eq!(emit_ranges(0, 10, 100), (0..10, 0..0));
+        assert_eq!(emit_ranges(90, 10, 100), (90..100, 0..0));
+
+        // The oldest particles at the start of the buffer are recycled
+        assert_eq!(emit_ranges(95, 10, 100), (95..100, 0..5));
+
+        // A burst larger than the buffer replaces every particle once
+        assert_eq!(emit_ranges(40, 250, 100), (40..100, 0..40));
+    }
+
+    #[test]
+    fn test_spawned_particles_stay_within_spread() {
+        let params = params();
+        let mut rng = Rng(1);
+        for _ in 0..100 {
+            let particle = spawn_particle(&params, &mut rng);
+            assert_eq!(particle.position, params.position);
+            assert_eq!(particle.life, params.lifetime);
+            assert_eq!(particle.color, params.color);
+
+            let offset: f32 = (0..3)
+                .map(|i| (particle.velocity[i] - params.velocity[i]).powi(2))
+                .sum::<f32>()
+                .sqrt();
+            assert!(offset <= params.spread + 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_rng_range() {
+        let mut rng = Rng(12345);
+        for _ in 0..1000 {
+            let value = rng.next_signed();
+            assert!((-1.0..1.0).contains(&value));
+        }
+    }
+}
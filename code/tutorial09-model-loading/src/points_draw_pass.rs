@@ -0,0 +1,342 @@
+use std::{cell::RefCell, rc::Rc};
+
+use wgpu::util::DeviceExt;
+
+/// Round marker with a constant size on screen
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Point {
+    pub position: [f32; 3],
+    /// Diameter in pixels
+    pub size: f32,
+    pub color: [f32; 4],
+}
+
+impl Point {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32, 2 => Float32x4];
+
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Point>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointsUniform {
+    viewport_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+impl PointsUniform {
+    fn new(ctx: &klgl::RenderContext) -> Self {
+        Self {
+            viewport_size: [ctx.config.width as f32, ctx.config.height as f32],
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Draws points as anti-aliased discs, e.g. to mark vertices or positions in the scene
+pub struct PointsDrawPass {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    pipeline: wgpu::RenderPipeline,
+    camera_bind_group: wgpu::BindGroup,
+    points_buffer: wgpu::Buffer,
+    points_bind_group: wgpu::BindGroup,
+    // Grows when needed and is reused otherwise
+    instances_buffer: Option<wgpu::Buffer>,
+    num_points: u32,
+}
+
+impl PointsDrawPass {
+    pub const NAME: &str = "points";
+
+    pub fn new(
+        ctx: Rc<RefCell<klgl::RenderContext>>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group: &wgpu::BindGroup,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let (pipeline, points_buffer, points_bind_group) = {
+            let ctx = ctx.borrow();
+            let points_buffer = ctx
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Points Buffer"),
+                    contents: bytemuck::cast_slice(&[PointsUniform::new(&ctx)]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+
+            let points_bind_group_layout =
+                ctx.device
+                    .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        entries: &[wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        }],
+                        label: Some("points_bind_group_layout"),
+                    });
+
+            let points_bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &points_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: points_buffer.as_entire_binding(),
+                }],
+                label: Some("points_bind_group"),
+            });
+
+            let pipeline = Self::create_pipeline(
+                &ctx.device,
+                &[camera_bind_group_layout, &points_bind_group_layout],
+                color_format,
+            );
+
+            (pipeline, points_buffer, points_bind_group)
+        };
+
+        Self {
+            ctx,
+            pipeline,
+            camera_bind_group: camera_bind_group.clone(),
+            points_buffer,
+            points_bind_group,
+            instances_buffer: None,
+            num_points: 0,
+        }
+    }
+
+    /// Replaces the points to draw
+    pub fn set_points(&mut self, points: &[Point]) {
+        self.num_points = points.len() as u32;
+        if points.is_empty() {
+            return;
+        }
+
+        let ctx = self.ctx.borrow();
+        let size = std::mem::size_of_val(points) as wgpu::BufferAddress;
+        if self
+            .instances_buffer
+            .as_ref()
+            .is_none_or(|buffer| buffer.size() < size)
+        {
+            self.instances_buffer = Some(ctx.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Points Instance Buffer"),
+                size: size.next_power_of_two(),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        if let Some(buffer) = &self.instances_buffer {
+            ctx.queue
+                .write_buffer(buffer, 0, bytemuck::cast_slice(points));
+        }
+    }
+
+    /// Keeps the point sizes in pixels after the surface was resized
+    pub fn on_resize(&self) {
+        let ctx = self.ctx.borrow();
+        ctx.queue.write_buffer(
+            &self.points_buffer,
+            0,
+            bytemuck::cast_slice(&[PointsUniform::new(&ctx)]),
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        texture_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Points Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::POINTS_SHADER.into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Points Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Points Render Pipeline Layout"),
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[Point::layout()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: texture_format,
+                    // The smooth edges fade into the scene
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Points are hidden by the scene but do not occlude anything themselves
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: klgl::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+impl klgl::DrawPass for PointsDrawPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn per_view(&self) -> bool {
+        true
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let Some(instances_buffer) = self
+            .instances_buffer
+            .as_ref()
+            .filter(|_| self.num_points != 0)
+        else {
+            return;
+        };
+
+        let mut render_pass = targets.begin_render_pass(encoder, "Points Render Pass");
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, targets.camera_or(&self.camera_bind_group), &[]);
+        render_pass.set_bind_group(1, &self.points_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, instances_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.num_points);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_point_layout() {
+        assert_eq!(std::mem::size_of::<Point>(), 32);
+        assert_eq!(std::mem::size_of::<PointsUniform>() % 16, 0);
+        let last = Point::ATTRIBUTES.last().unwrap();
+        assert_eq!(
+            last.offset + last.format.size(),
+            std::mem::size_of::<Point>() as wgpu::BufferAddress
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_large_point_is_round() {
+        use klgl::DrawPass;
+
+        const SIZE: u32 = 40;
+        let ctx = crate::test_utils::gpu_context(SIZE, SIZE);
+
+        // A white 32 pixel point in the middle of a black target
+        let camera = klgl::Camera::new(
+            cgmath::Point3::new(-5.0, 0.0, 0.0),
+            klgl::Rotator::from_direction(cgmath::Vector3::unit_x()),
+            1.0,
+            45.0,
+            0.1,
+            100.0,
+        );
+        let (camera_layout, camera_bind_group) =
+            crate::test_utils::camera_binding(&ctx.borrow().device, &camera);
+        let mut points = PointsDrawPass::new(
+            ctx.clone(),
+            &camera_layout,
+            &camera_bind_group,
+            wgpu::TextureFormat::Rgba8Unorm,
+        );
+        let radius = 16.0f32;
+        points.set_points(&[Point {
+            position: [0.0; 3],
+            size: 2.0 * radius,
+            color: [1.0; 4],
+        }]);
+
+        let ctx = ctx.borrow();
+        let color = klgl::Texture::create_render_target(
+            &ctx.device,
+            SIZE,
+            SIZE,
+            wgpu::TextureFormat::Rgba8Unorm,
+            "color",
+        );
+        let depth = klgl::Texture::create_depth_texture(&ctx.device, SIZE, SIZE, "depth");
+        let targets = klgl::PassTargets {
+            color: &color.view,
+            depth: Some(&depth.view),
+            surface: &color.view,
+            viewport: None,
+            camera: None,
+        };
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        klgl::ClearPass::new(wgpu::Color::BLACK).record(&mut encoder, &targets);
+        points.record(&mut encoder, &targets);
+        ctx.queue.submit([encoder.finish()]);
+
+        let coverage: Vec<f32> = crate::test_utils::read_rgba8(&ctx, &color.texture)
+            .iter()
+            .map(|texel| texel[0] as f32 / 255.0)
+            .collect();
+        let at = |x: u32, y: u32| coverage[(y * SIZE + x) as usize];
+
+        // The area is close to the area of the circle
+        let area: f32 = coverage.iter().sum();
+        let circle = std::f32::consts::PI * radius * radius;
+        assert!((area - circle).abs() / circle < 0.02, "{area} vs {circle}");
+
+        // Corners of the quad are cut off, the center and the edge midpoints are kept
+        let (first, last, middle) = (SIZE / 2 - 17, SIZE / 2 + 16, SIZE / 2);
+        for (x, y) in [(first, first), (last, first), (first, last), (last, last)] {
+            assert_eq!(at(x, y), 0.0);
+        }
+        assert_eq!(at(middle, middle), 1.0);
+        assert!(at(first + 1, middle) > 0.0 && at(middle, first + 1) > 0.0);
+
+        // Symmetric footprint with a soft edge
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                assert!((at(x, y) - at(SIZE - 1 - x, y)).abs() < 0.02);
+                assert!((at(x, y) - at(y, x)).abs() < 0.02);
+            }
+        }
+        assert!(coverage.iter().any(|c| *c > 0.0 && *c < 1.0));
+    }
+}
@@ -0,0 +1,209 @@
+use std::{cell::RefCell, rc::Rc};
+
+// Portal size relative to the screen
+const PORTAL_SCALE: f32 = 0.25;
+// Gap between the portal and the screen corner in pixels
+const PORTAL_MARGIN: u32 = 16;
+
+/// Top right corner of `area` covered by the portal
+pub fn portal_rect(area: klgl::Viewport) -> klgl::Viewport {
+    let width = ((area.width as f32 * PORTAL_SCALE) as u32).max(1);
+    let height = ((area.height as f32 * PORTAL_SCALE) as u32).max(1);
+    klgl::Viewport {
+        x: area.x + area.width.saturating_sub(width + PORTAL_MARGIN),
+        y: area.y + PORTAL_MARGIN.min(area.height - height),
+        width,
+        height,
+    }
+}
+
+/// Renders its own list of passes into an offscreen target and shows the result on a quad
+/// in the corner of the screen, the way a UI panel or a portal would.
+pub struct PortalPass {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    scene: klgl::PassList,
+    target: klgl::RenderTarget,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl PortalPass {
+    pub const NAME: &str = "portal";
+
+    /// The passes of `scene` have to render to `color_format`
+    pub fn new(
+        ctx: Rc<RefCell<klgl::RenderContext>>,
+        scene: klgl::PassList,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let (target, pipeline, bind_group_layout, bind_group) = {
+            let ctx = ctx.borrow();
+            let device = &ctx.device;
+            let target = Self::create_target(&ctx, color_format);
+
+            let bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                    label: Some("portal_bind_group_layout"),
+                });
+
+            let bind_group = Self::create_bind_group(device, &bind_group_layout, &target);
+            let pipeline = Self::create_pipeline(device, &bind_group_layout, color_format);
+            (target, pipeline, bind_group_layout, bind_group)
+        };
+
+        Self {
+            ctx,
+            scene,
+            target,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// Keeps the portal size relative to the screen
+    pub fn on_resize(&mut self) {
+        let ctx = self.ctx.borrow();
+        self.target = Self::create_target(&ctx, self.target.format());
+        self.bind_group =
+            Self::create_bind_group(&ctx.device, &self.bind_group_layout, &self.target);
+    }
+
+    fn create_target(
+        ctx: &klgl::RenderContext,
+        color_format: wgpu::TextureFormat,
+    ) -> klgl::RenderTarget {
+        let rect = portal_rect(ctx.scene_viewport());
+        klgl::RenderTarget::new(&ctx.device, rect.width, rect.height, color_format)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        target: &klgl::RenderTarget,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(target.color_view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&target.color().sampler),
+                },
+            ],
+            label: Some("portal_bind_group"),
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Portal Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::PORTAL_SHADER.into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Portal Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Portal Render Pipeline Layout"),
+                    bind_group_layouts: &[bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+impl klgl::DrawPass for PortalPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        self.scene.execute(encoder, &self.target.targets());
+
+        let quad_targets = klgl::PassTargets {
+            viewport: Some(portal_rect(self.ctx.borrow().scene_viewport())),
+            ..targets.color_only()
+        };
+        let mut render_pass = quad_targets.begin_render_pass(encoder, "Portal Render Pass");
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_portal_rect_is_in_the_corner() {
+        let area = klgl::Viewport {
+            x: 100,
+            y: 0,
+            width: 800,
+            height: 600,
+        };
+        let rect = portal_rect(area);
+        assert_eq!((rect.width, rect.height), (200, 150));
+        assert_eq!(rect.x + rect.width + PORTAL_MARGIN, area.x + area.width);
+        assert_eq!(rect.y, PORTAL_MARGIN);
+
+        // Tiny windows still get a valid rect inside the area
+        let rect = portal_rect(klgl::Viewport {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 2,
+        });
+        assert!(rect.x + rect.width <= 2 && rect.y + rect.height <= 2);
+    }
+}
@@ -0,0 +1,307 @@
+use std::{cell::RefCell, rc::Rc};
+
+use cgmath::{Deg, Point3, Vector3};
+use klgl::{CameraPose, Rotator, file_loader::FileLoader};
+use wgpu::util::DeviceExt;
+
+use crate::lights::PointLight;
+use crate::model::{Mesh, Model};
+use crate::models_draw_pass::{Instance, LoadingModel};
+
+#[derive(Debug, PartialEq, serde::Deserialize)]
+#[serde(default)]
+struct TransformDesc {
+    translation: [f32; 3],
+    // Yaw, pitch and roll in degrees
+    rotation: [f32; 3],
+    scale: f32,
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: 1.0,
+        }
+    }
+}
+
+impl TransformDesc {
+    fn matrix(&self) -> cgmath::Matrix4<f32> {
+        let [yaw, pitch, roll] = self.rotation;
+        let rotator = Rotator {
+            yaw: Deg(yaw),
+            pitch: Deg(pitch),
+            roll: Deg(roll),
+        };
+        cgmath::Matrix4::from_translation(Vector3::from(self.translation))
+            * rotator.to_matrix()
+            * cgmath::Matrix4::from_scale(self.scale)
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ModelDesc {
+    path: String,
+    // Mtl files and textures the model needs
+    #[serde(default)]
+    requirements: Vec<String>,
+    #[serde(default)]
+    transform: TransformDesc,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CameraDesc {
+    eye: [f32; 3],
+    yaw: f32,
+    pitch: f32,
+    #[serde(default)]
+    roll: f32,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct LightDesc {
+    position: [f32; 3],
+    radius: f32,
+    color: [f32; 3],
+    intensity: f32,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SceneDesc {
+    models: Vec<ModelDesc>,
+    #[serde(default)]
+    camera: Option<CameraDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+}
+
+impl SceneDesc {
+    fn from_json(json: &str) -> anyhow::Result<Self> {
+        let desc: SceneDesc = serde_json::from_str(json)?;
+        anyhow::ensure!(
+            desc.models.iter().all(|model| model.transform.scale > 0.0),
+            "Scene model scales must be positive"
+        );
+        Ok(desc)
+    }
+}
+
+struct SceneModel {
+    path: String,
+    instance: Instance,
+    // Never changes, so one buffer serves every frame
+    instance_buffer: wgpu::Buffer,
+    loading: Option<LoadingModel>,
+    model: Option<Model>,
+}
+
+/// Models placed by a JSON file, with the camera and lights to show them with.
+/// Every model is drawn once with its own transform, models appear as they finish loading.
+pub struct Scene {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    models: Vec<SceneModel>,
+    camera: Option<CameraPose>,
+    lights: Vec<PointLight>,
+}
+
+impl Scene {
+    /// Reads
+    /// `{"models": [{"path": "a.obj", "requirements": ["a.mtl"], "transform": {"translation": [x, y, z], "rotation": [yaw, pitch, roll], "scale": 1}}],
+    /// "camera": {"eye": [x, y, z], "yaw": 0, "pitch": 0}, "lights": [{"position": [x, y, z], "radius": 100, "color": [r, g, b], "intensity": 1}]}`
+    /// and starts loading the models. Angles are in degrees, the camera, lights,
+    /// requirements and any part of a transform may be omitted.
+    pub fn load(
+        json: &str,
+        file_loader: &mut FileLoader,
+        ctx: Rc<RefCell<klgl::RenderContext>>,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let desc = SceneDesc::from_json(json)?;
+        let models = desc
+            .models
+            .into_iter()
+            .map(|model| {
+                let instance = Instance::new(model.transform.matrix());
+                let instance_buffer =
+                    ctx.borrow()
+                        .device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("Scene Instance Buffer"),
+                            contents: bytemuck::bytes_of(&instance),
+                            usage: wgpu::BufferUsages::VERTEX,
+                        });
+                let loading = LoadingModel::new(
+                    file_loader,
+                    &model.path,
+                    texture_bind_group_layout.clone(),
+                    &model.requirements,
+                );
+                SceneModel {
+                    path: model.path,
+                    instance,
+                    instance_buffer,
+                    loading: Some(loading),
+                    model: None,
+                }
+            })
+            .collect();
+
+        let camera = desc.camera.map(|camera| CameraPose {
+            eye: Point3::from(camera.eye),
+            rotator: Rotator {
+                yaw: Deg(camera.yaw),
+                pitch: Deg(camera.pitch),
+                roll: Deg(camera.roll),
+            },
+        });
+        let lights = desc
+            .lights
+            .into_iter()
+            .map(|light| PointLight {
+                position: light.position,
+                radius: light.radius,
+                color: light.color,
+                intensity: light.intensity,
+            })
+            .collect();
+
+        Ok(Self {
+            ctx,
+            models,
+            camera,
+            lights,
+        })
+    }
+
+    /// Builds the models whose geometry arrived and streams their textures in
+    pub fn update(&mut self) {
+        let ctx = self.ctx.borrow();
+        for entry in &mut self.models {
+            let Some(loading) = &mut entry.loading else {
+                continue;
+            };
+
+            let mut failed = false;
+            let arrived = loading.update();
+            if let Some(model) = &mut entry.model {
+                loading.stream(&ctx, model, &arrived);
+            } else if let Some(model_result) = loading.build(&ctx) {
+                match model_result {
+                    Ok(mut model) => {
+                        log::info!("Scene model successfully loaded: {}", entry.path);
+                        model.write_shared_instances(&ctx, &[entry.instance]);
+                        entry.model = Some(model);
+                    }
+                    Err(err) => {
+                        log::error!("Failed to load scene model {}. Error: {}", entry.path, err);
+                        failed = true;
+                    }
+                }
+            }
+            if failed || loading.finished() {
+                entry.loading = None;
+            }
+        }
+    }
+
+    /// Draws the loaded models with whatever pipeline is already set on the render pass
+    pub fn render(&self, render_pass: &mut wgpu::RenderPass, camera_bind_group: &wgpu::BindGroup) {
+        self.render_filtered(render_pass, camera_bind_group, |_, _| true);
+    }
+
+    /// Draws only the meshes `filter` returns true for, see [`Model::draw_instanced_filtered`]
+    pub fn render_filtered<Filter>(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        camera_bind_group: &wgpu::BindGroup,
+        filter: Filter,
+    ) where
+        Filter: Fn(usize, &Mesh) -> bool,
+    {
+        for entry in &self.models {
+            if let Some(model) = &entry.model {
+                model.draw_instanced_filtered(
+                    render_pass,
+                    camera_bind_group,
+                    &entry.instance_buffer,
+                    0..1,
+                    &filter,
+                );
+            }
+        }
+    }
+
+    /// Where the scene wants the camera, if it says
+    pub fn camera(&self) -> Option<CameraPose> {
+        self.camera
+    }
+
+    pub fn lights(&self) -> &[PointLight] {
+        &self.lights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Transform;
+
+    #[test]
+    fn test_from_json() {
+        let desc = SceneDesc::from_json(
+            r#"{
+                "models": [
+                    {"path": "models/cube/cube.obj", "requirements": ["models/cube/cube.mtl"],
+                     "transform": {"translation": [10, 0, 5], "scale": 2}},
+                    {"path": "models/date_palm/date_palm.obj"}
+                ],
+                "camera": {"eye": [0, -100, 50], "yaw": 90, "pitch": -20},
+                "lights": [{"position": [0, 0, 40], "radius": 150, "color": [1, 1, 1], "intensity": 2}]
+            }"#,
+        )
+        .unwrap();
+
+        let paths: Vec<&str> = desc
+            .models
+            .iter()
+            .map(|model| model.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            ["models/cube/cube.obj", "models/date_palm/date_palm.obj"]
+        );
+        assert_eq!(desc.models[0].requirements, ["models/cube/cube.mtl"]);
+        assert!(desc.models[1].requirements.is_empty());
+
+        // Omitted parts of a transform keep their identity values
+        assert_eq!(
+            desc.models[0].transform,
+            TransformDesc {
+                translation: [10.0, 0.0, 5.0],
+                rotation: [0.0; 3],
+                scale: 2.0,
+            }
+        );
+        assert_eq!(desc.models[1].transform, TransformDesc::default());
+        let matrix = desc.models[0].transform.matrix();
+        assert_eq!(
+            matrix.transform_point(Point3::new(1.0, 1.0, 1.0)),
+            Point3::new(12.0, 2.0, 7.0)
+        );
+
+        let camera = desc.camera.unwrap();
+        assert_eq!((camera.yaw, camera.pitch, camera.roll), (90.0, -20.0, 0.0));
+        assert_eq!(desc.lights.len(), 1);
+        assert_eq!(desc.lights[0].radius, 150.0);
+
+        // Camera and lights are optional, models are not
+        assert!(SceneDesc::from_json(r#"{"models": []}"#).is_ok());
+        assert!(SceneDesc::from_json(r#"{"lights": []}"#).is_err());
+        assert!(
+            SceneDesc::from_json(r#"{"models": [{"path": "a.obj", "transform": {"scale": 0}}]}"#)
+                .is_err()
+        );
+    }
+}
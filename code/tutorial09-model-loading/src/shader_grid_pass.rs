@@ -0,0 +1,384 @@
+use std::{cell::RefCell, rc::Rc};
+
+use cgmath::{Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+// Every n-th line is drawn brighter
+const MAJOR_EVERY: f32 = 10.0;
+
+/// Plane through the origin the grid is drawn on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GridPlane {
+    XY,
+    XZ,
+    YZ,
+}
+
+impl GridPlane {
+    pub const ALL: [GridPlane; 3] = [GridPlane::XY, GridPlane::XZ, GridPlane::YZ];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|x| *x == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// Ground plane of a coordinate system, where the line grid is drawn too
+    pub fn ground(coordinate_system: klgl::CoordinateSystem) -> Self {
+        match coordinate_system {
+            klgl::CoordinateSystem::ZUp => GridPlane::XY,
+            klgl::CoordinateSystem::YUp => GridPlane::XZ,
+        }
+    }
+
+    fn axes(self) -> (Vector3<f32>, Vector3<f32>) {
+        match self {
+            GridPlane::XY => (Vector3::unit_x(), Vector3::unit_y()),
+            GridPlane::XZ => (Vector3::unit_x(), Vector3::unit_z()),
+            GridPlane::YZ => (Vector3::unit_y(), Vector3::unit_z()),
+        }
+    }
+}
+
+/// Has to match GridUniform in grid_shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    eye: [f32; 3],
+    spacing: f32,
+    axis_u: [f32; 3],
+    fade_distance: f32,
+    axis_v: [f32; 3],
+    major_every: f32,
+}
+
+/// Draws an infinite looking anti-aliased grid with a single quad that follows the camera
+pub struct ShaderGridPass {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    pipeline: wgpu::RenderPipeline,
+    camera_bind_group: wgpu::BindGroup,
+    grid_buffer: wgpu::Buffer,
+    grid_bind_group: wgpu::BindGroup,
+    eye: Point3<f32>,
+    plane: GridPlane,
+    spacing: f32,
+    fade_distance: f32,
+}
+
+impl ShaderGridPass {
+    pub const NAME: &str = "shader_grid";
+
+    pub fn new(
+        ctx: Rc<RefCell<klgl::RenderContext>>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group: &wgpu::BindGroup,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let eye = Point3::new(0.0, 0.0, 0.0);
+        let plane = GridPlane::XY;
+        let spacing = 1.0;
+        let fade_distance = 100.0;
+
+        let (pipeline, grid_buffer, grid_bind_group) = {
+            let ctx = ctx.borrow();
+            let grid_buffer = ctx
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Grid Buffer"),
+                    contents: bytemuck::cast_slice(&[Self::grid_uniform(
+                        eye,
+                        plane,
+                        spacing,
+                        fade_distance,
+                    )]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+
+            let grid_bind_group_layout =
+                ctx.device
+                    .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                        entries: &[wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        }],
+                        label: Some("grid_bind_group_layout"),
+                    });
+
+            let grid_bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &grid_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: grid_buffer.as_entire_binding(),
+                }],
+                label: Some("grid_bind_group"),
+            });
+
+            let pipeline = Self::create_pipeline(
+                &ctx.device,
+                &[camera_bind_group_layout, &grid_bind_group_layout],
+                color_format,
+            );
+            (pipeline, grid_buffer, grid_bind_group)
+        };
+
+        Self {
+            ctx,
+            pipeline,
+            camera_bind_group: camera_bind_group.clone(),
+            grid_buffer,
+            grid_bind_group,
+            eye,
+            plane,
+            spacing,
+            fade_distance,
+        }
+    }
+
+    pub fn spacing(&self) -> f32 {
+        self.spacing
+    }
+
+    /// Distance between minor lines in world units
+    pub fn set_spacing(&mut self, spacing: f32) {
+        self.spacing = spacing;
+        self.write_uniform();
+    }
+
+    pub fn plane(&self) -> GridPlane {
+        self.plane
+    }
+
+    pub fn set_plane(&mut self, plane: GridPlane) {
+        self.plane = plane;
+        self.write_uniform();
+    }
+
+    pub fn fade_distance(&self) -> f32 {
+        self.fade_distance
+    }
+
+    /// Distance from the camera at which the grid disappears
+    pub fn set_fade_distance(&mut self, fade_distance: f32) {
+        self.fade_distance = fade_distance;
+        self.write_uniform();
+    }
+
+    /// Has to be called when the camera moves so the grid follows it
+    pub fn set_eye(&mut self, eye: Point3<f32>) {
+        self.eye = eye;
+        self.write_uniform();
+    }
+
+    fn grid_uniform(
+        eye: Point3<f32>,
+        plane: GridPlane,
+        spacing: f32,
+        fade_distance: f32,
+    ) -> GridUniform {
+        let (axis_u, axis_v) = plane.axes();
+        GridUniform {
+            eye: eye.into(),
+            spacing,
+            axis_u: axis_u.into(),
+            fade_distance,
+            axis_v: axis_v.into(),
+            major_every: MAJOR_EVERY,
+        }
+    }
+
+    fn write_uniform(&self) {
+        self.ctx.borrow().queue.write_buffer(
+            &self.grid_buffer,
+            0,
+            bytemuck::cast_slice(&[Self::grid_uniform(
+                self.eye,
+                self.plane,
+                self.spacing,
+                self.fade_distance,
+            )]),
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Grid Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::GRID_SHADER.into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Grid Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Grid Render Pipeline Layout"),
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Visible from both sides of the plane
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Transparent, so it is hidden by the scene but does not hide anything
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: klgl::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+impl klgl::DrawPass for ShaderGridPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn per_view(&self) -> bool {
+        true
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let mut render_pass = targets.begin_render_pass(encoder, "Grid Render Pass");
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, targets.camera_or(&self.camera_bind_group), &[]);
+        render_pass.set_bind_group(1, &self.grid_bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{camera_binding, gpu_context, read_rgba8};
+    use cgmath::InnerSpace;
+
+    #[test]
+    fn test_uniform_layout() {
+        assert_eq!(std::mem::size_of::<GridUniform>(), 48);
+        assert_eq!(std::mem::offset_of!(GridUniform, axis_u), 16);
+        assert_eq!(std::mem::offset_of!(GridUniform, axis_v), 32);
+    }
+
+    #[test]
+    fn test_plane_axes_are_orthonormal() {
+        for plane in [GridPlane::XY, GridPlane::XZ, GridPlane::YZ] {
+            let (u, v) = plane.axes();
+            assert_eq!(u.magnitude(), 1.0);
+            assert_eq!(v.magnitude(), 1.0);
+            assert_eq!(u.dot(v), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_ground_plane_matches_the_coordinate_system() {
+        for system in [klgl::CoordinateSystem::ZUp, klgl::CoordinateSystem::YUp] {
+            assert_eq!(GridPlane::ground(system).axes(), system.ground_axes());
+        }
+    }
+
+    #[test]
+    fn test_planes_cycle() {
+        let mut plane = GridPlane::XY;
+        for _ in 0..GridPlane::ALL.len() {
+            plane = plane.next();
+        }
+        assert_eq!(plane, GridPlane::XY);
+        assert_eq!(GridPlane::YZ.next(), GridPlane::XY);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_top_down_lines_at_spacing() {
+        // 4 pixels per world unit. The eye is half a pixel off the origin,
+        // so lines go through the centers of pixels.
+        const SIZE: u32 = 64;
+        const PIXELS_PER_UNIT: f32 = 4.0;
+        let ctx = gpu_context(SIZE, SIZE);
+        let camera = klgl::Camera::new_orthographic(
+            Point3::new(0.125, 0.125, 10.0),
+            klgl::Rotator::from_direction(-Vector3::unit_z()),
+            1.0,
+            SIZE as f32 / PIXELS_PER_UNIT,
+            0.1,
+            100.0,
+        );
+        let (camera_layout, camera_bind_group) = camera_binding(&ctx.borrow().device, &camera);
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        let mut grid = ShaderGridPass::new(ctx.clone(), &camera_layout, &camera_bind_group, format);
+        grid.set_eye(*camera.get_eye());
+
+        for spacing in [1.0, 2.0] {
+            grid.set_spacing(spacing);
+
+            let ctx = ctx.borrow();
+            let color =
+                klgl::Texture::create_render_target(&ctx.device, SIZE, SIZE, format, "color");
+            let depth = klgl::Texture::create_depth_texture(&ctx.device, SIZE, SIZE, "depth");
+            let targets = klgl::PassTargets {
+                color: &color.view,
+                depth: Some(&depth.view),
+                surface: &color.view,
+                viewport: None,
+                camera: None,
+            };
+            let mut encoder = ctx
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            klgl::DrawPass::record(
+                &klgl::ClearPass::new(wgpu::Color::BLACK),
+                &mut encoder,
+                &targets,
+            );
+            klgl::DrawPass::record(&grid, &mut encoder, &targets);
+            ctx.queue.submit([encoder.finish()]);
+
+            // A row between two lines across it only crosses the lines along it
+            let pixels = read_rgba8(&ctx, &color.texture);
+            let row = (SIZE / 2 + 2) as usize;
+            let line_pixels: Vec<usize> = (0..SIZE as usize)
+                .filter(|x| pixels[row * SIZE as usize + x][..3].iter().any(|&c| c > 25))
+                .collect();
+            let step = (spacing * PIXELS_PER_UNIT) as usize;
+            let first = line_pixels[0];
+            assert!(first < step, "{line_pixels:?}");
+            let expected: Vec<usize> = (first..SIZE as usize).step_by(step).collect();
+            assert_eq!(line_pixels, expected, "spacing {spacing}");
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use std::{cell::RefCell, rc::Rc};
+
+use cgmath::{InnerSpace, Point3, Vector3};
+use klgl::{Camera, CameraUniform, Rotator};
+use wgpu::util::DeviceExt;
+
+use crate::model::{ModelVertex, Vertex};
+use crate::models_draw_pass::{Instance, ModelsDrawPass};
+
+const SHADOW_MAP_SIZE: u32 = 2048;
+
+/// What the main pass needs to sample the shadow map
+#[derive(Clone)]
+pub struct ShadowBinding {
+    pub layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+/// Renders the depth of the models from a directional light into a shadow map
+pub struct ShadowDrawPass {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    models: Rc<RefCell<ModelsDrawPass>>,
+    pipeline: wgpu::RenderPipeline,
+    shadow_map: klgl::Texture,
+    light_camera: Camera,
+    light_buffer: wgpu::Buffer,
+    // Binds the light matrix in place of the camera when rendering the shadow map
+    light_camera_bind_group: wgpu::BindGroup,
+    binding: ShadowBinding,
+}
+
+impl ShadowDrawPass {
+    pub const NAME: &str = "shadows";
+
+    pub fn new(ctx: Rc<RefCell<klgl::RenderContext>>, models: Rc<RefCell<ModelsDrawPass>>) -> Self {
+        let (pipeline, shadow_map, light_buffer, light_camera_bind_group, binding) = {
+            let ctx = ctx.borrow();
+            let models = models.borrow();
+            let device = &ctx.device;
+
+            let shadow_map = klgl::Texture::create_depth_texture_with_comparison(
+                device,
+                SHADOW_MAP_SIZE,
+                SHADOW_MAP_SIZE,
+                "shadow_map",
+            );
+
+            let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Shadow Light Buffer"),
+                contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+            let light_camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: models.camera_bind_group_layout(),
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                }],
+                label: Some("shadow_light_camera_bind_group"),
+            });
+
+            let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(shadow_map.sampler_binding_type()),
+                        count: None,
+                    },
+                ],
+                label: Some("shadow_bind_group_layout"),
+            });
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: light_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&shadow_map.sampler),
+                    },
+                ],
+                label: Some("shadow_bind_group"),
+            });
+
+            let pipeline = Self::create_pipeline(
+                device,
+                models.texture_bind_group_layout(),
+                models.camera_bind_group_layout(),
+            );
+
+            (
+                pipeline,
+                shadow_map,
+                light_buffer,
+                light_camera_bind_group,
+                ShadowBinding { layout, bind_group },
+            )
+        };
+
+        // The light is positioned by `set_light` before the first frame
+        Self {
+            ctx,
+            models,
+            pipeline,
+            shadow_map,
+            light_camera: Camera::new_orthographic(
+                Point3::new(0.0, 0.0, 0.0),
+                Rotator::from_direction(-Vector3::unit_z()),
+                1.0,
+                1.0,
+                0.0,
+                1.0,
+            ),
+            light_buffer,
+            light_camera_bind_group,
+            binding,
+        }
+    }
+
+    pub fn binding(&self) -> ShadowBinding {
+        self.binding.clone()
+    }
+
+    /// Points the light along `direction` so that its view volume encloses the
+    /// sphere around `focus` with the given `radius`.
+    pub fn set_light(&mut self, direction: Vector3<f32>, focus: Point3<f32>, radius: f32) {
+        fit_light_camera(&mut self.light_camera, direction, focus, radius);
+
+        let mut light_uniform = CameraUniform::new();
+        light_uniform.update_view_proj(&self.light_camera);
+        self.ctx.borrow().queue.write_buffer(
+            &self.light_buffer,
+            0,
+            bytemuck::cast_slice(&[light_uniform]),
+        );
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::TUTORIAL_9_SHADER.into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Shadow Render Pipeline Layout"),
+                    // Materials are bound by the model even though the depth pass ignores them
+                    bind_group_layouts: &[texture_bind_group_layout, camera_bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_shadow"),
+                buffers: &[ModelVertex::layout(), Instance::layout()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: klgl::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                // Pushes the stored depth away from the light to avoid shadow acne
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+fn fit_light_camera(camera: &mut Camera, direction: Vector3<f32>, focus: Point3<f32>, radius: f32) {
+    let direction = direction.normalize();
+    camera.set_eye(focus - direction * radius);
+    camera.set_rotator(Rotator::from_direction(direction));
+    camera.set_projection(klgl::Projection::Orthographic {
+        height: 2.0 * radius,
+    });
+    camera.set_clip_planes(0.0, 2.0 * radius);
+}
+
+impl klgl::DrawPass for ShadowDrawPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, _: &klgl::PassTargets) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Render Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.shadow_map.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        self.models
+            .borrow()
+            .draw_geometry(&mut render_pass, &self.light_camera_bind_group);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use klgl::DrawPass;
+
+    fn light_clip(camera: &Camera, point: Point3<f32>) -> cgmath::Vector4<f32> {
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(camera);
+        cgmath::Matrix4::from(uniform.view_proj) * point.to_homogeneous()
+    }
+
+    #[test]
+    fn test_light_camera_encloses_focus_sphere() {
+        let mut camera = Camera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Rotator::from_direction(Vector3::unit_x()),
+            1.0,
+            90.0,
+            0.1,
+            1.0,
+        );
+        let focus = Point3::new(10.0, -5.0, 2.0);
+        let radius = 50.0;
+        fit_light_camera(&mut camera, Vector3::new(0.3, 0.2, -1.0), focus, radius);
+
+        let center = light_clip(&camera, focus);
+        assert!(center.x.abs() < 1e-4 && center.y.abs() < 1e-4);
+        assert!((center.z - 0.5).abs() < 1e-4);
+
+        let offsets = [
+            Vector3::unit_x(),
+            -Vector3::unit_x(),
+            Vector3::unit_y(),
+            -Vector3::unit_y(),
+            Vector3::unit_z(),
+            -Vector3::unit_z(),
+        ];
+        for offset in offsets {
+            let clip = light_clip(&camera, focus + offset * radius * 0.99);
+            assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0);
+            assert!((0.0..=1.0).contains(&clip.z));
+        }
+    }
+
+    fn camera_binding(device: &wgpu::Device) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("camera_bind_group_layout"),
+        });
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("camera_bind_group"),
+        });
+        (layout, bind_group)
+    }
+
+    // Copies the shadow map to the CPU, one depth per texel in rows
+    fn read_depth(ctx: &klgl::RenderContext, texture: &klgl::Texture) -> Vec<f32> {
+        let bytes_per_row = SHADOW_MAP_SIZE * std::mem::size_of::<f32>() as u32;
+        let buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Map Readback"),
+            size: (bytes_per_row * SHADOW_MAP_SIZE) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::DepthOnly,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            texture.texture.size(),
+        );
+        ctx.queue.submit([encoder.finish()]);
+
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        ctx.device.poll(wgpu::Maintain::Wait);
+        bytemuck::cast_slice(&buffer.slice(..).get_mapped_range()).to_vec()
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_models_are_written_to_the_shadow_map() {
+        let ctx = crate::test_utils::gpu_context(64, 64);
+
+        let (camera_layout, camera_bind_group) = camera_binding(&ctx.borrow().device);
+        let mut models = crate::test_utils::cube_models(
+            &ctx,
+            &camera_layout,
+            &camera_bind_group,
+            wgpu::TextureFormat::Rgba16Float,
+            None,
+            "shadow_map",
+        );
+        // One instance of the cube, scaled to a side of 20 at the origin
+        models.set_instance_grid(&ctx.borrow().device, 1);
+        let models = Rc::new(RefCell::new(models));
+
+        let mut shadows = ShadowDrawPass::new(ctx.clone(), models.clone());
+        shadows.set_light(-Vector3::unit_z(), Point3::new(0.0, 0.0, 0.0), 50.0);
+
+        let ctx = ctx.borrow();
+        let color = klgl::Texture::create_render_target(
+            &ctx.device,
+            64,
+            64,
+            wgpu::TextureFormat::Rgba16Float,
+            "color",
+        );
+        let targets = klgl::PassTargets {
+            color: &color.view,
+            depth: None,
+            surface: &color.view,
+            viewport: None,
+            camera: None,
+        };
+        let mut uploader = klgl::FrameUploader::new();
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        models
+            .borrow_mut()
+            .upload_instances(&mut uploader, &mut encoder);
+        uploader.finish();
+        shadows.record(&mut encoder, &targets);
+        ctx.queue.submit([encoder.finish()]);
+
+        // The cube covers the center of the light's view and nothing else is in it
+        let depth = read_depth(&ctx, &shadows.shadow_map);
+        let center = (SHADOW_MAP_SIZE / 2 * (SHADOW_MAP_SIZE + 1)) as usize;
+        assert!(depth[center] < 1.0, "{}", depth[center]);
+        assert_eq!(depth[0], 1.0);
+    }
+}
@@ -0,0 +1,294 @@
+use std::{cell::RefCell, rc::Rc};
+
+use cgmath::Vector3;
+use wgpu::util::DeviceExt;
+
+// Linear colors of the sky gradient, kept below 1 so bloom leaves the sky alone
+const ZENITH_COLOR: [f32; 3] = [0.15, 0.35, 0.75];
+const HORIZON_COLOR: [f32; 3] = [0.65, 0.75, 0.9];
+const GROUND_COLOR: [f32; 3] = [0.2, 0.18, 0.16];
+
+/// Has to match SkyUniform in skybox_shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    up: [f32; 4],
+    zenith: [f32; 4],
+    horizon: [f32; 4],
+    ground: [f32; 4],
+}
+
+impl SkyUniform {
+    fn new(up: Vector3<f32>) -> Self {
+        let color = |[r, g, b]: [f32; 3]| [r, g, b, 1.0];
+        Self {
+            up: up.extend(0.0).into(),
+            zenith: color(ZENITH_COLOR),
+            horizon: color(HORIZON_COLOR),
+            ground: color(GROUND_COLOR),
+        }
+    }
+}
+
+/// Fills the background with a sky gradient at the far plane.
+///
+/// Has to run after the opaque models, which hide it through the depth test, and before
+/// anything blended over the scene, e.g. particles and the shader grid.
+pub struct SkyboxDrawPass {
+    pipeline: wgpu::RenderPipeline,
+    camera_bind_group: wgpu::BindGroup,
+    sky_bind_group: wgpu::BindGroup,
+    enabled: bool,
+}
+
+impl SkyboxDrawPass {
+    pub const NAME: &str = "skybox";
+
+    /// `up` points from the horizon to the zenith
+    pub fn new(
+        ctx: Rc<RefCell<klgl::RenderContext>>,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group: &wgpu::BindGroup,
+        color_format: wgpu::TextureFormat,
+        up: Vector3<f32>,
+    ) -> Self {
+        let ctx = ctx.borrow();
+        let sky_buffer = ctx
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Sky Buffer"),
+                contents: bytemuck::cast_slice(&[SkyUniform::new(up)]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
+        let sky_bind_group_layout =
+            ctx.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                    label: Some("sky_bind_group_layout"),
+                });
+
+        let sky_bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &sky_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: sky_buffer.as_entire_binding(),
+            }],
+            label: Some("sky_bind_group"),
+        });
+
+        let pipeline = Self::create_pipeline(
+            &ctx.device,
+            &[camera_bind_group_layout, &sky_bind_group_layout],
+            color_format,
+        );
+
+        Self {
+            pipeline,
+            camera_bind_group: camera_bind_group.clone(),
+            sky_bind_group,
+            enabled: false,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The pass keeps its place in the pass list while disabled and records nothing
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::SKYBOX_SHADER.into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Skybox Render Pipeline Layout"),
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Seen from the inside
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Every fragment is at the far plane. It passes only where the depth was cleared
+            // and nothing was drawn, and leaves the depth as it is.
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: klgl::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+impl klgl::DrawPass for SkyboxDrawPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn per_view(&self) -> bool {
+        true
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut render_pass = targets.begin_render_pass(encoder, "Skybox Render Pass");
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, targets.camera_or(&self.camera_bind_group), &[]);
+        render_pass.set_bind_group(1, &self.sky_bind_group, &[]);
+        render_pass.draw(0..36, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models_draw_pass::DebugMode;
+    use crate::test_utils::{camera_binding, cube_models, depth_stencil_state, read_rgba8};
+    use klgl::DrawPass;
+
+    const SIZE: u32 = 32;
+
+    #[test]
+    fn test_uniform_layout() {
+        assert_eq!(std::mem::size_of::<SkyUniform>(), 64);
+        assert_eq!(std::mem::offset_of!(SkyUniform, ground), 48);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_sky_fills_the_background_behind_the_models() {
+        let ctx = crate::test_utils::gpu_context(SIZE, SIZE);
+
+        // Looks at a cube in the middle, level with the horizon
+        let camera = klgl::Camera::new(
+            cgmath::Point3::new(-100.0, 0.0, 1.0),
+            klgl::Rotator::from_direction(Vector3::unit_x()),
+            1.0,
+            45.0,
+            1.0,
+            1000.0,
+        );
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        let (camera_layout, camera_bind_group) = camera_binding(&ctx.borrow().device, &camera);
+        let mut models = cube_models(
+            &ctx,
+            &camera_layout,
+            &camera_bind_group,
+            format,
+            depth_stencil_state(),
+            "skybox",
+        );
+        models.set_debug_mode(DebugMode::Depth);
+        models.set_instance_grid(&ctx.borrow().device, 1);
+        let mut skybox = SkyboxDrawPass::new(
+            ctx.clone(),
+            &camera_layout,
+            &camera_bind_group,
+            format,
+            Vector3::unit_z(),
+        );
+
+        let ctx = ctx.borrow();
+        let mut uploader = klgl::FrameUploader::new();
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        models.upload_instances(&mut uploader, &mut encoder);
+        uploader.finish();
+        ctx.queue.submit([encoder.finish()]);
+
+        let color = klgl::Texture::create_render_target(&ctx.device, SIZE, SIZE, format, "color");
+        let depth = klgl::Texture::create_depth_texture(&ctx.device, SIZE, SIZE, "depth");
+        let targets = klgl::PassTargets {
+            color: &color.view,
+            depth: Some(&depth.view),
+            surface: &color.view,
+            viewport: None,
+            camera: None,
+        };
+        let render = |skybox: &SkyboxDrawPass| {
+            let mut encoder = ctx
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            klgl::ClearPass::new(wgpu::Color::BLACK).record(&mut encoder, &targets);
+            models.record(&mut encoder, &targets);
+            skybox.record(&mut encoder, &targets);
+            ctx.queue.submit([encoder.finish()]);
+            read_rgba8(&ctx, &color.texture)
+        };
+
+        let without_sky = render(&skybox);
+        skybox.set_enabled(true);
+        let with_sky = render(&skybox);
+
+        // The cube in the middle is drawn the same, the sky does not cover it
+        let center = (SIZE / 2 * SIZE + SIZE / 2) as usize;
+        assert_ne!(without_sky[center], [0, 0, 0, 255]);
+        assert_eq!(with_sky[center], without_sky[center]);
+
+        // The background is black without the sky, blue above the horizon and ground below
+        let top = (SIZE / 2) as usize;
+        let bottom = ((SIZE - 1) * SIZE + SIZE / 2) as usize;
+        assert_eq!(without_sky[top], [0, 0, 0, 255]);
+        assert_eq!(without_sky[bottom], [0, 0, 0, 255]);
+        let [r, g, b, _] = with_sky[top].map(|c| c as f32 / 255.0);
+        assert!(b > g && g > r, "{:?}", with_sky[top]);
+        assert!(r > ZENITH_COLOR[0] && r < HORIZON_COLOR[0]);
+        for (c, expected) in with_sky[bottom].iter().zip(GROUND_COLOR) {
+            assert!((*c as f32 / 255.0 - expected).abs() < 0.01);
+        }
+    }
+}
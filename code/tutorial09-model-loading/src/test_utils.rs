@@ -0,0 +1,291 @@
+//! Helpers of the tests that render with a headless context and read the result back
+
+use std::{cell::RefCell, rc::Rc};
+
+use pollster::FutureExt;
+use wgpu::util::DeviceExt;
+
+use crate::models_draw_pass::ModelsDrawPass;
+
+// A cube with sides of 200 around the origin
+const CUBE_OBJ: &str = "v -100 -100 -100\nv 100 -100 -100\nv 100 100 -100\nv -100 100 -100\n\
+                        v -100 -100 100\nv 100 -100 100\nv 100 100 100\nv -100 100 100\n\
+                        f 1 3 2\nf 1 4 3\nf 5 6 7\nf 5 7 8\nf 1 2 6\nf 1 6 5\n\
+                        f 2 3 7\nf 2 7 6\nf 3 4 8\nf 3 8 7\nf 4 1 5\nf 4 5 8\n";
+
+/// Context of the tests that need an adapter. They are marked `#[ignore]`, run them with
+/// `cargo test -- --ignored` on a machine that has one.
+pub fn gpu_context(width: u32, height: u32) -> Rc<RefCell<klgl::RenderContext>> {
+    let ctx = klgl::RenderContext::headless(width, height)
+        .block_on()
+        .expect("No adapter to run the ignored GPU tests on");
+    Rc::new(RefCell::new(ctx))
+}
+
+/// Camera uniform of `camera` with the layout the passes of the app use
+pub fn camera_binding(
+    device: &wgpu::Device,
+    camera: &klgl::Camera,
+) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+        label: Some("camera_bind_group_layout"),
+    });
+    let mut uniform = klgl::CameraUniform::new();
+    uniform.update_view_proj(camera);
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Camera Buffer"),
+        contents: bytemuck::cast_slice(&[uniform]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+        label: Some("camera_bind_group"),
+    });
+    (layout, bind_group)
+}
+
+/// Models pass that draws a cube with sides of 200, scaled down to 20 by the instances.
+/// `name` keeps the temporary files of tests that run at the same time apart.
+pub fn cube_models(
+    ctx: &Rc<RefCell<klgl::RenderContext>>,
+    camera_layout: &wgpu::BindGroupLayout,
+    camera_bind_group: &wgpu::BindGroup,
+    color_format: wgpu::TextureFormat,
+    depth_stencil_state: Option<wgpu::DepthStencilState>,
+    name: &str,
+) -> ModelsDrawPass {
+    obj_models(
+        ctx,
+        camera_layout,
+        camera_bind_group,
+        color_format,
+        depth_stencil_state,
+        name,
+        CUBE_OBJ,
+    )
+}
+
+/// Models pass that draws the geometry of an obj file
+pub fn obj_models(
+    ctx: &Rc<RefCell<klgl::RenderContext>>,
+    camera_layout: &wgpu::BindGroupLayout,
+    camera_bind_group: &wgpu::BindGroup,
+    color_format: wgpu::TextureFormat,
+    depth_stencil_state: Option<wgpu::DepthStencilState>,
+    name: &str,
+    obj: &str,
+) -> ModelsDrawPass {
+    let dir = std::env::temp_dir().join(format!("tutorial09_{name}_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("model.obj"), obj).unwrap();
+
+    let lights = crate::lights::LightManager::new(ctx.clone());
+    let mut models = ModelsDrawPass::new(
+        &mut klgl::file_loader::FileLoader::new(),
+        ctx.clone(),
+        camera_layout,
+        camera_bind_group,
+        &lights,
+        color_format,
+        depth_stencil_state,
+    )
+    .block_on();
+    let loaded = models.load_from_disk(&dir.join("model.obj"));
+    std::fs::remove_dir_all(&dir).unwrap();
+    loaded.unwrap();
+    models
+}
+
+/// Depth state of the scene passes, nearer fragments win
+pub fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+    Some(wgpu::DepthStencilState {
+        format: klgl::Texture::DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::Less,
+        stencil: wgpu::StencilState::default(),
+        bias: wgpu::DepthBiasState::default(),
+    })
+}
+
+/// Render target of the given format filled with `bytes`, texels in rows without padding
+pub fn filled_texture(
+    ctx: &klgl::RenderContext,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    bytes: &[u8],
+) -> klgl::Texture {
+    let texel_size = format
+        .block_copy_size(None)
+        .expect("Only color textures can be filled");
+    assert_eq!(bytes.len(), (width * height * texel_size) as usize);
+    let texture =
+        klgl::Texture::create_render_target(&ctx.device, width, height, format, "test_texture");
+    ctx.queue.write_texture(
+        texture.texture.as_image_copy(),
+        bytes,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(width * texel_size),
+            rows_per_image: None,
+        },
+        texture.texture.size(),
+    );
+    texture
+}
+
+/// `Rgba16Float` texture with the given texels in rows
+pub fn hdr_texture(
+    ctx: &klgl::RenderContext,
+    width: u32,
+    height: u32,
+    texels: &[[f32; 4]],
+) -> klgl::Texture {
+    let bytes: Vec<u16> = texels.iter().flatten().map(|&c| f32_to_f16(c)).collect();
+    filled_texture(
+        ctx,
+        width,
+        height,
+        wgpu::TextureFormat::Rgba16Float,
+        bytemuck::cast_slice(&bytes),
+    )
+}
+
+/// Copies a color texture to the CPU, texels in rows without padding
+pub fn read_texture(ctx: &klgl::RenderContext, texture: &wgpu::Texture) -> Vec<u8> {
+    let texel_size = texture
+        .format()
+        .block_copy_size(None)
+        .expect("Only color textures can be read");
+    let row_size = texture.width() * texel_size;
+    let padded_row_size = row_size.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Test Readback Buffer"),
+        size: (padded_row_size * texture.height()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = ctx
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_size),
+                rows_per_image: None,
+            },
+        },
+        texture.size(),
+    );
+    ctx.queue.submit([encoder.finish()]);
+
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    ctx.device.poll(wgpu::Maintain::Wait);
+    let padded = buffer.slice(..).get_mapped_range();
+    padded
+        .chunks(padded_row_size as usize)
+        .flat_map(|row| &row[..row_size as usize])
+        .copied()
+        .collect()
+}
+
+/// Texels of an `Rgba16Float` texture
+pub fn read_hdr(ctx: &klgl::RenderContext, texture: &wgpu::Texture) -> Vec<[f32; 4]> {
+    assert_eq!(texture.format(), wgpu::TextureFormat::Rgba16Float);
+    read_texture(ctx, texture)
+        .chunks(8)
+        .map(|texel| {
+            std::array::from_fn(|i| {
+                f16_to_f32(u16::from_le_bytes([texel[2 * i], texel[2 * i + 1]]))
+            })
+        })
+        .collect()
+}
+
+/// Texels of an `R16Float` texture
+pub fn read_r16f(ctx: &klgl::RenderContext, texture: &wgpu::Texture) -> Vec<f32> {
+    assert_eq!(texture.format(), wgpu::TextureFormat::R16Float);
+    read_texture(ctx, texture)
+        .chunks(2)
+        .map(|texel| f16_to_f32(u16::from_le_bytes([texel[0], texel[1]])))
+        .collect()
+}
+
+/// Texels of an 8 bit RGBA texture, as stored. sRGB textures stay encoded.
+pub fn read_rgba8(ctx: &klgl::RenderContext, texture: &wgpu::Texture) -> Vec<[u8; 4]> {
+    assert_eq!(texture.format().block_copy_size(None), Some(4));
+    read_texture(ctx, texture)
+        .chunks(4)
+        .map(|texel| [texel[0], texel[1], texel[2], texel[3]])
+        .collect()
+}
+
+/// Decodes a component of an sRGB texture
+pub fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    match value <= 0.04045 {
+        true => value / 12.92,
+        false => ((value + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+// Truncates the mantissa and flushes values too small for a normal half float to zero.
+// Exact for the values tests use.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = ((bits >> 13) & 0x3ff) as u16;
+    match exponent {
+        ..=0 => sign,
+        31.. => sign | 0x7c00,
+        _ => sign | (exponent as u16) << 10 | mantissa,
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = match bits & 0x8000 {
+        0 => 1.0,
+        _ => -1.0,
+    };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32 / 1024.0;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-14),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa) * 2f32.powi(exponent - 15),
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_float_round_trip() {
+        for value in [0.0, 1.0, -2.0, 0.5, 0.25, 3.0, 100.0, 1.0 / 1024.0] {
+            assert_eq!(f16_to_f32(f32_to_f16(value)), value);
+        }
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+    }
+}
@@ -0,0 +1,352 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use wgpu::util::DeviceExt;
+
+/// Has to match the operator constants in tonemap_shader.wgsl
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TonemapOperator {
+    Reinhard = 0,
+    Aces = 1,
+}
+
+impl TonemapOperator {
+    pub const ALL: [TonemapOperator; 2] = [TonemapOperator::Reinhard, TonemapOperator::Aces];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|x| *x == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    operator: u32,
+    _padding: [u32; 2],
+}
+
+/// Maps the HDR scene color to the surface with a fullscreen triangle
+pub struct TonemapPass {
+    ctx: Rc<RefCell<klgl::RenderContext>>,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    exposure: f32,
+    operator: TonemapOperator,
+    // Written instead of the surface when more post processing follows
+    output: Option<wgpu::TextureView>,
+}
+
+impl TonemapPass {
+    pub const NAME: &str = "tonemap";
+    pub const MIN_EXPOSURE: f32 = 1.0 / 16.0;
+    pub const MAX_EXPOSURE: f32 = 16.0;
+
+    pub fn new(ctx: Rc<RefCell<klgl::RenderContext>>, hdr_texture: &klgl::Texture) -> Self {
+        let exposure = 1.0;
+        let operator = TonemapOperator::Aces;
+
+        let (pipeline, bind_group_layout, bind_group, uniform_buffer) = {
+            let ctx = ctx.borrow();
+            let device = &ctx.device;
+
+            let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Tonemap Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[TonemapUniform {
+                    exposure,
+                    operator: operator as u32,
+                    _padding: [0; 2],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+            let bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("tonemap_bind_group_layout"),
+                });
+
+            let bind_group =
+                Self::create_bind_group(device, &bind_group_layout, hdr_texture, &uniform_buffer);
+            let pipeline = Self::create_pipeline(
+                device,
+                &bind_group_layout,
+                ctx.config.format,
+                &ctx.surface_shader_constants(),
+            );
+            (pipeline, bind_group_layout, bind_group, uniform_buffer)
+        };
+
+        Self {
+            ctx,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            uniform_buffer,
+            exposure,
+            operator,
+            output: None,
+        }
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Clamped to `MIN_EXPOSURE..=MAX_EXPOSURE`
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = clamp_exposure(exposure);
+        self.write_uniform();
+    }
+
+    pub fn operator(&self) -> TonemapOperator {
+        self.operator
+    }
+
+    pub fn set_operator(&mut self, operator: TonemapOperator) {
+        self.operator = operator;
+        self.write_uniform();
+    }
+
+    /// Renders into `output` instead of the surface. Its format has to match the surface format.
+    pub fn set_output(&mut self, output: Option<wgpu::TextureView>) {
+        self.output = output;
+    }
+
+    /// Has to be called when the HDR texture is recreated
+    pub fn on_resize(&mut self, device: &wgpu::Device, hdr_texture: &klgl::Texture) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            hdr_texture,
+            &self.uniform_buffer,
+        );
+    }
+
+    fn write_uniform(&self) {
+        self.ctx.borrow().queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[TonemapUniform {
+                exposure: self.exposure,
+                operator: self.operator as u32,
+                _padding: [0; 2],
+            }]),
+        );
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        hdr_texture: &klgl::Texture,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&hdr_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        })
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        surface_format: wgpu::TextureFormat,
+        surface_constants: &HashMap<String, f64>,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tonemap Shader"),
+            source: wgpu::ShaderSource::Wgsl(tutorial_embedded_content::TONEMAP_SHADER.into()),
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Render Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Tonemap Render Pipeline Layout"),
+                    bind_group_layouts: &[bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                // Encodes to sRGB if the surface does not
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: surface_constants,
+                    ..Default::default()
+                },
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+fn clamp_exposure(exposure: f32) -> f32 {
+    // NaN would stick forever since every multiplication keeps it
+    if exposure.is_nan() {
+        return 1.0;
+    }
+    exposure.clamp(TonemapPass::MIN_EXPOSURE, TonemapPass::MAX_EXPOSURE)
+}
+
+impl klgl::DrawPass for TonemapPass {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, targets: &klgl::PassTargets) {
+        let targets = match &self.output {
+            Some(output) => klgl::PassTargets {
+                color: output,
+                depth: None,
+                surface: targets.surface,
+                viewport: None,
+                camera: None,
+            },
+            None => targets.surface_only(),
+        };
+        let mut render_pass = targets.begin_render_pass(encoder, "Tonemap Render Pass");
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{gpu_context, hdr_texture, read_rgba8, srgb_to_linear};
+
+    #[test]
+    fn test_clamp_exposure() {
+        assert_eq!(clamp_exposure(0.0), TonemapPass::MIN_EXPOSURE);
+        assert_eq!(clamp_exposure(-2.0), TonemapPass::MIN_EXPOSURE);
+        assert_eq!(
+            clamp_exposure(TonemapPass::MIN_EXPOSURE * 0.8),
+            TonemapPass::MIN_EXPOSURE
+        );
+        assert_eq!(clamp_exposure(1000.0), TonemapPass::MAX_EXPOSURE);
+        assert_eq!(clamp_exposure(2.5), 2.5);
+        assert_eq!(clamp_exposure(f32::NAN), 1.0);
+    }
+
+    #[test]
+    fn test_operators_cycle() {
+        let mut operator = TonemapOperator::Aces;
+        for _ in 0..TonemapOperator::ALL.len() {
+            operator = operator.next();
+        }
+        assert_eq!(operator, TonemapOperator::Aces);
+        assert_eq!(TonemapOperator::Reinhard.next(), TonemapOperator::Aces);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn test_hdr_color_is_tonemapped() {
+        let ctx = gpu_context(1, 1);
+        let hdr = hdr_texture(&ctx.borrow(), 1, 1, &[[0.0, 1.0, 3.0, 1.0]]);
+        let output = klgl::Texture::create_render_target(
+            &ctx.borrow().device,
+            1,
+            1,
+            ctx.borrow().config.format,
+            "output",
+        );
+        let mut tonemap = TonemapPass::new(ctx.clone(), &hdr);
+        tonemap.set_output(Some(output.view.clone()));
+
+        // Reinhard is c / (1 + c) and ACES is clamped to 1, exposure scales c first
+        let aces = |c: f32| (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14);
+        for (operator, exposure, expected) in [
+            (TonemapOperator::Reinhard, 1.0, [0.0, 0.5, 0.75]),
+            (TonemapOperator::Reinhard, 2.0, [0.0, 2.0 / 3.0, 6.0 / 7.0]),
+            (TonemapOperator::Aces, 1.0, [0.0, aces(1.0), aces(3.0)]),
+            (TonemapOperator::Aces, 16.0, [0.0, 1.0, 1.0]),
+        ] {
+            tonemap.set_operator(operator);
+            tonemap.set_exposure(exposure);
+
+            let ctx = ctx.borrow();
+            let mut encoder = ctx
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            let targets = klgl::PassTargets {
+                color: &hdr.view,
+                depth: None,
+                surface: &output.view,
+                viewport: None,
+                camera: None,
+            };
+            klgl::DrawPass::record(&tonemap, &mut encoder, &targets);
+            ctx.queue.submit([encoder.finish()]);
+
+            // The output is sRGB, 8 bits are precise to about 1% after decoding
+            let texel = read_rgba8(&ctx, &output.texture)[0];
+            for (channel, expected) in texel.iter().zip(expected) {
+                let actual = srgb_to_linear(*channel);
+                assert!(
+                    (actual - expected).abs() < 0.01,
+                    "{operator:?} at exposure {exposure}: {actual} != {expected}"
+                );
+            }
+        }
+    }
+}
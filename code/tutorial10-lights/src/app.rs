@@ -10,9 +10,9 @@ use winit::{
 
 use crate::models_draw_pass::ModelsDrawPass;
 use crate::{display_depth_draw_pass::DisplayDepthDrawPass, lines_draw_pass::LinesDrawPass};
-use klgl::{Camera, CameraController, CameraUniform, Rotator};
+use klgl::{Camera, CameraController, CameraPose, CameraUniform, Rotator};
 
-use cgmath::Deg;
+use cgmath::{Deg, Point3};
 use std::{cell::RefCell, iter, rc::Rc};
 use web_time::Instant;
 
@@ -22,7 +22,6 @@ struct Renderer {
 
     start_time: Instant,
     clear_color: wgpu::Color,
-    surface_configured: bool,
     frame_counter: klgl::FpsCounter,
     last_stat_print: Instant,
 
@@ -32,6 +31,8 @@ struct Renderer {
     display_depth_draw_pass: Option<DisplayDepthDrawPass>,
 
     camera: Camera,
+    // Pose at startup, T teleports back to it
+    initial_camera_pose: CameraPose,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
@@ -54,7 +55,7 @@ impl<'a> ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let renderer = Renderer::new(
             event_loop
-                .create_window(Window::default_attributes())
+                .create_window(klgl::WindowConfig::new("Tutorial 10: Lights").attributes())
                 .unwrap(),
         )
         .block_on();
@@ -73,13 +74,19 @@ impl<'a> ApplicationHandler for App {
             _ => {}
         }
     }
+
+    fn device_event(&mut self, _: &ActiveEventLoop, _: DeviceId, event: DeviceEvent) {
+        if let Some(s) = &mut self.renderer {
+            s.camera_controller.process_device_event(&event);
+        }
+    }
 }
 
 impl Renderer {
     async fn new(w: Window) -> Self {
         let render_context = Rc::new(RefCell::new(klgl::RenderContext::new(w).await));
 
-        let size = render_context.borrow().window.inner_size();
+        let size = render_context.borrow().window().inner_size();
         let depth_texture = klgl::Texture::create_depth_texture(
             &render_context.borrow().device,
             size.width,
@@ -176,12 +183,12 @@ impl Renderer {
             start_time: Instant::now(),
             depth_texture,
             clear_color: wgpu::Color::BLACK,
-            surface_configured: false,
             frame_counter: klgl::FpsCounter::new(),
             last_stat_print: Instant::now(),
             lines_draw_pass,
             models_draw_pass,
             display_depth_draw_pass: None,
+            initial_camera_pose: camera.pose(),
             camera,
             camera_uniform,
             camera_buffer,
@@ -220,18 +227,32 @@ impl Renderer {
                 PhysicalKey::Code(KeyCode::KeyO) => {
                     self.show_depth = event.state == ElementState::Pressed;
                 }
+                PhysicalKey::Code(KeyCode::F1)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    log::info!("{}", self.render_context.borrow().report());
+                }
+                PhysicalKey::Code(KeyCode::Home)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.camera.face_towards(Point3::new(0.0, 0.0, 0.0));
+                }
+                PhysicalKey::Code(KeyCode::KeyT)
+                    if event.state == ElementState::Pressed && !event.repeat =>
+                {
+                    self.camera.set_pose(self.initial_camera_pose);
+                }
                 _ => {}
             },
             WindowEvent::Resized(physical_size) => {
                 log::info!("physical_size: {physical_size:?}");
-                self.surface_configured = true;
                 self.resize(physical_size.width, physical_size.height);
             }
             WindowEvent::RedrawRequested => {
                 // This tells winit that we want another frame after this one
-                self.render_context.borrow().window.request_redraw();
+                self.render_context.borrow().window().request_redraw();
 
-                if !self.surface_configured {
+                if !self.render_context.borrow().is_configured() {
                     return;
                 }
 
@@ -340,11 +361,12 @@ impl Renderer {
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         self.frame_counter.register_entry(Instant::now());
-        if !self.surface_configured {
-            return Ok(());
-        }
 
-        let output = self.render_context.borrow().surface.get_current_texture()?;
+        let output = self
+            .render_context
+            .borrow()
+            .surface()
+            .get_current_texture()?;
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -13,9 +13,17 @@ use crate::model::{Model, ModelVertex, Vertex};
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct Instance {
     model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
 }
 
 impl Instance {
+    fn new(model: cgmath::Matrix4<f32>) -> Self {
+        Self {
+            model: model.into(),
+            normal: klgl::normal_matrix(&model).into(),
+        }
+    }
+
     fn layout() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -49,6 +57,22 @@ impl Instance {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // The normal matrix follows as three vec3 columns
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
@@ -99,8 +123,8 @@ impl LoadingModel {
     }
 
     pub fn update(&mut self) {
-        while let Ok(file_handle) = self.endpoint.receiver.try_recv() {
-            let path = self.endpoint.loader.path_by_id(file_handle.id).unwrap();
+        while let Ok((file_id, file_handle)) = self.endpoint.receiver.try_recv() {
+            let path = self.endpoint.loader.path_by_id(file_id).unwrap();
             self.received_files.insert(path, file_handle);
             if self.remaining > 0 {
                 self.remaining -= 1;
@@ -292,15 +316,14 @@ impl ModelsDrawPass {
 
                 let scale = cgmath::Matrix4::from_scale(0.1);
 
-                Instance {
-                    model: (cgmath::Matrix4::from_translation(cgmath::Vector3 {
+                Instance::new(
+                    cgmath::Matrix4::from_translation(cgmath::Vector3 {
                         x: (x as f32),
                         y: (y as f32),
                         z: 1.0,
                     }) * rotation.to_matrix()
-                        * scale)
-                        .into(),
-                }
+                        * scale,
+                )
             })
         }));
     }
@@ -0,0 +1,75 @@
+// Vertex shader
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+// A single triangle that covers the whole screen. Needs no vertex buffers.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+// Fragment shader
+
+struct BloomUniform {
+    threshold: f32,
+    intensity: f32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: BloomUniform;
+
+// Has to match BLUR_WEIGHTS in bloom_pass.rs
+const BLUR_WEIGHTS = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+// Keeps only the part of the color above the threshold
+@fragment
+fn fs_extract(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.tex_coords).rgb;
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - bloom.threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+// Bilinear filtering averages the source texels when rendering to a smaller target
+@fragment
+fn fs_copy(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(t_source, s_source, in.tex_coords).rgb, 1.0);
+}
+
+fn blur(tex_coords: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    let texel = direction / vec2<f32>(textureDimensions(t_source));
+    var result = textureSample(t_source, s_source, tex_coords).rgb * BLUR_WEIGHTS[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = texel * f32(i);
+        result += textureSample(t_source, s_source, tex_coords + offset).rgb * BLUR_WEIGHTS[i];
+        result += textureSample(t_source, s_source, tex_coords - offset).rgb * BLUR_WEIGHTS[i];
+    }
+    return vec4<f32>(result, 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.tex_coords, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_vertical(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.tex_coords, vec2<f32>(0.0, 1.0));
+}
+
+// Added on top of the scene color with additive blending
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(t_source, s_source, in.tex_coords).rgb * bloom.intensity, 1.0);
+}
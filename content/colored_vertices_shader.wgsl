@@ -33,3 +33,74 @@ fn vs_main(
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     return vec4<f32>(in.color, 1.0);
 }
+
+// Thick lines
+//
+// Every instance is a segment expanded into a screen-aligned quad of `line_width` pixels.
+
+struct LineUniform {
+    viewport_size: vec2<f32>,
+    line_width: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> line: LineUniform;
+
+struct SegmentInput {
+    @location(0) start: vec3<f32>,
+    @location(1) start_color: vec3<f32>,
+    @location(2) end: vec3<f32>,
+    @location(3) end_color: vec3<f32>,
+};
+
+// Moves the point along the segment onto the near plane (z = 0) if it is behind it
+fn clip_to_near(point: vec4<f32>, other: vec4<f32>) -> vec4<f32> {
+    if point.z >= 0.0 {
+        return point;
+    }
+    return mix(point, other, point.z / (point.z - other.z));
+}
+
+@vertex
+fn vs_thick_line(
+    @builtin(vertex_index) vertex_index: u32,
+    segment: SegmentInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let start_clip = camera.view_proj * vec4<f32>(segment.start, 1.0);
+    let end_clip = camera.view_proj * vec4<f32>(segment.end, 1.0);
+    if start_clip.z < 0.0 && end_clip.z < 0.0 {
+        // Entirely behind the camera, gets clipped
+        out.clip_position = vec4<f32>(0.0, 0.0, -1.0, 1.0);
+        out.color = segment.start_color;
+        return out;
+    }
+
+    let start = clip_to_near(start_clip, end_clip);
+    let end = clip_to_near(end_clip, start_clip);
+
+    // Two triangles. x selects the end of the segment, y the side of the line.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    let pixels = (end.xy / end.w - start.xy / start.w) * line.viewport_size;
+    var direction = vec2<f32>(1.0, 0.0);
+    if length(pixels) > 0.0001 {
+        direction = normalize(pixels);
+    }
+    // Half the width on each side, converted from pixels to normalized device coordinates
+    let offset = vec2<f32>(-direction.y, direction.x) * line.line_width / line.viewport_size;
+
+    let is_end = corner.x > 0.5;
+    let point = select(start, end, is_end);
+    out.clip_position = point + vec4<f32>(offset * corner.y * point.w, 0.0, 0.0);
+    out.color = select(segment.start_color, segment.end_color, is_end);
+    return out;
+}
@@ -5,6 +5,17 @@ var t_depth: texture_depth_2d;
 @group(0) @binding(1)
 var s_depth: sampler;
 
+struct OverlayUniform {
+    opacity: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+};
+
+// Only used by fs_overlay, passes drawing with fs_main don't bind it
+@group(0) @binding(2)
+var<uniform> overlay: OverlayUniform;
+
 struct VertexInput {
     @location(0) position: vec2<f32>,
 };
@@ -27,14 +38,37 @@ fn vs_main(
 
 // Fragment shader
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+// Set by the app when the surface does not encode to sRGB itself
+override ENCODE_SRGB: bool = false;
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn depth_color(in: VertexOutput) -> vec4<f32> {
     let near = 0.1;
     let far = 100.0;
     let depth = textureSampleLevel(t_depth, s_depth, in.uv, 0);
-    let r = (2.0 * near) / (far + near - depth * (far - near));
+    var r = (2.0 * near) / (far + near - depth * (far - near));
+    if ENCODE_SRGB {
+        r = linear_to_srgb(vec3<f32>(r)).x;
+    }
     return vec4<f32>(vec3<f32>(r), 1.0);
 
     //let depth = textureSampleLevel(t_depth, s_depth, in.uv, 0);
     //return vec4<f32>(vec3<f32>(depth), 1.0);
 }
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return depth_color(in);
+}
+
+// The alpha is the opacity of the overlay, for alpha blending over the scene
+@fragment
+fn fs_overlay(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = depth_color(in);
+    return vec4<f32>(color.rgb, color.a * overlay.opacity);
+}
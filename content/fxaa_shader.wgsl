@@ -0,0 +1,185 @@
+// Vertex shader
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+// A single triangle that covers the whole screen. Needs no vertex buffers.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+// Fragment shader
+//
+// FXAA 3.11 quality path. Finds luma edges, searches along them for their ends
+// and resamples the color across the edge with the bilinear filter.
+
+// Has to match FxaaUniform in fxaa_pass.rs
+struct FxaaUniform {
+    inverse_resolution: vec2<f32>,
+    edge_threshold: f32,
+    edge_threshold_min: f32,
+    subpixel_quality: f32,
+    search_steps: u32,
+};
+
+@group(0) @binding(0)
+var t_color: texture_2d<f32>;
+@group(0) @binding(1)
+var s_color: sampler;
+@group(0) @binding(2)
+var<uniform> fxaa: FxaaUniform;
+
+fn color_at(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_color, s_color, uv, 0.0).rgb;
+}
+
+// The color is linear so the square root brings luma close to perceptual
+fn luma(color: vec3<f32>) -> f32 {
+    return sqrt(dot(color, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+fn luma_at(uv: vec2<f32>, offset: vec2<f32>) -> f32 {
+    return luma(color_at(uv + offset * fxaa.inverse_resolution));
+}
+
+// Distance covered by each step of the edge search. Grows towards the end to reach long edges.
+fn search_step(i: u32) -> f32 {
+    if i == 0u {
+        return 1.0;
+    }
+    if i == 1u {
+        return 1.5;
+    }
+    if i + 1u == fxaa.search_steps {
+        return 8.0;
+    }
+    if i + 2u == fxaa.search_steps {
+        return 4.0;
+    }
+    return 2.0;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    let color = color_at(uv);
+
+    let luma_center = luma(color);
+    let luma_down = luma_at(uv, vec2<f32>(0.0, 1.0));
+    let luma_up = luma_at(uv, vec2<f32>(0.0, -1.0));
+    let luma_left = luma_at(uv, vec2<f32>(-1.0, 0.0));
+    let luma_right = luma_at(uv, vec2<f32>(1.0, 0.0));
+
+    let luma_min = min(luma_center, min(min(luma_down, luma_up), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_down, luma_up), max(luma_left, luma_right)));
+    let luma_range = luma_max - luma_min;
+
+    // Not an edge or too dark to notice
+    if luma_range < max(fxaa.edge_threshold_min, luma_max * fxaa.edge_threshold) {
+        return vec4<f32>(color, 1.0);
+    }
+
+    let luma_down_left = luma_at(uv, vec2<f32>(-1.0, 1.0));
+    let luma_up_right = luma_at(uv, vec2<f32>(1.0, -1.0));
+    let luma_up_left = luma_at(uv, vec2<f32>(-1.0, -1.0));
+    let luma_down_right = luma_at(uv, vec2<f32>(1.0, 1.0));
+
+    let luma_down_up = luma_down + luma_up;
+    let luma_left_right = luma_left + luma_right;
+    let luma_left_corners = luma_down_left + luma_up_left;
+    let luma_down_corners = luma_down_left + luma_down_right;
+    let luma_right_corners = luma_down_right + luma_up_right;
+    let luma_up_corners = luma_up_right + luma_up_left;
+
+    let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_down_up) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_left_right) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // Pick the side of the edge with the steepest gradient
+    // 1 is the neighbor in the negative texture coordinate direction
+    let luma1 = select(luma_left, luma_up, is_horizontal);
+    let luma2 = select(luma_right, luma_down, is_horizontal);
+    let gradient1 = luma1 - luma_center;
+    let gradient2 = luma2 - luma_center;
+    let is1_steepest = abs(gradient1) >= abs(gradient2);
+    let gradient_scaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    var step_length = select(fxaa.inverse_resolution.x, fxaa.inverse_resolution.y, is_horizontal);
+    var luma_local_average: f32;
+    if is1_steepest {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma1 + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma2 + luma_center);
+    }
+
+    // Start the search in the middle between the pixel and its neighbor across the edge
+    var current_uv = uv;
+    if is_horizontal {
+        current_uv.y += step_length * 0.5;
+    } else {
+        current_uv.x += step_length * 0.5;
+    }
+
+    let offset = select(
+        vec2<f32>(0.0, fxaa.inverse_resolution.y),
+        vec2<f32>(fxaa.inverse_resolution.x, 0.0),
+        is_horizontal,
+    );
+    var uv1 = current_uv - offset * search_step(0u);
+    var uv2 = current_uv + offset * search_step(0u);
+    var luma_end1 = luma(color_at(uv1)) - luma_local_average;
+    var luma_end2 = luma(color_at(uv2)) - luma_local_average;
+    var reached1 = abs(luma_end1) >= gradient_scaled;
+    var reached2 = abs(luma_end2) >= gradient_scaled;
+
+    for (var i = 1u; i < fxaa.search_steps && !(reached1 && reached2); i++) {
+        if !reached1 {
+            uv1 -= offset * search_step(i);
+            luma_end1 = luma(color_at(uv1)) - luma_local_average;
+            reached1 = abs(luma_end1) >= gradient_scaled;
+        }
+        if !reached2 {
+            uv2 += offset * search_step(i);
+            luma_end2 = luma(color_at(uv2)) - luma_local_average;
+            reached2 = abs(luma_end2) >= gradient_scaled;
+        }
+    }
+
+    let distance1 = select(uv.y - uv1.y, uv.x - uv1.x, is_horizontal);
+    let distance2 = select(uv2.y - uv.y, uv2.x - uv.x, is_horizontal);
+    let is_direction1 = distance1 < distance2;
+    let distance_final = min(distance1, distance2);
+    let edge_length = distance1 + distance2;
+    let pixel_offset = -distance_final / edge_length + 0.5;
+
+    // Only move towards the edge if the end we are closer to varies the same way as the center
+    let is_luma_center_smaller = luma_center < luma_local_average;
+    let correct_variation = (select(luma_end2, luma_end1, is_direction1) < 0.0) != is_luma_center_smaller;
+    var final_offset = select(0.0, pixel_offset, correct_variation);
+
+    // Sub-pixel aliasing for details thinner than a pixel
+    let luma_average = (1.0 / 12.0) * (2.0 * (luma_down_up + luma_left_right) + luma_left_corners + luma_right_corners);
+    let subpixel1 = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+    let subpixel2 = (-2.0 * subpixel1 + 3.0) * subpixel1 * subpixel1;
+    final_offset = max(final_offset, subpixel2 * subpixel2 * fxaa.subpixel_quality);
+
+    var final_uv = uv;
+    if is_horizontal {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    return vec4<f32>(color_at(final_uv), 1.0);
+}
@@ -0,0 +1,82 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Has to match GridUniform in shader_grid_pass.rs
+struct GridUniform {
+    eye: vec3<f32>,
+    spacing: f32,
+    // The plane goes through the origin and is spanned by these two axes
+    axis_u: vec3<f32>,
+    fade_distance: f32,
+    axis_v: vec3<f32>,
+    major_every: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> grid: GridUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+};
+
+// A quad under the camera that covers the visible part of the plane
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index] * grid.fade_distance;
+    let center = grid.axis_u * dot(grid.eye, grid.axis_u) + grid.axis_v * dot(grid.eye, grid.axis_v);
+    let world_position = center + grid.axis_u * corner.x + grid.axis_v * corner.y;
+
+    var out: VertexOutput;
+    out.world_position = world_position;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+// 1 on a line, 0 away from it. Lines are about one pixel wide at any distance
+// because the distance to the line is measured in pixels with the derivatives.
+fn grid_lines(coord: vec2<f32>, spacing: f32) -> f32 {
+    let scaled = coord / spacing;
+    let footprint = fwidth(scaled);
+    let distance = abs(fract(scaled - 0.5) - 0.5) / footprint;
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<f32>(dot(in.world_position, grid.axis_u), dot(in.world_position, grid.axis_v));
+    let minor = grid_lines(coord, grid.spacing);
+    let major = grid_lines(coord, grid.spacing * grid.major_every);
+
+    // Axes are tinted with the color of the axis they run along
+    let footprint = fwidth(coord);
+    let along_u = 1.0 - min(abs(coord.y) / footprint.y, 1.0);
+    let along_v = 1.0 - min(abs(coord.x) / footprint.x, 1.0);
+
+    var color = vec3<f32>(0.5);
+    color = mix(color, grid.axis_u, along_u);
+    color = mix(color, grid.axis_v, along_v);
+
+    let fade = 1.0 - smoothstep(0.5, 1.0, length(in.world_position - grid.eye) / grid.fade_distance);
+    let alpha = max(max(minor * 0.4, major), max(along_u, along_v)) * fade;
+    if alpha <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(color, alpha);
+}
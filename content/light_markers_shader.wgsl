@@ -0,0 +1,76 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Has to match the layout in tutorial_9_shader.wgsl
+const MAX_POINT_LIGHTS: u32 = 16u;
+
+struct PointLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    intensity: f32,
+};
+
+struct PointLights {
+    items: array<PointLight, MAX_POINT_LIGHTS>,
+    count: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> point_lights: PointLights;
+
+struct MarkerUniform {
+    // Half size of a marker in normalized device coordinates
+    half_size: vec2<f32>,
+};
+
+@group(2) @binding(0)
+var<uniform> marker: MarkerUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+// One quad per light made of two triangles. Needs no vertex buffers.
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    let light = point_lights.items[instance_index];
+
+    var out: VertexOutput;
+    out.corner = corner;
+    out.color = light.color;
+    out.clip_position = camera.view_proj * vec4<f32>(light.position, 1.0);
+    // Offsetting in clip space keeps the marker facing the camera with a constant screen size
+    out.clip_position += vec4<f32>(corner * marker.half_size * out.clip_position.w, 0.0, 0.0);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (dot(in.corner, in.corner) > 1.0) {
+        discard;
+    }
+    return vec4<f32>(in.color, 1.0);
+}
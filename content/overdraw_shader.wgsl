@@ -0,0 +1,38 @@
+// Vertex shader
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// A single triangle that covers the whole screen. Needs no vertex buffers.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+// Fragment shader
+
+// Layers at which the color turns fully red
+const MAX_LAYERS: f32 = 8.0;
+
+// Fragments drawn to each pixel, in the red channel
+@group(0) @binding(0)
+var t_overdraw: texture_2d<f32>;
+
+// Black where nothing was drawn, then blue for one layer through green to red
+fn heat_color(layers: f32) -> vec3<f32> {
+    if layers <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let t = clamp((layers - 1.0) / (MAX_LAYERS - 1.0), 0.0, 1.0);
+    return vec3<f32>(max(2.0 * t - 1.0, 0.0), 1.0 - abs(2.0 * t - 1.0), max(1.0 - 2.0 * t, 0.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let layers = textureLoad(t_overdraw, vec2<i32>(in.clip_position.xy), 0).r;
+    return vec4<f32>(heat_color(layers), 1.0);
+}
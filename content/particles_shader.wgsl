@@ -0,0 +1,110 @@
+// Has to match `Particle` in particles.rs
+struct Particle {
+    position: vec3<f32>,
+    // Seconds left. The slot is free when it is not positive.
+    life: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+    color: vec4<f32>,
+};
+
+// Compute shader
+
+struct SimParams {
+    gravity: vec3<f32>,
+    dt: f32,
+};
+
+@group(0) @binding(0)
+var<storage, read_write> particles: array<Particle>;
+
+@group(0) @binding(1)
+var<uniform> params: SimParams;
+
+@compute @workgroup_size(64)
+fn cs_update(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= arrayLength(&particles)) {
+        return;
+    }
+
+    var particle = particles[index];
+    if (particle.life <= 0.0) {
+        return;
+    }
+
+    particle.velocity += params.gravity * params.dt;
+    particle.position += particle.velocity * params.dt;
+    particle.life -= params.dt;
+    particles[index] = particle;
+}
+
+// Vertex shader
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct BillboardUniform {
+    // Half size of a billboard in clip space, shrinks with distance like the scene
+    half_size: vec2<f32>,
+};
+
+@group(1) @binding(0)
+var<storage, read> render_particles: array<Particle>;
+
+@group(1) @binding(1)
+var<uniform> billboard: BillboardUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+// One quad per particle slot. Free slots are moved outside of the clip volume.
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    let particle = render_particles[instance_index];
+
+    var out: VertexOutput;
+    out.corner = corner;
+    if (particle.life <= 0.0) {
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        out.color = vec4<f32>(0.0);
+        return out;
+    }
+
+    // Fade out towards the end of the lifetime
+    let fade = clamp(particle.life / particle.lifetime, 0.0, 1.0);
+    out.color = vec4<f32>(particle.color.rgb * fade, particle.color.a);
+    out.clip_position = camera.view_proj * vec4<f32>(particle.position, 1.0);
+    out.clip_position += vec4<f32>(corner * billboard.half_size, 0.0, 0.0);
+    return out;
+}
+
+// Fragment shader
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance_squared = dot(in.corner, in.corner);
+    if (distance_squared > 1.0) {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb * (1.0 - distance_squared), in.color.a);
+}
@@ -0,0 +1,72 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct PointsUniform {
+    // Size of the render target in pixels
+    viewport_size: vec2<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> points: PointsUniform;
+
+struct PointInput {
+    @location(0) position: vec3<f32>,
+    // Diameter in pixels
+    @location(1) size: f32,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Position in the quad relative to the point, the point itself is the unit circle
+    @location(0) offset: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+// One quad per point made of two triangles, sized in pixels
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    point: PointInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let radius = max(point.size * 0.5, 0.5);
+    // One more pixel around the circle leaves room for the smooth edge
+    let corner = corners[vertex_index] * (radius + 1.0);
+
+    var out: VertexOutput;
+    out.offset = corner / radius;
+    out.color = point.color;
+    out.clip_position = camera.view_proj * vec4<f32>(point.position, 1.0);
+    // Pixels to clip space, scaled by w so the size stays the same at any distance
+    out.clip_position += vec4<f32>(corner * 2.0 / points.viewport_size * out.clip_position.w, 0.0, 0.0);
+    return out;
+}
+
+// Fragment shader
+
+// Has to match point_coverage in points_draw_pass.rs
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(in.offset);
+    // How much the distance changes per pixel, the edge fades over one pixel
+    let edge = max(fwidth(distance), 1e-4);
+    let coverage = clamp((1.0 - distance) / edge + 0.5, 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
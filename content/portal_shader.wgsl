@@ -0,0 +1,35 @@
+// Vertex shader
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+// A single triangle that covers the viewport, which is set to the corner of the screen
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+// Fragment shader
+
+@group(0) @binding(0)
+var t_portal: texture_2d<f32>;
+@group(0) @binding(1)
+var s_portal: sampler;
+
+// Frame around the picture, in texture coordinates
+const BORDER: f32 = 0.01;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let edge = min(in.tex_coords, 1.0 - in.tex_coords);
+    if min(edge.x, edge.y) < BORDER {
+        return vec4<f32>(0.8, 0.8, 0.8, 1.0);
+    }
+    return vec4<f32>(textureSample(t_portal, s_portal, in.tex_coords).rgb, 1.0);
+}
@@ -0,0 +1,67 @@
+// Vertex shader
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Has to match SkyUniform in skybox_draw_pass.rs
+struct SkyUniform {
+    up: vec4<f32>,
+    zenith: vec4<f32>,
+    horizon: vec4<f32>,
+    ground: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> sky: SkyUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+};
+
+// A cube around the camera. Its size does not matter, every fragment ends up on the far plane.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var corners = array<vec3<f32>, 8>(
+        vec3<f32>(-1.0, -1.0, -1.0),
+        vec3<f32>(1.0, -1.0, -1.0),
+        vec3<f32>(1.0, 1.0, -1.0),
+        vec3<f32>(-1.0, 1.0, -1.0),
+        vec3<f32>(-1.0, -1.0, 1.0),
+        vec3<f32>(1.0, -1.0, 1.0),
+        vec3<f32>(1.0, 1.0, 1.0),
+        vec3<f32>(-1.0, 1.0, 1.0),
+    );
+    var indices = array<u32, 36>(
+        0u, 2u, 1u, 0u, 3u, 2u,
+        4u, 5u, 6u, 4u, 6u, 7u,
+        0u, 1u, 5u, 0u, 5u, 4u,
+        1u, 2u, 6u, 1u, 6u, 5u,
+        2u, 3u, 7u, 2u, 7u, 6u,
+        3u, 0u, 4u, 3u, 4u, 7u,
+    );
+    let direction = corners[indices[vertex_index]];
+
+    var out: VertexOutput;
+    out.direction = direction;
+    let clip_position = camera.view_proj * vec4<f32>(camera.eye.xyz + direction, 1.0);
+    // Depth of z / w = 1, so anything drawn before covers the sky
+    out.clip_position = clip_position.xyww;
+    return out;
+}
+
+// Fragment shader
+
+// Blends from the horizon up to the zenith and fades to the ground color just below the horizon
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let height = dot(normalize(in.direction), sky.up.xyz);
+    let sky_color = mix(sky.horizon.rgb, sky.zenith.rgb, sqrt(max(height, 0.0)));
+    let color = mix(sky.ground.rgb, sky_color, smoothstep(-0.02, 0.0, height));
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,75 @@
+// Vertex shader
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+// A single triangle that covers the whole screen. Needs no vertex buffers.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+// Fragment shader
+
+// Has to match TonemapOperator in tonemap_pass.rs
+const OPERATOR_REINHARD: u32 = 0u;
+const OPERATOR_ACES: u32 = 1u;
+
+struct TonemapUniform {
+    exposure: f32,
+    tonemap_operator: u32,
+};
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var s_hdr: sampler;
+@group(0) @binding(2)
+var<uniform> tonemap: TonemapUniform;
+
+// Set by the app when the surface does not encode to sRGB itself
+override ENCODE_SRGB: bool = false;
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (vec3<f32>(1.0) + color);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.tex_coords).rgb * tonemap.exposure;
+    var ldr: vec3<f32>;
+    switch tonemap.tonemap_operator {
+        case OPERATOR_ACES: {
+            ldr = aces(hdr);
+        }
+        default: {
+            ldr = reinhard(hdr);
+        }
+    }
+    if ENCODE_SRGB {
+        ldr = linear_to_srgb(ldr);
+    }
+    return vec4<f32>(ldr, 1.0);
+}
@@ -2,6 +2,9 @@
 
 struct CameraUniform {
     view_proj: mat4x4<f32>,
+    eye: vec4<f32>,
+    // Near and far plane distances in x and y
+    clip_planes: vec4<f32>,
 };
 
 @group(1) @binding(0)
@@ -12,6 +15,10 @@ struct InstanceInput {
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    // Inverse transpose of the model matrix, keeps normals perpendicular under non-uniform scale
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
 };
 
 struct VertexInput {
@@ -20,24 +27,70 @@ struct VertexInput {
     @location(2) normal: vec3<f32>,
 };
 
+// Per vertex color, multiplied with the texture
+struct ColorInput {
+    @location(4) color: vec3<f32>,
+};
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(5) color: vec3<f32>,
 };
 
-@vertex
-fn vs_main(
-    model: VertexInput, instance: InstanceInput,
-) -> VertexOutput {
+fn transform_vertex(model: VertexInput, instance: InstanceInput) -> VertexOutput {
     let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
         instance.model_matrix_1,
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_normal = normal_matrix * model.normal;
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    out.color = vec3<f32>(1.0);
+    return out;
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput, instance: InstanceInput,
+) -> VertexOutput {
+    return transform_vertex(model, instance);
+}
+
+// Texture coordinate transform and lighting of the material
+struct MaterialUniform {
+    tex_scale: vec2<f32>,
+    tex_offset: vec2<f32>,
+    ambient: f32,
+    diffuse: f32,
+    specular: f32,
+    shininess: f32,
+};
+
+@group(0) @binding(3)
+var<uniform> material: MaterialUniform;
+
+// Same as vs_main for vertex buffers that have colors. Also applies the texture coordinate
+// transform of the material, per vertex is enough because the transform is affine.
+@vertex
+fn vs_colored(
+    model: VertexInput, color: ColorInput, instance: InstanceInput,
+) -> VertexOutput {
+    var out = transform_vertex(model, instance);
+    out.tex_coords = model.tex_coords * material.tex_scale + material.tex_offset;
+    out.color = color.color;
     return out;
 }
 
@@ -50,5 +103,203 @@ var s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return vec4<f32>(color.rgb * in.color, color.a);
+}
+
+// Debug visualizations
+
+@fragment
+fn fs_normals(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(normalize(in.world_normal) * 0.5 + 0.5, 1.0);
+}
+
+@fragment
+fn fs_tex_coords(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(fract(in.tex_coords), 0.0, 1.0);
+}
+
+@fragment
+fn fs_depth(in: VertexOutput) -> @location(0) vec4<f32> {
+    let near = camera.clip_planes.x;
+    let far = camera.clip_planes.y;
+    // Distance from the camera of a depth in the 0..1 range, shown as a fraction of far
+    let depth = in.clip_position.z;
+    let distance = near * far / (far - depth * (far - near));
+    return vec4<f32>(vec3<f32>(distance / far), 1.0);
+}
+
+// Point lights
+
+const MAX_POINT_LIGHTS: u32 = 16u;
+
+struct PointLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    intensity: f32,
+};
+
+struct PointLights {
+    items: array<PointLight, MAX_POINT_LIGHTS>,
+    count: u32,
+};
+
+@group(2) @binding(0)
+var<uniform> point_lights: PointLights;
+
+fn point_lights_diffuse(world_position: vec3<f32>, world_normal: vec3<f32>) -> vec3<f32> {
+    let normal = normalize(world_normal);
+    var result = vec3<f32>(0.0);
+    for (var i = 0u; i < min(point_lights.count, MAX_POINT_LIGHTS); i += 1u) {
+        let light = point_lights.items[i];
+        let to_light = light.position - world_position;
+        let distance = length(to_light);
+        let falloff = clamp(1.0 - distance / light.radius, 0.0, 1.0);
+        let diffuse = max(dot(normal, to_light / max(distance, 1e-4)), 0.0);
+        result += light.color * light.intensity * diffuse * falloff * falloff;
+    }
+    return result;
+}
+
+// Blinn-Phong highlights of the point lights
+fn point_lights_specular(
+    world_position: vec3<f32>, world_normal: vec3<f32>, shininess: f32,
+) -> vec3<f32> {
+    let normal = normalize(world_normal);
+    let to_eye = normalize(camera.eye.xyz - world_position);
+    var result = vec3<f32>(0.0);
+    for (var i = 0u; i < min(point_lights.count, MAX_POINT_LIGHTS); i += 1u) {
+        let light = point_lights.items[i];
+        let to_light = light.position - world_position;
+        let distance = length(to_light);
+        let light_direction = to_light / max(distance, 1e-4);
+        let falloff = clamp(1.0 - distance / light.radius, 0.0, 1.0);
+        // Surfaces facing away from the light get no highlight
+        let facing = select(0.0, 1.0, dot(normal, light_direction) > 0.0);
+        let half_direction = normalize(light_direction + to_eye);
+        let highlight = pow(max(dot(normal, half_direction), 0.0), shininess) * facing;
+        result += light.color * light.intensity * highlight * falloff * falloff;
+    }
+    return result;
+}
+
+// `ambient` is the light that reaches the surface without the point lights
+fn shade_material(color: vec3<f32>, ambient: f32, in: VertexOutput) -> vec3<f32> {
+    let diffuse = point_lights_diffuse(in.world_position, in.world_normal);
+    let specular = point_lights_specular(in.world_position, in.world_normal, material.shininess);
+    let light = ambient * material.ambient + diffuse * material.diffuse;
+    return color * light + specular * material.specular;
+}
+
+@fragment
+fn fs_lit(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return vec4<f32>(shade_material(color.rgb * in.color, 1.0, in), color.a);
+}
+
+// Shadow mapping
+
+struct ShadowLight {
+    view_proj: mat4x4<f32>,
+};
+
+@group(3) @binding(0)
+var<uniform> shadow_light: ShadowLight;
+@group(3) @binding(1)
+var t_shadow: texture_depth_2d;
+@group(3) @binding(2)
+var s_shadow: sampler_comparison;
+
+// Renders the scene depth from the light's point of view. The light matrix is bound as the camera.
+@vertex
+fn vs_shadow(
+    model: VertexInput, instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+// Drawn after vs_shadow with additive blending, so every fragment adds one layer
+// to the overdraw target
+@fragment
+fn fs_overdraw() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 0.0, 0.0);
+}
+
+// 1.0 when the point is lit, 0.0 when it is fully in shadow
+fn shadow_factor(world_position: vec3<f32>) -> f32 {
+    let light_clip = shadow_light.view_proj * vec4<f32>(world_position, 1.0);
+    let ndc = light_clip.xyz / light_clip.w;
+    // Clip space y points up while texture v points down
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    let bias = 0.002;
+    let lit = textureSampleCompareLevel(t_shadow, s_shadow, uv, ndc.z - bias);
+    let outside = any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0;
+    return select(lit, 1.0, outside);
+}
+
+@fragment
+fn fs_shadowed(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let sun = mix(0.35, 1.0, shadow_factor(in.world_position));
+    return vec4<f32>(shade_material(color.rgb * in.color, sun, in), color.a);
+}
+
+// Texture array mode: all diffuse textures of a model are layers of one texture
+// and every vertex knows the layer of its material
+
+@group(0) @binding(2)
+var t_diffuse_array: texture_2d_array<f32>;
+
+struct ArrayVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) layer: u32,
+    @location(4) color: vec3<f32>,
+};
+
+struct ArrayVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) @interpolate(flat) layer: u32,
+    @location(5) color: vec3<f32>,
+};
+
+@vertex
+fn vs_array(
+    model: ArrayVertexInput, instance: InstanceInput,
+) -> ArrayVertexOutput {
+    let transformed = transform_vertex(VertexInput(model.position, model.tex_coords, model.normal), instance);
+    var out: ArrayVertexOutput;
+    out.clip_position = transformed.clip_position;
+    out.tex_coords = transformed.tex_coords;
+    out.world_normal = transformed.world_normal;
+    out.world_position = transformed.world_position;
+    out.layer = model.layer;
+    out.color = model.color;
+    return out;
+}
+
+@fragment
+fn fs_lit_array(in: ArrayVertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse_array, s_diffuse, in.tex_coords, in.layer);
+    let light = 1.0 + point_lights_diffuse(in.world_position, in.world_normal);
+    return vec4<f32>(color.rgb * in.color * light, color.a);
+}
+
+@fragment
+fn fs_shadowed_array(in: ArrayVertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_diffuse_array, s_diffuse, in.tex_coords, in.layer);
+    let sun = mix(0.35, 1.0, shadow_factor(in.world_position));
+    let light = sun + point_lights_diffuse(in.world_position, in.world_normal);
+    return vec4<f32>(color.rgb * in.color * light, color.a);
 }